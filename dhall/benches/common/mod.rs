@@ -0,0 +1,105 @@
+//! Inputs and timing helpers shared by the benchmarks.
+//!
+//! The inputs are generated, so that the benchmarks don't need network access or the Prelude to be
+//! checked out.
+#![allow(dead_code)]
+
+use std::time::{Duration, Instant};
+
+/// The Prelude functions used by `prelude_bindings`, inlined.
+const PRELUDE: &str = r#"
+let map =
+      λ(a : Type) →
+      λ(b : Type) →
+      λ(f : a → b) →
+      λ(xs : List a) →
+        List/build
+          b
+          ( λ(list : Type) →
+            λ(cons : b → list → list) →
+              List/fold a xs list (λ(x : a) → cons (f x))
+          )
+
+let concatMap =
+      λ(a : Type) →
+      λ(b : Type) →
+      λ(f : a → List b) →
+      λ(xs : List a) →
+        List/build
+          b
+          ( λ(list : Type) →
+            λ(cons : b → list → list) →
+            λ(nil : list) →
+              List/fold a xs list (λ(x : a) → List/fold b (f x) list cons) nil
+          )
+
+let Entry = { name : Text, value : Optional Natural, tags : List Text }
+"#;
+
+/// An expression with `n` bindings, each mapping over a list with Prelude-like functions, as code
+/// based on the Prelude does.
+pub fn prelude_bindings(n: usize) -> String {
+    let mut src = String::from(PRELUDE);
+    for i in 0..n {
+        src.push_str(&format!(
+            "let x{i} = map Natural Entry \
+             (λ(n : Natural) → {{ name = Natural/show n, value = Some n, \
+             tags = concatMap Natural Text (λ(m : Natural) → [Natural/show m]) [n, {i}] }}) \
+             [1, 2, {i}]\n",
+            i = i
+        ));
+    }
+    let all: Vec<_> = (0..n).map(|i| format!("x{}", i)).collect();
+    src.push_str(&format!("in [{}]", all.join(", ")));
+    src
+}
+
+/// A record with `n` fields, each holding a record of data, like a large generated configuration.
+pub fn large_record(n: usize) -> String {
+    let fields: Vec<_> = (0..n)
+        .map(|i| {
+            format!(
+                "field{i} = {{ id = {i}, name = \"item {i}\", enabled = {b}, \
+                 weight = {i}.5, tags = [\"a\", \"b{i}\"], parent = {p} }}",
+                i = i,
+                b = if i % 2 == 0 { "True" } else { "False" },
+                p = if i == 0 {
+                    "None Natural".to_owned()
+                } else {
+                    format!("Some {}", i - 1)
+                },
+            )
+        })
+        .collect();
+    format!("{{ {} }}", fields.join(", "))
+}
+
+/// Runs `run`, which returns the time taken by the part being measured, until it has taken about
+/// a second, and returns the median time.
+pub fn measure(mut run: impl FnMut() -> Duration) -> Duration {
+    let start = Instant::now();
+    let mut times = Vec::new();
+    while times.len() < 5
+        || (start.elapsed() < Duration::from_secs(1) && times.len() < 1000)
+    {
+        times.push(run());
+    }
+    times.sort();
+    times[times.len() / 2]
+}
+
+/// Runs `f` and returns the time it took.
+pub fn time(f: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+/// The benchmarks to run: those whose name contains the first argument that isn't a flag, like
+/// `cargo bench -p dhall -- records`, or all of them.
+pub fn selected(name: &str) -> bool {
+    match std::env::args().skip(1).find(|arg| !arg.starts_with('-')) {
+        Some(filter) => name.contains(&filter),
+        None => true,
+    }
+}
@@ -0,0 +1,62 @@
+//! Measures each phase of loading a Dhall expression on a few kinds of inputs. Run with
+//! `cargo bench -p dhall --bench phases`, optionally followed by `-- <filter>`, e.g.
+//! `-- records/typecheck`.
+mod common;
+
+use common::{large_record, measure, prelude_bindings, selected, time};
+use dhall::syntax::binary;
+use dhall::{Ctxt, Parsed};
+
+fn bench_phases(corpus: &str, src: &str) {
+    let run = |phase: &str, f: &mut dyn FnMut() -> std::time::Duration| {
+        let name = format!("{}/{}", corpus, phase);
+        if selected(&name) {
+            println!("{:<24} {:>12?}", name, measure(f));
+        }
+    };
+
+    run("parse", &mut || {
+        time(|| {
+            Parsed::parse_str(src).unwrap();
+        })
+    });
+    run("typecheck", &mut || {
+        Ctxt::with_new(|cx| {
+            let resolved = Parsed::parse_str(src).unwrap().resolve(cx).unwrap();
+            time(|| {
+                resolved.typecheck(cx).unwrap();
+            })
+        })
+    });
+    run("normalize", &mut || {
+        Ctxt::with_new(|cx| {
+            let resolved = Parsed::parse_str(src).unwrap().resolve(cx).unwrap();
+            let typed = resolved.typecheck(cx).unwrap();
+            time(|| {
+                typed.normalize(cx).unwrap().to_expr(cx);
+            })
+        })
+    });
+
+    let normalized = Ctxt::with_new(|cx| {
+        let resolved = Parsed::parse_str(src).unwrap().resolve(cx).unwrap();
+        let typed = resolved.typecheck(cx).unwrap();
+        typed.normalize(cx).unwrap().to_expr(cx)
+    });
+    let bytes = binary::encode(&normalized).unwrap();
+    run("encode", &mut || {
+        time(|| {
+            binary::encode(&normalized).unwrap();
+        })
+    });
+    run("decode", &mut || {
+        time(|| {
+            Parsed::parse_binary(&bytes).unwrap();
+        })
+    });
+}
+
+fn main() {
+    bench_phases("prelude", &prelude_bindings(100));
+    bench_phases("records", &large_record(250));
+}
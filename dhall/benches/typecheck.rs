@@ -0,0 +1,41 @@
+//! Measures how typechecking scales on inputs that use `List/map`-like functions heavily, as code
+//! based on the Prelude does. Run with `cargo bench -p dhall --bench typecheck`.
+//!
+//! Each size should take about twice as long as the previous one. The benchmark fails if a size
+//! takes four times as long, as it would if some work was repeated for each binding in scope.
+mod common;
+
+use common::{measure, prelude_bindings, time};
+use dhall::{Ctxt, Parsed};
+
+fn main() {
+    let mut previous = None;
+    for &n in &[50, 100, 200, 400] {
+        let src = prelude_bindings(n);
+        let duration = measure(|| {
+            Ctxt::with_new(|cx| {
+                let resolved =
+                    Parsed::parse_str(&src).unwrap().resolve(cx).unwrap();
+                time(|| {
+                    let typed = resolved.typecheck(cx).unwrap();
+                    let ty = typed.get_type().unwrap().to_expr(cx);
+                    assert!(
+                        ty.to_string().starts_with("List (List {"),
+                        "unexpected type: {}",
+                        ty
+                    );
+                })
+            })
+        });
+        println!("{:>4} bindings: {:?}", n, duration);
+        if let Some(previous) = previous {
+            assert!(
+                duration < previous * 4,
+                "typechecking {} bindings is quadratic: {:?}",
+                n,
+                duration
+            );
+        }
+        previous = Some(duration);
+    }
+}
@@ -0,0 +1,678 @@
+//! Queries about the expression at a given position in a source file, to build editor tooling
+//! like a language server on top of this crate.
+//!
+//! Positions are byte offsets into the text that was parsed. Imported files are not looked into,
+//! except by [`field_origins`].
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::error::{Error, TypeError};
+use crate::operations::{BinOp, OpKind};
+use crate::semantics::{
+    type_with, Hir, HirKind, ImportLocation, NirKind, TyEnv,
+};
+use crate::syntax::{Expr, ExprKind, Label, Span, V};
+use crate::utils::ensure_sufficient_stack;
+use crate::{Ctxt, Parsed, Resolved};
+
+/// The type of the expression under the cursor.
+#[derive(Debug, Clone)]
+pub struct TypeAt {
+    /// The range of the expression.
+    pub range: Range<usize>,
+    pub ty: Expr,
+}
+
+/// Where the variable or import under the cursor comes from.
+#[derive(Debug, Clone)]
+pub enum Definition {
+    /// A variable bound by the `let`, `λ` or `∀` expression at this range.
+    Binder(Label, Range<usize>),
+    /// An import of this location.
+    Import(ImportLocation),
+}
+
+/// A candidate to complete the field name after a `.`.
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub name: Label,
+    /// The type of the field, or of the payload of the union alternative. `None` for alternatives
+    /// without a payload.
+    pub ty: Option<Expr>,
+}
+
+/// A variable introduced by a `let`, `λ` or `∀` expression.
+#[derive(Debug, Clone)]
+pub struct Binder {
+    pub name: Label,
+    /// The range of the expression that introduces it.
+    pub range: Range<usize>,
+}
+
+/// Where each variable of a file is bound.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    /// Every binder of the file, outermost first.
+    pub binders: Vec<Binder>,
+    /// Every occurrence of a bound variable, with the index of its binder in `binders`.
+    pub occurrences: Vec<(Range<usize>, usize)>,
+    /// Every occurrence of a variable that isn't bound anywhere.
+    pub free: Vec<(V, Range<usize>)>,
+}
+
+impl SymbolTable {
+    /// The occurrences of the variable introduced by `binders[binder]`.
+    pub fn occurrences_of(
+        &self,
+        binder: usize,
+    ) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.occurrences
+            .iter()
+            .filter(move |(_, b)| *b == binder)
+            .map(|(range, _)| range.clone())
+    }
+    /// The binders whose variable is never used.
+    pub fn unused_binders(&self) -> impl Iterator<Item = &Binder> + '_ {
+        self.binders
+            .iter()
+            .enumerate()
+            .filter(move |(i, _)| self.occurrences_of(*i).next().is_none())
+            .map(|(_, binder)| binder)
+    }
+}
+
+/// Where the value of a record field is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldOrigin {
+    /// The file, or other import location, that contains the value.
+    pub location: ImportLocation,
+    /// The line and column, both starting at 1, where the value starts. `None` if it has no source
+    /// location, e.g. for the fields of `{ a.b = 1 }` that were desugared.
+    pub line_col: Option<(usize, usize)>,
+}
+
+/// Returns the type of the smallest expression that contains `offset`, or `None` if there is no
+/// expression there. The enclosing `let` and function bindings are typechecked to get the types
+/// of the variables in scope, but the rest of the file may contain type errors.
+pub fn type_at<'cx>(
+    cx: Ctxt<'cx>,
+    file: &Resolved<'cx>,
+    offset: usize,
+) -> Result<Option<TypeAt>, TypeError> {
+    let path = path_to(&file.0, offset);
+    let target = match path.last() {
+        Some(target) => *target,
+        None => return Ok(None),
+    };
+
+    let env = env_at(cx, &path)?;
+    let tir = type_with(&env, target, None)?;
+    Ok(Some(TypeAt {
+        range: target.span().byte_range().unwrap(),
+        ty: tir.ty().to_expr_tyenv(&env),
+    }))
+}
+
+/// Returns the fields of the record, or the alternatives of the union, before the `.` under the
+/// cursor, e.g. in `{ a = 1, b = True }.`, sorted by name. The cursor may be in the middle of a
+/// field name, in which case only the names that start with the part before the cursor are
+/// returned.
+///
+/// This takes the source text, because `src` doesn't parse when the field name is missing.
+/// Imports are resolved relative to the current directory, like `Parsed::parse_str`.
+pub fn field_completions<'cx>(
+    cx: Ctxt<'cx>,
+    src: &str,
+    offset: usize,
+) -> Result<Vec<Completion>, Error> {
+    let is_label_char =
+        |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '/';
+    let start = src[..offset]
+        .rfind(|c| !is_label_char(c))
+        .map_or(0, |i| i + 1);
+    let end = src[offset..]
+        .find(|c| !is_label_char(c))
+        .map_or(src.len(), |i| offset + i);
+    if !src[..start].trim_end().ends_with('.') {
+        return Ok(Vec::new());
+    }
+    let prefix = &src[start..offset];
+
+    // Replace the field name, which may be missing or incomplete, with an arbitrary one, so that
+    // the text parses.
+    let src = format!("{}_{}", &src[..start], &src[end..]);
+    let file = Parsed::parse_str(&src)?.resolve(cx)?;
+    let path = path_to(&file.0, start);
+    let scrut = match path.last().map(|hir| hir.kind()) {
+        Some(HirKind::Expr(ExprKind::Op(OpKind::Field(scrut, _)))) => scrut,
+        _ => return Ok(Vec::new()),
+    };
+
+    let env = env_at(cx, &path)?;
+    let tir = type_with(&env, scrut, None)?;
+    let mut completions: Vec<Completion> = match tir.ty().kind() {
+        NirKind::RecordType(kts) => kts
+            .iter()
+            .map(|(name, ty)| Completion {
+                name: name.clone(),
+                ty: Some(ty.to_expr_tyenv(&env)),
+            })
+            .collect(),
+        NirKind::Const(_) => match tir.eval(&env).kind() {
+            NirKind::UnionType(kts) => kts
+                .iter()
+                .map(|(name, ty)| Completion {
+                    name: name.clone(),
+                    ty: ty.as_ref().map(|ty| ty.to_expr_tyenv(&env)),
+                })
+                .collect(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+    completions.retain(|c| String::from(&c.name).starts_with(prefix));
+    completions.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(completions)
+}
+
+/// Returns where the variable or import at `offset` is defined, or `None` if there is none there.
+pub fn definition_at<'cx>(
+    cx: Ctxt<'cx>,
+    file: &Resolved<'cx>,
+    offset: usize,
+) -> Option<Definition> {
+    let path = path_to(&file.0, offset);
+    let target = path.last()?;
+
+    // Innermost last.
+    let mut binders = Vec::new();
+    for (parent, child) in path.iter().zip(path.iter().skip(1)) {
+        match parent.kind() {
+            HirKind::Expr(ExprKind::Lam(l, _, body))
+            | HirKind::Expr(ExprKind::Pi(l, _, body))
+            | HirKind::Expr(ExprKind::Let(l, _, _, body))
+                if std::ptr::eq(body, *child) =>
+            {
+                binders.push((l, parent.span().byte_range()));
+            }
+            _ => {}
+        }
+    }
+
+    match target.kind() {
+        HirKind::Var(v) => {
+            let (l, range) = &binders[binders.len() - 1 - v.idx()];
+            Some(Definition::Binder((*l).clone(), range.clone()?))
+        }
+        HirKind::Import(import) => {
+            let stored = &cx[import];
+            let location = stored.base_location.chain(&stored.import).ok()?;
+            Some(Definition::Import(location))
+        }
+        _ => None,
+    }
+}
+
+/// Returns where the value of each field of the record `file` is set, including the fields of its
+/// nested records, keyed by their path. `location` is where `file` was read from.
+///
+/// This follows `let` bindings, field selections, projections, `⫽`, `∧` and `with`, and goes
+/// into the imported files whose source was kept with [`Ctxt::keep_import_sources`]. The fields of
+/// records that are computed in other ways, e.g. returned by functions, are left out.
+pub fn field_origins<'cx>(
+    cx: Ctxt<'cx>,
+    file: &Resolved<'cx>,
+    location: &ImportLocation,
+) -> BTreeMap<Vec<Label>, FieldOrigin> {
+    let mut out = BTreeMap::new();
+    if let Some(origins) = origins_of(cx, &file.0, location, &mut Vec::new()) {
+        flatten_origins(origins, &mut Vec::new(), &mut out);
+    }
+    out
+}
+
+/// The origin of each field of a record, with the origins of its own fields if it is a record
+/// whose fields could be tracked.
+#[derive(Debug, Clone, Default)]
+struct Origins(BTreeMap<Label, (FieldOrigin, Option<Origins>)>);
+
+/// `scope` holds the origins of the `let`-bound variables in scope, innermost last.
+fn origins_of<'cx>(
+    cx: Ctxt<'cx>,
+    hir: &Hir<'cx>,
+    location: &ImportLocation,
+    scope: &mut Vec<Option<Origins>>,
+) -> Option<Origins> {
+    let origin = |span: Span| FieldOrigin {
+        location: location.clone(),
+        line_col: span.start_line_col(),
+    };
+    ensure_sufficient_stack(|| match hir.kind() {
+        HirKind::Var(v) => scope[scope.len() - 1 - v.idx()].clone(),
+        HirKind::MissingVar(_) => None,
+        HirKind::Import(import) => {
+            let stored = &cx[import];
+            let location = stored.base_location.chain(&stored.import).ok()?;
+            let source = cx.import_source(&location)?;
+            origins_of(cx, &source, &location, &mut Vec::new())
+        }
+        HirKind::ImportAlternative(alt, left, right) => {
+            let selected = if cx[alt].unwrap_selected() {
+                left
+            } else {
+                right
+            };
+            origins_of(cx, selected, location, scope)
+        }
+        HirKind::Expr(ExprKind::RecordLit(kvs)) => Some(Origins(
+            kvs.iter()
+                .map(|(k, v)| {
+                    let nested = origins_of(cx, v, location, scope);
+                    (k.clone(), (origin(v.span()), nested))
+                })
+                .collect(),
+        )),
+        HirKind::Expr(ExprKind::Let(_, _, val, body)) => {
+            let val = origins_of(cx, val, location, scope);
+            scope.push(val);
+            let body = origins_of(cx, body, location, scope);
+            scope.pop();
+            body
+        }
+        HirKind::Expr(ExprKind::Annot(x, _)) => {
+            origins_of(cx, x, location, scope)
+        }
+        HirKind::Expr(ExprKind::Op(op)) => match op {
+            OpKind::BinOp(BinOp::RightBiasedRecordMerge, x, y) => {
+                let mut x = origins_of(cx, x, location, scope)?;
+                x.0.extend(origins_of(cx, y, location, scope)?.0);
+                Some(x)
+            }
+            OpKind::BinOp(BinOp::RecursiveRecordMerge, x, y) => {
+                let x = origins_of(cx, x, location, scope)?;
+                let y = origins_of(cx, y, location, scope)?;
+                Some(merge_origins(x, y))
+            }
+            OpKind::With(x, path, v) => {
+                let mut x = origins_of(cx, x, location, scope)?;
+                let nested = origins_of(cx, v, location, scope);
+                set_origin(&mut x, path, origin(v.span()), nested);
+                Some(x)
+            }
+            OpKind::Field(x, l) => {
+                origins_of(cx, x, location, scope)?.0.remove(l)?.1
+            }
+            OpKind::Projection(x, ls) => {
+                let mut x = origins_of(cx, x, location, scope)?;
+                x.0.retain(|k, _| ls.contains(k));
+                Some(x)
+            }
+            _ => None,
+        },
+        HirKind::Expr(_) => None,
+    })
+}
+
+/// The origins of the result of `x ∧ y`.
+fn merge_origins(mut x: Origins, y: Origins) -> Origins {
+    for (k, (y_origin, y_nested)) in y.0 {
+        let entry = match x.0.remove(&k) {
+            Some((x_origin, Some(x_nested))) => {
+                // The record desugared from e.g. `x.w = 3` has no location.
+                let origin = match y_origin.line_col {
+                    Some(_) => y_origin,
+                    None => x_origin,
+                };
+                let nested = y_nested.map(|y| merge_origins(x_nested, y));
+                (origin, nested)
+            }
+            _ => (y_origin, y_nested),
+        };
+        x.0.insert(k, entry);
+    }
+    x
+}
+
+/// Updates the origins for `x with path = v`.
+fn set_origin(
+    x: &mut Origins,
+    path: &[Label],
+    origin: FieldOrigin,
+    nested: Option<Origins>,
+) {
+    let (first, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    if rest.is_empty() {
+        x.0.insert(first.clone(), (origin, nested));
+    } else {
+        // Intermediate records that didn't exist are created by the `with`.
+        let entry =
+            x.0.entry(first.clone())
+                .or_insert_with(|| (origin.clone(), None));
+        let entry_nested = entry.1.get_or_insert_with(Origins::default);
+        set_origin(entry_nested, rest, origin, nested);
+    }
+}
+
+fn flatten_origins(
+    origins: Origins,
+    prefix: &mut Vec<Label>,
+    out: &mut BTreeMap<Vec<Label>, FieldOrigin>,
+) {
+    for (k, (origin, nested)) in origins.0 {
+        prefix.push(k);
+        out.insert(prefix.clone(), origin);
+        if let Some(nested) = nested {
+            flatten_origins(nested, prefix, out);
+        }
+        prefix.pop();
+    }
+}
+
+/// Maps every variable of the file to its binder. Desugared expressions without a source location
+/// are left out.
+pub fn symbols(file: &Resolved<'_>) -> SymbolTable {
+    let mut table = SymbolTable::default();
+    collect_symbols(&file.0, &mut Vec::new(), &mut table);
+    table
+}
+
+/// `scope` holds the indices in `table.binders` of the variables in scope, innermost last.
+fn collect_symbols(
+    hir: &Hir<'_>,
+    scope: &mut Vec<Option<usize>>,
+    table: &mut SymbolTable,
+) {
+    ensure_sufficient_stack(|| match hir.kind() {
+        HirKind::Var(v) => {
+            let binder = scope[scope.len() - 1 - v.idx()];
+            if let (Some(range), Some(binder)) =
+                (hir.span().byte_range(), binder)
+            {
+                table.occurrences.push((range, binder));
+            }
+        }
+        HirKind::MissingVar(v) => {
+            if let Some(range) = hir.span().byte_range() {
+                table.free.push((v.clone(), range));
+            }
+        }
+        HirKind::Import(_) => {}
+        HirKind::ImportAlternative(_, left, right) => {
+            collect_symbols(left, scope, table);
+            collect_symbols(right, scope, table);
+        }
+        HirKind::Expr(e) => {
+            let binder = match e {
+                ExprKind::Lam(l, _, _)
+                | ExprKind::Pi(l, _, _)
+                | ExprKind::Let(l, _, _, _) => {
+                    hir.span().byte_range().map(|range| {
+                        table.binders.push(Binder {
+                            name: l.clone(),
+                            range,
+                        });
+                        table.binders.len() - 1
+                    })
+                }
+                _ => None,
+            };
+            e.traverse_ref_maybe_binder(|l, child| {
+                if l.is_some() {
+                    scope.push(binder);
+                }
+                collect_symbols(child, scope, table);
+                if l.is_some() {
+                    scope.pop();
+                }
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        }
+    })
+}
+
+/// The typing environment of the last node of `path`.
+fn env_at<'cx>(
+    cx: Ctxt<'cx>,
+    path: &[&Hir<'cx>],
+) -> Result<TyEnv<'cx>, TypeError> {
+    let mut env = TyEnv::new(cx);
+    for (parent, child) in path.iter().zip(path.iter().skip(1)) {
+        env = match parent.kind() {
+            HirKind::Expr(ExprKind::Lam(l, annot, body))
+            | HirKind::Expr(ExprKind::Pi(l, annot, body))
+                if std::ptr::eq(body, *child) =>
+            {
+                let annot = type_with(&env, annot, None)?.eval_to_type(&env)?;
+                env.insert_type(l, annot)
+            }
+            HirKind::Expr(ExprKind::Let(l, annot, val, body))
+                if std::ptr::eq(body, *child) =>
+            {
+                let annot = annot
+                    .as_ref()
+                    .map(|t| type_with(&env, t, None)?.eval_to_type(&env))
+                    .transpose()?;
+                let val = type_with(&env, val, annot)?;
+                env.insert_value(l, val.eval(&env), val.ty().clone())
+            }
+            _ => env,
+        };
+    }
+    Ok(env)
+}
+
+/// Returns the nodes from the root to the smallest expression that contains `offset`. Nodes
+/// without a source location, e.g. desugared ones, are only included if one of their children
+/// contains `offset`.
+fn path_to<'a, 'cx>(hir: &'a Hir<'cx>, offset: usize) -> Vec<&'a Hir<'cx>> {
+    let mut path = Vec::new();
+    push_path_to(hir, offset, &mut path);
+    path
+}
+
+fn push_path_to<'a, 'cx>(
+    hir: &'a Hir<'cx>,
+    offset: usize,
+    path: &mut Vec<&'a Hir<'cx>>,
+) -> bool {
+    ensure_sufficient_stack(|| {
+        let range = hir.span().byte_range();
+        if let Some(range) = &range {
+            if !range.contains(&offset) {
+                return false;
+            }
+        }
+        path.push(hir);
+        let found = match hir.kind() {
+            HirKind::ImportAlternative(_, left, right) => {
+                push_path_to(left, offset, path)
+                    || push_path_to(right, offset, path)
+            }
+            HirKind::Expr(e) => {
+                let mut found = false;
+                e.traverse_ref(|child| {
+                    found = found || push_path_to(child, offset, path);
+                    Ok::<_, ()>(())
+                })
+                .unwrap();
+                found
+            }
+            HirKind::Var(_) | HirKind::MissingVar(_) | HirKind::Import(_) => {
+                false
+            }
+        };
+        if !found && range.is_none() {
+            path.pop();
+            return false;
+        }
+        true
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hover_and_definitions() {
+        let src =
+            "let x = 1 in \\(y : Bool) -> if y then x else 2 ? ./foo.dhall";
+        let var_x = src.find("x else").unwrap();
+        let var_y = src.find("y then").unwrap();
+        let lam = src.find('\\').unwrap();
+        let import = src.find("./foo").unwrap();
+
+        Ctxt::with_new(|cx| {
+            let file = Parsed::parse_str(src).unwrap().resolve(cx).unwrap();
+            let type_at = |offset| {
+                type_at(cx, &file, offset)
+                    .unwrap()
+                    .map(|t| (t.range, t.ty.to_string()))
+            };
+            assert_eq!(
+                type_at(var_x),
+                Some((var_x..var_x + 1, "Natural".into()))
+            );
+            assert_eq!(type_at(var_y), Some((var_y..var_y + 1, "Bool".into())));
+            assert_eq!(
+                type_at(lam),
+                Some((lam..src.len(), "∀(y : Bool) → Natural".into()))
+            );
+            assert_eq!(type_at(src.len()), None);
+
+            match definition_at(cx, &file, var_x) {
+                Some(Definition::Binder(l, range)) => {
+                    assert_eq!(l, "x".into());
+                    assert_eq!(range, 0..src.len());
+                }
+                def => panic!("unexpected definition: {:?}", def),
+            }
+            match definition_at(cx, &file, var_y) {
+                Some(Definition::Binder(l, range)) => {
+                    assert_eq!(l, "y".into());
+                    assert_eq!(range, lam..src.len());
+                }
+                def => panic!("unexpected definition: {:?}", def),
+            }
+            match definition_at(cx, &file, import) {
+                Some(Definition::Import(location)) => {
+                    assert!(location
+                        .as_local_path()
+                        .unwrap()
+                        .ends_with("foo.dhall"))
+                }
+                def => panic!("unexpected definition: {:?}", def),
+            }
+            assert!(definition_at(cx, &file, 0).is_none());
+        })
+    }
+
+    #[test]
+    fn symbol_table() {
+        let src =
+            "let x = 1 let unused = 2 in \\(y : Natural) -> x + y + x + z";
+        Ctxt::with_new(|cx| {
+            let file = Parsed::parse_str(src).unwrap().resolve(cx).unwrap();
+            let table = symbols(&file);
+            let names: Vec<_> =
+                table.binders.iter().map(|b| b.name.to_string()).collect();
+            assert_eq!(names, vec!["x", "unused", "y"]);
+            assert_eq!(
+                table.binders[2].range,
+                src.find('\\').unwrap()..src.len()
+            );
+
+            let occurrences_of = |i| {
+                table
+                    .occurrences_of(i)
+                    .map(|r| &src[r.start..r.end + 2])
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(occurrences_of(0), vec!["x +", "x +"]);
+            assert_eq!(occurrences_of(1), Vec::<&str>::new());
+            assert_eq!(occurrences_of(2), vec!["y +"]);
+
+            let unused: Vec<_> =
+                table.unused_binders().map(|b| b.name.to_string()).collect();
+            assert_eq!(unused, vec!["unused"]);
+            let free: Vec<_> =
+                table.free.iter().map(|(v, _)| v.clone()).collect();
+            assert_eq!(free, vec![V("z".into(), 0)]);
+        })
+    }
+
+    #[test]
+    fn origins_of_fields() {
+        let src = "let a = { x = { y = 1 }, z = 2 }\n\
+                   in  (a ∧ { x.w = 3 }).{ x } ⫽ { v = \\(b : Bool) -> b }";
+        Ctxt::with_new(|cx| {
+            let file = Parsed::parse_str(src).unwrap().resolve(cx).unwrap();
+            let location = ImportLocation::dhall_code_of_unknown_origin();
+            let origins: Vec<_> = field_origins(cx, &file, &location)
+                .into_iter()
+                .map(|(path, origin)| {
+                    let path: Vec<_> =
+                        path.iter().map(|l| l.to_string()).collect();
+                    (path.join("."), origin.line_col)
+                })
+                .collect();
+            assert_eq!(
+                origins,
+                vec![
+                    ("v".to_owned(), Some((2, 37))),
+                    ("x".to_owned(), Some((1, 15))),
+                    ("x.w".to_owned(), Some((2, 18))),
+                    ("x.y".to_owned(), Some((1, 21))),
+                ]
+            );
+        })
+    }
+
+    #[test]
+    fn completions_after_dot() {
+        let prelude = "let r = { foo = 1, bar = True, baz = \"\" } \
+                       let U = < A : Natural | B > \
+                       in ";
+        Ctxt::with_new(|cx| {
+            let complete = |src: &str, cursor: &str| {
+                let src = format!("{}{}", prelude, src);
+                let offset = src.rfind(cursor).unwrap() + cursor.len();
+                field_completions(cx, &src, offset)
+                    .unwrap()
+                    .into_iter()
+                    .map(|c| {
+                        (c.name.to_string(), c.ty.map(|ty| ty.to_string()))
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let some =
+                |name: &str, ty: &str| (name.to_string(), Some(ty.into()));
+
+            assert_eq!(
+                complete("r.", "r."),
+                vec![
+                    some("bar", "Bool"),
+                    some("baz", "Text"),
+                    some("foo", "Natural")
+                ]
+            );
+            assert_eq!(
+                complete("[ r.bar, False ]", "r.ba"),
+                vec![some("bar", "Bool"), some("baz", "Text")]
+            );
+            assert_eq!(
+                complete("\\(x : { y : { z : Natural } }) -> x.y. + 1", "x.y."),
+                vec![some("z", "Natural")]
+            );
+            assert_eq!(
+                complete("U.", "U."),
+                vec![some("A", "Natural"), ("B".into(), None)]
+            );
+            assert_eq!(complete("r", "r"), vec![]);
+        })
+    }
+}
@@ -1,12 +1,16 @@
+use num_integer::Integer as _;
+use num_traits::{One, ToPrimitive, Zero};
 use std::collections::{BTreeMap, HashMap};
-use std::convert::TryInto;
 
 use crate::operations::{BinOp, OpKind};
 use crate::semantics::{nze, Hir, HirKind, Nir, NirKind, NzEnv, VarEnv};
 use crate::syntax::Const::Type;
 use crate::syntax::{
-    Const, Expr, ExprKind, InterpolatedText, InterpolatedTextContents, Label,
-    NaiveDouble, NumKind, Span, UnspannedExpr, V,
+    integer_to_natural, natural_from_usize, natural_to_integer,
+};
+use crate::syntax::{
+    Const, Expr, ExprKind, InterpolatedTextContents, Label, NaiveDouble,
+    NumKind, Span, UnspannedExpr, V,
 };
 use crate::{Ctxt, Parsed};
 
@@ -293,7 +297,7 @@ macro_rules! make_closure {
         rc(ExprKind::Op(OpKind::BinOp(
             BinOp::NaturalPlus,
             make_closure!($($v)*),
-            rc(ExprKind::Num(NumKind::Natural(1)))
+            rc(ExprKind::Num(NumKind::Natural(One::one())))
         )))
     };
     ([ $($head:tt)* ] # $($tail:tt)*) => {{
@@ -307,6 +311,31 @@ macro_rules! make_closure {
     }};
 }
 
+/// The result of `Text/show`: a double-quoted literal, escaped exactly as the standard specifies.
+/// This is not how the printer escapes text, which also escapes invisible characters.
+fn show_text(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '$' => out.push_str("\\u0024"),
+            '\\' => out.push_str("\\\\"),
+            '\u{0008}' => out.push_str("\\b"),
+            '\u{000C}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{0000}'..='\u{001F}' => {
+                out.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
 #[allow(clippy::cognitive_complexity)]
 fn apply_builtin<'cx>(
     b: Builtin,
@@ -314,6 +343,9 @@ fn apply_builtin<'cx>(
     env: NzEnv<'cx>,
 ) -> NirKind<'cx> {
     let cx = env.cx();
+    if cx.check_interrupted().is_err() {
+        return NirKind::Interrupted;
+    }
     use NirKind::*;
     use NumKind::{Bool, Double, Integer, Natural};
 
@@ -323,14 +355,17 @@ fn apply_builtin<'cx>(
         Nir(Nir<'cx>),
         DoneAsIs,
     }
+    // These expansions are small and well-typed, so typechecking them must not be interrupted.
     let make_closure = |e| {
-        Parsed::from_expr_without_imports(e)
-            .resolve(cx)
-            .unwrap()
-            .typecheck(cx)
-            .unwrap()
-            .as_hir()
-            .eval(env.clone())
+        cx.uninterruptible(|| {
+            Parsed::from_expr_without_imports(e)
+                .resolve(cx)
+                .unwrap()
+                .typecheck(cx)
+                .unwrap()
+                .as_hir()
+                .eval(env.clone())
+        })
     };
 
     let ret = match (b, args.as_slice()) {
@@ -346,19 +381,26 @@ fn apply_builtin<'cx>(
             Ret::NirKind(EmptyOptionalLit(t.clone()))
         }
         (Builtin::NaturalIsZero, [n]) => match &*n.kind() {
-            Num(Natural(n)) => Ret::NirKind(Num(Bool(*n == 0))),
+            Num(Natural(n)) => Ret::NirKind(Num(Bool(n.is_zero()))),
             _ => Ret::DoneAsIs,
         },
         (Builtin::NaturalEven, [n]) => match &*n.kind() {
-            Num(Natural(n)) => Ret::NirKind(Num(Bool(*n % 2 == 0))),
+            Num(Natural(n)) => Ret::NirKind(Num(Bool(n.is_even()))),
             _ => Ret::DoneAsIs,
         },
         (Builtin::NaturalOdd, [n]) => match &*n.kind() {
-            Num(Natural(n)) => Ret::NirKind(Num(Bool(*n % 2 != 0))),
+            Num(Natural(n)) => Ret::NirKind(Num(Bool(n.is_odd()))),
             _ => Ret::DoneAsIs,
         },
         (Builtin::NaturalToInteger, [n]) => match &*n.kind() {
-            Num(Natural(n)) => Ret::NirKind(Num(Integer(*n as i64))),
+            Num(Natural(n)) => match natural_to_integer(n) {
+                Some(n) => Ret::NirKind(Num(Integer(n))),
+                // Without the `bigint` feature, the `Natural` may not fit in an `Integer`.
+                None => {
+                    cx.natural_overflow(|| format!("Natural/toInteger {}", n));
+                    Ret::DoneAsIs
+                }
+            },
             _ => Ret::DoneAsIs,
         },
         (Builtin::NaturalShow, [n]) => match &*n.kind() {
@@ -367,16 +409,19 @@ fn apply_builtin<'cx>(
         },
         (Builtin::NaturalSubtract, [a, b]) => match (&*a.kind(), &*b.kind()) {
             (Num(Natural(a)), Num(Natural(b))) => {
-                Ret::NirKind(Num(Natural(if b > a { b - a } else { 0 })))
+                let n = if b > a { b - a } else { Zero::zero() };
+                Ret::NirKind(Num(Natural(n)))
+            }
+            (Num(Natural(a)), _) if a.is_zero() => Ret::Nir(b.clone()),
+            (_, Num(Natural(b))) if b.is_zero() => {
+                Ret::NirKind(Num(Natural(Zero::zero())))
             }
-            (Num(Natural(0)), _) => Ret::Nir(b.clone()),
-            (_, Num(Natural(0))) => Ret::NirKind(Num(Natural(0))),
-            _ if a == b => Ret::NirKind(Num(Natural(0))),
+            _ if a == b => Ret::NirKind(Num(Natural(Zero::zero()))),
             _ => Ret::DoneAsIs,
         },
         (Builtin::IntegerShow, [n]) => match &*n.kind() {
             Num(Integer(n)) => {
-                let s = if *n < 0 {
+                let s = if *n < Zero::zero() {
                     n.to_string()
                 } else {
                     format!("+{}", n)
@@ -387,7 +432,8 @@ fn apply_builtin<'cx>(
         },
         (Builtin::IntegerToDouble, [n]) => match &*n.kind() {
             Num(Integer(n)) => {
-                Ret::NirKind(Num(Double(NaiveDouble::from(*n as f64))))
+                let n = n.to_f64().unwrap_or(f64::NAN);
+                Ret::NirKind(Num(Double(NaiveDouble::from(n))))
             }
             _ => Ret::DoneAsIs,
         },
@@ -397,7 +443,8 @@ fn apply_builtin<'cx>(
         },
         (Builtin::IntegerClamp, [n]) => match &*n.kind() {
             Num(Integer(n)) => {
-                Ret::NirKind(Num(Natural((*n).try_into().unwrap_or(0))))
+                let n = integer_to_natural(n).unwrap_or_else(Zero::zero);
+                Ret::NirKind(Num(Natural(n)))
             }
             _ => Ret::DoneAsIs,
         },
@@ -406,41 +453,28 @@ fn apply_builtin<'cx>(
             _ => Ret::DoneAsIs,
         },
         (Builtin::TextShow, [v]) => match &*v.kind() {
-            TextLit(tlit) => {
-                if let Some(s) = tlit.as_text() {
-                    // Printing InterpolatedText takes care of all the escaping
-                    let txt: InterpolatedText<Expr> =
-                        std::iter::once(InterpolatedTextContents::Text(s))
-                            .collect();
-                    Ret::Nir(Nir::from_text(txt))
-                } else {
-                    Ret::DoneAsIs
-                }
-            }
+            TextLit(tlit) => match tlit.as_text() {
+                Some(s) => Ret::Nir(Nir::from_text(show_text(&s))),
+                None => Ret::DoneAsIs,
+            },
             _ => Ret::DoneAsIs,
         },
         (Builtin::TextReplace, [needle, replacement, haystack]) => {
-            // Helper to match a Nir as a text literal
-            fn nir_to_string(n: &Nir) -> Option<String> {
-                match &*n.kind() {
-                    TextLit(n_lit) => n_lit.as_text(),
-                    _ => None,
-                }
-            }
-
-            // The needle needs to be fully evaluated as Text otherwise no
-            // progress can be made
-            match nir_to_string(needle) {
-                // When the needle is empty the haystack is returned untouched
-                Some(n) if n.is_empty() => Ret::Nir(haystack.clone()),
-                Some(n) => {
-                    // The haystack needs to be fully evaluated as Text otherwise no
-                    // progress can be made
-                    if let Some(h) = nir_to_string(haystack) {
-                        // Fast case when replacement is fully evaluated
-                        if let Some(r) = nir_to_string(replacement) {
-                            Ret::Nir(Nir::from_text(h.replace(&n, &r)))
-                        } else {
+            let text = |n: &Nir<'cx>| match &*n.kind() {
+                TextLit(lit) => lit.as_str().map(str::to_owned),
+                _ => None,
+            };
+            // The needle and the haystack need to be fully evaluated as Text otherwise no
+            // progress can be made. When the needle is empty, or doesn't occur in the haystack,
+            // the haystack is returned untouched, without copying it.
+            match (text(needle), &*haystack.kind()) {
+                (Some(n), _) if n.is_empty() => Ret::Nir(haystack.clone()),
+                (Some(n), TextLit(h)) => match h.as_str() {
+                    Some(h) if !h.contains(&n) => Ret::Nir(haystack.clone()),
+                    // Fast case when replacement is fully evaluated: the text is built once.
+                    Some(h) => match text(replacement) {
+                        Some(r) => Ret::Nir(Nir::from_text(h.replace(&n, &r))),
+                        None => {
                             use itertools::Itertools;
 
                             let parts = h.split(&n).map(|s| {
@@ -450,37 +484,39 @@ fn apply_builtin<'cx>(
                                 replacement.clone(),
                             );
 
-                            Ret::Nir(Nir::from_kind(NirKind::TextLit(
+                            Ret::NirKind(
                                 nze::nir::TextLit::new(Itertools::intersperse(
                                     parts,
                                     replacement,
-                                )),
-                            )))
+                                ))
+                                .into_nirkind(),
+                            )
                         }
-                    } else {
-                        Ret::DoneAsIs
-                    }
-                }
+                    },
+                    None => Ret::DoneAsIs,
+                },
                 _ => Ret::DoneAsIs,
             }
         }
         (Builtin::ListLength, [_, l]) => match &*l.kind() {
-            EmptyListLit(_) => Ret::NirKind(Num(Natural(0))),
-            NEListLit(xs) => Ret::NirKind(Num(Natural(xs.len() as u64))),
+            EmptyListLit(_) => Ret::NirKind(Num(Natural(Zero::zero()))),
+            NEListLit(xs) => {
+                Ret::NirKind(Num(Natural(natural_from_usize(xs.len()))))
+            }
             _ => Ret::DoneAsIs,
         },
         (Builtin::ListHead, [_, l]) => match &*l.kind() {
             EmptyListLit(n) => Ret::NirKind(EmptyOptionalLit(n.clone())),
             NEListLit(xs) => {
-                Ret::NirKind(NEOptionalLit(xs.iter().next().unwrap().clone()))
+                Ret::NirKind(NEOptionalLit(xs.front().unwrap().clone()))
             }
             _ => Ret::DoneAsIs,
         },
         (Builtin::ListLast, [_, l]) => match &*l.kind() {
             EmptyListLit(n) => Ret::NirKind(EmptyOptionalLit(n.clone())),
-            NEListLit(xs) => Ret::NirKind(NEOptionalLit(
-                xs.iter().rev().next().unwrap().clone(),
-            )),
+            NEListLit(xs) => {
+                Ret::NirKind(NEOptionalLit(xs.back().unwrap().clone()))
+            }
             _ => Ret::DoneAsIs,
         },
         (Builtin::ListReverse, [_, l]) => match &*l.kind() {
@@ -512,7 +548,9 @@ fn apply_builtin<'cx>(
                                     let mut kvs = HashMap::new();
                                     kvs.insert(
                                         "index".into(),
-                                        Nir::from_kind(Num(Natural(i as u64))),
+                                        Nir::from_kind(Num(Natural(
+                                            natural_from_usize(i),
+                                        ))),
                                     );
                                     kvs.insert("value".into(), e.clone());
                                     Nir::from_kind(RecordLit(kvs))
@@ -559,14 +597,15 @@ fn apply_builtin<'cx>(
                     λ(x : Natural) ->
                     1 + var(x)
                 )))
-                .app(Num(Natural(0)).into_nir()),
+                .app(Num(Natural(Zero::zero())).into_nir()),
         ),
 
         (Builtin::NaturalFold, [n, t, succ, zero]) => match &*n.kind() {
-            Num(Natural(0)) => Ret::Nir(zero.clone()),
+            Num(Natural(n)) if n.is_zero() => Ret::Nir(zero.clone()),
             Num(Natural(n)) => {
+                let n = n - crate::syntax::Natural::one();
                 let fold = Nir::from_builtin(cx, Builtin::NaturalFold)
-                    .app(Num(Natural(n - 1)).into_nir())
+                    .app(Num(Natural(n)).into_nir())
                     .app(t.clone())
                     .app(succ.clone())
                     .app(zero.clone());
@@ -1,10 +1,23 @@
 use elsa::vec::FrozenVec;
 use once_cell::sync::OnceCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::ops::{Deref, Index};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-use crate::semantics::{Import, ImportLocation, ImportNode};
-use crate::syntax::Span;
+use crate::builtins::Builtin;
+use crate::error::{Error, ErrorKind};
+use crate::profile::ImportCost;
+use crate::semantics::{
+    default_home_dir, Dependencies, Hir, Import, ImportLocation, ImportNode,
+    Nir, Type,
+};
+use crate::syntax::{ParseLimits, Span};
+use crate::trace::{StepKind, Trace, TraceOptions, Tracer};
 use crate::Typed;
 
 /////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -16,10 +29,42 @@ pub struct CtxtS<'cx> {
     imports: FrozenVec<Box<StoredImport<'cx>>>,
     import_alternatives: FrozenVec<Box<StoredImportAlternative<'cx>>>,
     import_results: FrozenVec<Box<StoredImportResult<'cx>>>,
+    cancellation: OnceCell<CancellationToken>,
+    /// Number of running operations that can be interrupted.
+    interruptible: Cell<usize>,
+    /// Number of running `uninterruptible` calls.
+    uninterruptible: Cell<usize>,
+    /// Why the outermost running interruptible operation was interrupted, if it was.
+    interrupted: RefCell<Option<Interrupted>>,
+    eval_limits: Cell<EvalLimits>,
+    /// The memory counted by `count_memory` since the outermost running interruptible operation
+    /// started.
+    eval_memory: Cell<usize>,
+    /// The result of each import fetched so far, so that it is only fetched once per context.
+    import_cache: RefCell<HashMap<ImportLocation, ImportResultId<'cx>>>,
+    /// The type of each builtin typechecked so far, since it doesn't depend on where it is used.
+    builtin_types: RefCell<HashMap<Builtin, Type<'cx>>>,
+    home_dir: OnceCell<PathBuf>,
+    parse_limits: Cell<ParseLimits>,
+    require_frozen_remote_imports: Cell<bool>,
+    skip_referential_sanity_check: Cell<bool>,
+    header_forwarding: Cell<HeaderForwarding>,
+    /// `None` unless `keep_import_sources` was called.
+    import_sources: RefCell<Option<HashMap<ImportLocation, Hir<'cx>>>>,
+    /// `None` unless `profile_imports` was called.
+    import_costs: RefCell<Option<Vec<ImportCost>>>,
+    /// What each Dhall file fetched so far read from its environment, transitively.
+    import_dependencies: RefCell<HashMap<ImportLocation, Rc<Dependencies>>>,
+    /// `None` unless a trace was started.
+    tracer: RefCell<Option<Tracer<'cx>>>,
 }
 
 /// Context for the dhall compiler. Stores various global maps.
 /// Access the relevant value using `cx[id]`.
+///
+/// A context can be reused to load many expressions, e.g. in a server: each import is then only
+/// fetched and typechecked once. Everything it stores is freed when the closure given to
+/// `with_new` returns.
 #[derive(Copy, Clone)]
 pub struct Ctxt<'cx>(&'cx CtxtS<'cx>);
 
@@ -30,6 +75,329 @@ impl Ctxt<'_> {
         f(cx)
     }
 }
+impl<'cx> Ctxt<'cx> {
+    /// Makes evaluation in this context stop once `token` is cancelled. This is checked between
+    /// imports and regularly during typechecking and normalization; the interrupted operation then
+    /// fails with `ErrorKind::Cancelled` (or `TypeMessage::Cancelled` when typechecking). Only the
+    /// first token set is used.
+    ///
+    /// The values that were being evaluated when the token was cancelled are left unevaluated, so
+    /// the values obtained before stay valid. Every typechecking or normalization started
+    /// afterwards fails the same way. Normalized values are only evaluated as far as needed, so
+    /// read them in `Ctxt::evaluate` for the rest of their evaluation to be interruptible too.
+    pub fn set_cancellation_token(self, token: CancellationToken) {
+        let _ = self.0.cancellation.set(token);
+    }
+    /// Whether the cancellation token of this context, if any, has been cancelled.
+    pub fn is_cancelled(self) -> bool {
+        matches!(self.0.cancellation.get(), Some(token) if token.is_cancelled())
+    }
+
+    /// Makes typechecking and normalization fail once they exceed `limits`, e.g. when they
+    /// evaluate untrusted input. This replaces any limits set before. The limits apply to each
+    /// expression separately, including each imported file. The values that were being evaluated
+    /// when a limit was exceeded are evaluated again if needed later, e.g. after raising the limits.
+    /// Like cancellation, the limits only apply to reading a normalized value in `Ctxt::evaluate`.
+    pub fn set_eval_limits(self, limits: EvalLimits) {
+        self.0.eval_limits.set(limits);
+    }
+    /// The limits that evaluation is subject to.
+    pub fn eval_limits(self) -> EvalLimits {
+        self.0.eval_limits.get()
+    }
+
+    /// Makes `~/` imports relative to `path` instead of the home directory of the current user,
+    /// e.g. for tests or sandboxes. Only the first directory set is used.
+    pub fn set_home_dir(self, path: PathBuf) {
+        let _ = self.0.home_dir.set(path);
+    }
+    /// The directory that `~/` imports are relative to, if any.
+    pub fn home_dir(self) -> Option<PathBuf> {
+        match self.0.home_dir.get() {
+            Some(path) => Some(path.clone()),
+            None => default_home_dir(),
+        }
+    }
+
+    /// Makes imported files fail to parse if they exceed `limits`, e.g. when they come from
+    /// untrusted sources. This replaces any limits set before, but doesn't apply to the imports
+    /// that were already fetched in this context.
+    pub fn set_parse_limits(self, limits: ParseLimits) {
+        self.0.parse_limits.set(limits);
+    }
+    /// The limits that imported files are parsed with.
+    pub fn parse_limits(self) -> ParseLimits {
+        self.0.parse_limits.get()
+    }
+
+    /// Makes remote imports fail unless they are protected by an integrity hash (`sha256:...`),
+    /// e.g. to enforce that deployments only load pinned code. The imports of a file that is
+    /// itself protected by a hash are covered by it, so they don't need one. Imports `as
+    /// Location` are always allowed since they fetch nothing. This replaces any setting made
+    /// before.
+    pub fn set_require_frozen_remote_imports(self, require: bool) {
+        self.0.require_frozen_remote_imports.set(require);
+    }
+    /// Whether remote imports must be protected by an integrity hash.
+    pub fn requires_frozen_remote_imports(self) -> bool {
+        self.0.require_frozen_remote_imports.get()
+    }
+
+    /// Enables or disables the referential sanity check of the standard, which is enabled by
+    /// default: remote files can't import local files or environment variables, and can only
+    /// import remote files from another origin if those allow it with the
+    /// `Access-Control-Allow-Origin` header, like for cross-origin requests in browsers. Only
+    /// disable it if the remote files are trusted.
+    pub fn set_referential_sanity_check(self, check: bool) {
+        self.0.skip_referential_sanity_check.set(!check);
+    }
+    /// Whether the referential sanity check is enabled.
+    pub fn referential_sanity_check(self) -> bool {
+        !self.0.skip_referential_sanity_check.get()
+    }
+
+    /// Sets which HTTP headers the relative imports of remote files are fetched with. This
+    /// replaces any policy set before.
+    pub fn set_header_forwarding(self, policy: HeaderForwarding) {
+        self.0.header_forwarding.set(policy);
+    }
+    /// Which HTTP headers the relative imports of remote files are fetched with.
+    pub fn header_forwarding(self) -> HeaderForwarding {
+        self.0.header_forwarding.get()
+    }
+
+    /// The result of the import from `location`, if it was already fetched in this context.
+    pub(crate) fn cached_import(
+        self,
+        location: &ImportLocation,
+    ) -> Option<ImportResultId<'cx>> {
+        self.0.import_cache.borrow().get(location).copied()
+    }
+    /// Remembers the result of the import from `location` for the rest of this context.
+    pub(crate) fn cache_import(
+        self,
+        location: ImportLocation,
+        result: ImportResultId<'cx>,
+    ) {
+        self.0.import_cache.borrow_mut().insert(location, result);
+    }
+
+    /// The type of the builtin `b`, if it was already typechecked in this context.
+    pub(crate) fn cached_builtin_type(self, b: Builtin) -> Option<Type<'cx>> {
+        self.0.builtin_types.borrow().get(&b).cloned()
+    }
+    /// Remembers the type of the builtin `b` for the rest of this context.
+    pub(crate) fn cache_builtin_type(self, b: Builtin, ty: Type<'cx>) {
+        self.0.builtin_types.borrow_mut().insert(b, ty);
+    }
+
+    /// Keeps the source of the Dhall files imported from now on, with its locations, so that
+    /// [`field_origins`](crate::analysis::field_origins) can look into them. By default only their
+    /// normalized value is kept.
+    pub fn keep_import_sources(self) {
+        self.0
+            .import_sources
+            .borrow_mut()
+            .get_or_insert_with(HashMap::new);
+    }
+    /// The source of the Dhall file imported from `location`, if it was kept.
+    pub fn import_source(self, location: &ImportLocation) -> Option<Hir<'cx>> {
+        self.0
+            .import_sources
+            .borrow()
+            .as_ref()?
+            .get(location)
+            .cloned()
+    }
+    /// Stores the source of an imported file if `keep_import_sources` was called.
+    pub(crate) fn store_import_source(
+        self,
+        location: &ImportLocation,
+        hir: &Hir<'cx>,
+    ) {
+        if let Some(sources) = self.0.import_sources.borrow_mut().as_mut() {
+            sources.insert(location.clone(), hir.clone());
+        }
+    }
+
+    /// What the Dhall file imported from `location` read from its environment, if it was fetched.
+    pub(crate) fn import_dependencies(
+        self,
+        location: &ImportLocation,
+    ) -> Option<Rc<Dependencies>> {
+        self.0.import_dependencies.borrow().get(location).cloned()
+    }
+    /// Remembers what the Dhall file imported from `location` read from its environment.
+    pub(crate) fn store_import_dependencies(
+        self,
+        location: &ImportLocation,
+        deps: Dependencies,
+    ) {
+        self.0
+            .import_dependencies
+            .borrow_mut()
+            .insert(location.clone(), Rc::new(deps));
+    }
+
+    /// Records the cost of each import fetched from now on, to be retrieved with
+    /// `take_import_costs`. Imports that were already fetched in this context are not fetched
+    /// again, so they are not recorded. See [`profile`](crate::profile).
+    pub fn profile_imports(self) {
+        self.0
+            .import_costs
+            .borrow_mut()
+            .get_or_insert_with(Vec::new);
+    }
+    /// Returns the import costs recorded so far, in the order the imports finished loading, and
+    /// forgets them.
+    pub fn take_import_costs(self) -> Vec<ImportCost> {
+        match self.0.import_costs.borrow_mut().as_mut() {
+            Some(costs) => std::mem::take(costs),
+            None => Vec::new(),
+        }
+    }
+    /// Records the cost of an import if `profile_imports` was called.
+    pub(crate) fn record_import_cost(self, cost: ImportCost) {
+        if let Some(costs) = self.0.import_costs.borrow_mut().as_mut() {
+            costs.push(cost);
+        }
+    }
+
+    /// Records the reduction steps done from now on, until `take_trace` is called. This discards
+    /// any trace started before. See [`trace`](crate::trace).
+    pub fn start_trace(self, options: TraceOptions) {
+        *self.0.tracer.borrow_mut() = Some(Tracer::new(options));
+    }
+    /// Stops recording reduction steps and returns those recorded since `start_trace`, or `None`
+    /// if no trace was started.
+    pub fn take_trace(self) -> Option<Trace> {
+        let tracer = self.0.tracer.borrow_mut().take()?;
+        Some(tracer.finish(self))
+    }
+    /// Whether a trace was started. Checked first to avoid building steps that are not recorded.
+    pub(crate) fn is_tracing(self) -> bool {
+        self.0.tracer.borrow().is_some()
+    }
+    /// Records a reduction step if a trace was started.
+    pub(crate) fn trace_step(
+        self,
+        kind: StepKind,
+        before: Nir<'cx>,
+        after: Nir<'cx>,
+    ) {
+        if self.is_interrupted() {
+            return;
+        }
+        if let Some(tracer) = self.0.tracer.borrow_mut().as_mut() {
+            tracer.record(kind, before, after);
+        }
+    }
+
+    /// Runs `f`, which forces the values it reads, with the checks of `Typed::normalize`: it fails
+    /// if `Natural` arithmetic overflows, if the context gets cancelled, or if the memory limit is
+    /// exceeded. Lazily evaluated parts of a normalized value, e.g. the elements of a list, are
+    /// only checked if they are forced in such a call; elsewhere, an overflowing operation is left
+    /// unevaluated.
+    pub fn evaluate<T>(self, f: impl FnOnce() -> T) -> Result<T, Error> {
+        self.interruptible(f).map_err(|e| ErrorKind::from(e).into())
+    }
+
+    /// Runs `f`, which is interrupted by `check_interrupted` if the context gets cancelled, by
+    /// `count_memory` if it exceeds the memory limit, or by `natural_overflow`. Once interrupted,
+    /// evaluation stops making
+    /// progress and typechecking fails, so that `f` returns quickly; its result is then replaced
+    /// with the reason of the interruption. The values that were being evaluated are left
+    /// unevaluated, so the context can still be used afterwards.
+    pub(crate) fn interruptible<T>(
+        self,
+        f: impl FnOnce() -> T,
+    ) -> Result<T, Interrupted> {
+        if self.0.uninterruptible.get() != 0 {
+            return Ok(f());
+        }
+        let depth = &self.0.interruptible;
+        if depth.get() == 0 {
+            self.0.eval_memory.set(0);
+            self.0.interrupted.replace(None);
+        }
+        depth.set(depth.get() + 1);
+        let ret = f();
+        depth.set(depth.get() - 1);
+        let interrupted = self.0.interrupted.borrow().clone();
+        match interrupted {
+            Some(interrupted) => Err(interrupted),
+            None => Ok(ret),
+        }
+    }
+    /// Runs `f` without interruptions, e.g. to typecheck the expansion of a builtin in the middle
+    /// of a normalization, or to compute a value that is cached in the context.
+    pub(crate) fn uninterruptible<T>(self, f: impl FnOnce() -> T) -> T {
+        let depth = &self.0.uninterruptible;
+        depth.set(depth.get() + 1);
+        let ret = f();
+        depth.set(depth.get() - 1);
+        ret
+    }
+    fn can_interrupt(self) -> bool {
+        self.0.interruptible.get() != 0 && self.0.uninterruptible.get() == 0
+    }
+    /// Why the running operation was interrupted, if it was. Once this is set, the values being
+    /// evaluated are incomplete: evaluation returns `NirKind::Interrupted` instead of making
+    /// progress.
+    pub(crate) fn interruption(self) -> Option<Interrupted> {
+        if self.can_interrupt() {
+            self.0.interrupted.borrow().clone()
+        } else {
+            None
+        }
+    }
+    pub(crate) fn is_interrupted(self) -> bool {
+        self.can_interrupt() && self.0.interrupted.borrow().is_some()
+    }
+    fn interrupt(self, interrupted: Interrupted) -> Result<(), Interrupted> {
+        self.0.interrupted.replace(Some(interrupted.clone()));
+        Err(interrupted)
+    }
+    /// Fails if the running operation should stop, because it was interrupted or the context was
+    /// cancelled. Outside of an interruptible operation, e.g. when a value is forced lazily after
+    /// the fact, this always succeeds.
+    pub(crate) fn check_interrupted(self) -> Result<(), Interrupted> {
+        if !self.can_interrupt() {
+            return Ok(());
+        }
+        let interrupted = self.0.interrupted.borrow().clone();
+        match interrupted {
+            Some(interrupted) => Err(interrupted),
+            None if self.is_cancelled() => {
+                self.interrupt(Interrupted::Cancelled)
+            }
+            None => Ok(()),
+        }
+    }
+    /// Records that evaluation built values of about `bytes` bytes, and fails like
+    /// `check_interrupted` if that exceeds the memory limit.
+    pub(crate) fn count_memory(self, bytes: usize) -> Result<(), Interrupted> {
+        if !self.can_interrupt() {
+            return Ok(());
+        }
+        let total = self.0.eval_memory.get().saturating_add(bytes);
+        self.0.eval_memory.set(total);
+        match self.0.eval_limits.get().max_memory {
+            Some(max) if total > max => {
+                self.interrupt(Interrupted::MemoryLimit(max))
+            }
+            _ => self.check_interrupted(),
+        }
+    }
+    /// Records that `Natural` arithmetic overflowed, which interrupts the running operation. `op`
+    /// prints the operation, for the error. Outside of an interruptible operation, the caller
+    /// leaves the operation unevaluated.
+    pub(crate) fn natural_overflow(self, op: impl FnOnce() -> String) {
+        if self.can_interrupt() && !self.is_interrupted() {
+            let _ = self.interrupt(Interrupted::NaturalOverflow(op()));
+        }
+    }
+}
 impl<'cx> Deref for Ctxt<'cx> {
     type Target = &'cx CtxtS<'cx>;
     fn deref(&self) -> &&'cx CtxtS<'cx> {
@@ -55,17 +423,91 @@ impl<'cx> std::fmt::Debug for Ctxt<'cx> {
     }
 }
 
+/////////////////////////////////////////////////////////////////////////////////////////////////////
+// Cancellation
+
+/// Lets another thread interrupt a long evaluation, e.g. when the file being evaluated has changed
+/// in an editor. Clones share the same state. See `Ctxt::set_cancellation_token`.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Requests that the evaluations using this token stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Limits on the resources used to evaluate an expression, to protect services that evaluate
+/// untrusted input, see `Ctxt::set_eval_limits`. There are no limits by default.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct EvalLimits {
+    /// The maximum amount of memory that typechecking or normalizing an expression may use, in
+    /// bytes. It is estimated from the size of the values built, including those that were freed
+    /// since, so it also bounds the work done. For example, building a list one element at a time,
+    /// as `List/build` does, counts every intermediate list.
+    pub max_memory: Option<usize>,
+}
+
+/// Which HTTP headers are sent to fetch the relative imports of a remote file, see
+/// `Ctxt::set_header_forwarding`. Imports always send the headers of their own `using` clause.
+/// The headers sent for each import are recorded by `Ctxt::profile_imports`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeaderForwarding {
+    /// The headers that the remote file was fetched with, as the standard specifies. Relative
+    /// imports come from the same origin as the file.
+    SameOrigin,
+    /// None, e.g. so that credentials meant for one file are never sent again.
+    Never,
+}
+
+// `#[default]` on enum variants needs a more recent compiler than we support.
+#[allow(clippy::derivable_impls)]
+impl Default for HeaderForwarding {
+    fn default() -> Self {
+        HeaderForwarding::SameOrigin
+    }
+}
+
+/// Why an interruptible operation stopped early.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Interrupted {
+    Cancelled,
+    /// The memory limit, which was exceeded.
+    MemoryLimit(usize),
+    /// `Natural` arithmetic overflowed. Holds the offending operation, printed.
+    NaturalOverflow(String),
+}
+
 /////////////////////////////////////////////////////////////////////////////////////////////////////
 // Imports
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct ImportId<'cx>(usize, PhantomData<&'cx ()>);
 
+impl<'cx> ImportId<'cx> {
+    /// The same id without the lifetime of its context. Only `Hir::new` may use this, to store the
+    /// id in a `Hir` node: those are stored without their lifetime so that they can be dropped
+    /// iteratively. The node is only ever read back as a `Hir<'cx>`, so the id can't leave its
+    /// context.
+    pub(super) fn relabel(self) -> ImportId<'static> {
+        ImportId(self.0, PhantomData)
+    }
+}
+
 /// What's stored for each `ImportId`. Allows getting and setting a result for this import.
 pub struct StoredImport<'cx> {
     cx: Ctxt<'cx>,
     pub base_location: ImportLocation,
     pub import: Import,
+    /// The `using` expression of a remote import.
+    pub headers: Option<Hir<'cx>>,
     pub span: Span,
     result: OnceCell<ImportResultId<'cx>>,
 }
@@ -108,12 +550,14 @@ impl<'cx> Ctxt<'cx> {
         self,
         base_location: ImportLocation,
         import: Import,
+        headers: Option<Hir<'cx>>,
         span: Span,
     ) -> ImportId<'cx> {
         let stored = StoredImport {
             cx: self,
             base_location,
             import,
+            headers,
             span,
             result: OnceCell::new(),
         };
@@ -135,6 +579,16 @@ impl<'cx> Index<ImportId<'cx>> for CtxtS<'cx> {
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct ImportAlternativeId<'cx>(usize, PhantomData<&'cx ()>);
 
+impl<'cx> ImportAlternativeId<'cx> {
+    /// The same id without the lifetime of its context. Only `Hir::new` may use this, to store the
+    /// id in a `Hir` node: those are stored without their lifetime so that they can be dropped
+    /// iteratively. The node is only ever read back as a `Hir<'cx>`, so the id can't leave its
+    /// context.
+    pub(super) fn relabel(self) -> ImportAlternativeId<'static> {
+        ImportAlternativeId(self.0, PhantomData)
+    }
+}
+
 /// What's stored for each `ImportAlternativeId`.
 pub struct StoredImportAlternative<'cx> {
     pub left_imports: Box<[ImportNode<'cx>]>,
@@ -1,10 +1,24 @@
+use std::ops::Range;
+
+#[cfg(feature = "annotate-snippets")]
 use annotate_snippets::{
     display_list::DisplayList,
-    snippet::{Annotation, AnnotationType, Slice, Snippet, SourceAnnotation},
+    snippet::{Annotation, Slice, Snippet, SourceAnnotation},
 };
 
+use crate::error::{English, Message, MessageCatalog};
 use crate::syntax::{ParsedSpan, Span};
 
+/// What an annotation of an error is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnotationType {
+    Error,
+    Warning,
+    Info,
+    Note,
+    Help,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct ErrorBuilder {
     title: FreeAnnotation,
@@ -18,43 +32,23 @@ pub struct ErrorBuilder {
 #[derive(Debug, Clone)]
 struct SpannedAnnotation {
     span: ParsedSpan,
-    message: String,
+    message: Message,
     annotation_type: AnnotationType,
 }
 
 #[derive(Debug, Clone)]
-struct FreeAnnotation {
-    message: String,
-    annotation_type: AnnotationType,
+pub(super) struct FreeAnnotation {
+    pub(super) message: Message,
+    pub(super) annotation_type: AnnotationType,
 }
 
-impl SpannedAnnotation {
-    fn to_annotation(&self) -> SourceAnnotation<'_> {
-        SourceAnnotation {
-            label: &self.message,
-            annotation_type: self.annotation_type,
-            range: self.span.as_char_range(),
-        }
-    }
-}
-
-impl FreeAnnotation {
-    fn to_annotation(&self) -> Annotation<'_> {
-        Annotation {
-            label: Some(&self.message),
-            id: None,
-            annotation_type: self.annotation_type,
-        }
-    }
-}
-
-/// A builder that uses the annotate_snippets library to display nice error messages about source
-/// code locations.
+/// A builder that uses the annotate_snippets library, if enabled, to display nice error messages
+/// about source code locations.
 impl ErrorBuilder {
-    pub fn new(message: impl ToString) -> Self {
+    pub fn new(message: impl Into<Message>) -> Self {
         ErrorBuilder {
             title: FreeAnnotation {
-                message: message.to_string(),
+                message: message.into(),
                 annotation_type: AnnotationType::Error,
             },
             annotations: Vec::new(),
@@ -66,7 +60,7 @@ impl ErrorBuilder {
     pub fn span_annot(
         &mut self,
         span: Span,
-        message: impl ToString,
+        message: impl Into<Message>,
         annotation_type: AnnotationType,
     ) -> &mut Self {
         // Ignore spans not coming from a source file
@@ -76,18 +70,18 @@ impl ErrorBuilder {
         };
         self.annotations.push(SpannedAnnotation {
             span,
-            message: message.to_string(),
+            message: message.into(),
             annotation_type,
         });
         self
     }
     pub fn footer_annot(
         &mut self,
-        message: impl ToString,
+        message: impl Into<Message>,
         annotation_type: AnnotationType,
     ) -> &mut Self {
         self.footer.push(FreeAnnotation {
-            message: message.to_string(),
+            message: message.into(),
             annotation_type,
         });
         self
@@ -96,27 +90,27 @@ impl ErrorBuilder {
     pub fn span_err(
         &mut self,
         span: Span,
-        message: impl ToString,
+        message: impl Into<Message>,
     ) -> &mut Self {
         self.span_annot(span, message, AnnotationType::Error)
     }
     pub fn span_help(
         &mut self,
         span: Span,
-        message: impl ToString,
+        message: impl Into<Message>,
     ) -> &mut Self {
         self.span_annot(span, message, AnnotationType::Help)
     }
-    pub fn help(&mut self, message: impl ToString) -> &mut Self {
+    pub fn help(&mut self, message: impl Into<Message>) -> &mut Self {
         self.footer_annot(message, AnnotationType::Help)
     }
-    pub fn note(&mut self, message: impl ToString) -> &mut Self {
+    pub fn note(&mut self, message: impl Into<Message>) -> &mut Self {
         self.footer_annot(message, AnnotationType::Note)
     }
 
-    // TODO: handle multiple files
+    /// Builds the error, to be displayed or inspected later.
     #[allow(clippy::drop_ref)]
-    pub fn format(&mut self) -> String {
+    pub fn build(&mut self) -> Diagnostic {
         if self.consumed {
             panic!("tried to format the same ErrorBuilder twice")
         }
@@ -124,44 +118,189 @@ impl ErrorBuilder {
         self.consumed = true;
         drop(self); // Get rid of the self reference so we don't use it by mistake.
 
-        let input;
-        let slices = if this.annotations.is_empty() {
-            Vec::new()
-        } else {
-            input = this.annotations[0].span.to_input();
-            let annotations = this
-                .annotations
-                .iter()
-                .map(|annot| annot.to_annotation())
-                .collect();
-            vec![Slice {
-                source: &input,
+        #[cfg(feature = "annotate-snippets")]
+        let input = this.annotations.first().map(|annot| annot.span.to_input());
+        let origin = this
+            .annotations
+            .first()
+            .and_then(|annot| annot.span.origin())
+            .map(str::to_owned);
+        let spans = this
+            .annotations
+            .into_iter()
+            .map(|annot| DiagnosticSpan {
+                byte_range: annot.span.byte_range(),
+                #[cfg(feature = "annotate-snippets")]
+                char_range: annot.span.as_char_range(),
+                start_line_col: annot.span.start_line_col(),
+                message: annot.message,
+                annotation_type: annot.annotation_type,
+            })
+            .collect();
+        Diagnostic {
+            title: this.title,
+            #[cfg(feature = "annotate-snippets")]
+            input,
+            origin,
+            spans,
+            footer: this.footer,
+        }
+    }
+
+    pub fn format(&mut self) -> String {
+        self.build().to_string()
+    }
+}
+
+/// An error message about source code locations, made with an `ErrorBuilder`. It displays as a
+/// snippet of the source with the locations annotated, or without the `annotate-snippets` feature
+/// as a list of the locations. Unlike spans, it can be sent between threads.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub(super) title: FreeAnnotation,
+    /// The source text the spans point to.
+    #[cfg(feature = "annotate-snippets")]
+    input: Option<String>,
+    /// The file path or URL the source text was read from, if any.
+    pub(super) origin: Option<String>,
+    pub(super) spans: Vec<DiagnosticSpan>,
+    pub(super) footer: Vec<FreeAnnotation>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct DiagnosticSpan {
+    pub(super) byte_range: Range<usize>,
+    #[cfg(feature = "annotate-snippets")]
+    char_range: (usize, usize),
+    pub(super) start_line_col: (usize, usize),
+    pub(super) message: Message,
+    pub(super) annotation_type: AnnotationType,
+}
+
+impl Diagnostic {
+    /// The error as text, with its messages taken from `catalog`.
+    // TODO: handle multiple files
+    #[cfg(feature = "annotate-snippets")]
+    pub fn render(&self, catalog: &dyn MessageCatalog) -> String {
+        let title = self.title.message.render(catalog);
+        let labels: Vec<String> = self
+            .spans
+            .iter()
+            .map(|annot| annot.message.render(catalog))
+            .collect();
+        let footer: Vec<String> = self
+            .footer
+            .iter()
+            .map(|annot| annot.message.render(catalog))
+            .collect();
+
+        let slices = match &self.input {
+            None => Vec::new(),
+            Some(input) => vec![Slice {
+                source: input,
                 line_start: 1, // TODO
                 origin: Some("<current file>"),
                 fold: true,
-                annotations,
-            }]
+                annotations: self
+                    .spans
+                    .iter()
+                    .zip(&labels)
+                    .map(|(annot, label)| SourceAnnotation {
+                        label,
+                        annotation_type: annot.annotation_type.into(),
+                        range: annot.char_range,
+                    })
+                    .collect(),
+            }],
         };
-        let footer = this
+        let footer = self
             .footer
             .iter()
-            .map(|annot| annot.to_annotation())
+            .zip(&footer)
+            .map(|(annot, label)| Annotation {
+                label: Some(label),
+                id: None,
+                annotation_type: annot.annotation_type.into(),
+            })
             .collect();
 
         let snippet = Snippet {
-            title: Some(this.title.to_annotation()),
+            title: Some(Annotation {
+                label: Some(&title),
+                id: None,
+                annotation_type: self.title.annotation_type.into(),
+            }),
             slices,
             footer,
             opt: Default::default(),
         };
         DisplayList::from(snippet).to_string()
     }
+
+    /// The error as text, with its messages taken from `catalog`.
+    #[cfg(not(feature = "annotate-snippets"))]
+    pub fn render(&self, catalog: &dyn MessageCatalog) -> String {
+        let mut out = format!(
+            "{}: {}",
+            self.title.annotation_type.name(),
+            self.title.message.render(catalog)
+        );
+        for annot in &self.spans {
+            let (line, column) = annot.start_line_col;
+            out += &format!(
+                "\n --> {}:{}: {}",
+                line,
+                column,
+                annot.message.render(catalog)
+            );
+        }
+        for annot in &self.footer {
+            out += &format!(
+                "\n  = {}: {}",
+                annot.annotation_type.name(),
+                annot.message.render(catalog)
+            );
+        }
+        out
+    }
+}
+
+impl AnnotationType {
+    pub(super) fn name(self) -> &'static str {
+        match self {
+            AnnotationType::Error => "error",
+            AnnotationType::Warning => "warning",
+            AnnotationType::Info => "info",
+            AnnotationType::Note => "note",
+            AnnotationType::Help => "help",
+        }
+    }
+}
+
+#[cfg(feature = "annotate-snippets")]
+impl From<AnnotationType> for annotate_snippets::snippet::AnnotationType {
+    fn from(x: AnnotationType) -> Self {
+        use annotate_snippets::snippet::AnnotationType as A;
+        match x {
+            AnnotationType::Error => A::Error,
+            AnnotationType::Warning => A::Warning,
+            AnnotationType::Info => A::Info,
+            AnnotationType::Note => A::Note,
+            AnnotationType::Help => A::Help,
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.render(&English))
+    }
 }
 
 impl Default for FreeAnnotation {
     fn default() -> Self {
         FreeAnnotation {
-            message: String::new(),
+            message: Message::new(""),
             annotation_type: AnnotationType::Error,
         }
     }
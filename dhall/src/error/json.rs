@@ -0,0 +1,206 @@
+use std::fmt::Write;
+use std::ops::Range;
+
+use pest::error::{ErrorVariant, InputLocation, LineColLocation};
+
+use crate::error::{
+    AnnotationType, Error, ErrorKind, ImportError, TypeMessage,
+};
+use crate::syntax::ParseError;
+
+/// A location in the source, as output in JSON.
+struct JsonSpan {
+    range: Range<usize>,
+    /// The line and column where it starts, if the source text is known.
+    line_col: Option<(usize, usize)>,
+    label: Option<String>,
+    severity: AnnotationType,
+}
+
+impl Error {
+    /// The error as a JSON object, for tools like CI pipelines and editor plugins. Its fields
+    /// are:
+    ///
+    /// - `code`: the kind of error, e.g. `"parse"`, `"import"` or `"type"`;
+    /// - `message`: a one-line description, without the source snippet;
+    /// - `file`: the file path or URL of the source the error points to, or `null` if unknown;
+    /// - `span`: the main location of the error in the source, or `null`;
+    /// - `related`: the other locations involved;
+    /// - `notes`: the helps and notes, each with a `severity` and a `message`.
+    ///
+    /// A location has the `start` and `end` byte offsets, the `line` and `column` where it
+    /// starts (from 1, `null` if unknown), a `label` (`null` if none) and a `severity`
+    /// (`"error"`, `"help"`, ...).
+    pub fn to_json(&self) -> String {
+        let mut message = self.to_string();
+        let mut file = self.file.as_deref();
+        let mut spans = Vec::new();
+        let mut notes = Vec::new();
+        match &self.kind {
+            ErrorKind::Parse(err) => {
+                message = parse_error_message(err);
+                spans.push(parse_error_span(err));
+            }
+            ErrorKind::Resolve(ImportError::MissingEnvVar { span, .. })
+            | ErrorKind::Resolve(ImportError::UnfrozenRemoteImport {
+                span,
+                ..
+            })
+            | ErrorKind::Resolve(ImportError::UncheckableHash { span }) => {
+                spans.extend(span.clone().map(|range| JsonSpan {
+                    range,
+                    line_col: None,
+                    label: None,
+                    severity: AnnotationType::Error,
+                }))
+            }
+            ErrorKind::Typecheck(err) => match &err.message {
+                TypeMessage::Custom(msg) => message = msg.to_string(),
+                TypeMessage::Spanned(diagnostic) => {
+                    message = diagnostic.title.message.to_string();
+                    file = file.or(diagnostic.origin.as_deref());
+                    spans.extend(diagnostic.spans.iter().map(|span| {
+                        JsonSpan {
+                            range: span.byte_range.clone(),
+                            line_col: Some(span.start_line_col),
+                            label: Some(span.message.to_string()),
+                            severity: span.annotation_type,
+                        }
+                    }));
+                    notes.extend(diagnostic.footer.iter().map(|annot| {
+                        (annot.message.to_string(), annot.annotation_type)
+                    }));
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        // The main span is the first error one; the others are related.
+        let main = spans
+            .iter()
+            .position(|span| matches!(span.severity, AnnotationType::Error))
+            .map(|i| spans.remove(i));
+
+        let mut out = String::new();
+        write!(out, "{{\"code\":{}", json_string(code(&self.kind))).unwrap();
+        write!(out, ",\"message\":{}", json_string(&message)).unwrap();
+        write!(out, ",\"file\":{}", json_option(file.map(json_string)))
+            .unwrap();
+        write!(out, ",\"span\":{}", json_option(main.map(span_json))).unwrap();
+        let related: Vec<_> = spans.into_iter().map(span_json).collect();
+        write!(out, ",\"related\":[{}]", related.join(",")).unwrap();
+        let notes: Vec<_> = notes
+            .into_iter()
+            .map(|(message, severity)| {
+                format!(
+                    "{{\"severity\":{},\"message\":{}}}",
+                    json_string(severity.name()),
+                    json_string(&message)
+                )
+            })
+            .collect();
+        write!(out, ",\"notes\":[{}]}}", notes.join(",")).unwrap();
+        out
+    }
+}
+
+fn code(kind: &ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::IO(_) => "io",
+        ErrorKind::Parse(_) => "parse",
+        ErrorKind::Decode(_) => "decode",
+        ErrorKind::Encode(_) => "encode",
+        ErrorKind::Resolve(_) => "import",
+        ErrorKind::Typecheck(_) => "type",
+        ErrorKind::Eval(_) => "eval",
+        ErrorKind::Cache(_) => "cache",
+        ErrorKind::Cancelled => "cancelled",
+        ErrorKind::MemoryLimitExceeded(_) => "memory-limit-exceeded",
+        ErrorKind::UnknownReplCommand(_) => "unknown-repl-command",
+        ErrorKind::CyclicDefinition(_) => "cyclic-definition",
+    }
+}
+
+/// The message of a parse error, worded like pest does below the snippet.
+fn parse_error_message(err: &ParseError) -> String {
+    fn enumerate<R: std::fmt::Debug>(rules: &[R]) -> String {
+        let rules: Vec<_> = rules.iter().map(|r| format!("{:?}", r)).collect();
+        match rules.as_slice() {
+            [r] => r.clone(),
+            [r1, r2] => format!("{} or {}", r1, r2),
+            [init @ .., last] => format!("{}, or {}", init.join(", "), last),
+            [] => String::new(),
+        }
+    }
+    match &err.variant {
+        ErrorVariant::CustomError { message } => message.clone(),
+        ErrorVariant::ParsingError {
+            positives,
+            negatives,
+        } => match (negatives.is_empty(), positives.is_empty()) {
+            (false, false) => format!(
+                "unexpected {}; expected {}",
+                enumerate(negatives),
+                enumerate(positives)
+            ),
+            (false, true) => format!("unexpected {}", enumerate(negatives)),
+            (true, false) => format!("expected {}", enumerate(positives)),
+            (true, true) => "unknown parsing error".to_owned(),
+        },
+    }
+}
+
+fn parse_error_span(err: &ParseError) -> JsonSpan {
+    let range = match err.location {
+        InputLocation::Pos(pos) => pos..pos,
+        InputLocation::Span((start, end)) => start..end,
+    };
+    let line_col = match err.line_col {
+        LineColLocation::Pos(line_col) => line_col,
+        LineColLocation::Span(line_col, _) => line_col,
+    };
+    JsonSpan {
+        range,
+        line_col: Some(line_col),
+        label: None,
+        severity: AnnotationType::Error,
+    }
+}
+
+fn span_json(span: JsonSpan) -> String {
+    let (line, column) = match span.line_col {
+        Some((line, column)) => (Some(line), Some(column)),
+        None => (None, None),
+    };
+    format!(
+        "{{\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"label\":{},\"severity\":{}}}",
+        span.range.start,
+        span.range.end,
+        json_option(line.map(|x| x.to_string())),
+        json_option(column.map(|x| x.to_string())),
+        json_option(span.label.as_deref().map(json_string)),
+        json_string(span.severity.name()),
+    )
+}
+
+fn json_option(x: Option<String>) -> String {
+    x.unwrap_or_else(|| "null".to_owned())
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < '\u{20}' => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,345 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use crate::error::Error;
+
+/// What an error says to the user: which message it is, and the values it refers to. It is only
+/// turned into text when displayed, by a [`MessageCatalog`], so that applications can translate
+/// or reword the errors they show.
+///
+/// # Example
+///
+/// ```rust
+/// use std::borrow::Cow;
+/// use dhall::error::{English, Message, MessageCatalog};
+///
+/// struct French;
+/// impl MessageCatalog for French {
+///     fn template(&self, id: &str) -> Option<Cow<'_, str>> {
+///         match id {
+///             "UnboundVariable" => Some("variable `{name}` inconnue".into()),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// let msg = Message::new("UnboundVariable").with("name", "x");
+/// assert_eq!(msg.render(&English), "unbound variable `x`");
+/// assert_eq!(msg.render(&French), "variable `x` inconnue");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Message {
+    id: &'static str,
+    args: Vec<(&'static str, Arg)>,
+}
+
+#[derive(Debug, Clone)]
+enum Arg {
+    Text(String),
+    Message(Message),
+    Error(Arc<Error>),
+}
+
+/// The text of messages. See [`Message`].
+pub trait MessageCatalog {
+    /// The text of the message `id`, with `{name}` where the value of its argument `name` goes,
+    /// or `None` to use the English text. The ids and their English text are listed in
+    /// [`English::MESSAGES`].
+    fn template(&self, id: &str) -> Option<Cow<'_, str>>;
+}
+
+/// The messages as displayed by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct English;
+
+impl Message {
+    pub fn new(id: &'static str) -> Self {
+        Message {
+            id,
+            args: Vec::new(),
+        }
+    }
+    /// Adds an argument, e.g. a name or a printed expression, that is inserted as is.
+    pub fn with(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.args.push((name, Arg::Text(value.to_string())));
+        self
+    }
+    /// Adds an argument that is itself a message, rendered with the same catalog.
+    pub fn with_message(mut self, name: &'static str, value: Message) -> Self {
+        self.args.push((name, Arg::Message(value)));
+        self
+    }
+
+    /// A message that shows another error, e.g. that of an import where it is imported.
+    pub fn from_error(err: Error) -> Self {
+        let mut msg = Message::new("Error");
+        msg.args.push(("error", Arg::Error(Arc::new(err))));
+        msg
+    }
+
+    pub fn id(&self) -> &'static str {
+        self.id
+    }
+
+    /// The text of the message, taken from `catalog`, falling back to English, and then to the
+    /// id for messages that are not in the English catalog.
+    pub fn render(&self, catalog: &dyn MessageCatalog) -> String {
+        let template = catalog
+            .template(self.id)
+            .or_else(|| English.template(self.id))
+            .unwrap_or(Cow::Borrowed(self.id));
+        let mut out = String::with_capacity(template.len());
+        let mut rest = &*template;
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let arg = rest.find('}').and_then(|end| {
+                let name = &rest[1..end];
+                let (_, value) = self.args.iter().find(|(n, _)| *n == name)?;
+                Some((end, value))
+            });
+            match arg {
+                Some((end, value)) => {
+                    match value {
+                        Arg::Text(text) => out.push_str(text),
+                        Arg::Message(msg) => out.push_str(&msg.render(catalog)),
+                        Arg::Error(err) => out.push_str(&err.render(catalog)),
+                    }
+                    rest = &rest[end + 1..];
+                }
+                // Not a placeholder, e.g. a record type.
+                None => {
+                    out.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+impl From<&'static str> for Message {
+    fn from(id: &'static str) -> Self {
+        Message::new(id)
+    }
+}
+
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.render(&English))
+    }
+}
+
+impl MessageCatalog for English {
+    fn template(&self, id: &str) -> Option<Cow<'_, str>> {
+        English::MESSAGES
+            .iter()
+            .find(|(i, _)| *i == id)
+            .map(|(_, text)| Cow::Borrowed(*text))
+    }
+}
+
+impl English {
+    /// The ids of the messages, with their English text.
+    pub const MESSAGES: &'static [(&'static str, &'static str)] = &[
+        // Errors other than type errors
+        ("Error", "{error}"),
+        ("TypeError", "Type error: {error}"),
+        ("MissingEnvVar", "Missing environment variable `{name}`"),
+        (
+            "UnfrozenRemoteImport",
+            "Remote import `{url}` is not protected by an integrity hash",
+        ),
+        (
+            "NaturalOverflow",
+            "Evaluation error: Natural overflow in `{op}`",
+        ),
+        ("EncodeError", "Encode error: {error}"),
+        ("Cancelled", "Evaluation was cancelled"),
+        (
+            "MemoryLimitExceeded",
+            "Evaluation exceeded the memory limit of {max} bytes",
+        ),
+        ("UnknownReplCommand", "Unknown command: `{line}`"),
+        ("CyclicDefinition", "Definition `{name}` depends on itself"),
+        // Imports
+        ("HashMismatch", "hash mismatch"),
+        ("ExpectedHash", "Expected sha256:{hash}"),
+        ("FoundHash", "Found    sha256:{hash}"),
+        ("ImportFailed", "error"),
+        (
+            "UncheckableHash",
+            "This import is protected by an integrity hash, which this build of dhall-rust \
+             can't check because it lacks the `binary` feature",
+        ),
+        ("DownloadFailed", "Failed to download `{url}`: {reason}"),
+        (
+            "InvalidHeaders",
+            "Invalid headers: expected `List { mapKey : Text, mapValue : \
+             Text }`, found `{found}`",
+        ),
+        // Type errors shown by the name the standard gives them
+        ("AssertMismatch", "AssertMismatch"),
+        ("AssertMustTakeEquivalence", "AssertMustTakeEquivalence"),
+        ("BinOpTypeMismatch", "BinOpTypeMismatch"),
+        ("DuplicateFieldName", "DuplicateFieldName"),
+        (
+            "EquivalenceArgumentsMustBeTerms",
+            "EquivalenceArgumentsMustBeTerms",
+        ),
+        ("EquivalenceTypeMismatch", "EquivalenceTypeMismatch"),
+        ("IfBranchMismatch", "IfBranchMismatch"),
+        (
+            "IfBranchMustBeTermTypeOrKind",
+            "IfBranchMustBeTermTypeOrKind",
+        ),
+        ("InvalidFieldType", "InvalidFieldType"),
+        ("InvalidListElement", "InvalidListElement"),
+        ("InvalidListType", "InvalidListType"),
+        ("InvalidOptionalType", "InvalidOptionalType"),
+        ("InvalidPredicate", "InvalidPredicate"),
+        ("InvalidTextInterpolation", "InvalidTextInterpolation"),
+        ("InvalidVariantType", "InvalidVariantType"),
+        ("Merge1ArgMustBeRecord", "Merge1ArgMustBeRecord"),
+        (
+            "Merge2ArgMustBeUnionOrOptional",
+            "Merge2ArgMustBeUnionOrOptional",
+        ),
+        ("MergeAnnotMismatch", "MergeAnnotMismatch"),
+        ("MergeEmptyNeedsAnnotation", "MergeEmptyNeedsAnnotation"),
+        ("MergeHandlerMissingVariant", "MergeHandlerMissingVariant"),
+        ("MergeHandlerTypeMismatch", "MergeHandlerTypeMismatch"),
+        ("MergeReturnTypeIsDependent", "MergeReturnTypeIsDependent"),
+        ("MergeVariantMissingHandler", "MergeVariantMissingHandler"),
+        ("MissingRecordField", "MissingRecordField"),
+        ("MissingUnionField", "MissingUnionField"),
+        ("MustCombineRecord", "MustCombineRecord"),
+        ("NotARecord", "NotARecord"),
+        (
+            "ProjectionByExprTakesRecordType",
+            "ProjectionByExprTakesRecordType",
+        ),
+        ("ProjectionMissingEntry", "ProjectionMissingEntry"),
+        ("ProjectionMustBeRecord", "ProjectionMustBeRecord"),
+        ("ProjectionWrongType", "ProjectionWrongType"),
+        (
+            "RecordTypeMergeRequiresRecordType",
+            "RecordTypeMergeRequiresRecordType",
+        ),
+        ("WithMustBeRecord", "WithMustBeRecord"),
+        // Variables and annotations
+        ("UnboundVariable", "unbound variable `{name}`"),
+        ("NotInScope", "not found in this scope"),
+        ("DidYouMean", "did you mean `{name}`?"),
+        ("AnnotMismatch", "annot mismatch"),
+        (
+            "AnnotMismatchTypes",
+            "annot mismatch: {found} != {expected}",
+        ),
+        (
+            "AnnotMismatchLabel",
+            "the type of this doesn't match its annotation",
+        ),
+        ("SortHasNoType", "Sort does not have a type"),
+        ("InvalidOutputType", "Invalid output type"),
+        ("ExpectedType", "Expected a type, found: `{found}`"),
+        ("ThisHasType", "this has type: `{ty}`"),
+        (
+            "TypePositionHelp",
+            "An expression in type position must have type `Type`, `Kind` \
+             or `Sort`",
+        ),
+        // Functions
+        ("WrongArgumentType", "wrong type of function argument"),
+        (
+            "ExpectsOtherArgumentType",
+            "this expects an argument of another type",
+        ),
+        ("ArgumentHasOtherType", "but this has a different type"),
+        (
+            "ExpectsArgumentType",
+            "this expects an argument of type: {ty}",
+        ),
+        ("ArgumentHasType", "but this has type: {ty}"),
+        (
+            "ExpectedFoundType",
+            "expected type `{expected}`\n   found type `{found}`",
+        ),
+        ("NotAFunction", "expected function, found `{ty}`"),
+        (
+            "ApplicationRequiresFunction",
+            "function application requires a function",
+        ),
+        // Records and unions
+        ("DuplicateField", "duplicate field `{name}`"),
+        ("FirstDefinedHere", "first defined here"),
+        ("MissingField", "missing field `{entry}`"),
+        ("MissingAlternative", "missing alternative `{entry}`"),
+        ("UnexpectedField", "unexpected field `{entry}`"),
+        ("UnexpectedAlternative", "unexpected alternative `{entry}`"),
+        (
+            "FieldTypeMismatch",
+            "field `{path}`: expected {expected}, found {found}",
+        ),
+        (
+            "AlternativeTypeMismatch",
+            "alternative `{path}`: expected {expected}, found {found}",
+        ),
+        (
+            "ExpectedRecordType",
+            "{prefix}expected a record type, found a union type",
+        ),
+        (
+            "ExpectedUnionType",
+            "{prefix}expected a union type, found a record type",
+        ),
+        ("Code", "`{code}`"),
+        ("NoPayload", "no payload"),
+        // `merge`
+        ("WrongHandlerInputType", "Wrong handler input type"),
+        ("InThisMerge", "in this merge expression"),
+        (
+            "HandlerExpects",
+            "the handler for `{name}` expects a value of type: `{ty}`",
+        ),
+        (
+            "VariantHasType",
+            "but the corresponding variant has type: `{ty}`",
+        ),
+        ("HandlerNotAFunction", "merge handler is not a function"),
+        (
+            "HandlerHasType",
+            "the handler for `{name}` has type: `{ty}`",
+        ),
+        (
+            "CorrespondingVariantHasType",
+            "the corresponding variant has type: `{ty}`",
+        ),
+        (
+            "HandlerMustBeFunction",
+            "a handler for this variant must be a function that takes an \
+             input of type: `{ty}`",
+        ),
+        // `toMap`
+        (
+            "ToMapRecordKind",
+            "`toMap` only accepts records of type `Type`",
+        ),
+        ("ToMapNotRecord", "The argument to `toMap` must be a record"),
+        (
+            "ToMapEmptyNeedsAnnotation",
+            "`toMap` applied to an empty record requires a type annotation",
+        ),
+        (
+            "ToMapInvalidAnnotation",
+            "The type of `toMap x` must be of the form `List { mapKey : \
+             Text, mapValue : T }`",
+        ),
+        (
+            "ToMapHeterogenousRecord",
+            "Every field of the record must have the same type",
+        ),
+        ("ToMapAnnotMismatch", "Annotation mismatch"),
+    ];
+}
@@ -1,16 +1,24 @@
 use std::io::Error as IOError;
+use std::ops::Range;
 
+use crate::ctxt::Interrupted;
 use crate::semantics::resolve::{CyclesStack, ImportLocation};
 use crate::syntax::{Import, ParseError};
 
 mod builder;
+mod json;
+mod message;
 pub use builder::*;
+pub use message::*;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
+    /// The file path or URL of the text that failed to parse, if any. Other errors that point to
+    /// the source know it from their spans.
+    file: Option<String>,
 }
 
 #[derive(Debug)]
@@ -22,15 +30,53 @@ pub enum ErrorKind {
     Encode(EncodeError),
     Resolve(ImportError),
     Typecheck(TypeError),
+    Eval(EvalError),
     Cache(CacheError),
+    /// The `CancellationToken` of the context was cancelled.
+    Cancelled,
+    /// Evaluation used more memory than allowed by `EvalLimits::max_memory`, which this holds.
+    MemoryLimitExceeded(usize),
+    /// A line given to `repl::Session::eval` that starts with `:` but is not a known command, or
+    /// lacks its argument. Holds the line.
+    UnknownReplCommand(String),
+    /// A definition of a `notebook::Notebook` that refers to itself, directly or through other
+    /// definitions. Holds its name.
+    CyclicDefinition(String),
 }
 
 #[derive(Debug)]
 pub enum ImportError {
     Missing,
-    MissingEnvVar,
+    /// An `env:` import of a variable that isn't set.
+    MissingEnvVar {
+        name: String,
+        /// The byte range of the import in the file that contains it, if it comes from source
+        /// text.
+        span: Option<Range<usize>>,
+    },
     MissingHome,
+    /// A remote import without an integrity hash, while the context requires one. See
+    /// `Ctxt::set_require_frozen_remote_imports`.
+    UnfrozenRemoteImport {
+        url: url::Url,
+        /// The byte range of the import in the file that contains it, if it comes from source
+        /// text.
+        span: Option<Range<usize>>,
+    },
     SanityCheck,
+    /// A remote import that couldn't be downloaded: the request failed, the server answered with
+    /// an error status, or the body isn't text.
+    DownloadFailed {
+        url: url::Url,
+        reason: String,
+    },
+    /// An import protected by an integrity hash, while this build can't compute hashes because it
+    /// lacks the `binary` feature.
+    UncheckableHash {
+        /// The byte range of the import in the file that contains it, if it comes from source
+        /// text.
+        span: Option<Range<usize>>,
+    },
     UnexpectedImport(Import<()>),
     ImportCycle(CyclesStack, ImportLocation),
     Url(url::ParseError),
@@ -38,12 +84,17 @@ pub enum ImportError {
 
 #[derive(Debug)]
 pub enum DecodeError {
+    #[cfg(feature = "binary")]
     CBORError(serde_cbor::error::Error),
     WrongFormatError(String),
+    /// The data was encoded by a version of the standard that can't be decoded anymore, e.g. with
+    /// an unknown version tag or a construct that was removed. Holds a description of it.
+    UnsupportedEncodingVersion(String),
 }
 
 #[derive(Debug)]
 pub enum EncodeError {
+    #[cfg(feature = "binary")]
     CBORError(serde_cbor::error::Error),
 }
 
@@ -56,7 +107,23 @@ pub struct TypeError {
 /// The specific type error
 #[derive(Debug)]
 pub enum TypeMessage {
-    Custom(String),
+    Custom(Message),
+    /// An error about locations in the source.
+    Spanned(Diagnostic),
+    /// Typechecking was interrupted by the `CancellationToken` of the context.
+    Cancelled,
+    /// Typechecking used more memory than allowed by `EvalLimits::max_memory`, which this holds.
+    MemoryLimitExceeded(usize),
+    /// `Natural` arithmetic overflowed while normalizing a type. Contains the offending operation,
+    /// printed.
+    NaturalOverflow(String),
+}
+
+/// An error that happened during normalization
+#[derive(Debug)]
+pub enum EvalError {
+    /// `Natural` arithmetic overflowed. Contains the offending operation, printed.
+    NaturalOverflow(String),
 }
 
 #[derive(Debug)]
@@ -68,11 +135,59 @@ pub enum CacheError {
 
 impl Error {
     pub fn new(kind: ErrorKind) -> Self {
-        Error { kind }
+        Error { kind, file: None }
     }
     pub fn kind(&self) -> &ErrorKind {
         &self.kind
     }
+    /// The error as text, with its messages taken from `catalog`. Displaying the error gives it
+    /// in English.
+    ///
+    /// Only the messages of dhall itself can be translated: the errors of the system, of the
+    /// parser and of the binary decoder are shown as they are.
+    pub fn render(&self, catalog: &dyn MessageCatalog) -> String {
+        let message = match &self.kind {
+            ErrorKind::IO(err) => return err.to_string(),
+            ErrorKind::Parse(err) => return err.to_string(),
+            ErrorKind::Decode(err) => return format!("{:?}", err),
+            ErrorKind::Encode(err) => err.message(),
+            ErrorKind::Resolve(ImportError::MissingEnvVar { name, .. }) => {
+                Message::new("MissingEnvVar").with("name", name)
+            }
+            ErrorKind::Resolve(ImportError::UnfrozenRemoteImport {
+                url,
+                ..
+            }) => Message::new("UnfrozenRemoteImport").with("url", url),
+            ErrorKind::Resolve(ImportError::UncheckableHash { .. }) => {
+                Message::new("UncheckableHash")
+            }
+            ErrorKind::Resolve(ImportError::DownloadFailed { url, reason }) => {
+                Message::new("DownloadFailed")
+                    .with("url", url)
+                    .with("reason", reason)
+            }
+            ErrorKind::Resolve(err) => return format!("{:?}", err),
+            ErrorKind::Typecheck(err) => return err.render(catalog),
+            ErrorKind::Eval(err) => err.message(),
+            ErrorKind::Cache(err) => return format!("{:?}", err),
+            ErrorKind::Cancelled => Message::new("Cancelled"),
+            ErrorKind::MemoryLimitExceeded(max) => {
+                Message::new("MemoryLimitExceeded").with("max", max)
+            }
+            ErrorKind::UnknownReplCommand(line) => {
+                Message::new("UnknownReplCommand").with("line", line)
+            }
+            ErrorKind::CyclicDefinition(name) => {
+                Message::new("CyclicDefinition").with("name", name)
+            }
+        };
+        message.render(catalog)
+    }
+    /// Records the file path or URL of the text that failed to parse.
+    pub(crate) fn in_file(mut self, file: &str) -> Self {
+        self.file = Some(file.to_owned());
+        self
+    }
 }
 
 impl TypeError {
@@ -81,24 +196,117 @@ impl TypeError {
     }
 }
 
-impl std::fmt::Display for TypeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl TypeError {
+    /// The error as text, with its messages taken from `catalog`.
+    pub fn render(&self, catalog: &dyn MessageCatalog) -> String {
         use TypeMessage::*;
-        let msg = match &self.message {
-            Custom(s) => format!("Type error: {}", s),
+        let error = match &self.message {
+            Custom(msg) => msg.render(catalog),
+            Spanned(d) => d.render(catalog),
+            Cancelled => return Message::new("Cancelled").render(catalog),
+            MemoryLimitExceeded(max) => {
+                return Message::new("MemoryLimitExceeded")
+                    .with("max", max)
+                    .render(catalog)
+            }
+            NaturalOverflow(op) => {
+                return Message::new("NaturalOverflow")
+                    .with("op", op)
+                    .render(catalog)
+            }
         };
-        write!(f, "{}", msg)
+        Message::new("TypeError")
+            .with("error", error)
+            .render(catalog)
+    }
+}
+
+impl std::fmt::Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.render(&English))
     }
 }
 
 impl std::error::Error for TypeError {}
 
+impl From<&'static str> for TypeMessage {
+    fn from(x: &'static str) -> TypeMessage {
+        TypeMessage::Custom(x.into())
+    }
+}
+
+impl From<Message> for TypeMessage {
+    fn from(x: Message) -> TypeMessage {
+        TypeMessage::Custom(x)
+    }
+}
+
+impl From<Diagnostic> for TypeMessage {
+    fn from(x: Diagnostic) -> TypeMessage {
+        TypeMessage::Spanned(x)
+    }
+}
+
+impl From<Interrupted> for TypeMessage {
+    fn from(x: Interrupted) -> TypeMessage {
+        match x {
+            Interrupted::Cancelled => TypeMessage::Cancelled,
+            Interrupted::MemoryLimit(max) => {
+                TypeMessage::MemoryLimitExceeded(max)
+            }
+            Interrupted::NaturalOverflow(op) => {
+                TypeMessage::NaturalOverflow(op)
+            }
+        }
+    }
+}
+
+impl From<Interrupted> for ErrorKind {
+    fn from(x: Interrupted) -> ErrorKind {
+        match x {
+            Interrupted::Cancelled => ErrorKind::Cancelled,
+            Interrupted::MemoryLimit(max) => {
+                ErrorKind::MemoryLimitExceeded(max)
+            }
+            Interrupted::NaturalOverflow(op) => {
+                ErrorKind::Eval(EvalError::NaturalOverflow(op))
+            }
+        }
+    }
+}
+
+impl EvalError {
+    fn message(&self) -> Message {
+        match self {
+            EvalError::NaturalOverflow(op) => {
+                Message::new("NaturalOverflow").with("op", op)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.message().fmt(f)
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl EncodeError {
+    fn message(&self) -> Message {
+        match *self {
+            #[cfg(feature = "binary")]
+            EncodeError::CBORError(ref e) => {
+                Message::new("EncodeError").with("error", e)
+            }
+        }
+    }
+}
+
 impl std::fmt::Display for EncodeError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let msg = match self {
-            EncodeError::CBORError(e) => format!("Encode error: {}", e),
-        };
-        write!(f, "{}", msg)
+        self.message().fmt(f)
     }
 }
 
@@ -106,15 +314,7 @@ impl std::error::Error for EncodeError {}
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match &self.kind {
-            ErrorKind::IO(err) => write!(f, "{}", err),
-            ErrorKind::Parse(err) => write!(f, "{}", err),
-            ErrorKind::Decode(err) => write!(f, "{:?}", err),
-            ErrorKind::Encode(err) => write!(f, "{:?}", err),
-            ErrorKind::Resolve(err) => write!(f, "{:?}", err),
-            ErrorKind::Typecheck(err) => write!(f, "{}", err),
-            ErrorKind::Cache(err) => write!(f, "{:?}", err),
-        }
+        f.write_str(&self.render(&English))
     }
 }
 
@@ -156,7 +356,21 @@ impl From<ImportError> for Error {
 }
 impl From<TypeError> for Error {
     fn from(err: TypeError) -> Error {
-        ErrorKind::Typecheck(err).into()
+        match err.message {
+            TypeMessage::Cancelled => ErrorKind::Cancelled.into(),
+            TypeMessage::MemoryLimitExceeded(max) => {
+                ErrorKind::MemoryLimitExceeded(max).into()
+            }
+            TypeMessage::NaturalOverflow(op) => {
+                ErrorKind::Eval(EvalError::NaturalOverflow(op)).into()
+            }
+            _ => ErrorKind::Typecheck(err).into(),
+        }
+    }
+}
+impl From<EvalError> for Error {
+    fn from(err: EvalError) -> Error {
+        ErrorKind::Eval(err).into()
     }
 }
 impl From<CacheError> for Error {
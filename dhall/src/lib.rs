@@ -13,25 +13,34 @@
     unknown_lints
 )]
 
+pub mod analysis;
 pub mod builtins;
 pub mod ctxt;
 pub mod error;
+pub mod notebook;
 pub mod operations;
+pub mod profile;
+pub mod repl;
 pub mod semantics;
+#[cfg(feature = "binary")]
+pub mod store;
 pub mod syntax;
+pub mod trace;
 pub mod utils;
 
 use std::path::Path;
+use std::rc::Rc;
 use url::Url;
 
 use crate::error::{Error, TypeError};
 use crate::semantics::parse;
 use crate::semantics::resolve;
 use crate::semantics::resolve::ImportLocation;
-use crate::semantics::{typecheck, typecheck_with, Hir, Nir, Tir, Type};
+use crate::semantics::{typecheck, typecheck_with, Cache, Hir, Nir, Tir, Type};
 use crate::syntax::Expr;
 
 pub use ctxt::*;
+pub use semantics::{bundle, bundle_with, BundleOptions, Dependencies};
 
 #[derive(Debug, Clone)]
 pub struct Parsed(Expr, ImportLocation);
@@ -71,15 +80,33 @@ impl Parsed {
     pub fn parse_file(f: &Path) -> Result<Parsed, Error> {
         parse::parse_file(f)
     }
+    /// Like `parse_file`, but fails if the expression exceeds `limits`. Use
+    /// `Ctxt::set_parse_limits` to limit its imports too.
+    pub fn parse_file_with_limits(
+        f: &Path,
+        limits: syntax::ParseLimits,
+    ) -> Result<Parsed, Error> {
+        parse::parse_file_with_limits(f, limits)
+    }
     pub fn parse_remote(url: Url) -> Result<Parsed, Error> {
         parse::parse_remote(url)
     }
     pub fn parse_str(s: &str) -> Result<Parsed, Error> {
         parse::parse_str(s)
     }
+    /// Like `parse_str`, but fails if the expression exceeds `limits`. Use
+    /// `Ctxt::set_parse_limits` to limit its imports too.
+    pub fn parse_str_with_limits(
+        s: &str,
+        limits: syntax::ParseLimits,
+    ) -> Result<Parsed, Error> {
+        parse::parse_str_with_limits(s, limits)
+    }
+    #[cfg(feature = "binary")]
     pub fn parse_binary_file(f: &Path) -> Result<Parsed, Error> {
         parse::parse_binary_file(f)
     }
+    #[cfg(feature = "binary")]
     #[allow(dead_code)]
     pub fn parse_binary(data: &[u8]) -> Result<Parsed, Error> {
         parse::parse_binary(data)
@@ -88,6 +115,14 @@ impl Parsed {
     pub fn resolve<'cx>(self, cx: Ctxt<'cx>) -> Result<Resolved<'cx>, Error> {
         resolve::resolve(cx, self)
     }
+    /// Like `resolve`, with a custom cache for hashed imports. `None` disables caching.
+    pub fn resolve_with_cache<'cx>(
+        self,
+        cx: Ctxt<'cx>,
+        cache: Option<Rc<dyn Cache>>,
+    ) -> Result<Resolved<'cx>, Error> {
+        resolve::resolve_with_cache(cx, self, cache)
+    }
     pub fn skip_resolve<'cx>(
         self,
         cx: Ctxt<'cx>,
@@ -104,6 +139,18 @@ impl Parsed {
         let Parsed(expr, import_location) = self;
         Parsed(expr.add_let_binding(label, value), import_location)
     }
+
+    /// Apply the parsed expression, assumed to be a function, to `arg`.
+    pub fn apply_to(self, arg: Expr) -> Parsed {
+        let Parsed(expr, import_location) = self;
+        Parsed(expr.apply_to(arg), import_location)
+    }
+
+    /// Select the field `label` of the parsed expression, assumed to be a record.
+    pub fn select_field(self, label: syntax::Label) -> Parsed {
+        let Parsed(expr, import_location) = self;
+        Parsed(expr.select_field(label), import_location)
+    }
 }
 
 impl<'cx> Resolved<'cx> {
@@ -121,6 +168,19 @@ impl<'cx> Resolved<'cx> {
     pub fn to_expr(&self, cx: Ctxt<'cx>) -> Expr {
         self.0.to_expr_noopts(cx)
     }
+    /// Typechecks the expression and returns it with its inferred types written out, like `dhall
+    /// type --annotate`: the bindings of the outermost `let` block are annotated with their type,
+    /// and so is the body of the block.
+    pub fn annotate(&self, cx: Ctxt<'cx>) -> Result<Expr, TypeError> {
+        Ok(semantics::annotate(cx, &self.0)?.to_expr_noopts(cx))
+    }
+    /// The environment variables and local files that resolving the imports of this expression
+    /// read, transitively, e.g. for a deployment tool to declare them. This includes the imports
+    /// tried on the left of a `?` that failed, but not the imports that were found in the cache
+    /// from their integrity hash, since those were not read. Imports `as Location` read nothing.
+    pub fn dependencies(&self, cx: Ctxt<'cx>) -> Dependencies {
+        semantics::dependencies(cx, &self.0)
+    }
 }
 
 impl<'cx> Typed<'cx> {
@@ -130,12 +190,22 @@ impl<'cx> Typed<'cx> {
             ty: tir.ty().clone(),
         }
     }
-    /// Reduce an expression to its normal form, performing beta reduction
-    pub fn normalize(&self, cx: Ctxt<'cx>) -> Normalized<'cx> {
-        Normalized(self.hir.eval_closed_expr(cx))
+    /// Reduce an expression to its normal form, performing beta reduction.
+    ///
+    /// Fails if `Natural` arithmetic overflowed during normalization. Parts of the value, e.g. the
+    /// elements of a list, are evaluated lazily when they are read; use `Ctxt::evaluate` to check
+    /// them too.
+    pub fn normalize(&self, cx: Ctxt<'cx>) -> Result<Normalized<'cx>, Error> {
+        let nir = self.hir.eval_closed_expr(cx);
+        // Evaluate the outermost layer now, so that it fails here.
+        cx.evaluate(|| {
+            nir.kind();
+        })?;
+        Ok(Normalized(nir))
     }
 
     /// Converts a value back to the corresponding AST expression.
+    #[cfg_attr(not(feature = "binary"), allow(dead_code))]
     fn to_expr(&self, cx: Ctxt<'cx>) -> Expr {
         self.hir.to_expr(cx, ToExprOptions { alpha: false })
     }
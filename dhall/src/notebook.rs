@@ -0,0 +1,220 @@
+//! Named definitions that can be added, replaced and removed in any order, e.g. for the cells of a
+//! notebook.
+//!
+//! Unlike the `let` bindings of a [`repl::Session`](crate::repl::Session), a definition can be
+//! replaced after others that use it were made. Those dependents, and theirs in turn, are then
+//! stale: they are evaluated again, with the new definition, the next time they are needed. A
+//! definition that refers to itself, directly or through others, is rejected.
+//!
+//! Imports are resolved from the current directory.
+//!
+//! # Example
+//!
+//! ```rust
+//! # fn main() -> Result<(), dhall::error::Error> {
+//! use dhall::notebook::Notebook;
+//!
+//! let mut notebook = Notebook::new();
+//! notebook.define("rate", "2")?;
+//! notebook.define("price", "rate * 10")?;
+//! assert_eq!(notebook.eval("price")?.to_string(), "20");
+//!
+//! // `price` uses `rate`, so it must be evaluated again.
+//! let stale = notebook.define("rate", "3")?;
+//! assert_eq!(stale, vec!["price".into()]);
+//! assert_eq!(notebook.eval("price")?.to_string(), "30");
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::error::{Error, ErrorKind};
+use crate::semantics::resolve::ImportLocation;
+use crate::syntax::{parse_expr, Expr, ExprKind, Label, V};
+use crate::utils::ensure_sufficient_stack;
+use crate::{Ctxt, Parsed, Typed};
+
+/// A set of named definitions that can refer to each other. See the [module docs](self).
+#[derive(Debug, Clone, Default)]
+pub struct Notebook {
+    definitions: BTreeMap<Label, Definition>,
+}
+
+#[derive(Debug, Clone)]
+struct Definition {
+    expr: Expr,
+    /// The names it refers to, whether they are defined or not.
+    refs: BTreeSet<Label>,
+    /// The normalized value, or `None` if a definition it depends on changed since.
+    value: Option<Expr>,
+}
+
+impl Notebook {
+    pub fn new() -> Self {
+        Notebook::default()
+    }
+
+    /// Adds a definition, or replaces the one with the same name. It is typechecked and evaluated
+    /// right away, and nothing changes if that fails. Returns the definitions that depend on it
+    /// and are now stale, in name order.
+    pub fn define(
+        &mut self,
+        name: &str,
+        source: &str,
+    ) -> Result<Vec<Label>, Error> {
+        let name = Label::from(name);
+        let expr = parse_expr(source)?;
+        let refs = free_variables(&expr);
+        if refs.contains(&name) || self.dependencies(&refs).contains(&name) {
+            return Err(ErrorKind::CyclicDefinition(name.to_string()).into());
+        }
+        let value = self.with_typed(&expr, &refs, |cx, typed| {
+            let value = typed.normalize(cx)?;
+            cx.evaluate(|| value.to_expr(cx))
+        })?;
+        let stale = self.invalidate_dependents(&name);
+        let definition = Definition {
+            expr,
+            refs,
+            value: Some(value),
+        };
+        self.definitions.insert(name, definition);
+        Ok(stale)
+    }
+
+    /// Removes a definition. Returns the definitions that depend on it and are now stale; they
+    /// will fail to evaluate until it is defined again.
+    pub fn remove(&mut self, name: &str) -> Vec<Label> {
+        match self.definitions.remove(name) {
+            Some(_) => self.invalidate_dependents(&name.into()),
+            None => Vec::new(),
+        }
+    }
+
+    /// Evaluates an expression that may refer to the definitions, evaluating again those that are
+    /// stale.
+    pub fn eval(&mut self, source: &str) -> Result<Expr, Error> {
+        let expr = parse_expr(source)?;
+        let refs = free_variables(&expr);
+        self.with_typed(&expr, &refs, |cx, typed| {
+            let value = typed.normalize(cx)?;
+            cx.evaluate(|| value.to_expr(cx))
+        })
+    }
+
+    /// The type of an expression that may refer to the definitions.
+    pub fn type_of(&mut self, source: &str) -> Result<Expr, Error> {
+        let expr = parse_expr(source)?;
+        let refs = free_variables(&expr);
+        self.with_typed(&expr, &refs, |cx, typed| {
+            Ok(typed.get_type()?.to_expr(cx))
+        })
+    }
+
+    /// The names of the definitions, in order.
+    pub fn names(&self) -> impl Iterator<Item = &Label> {
+        self.definitions.keys()
+    }
+
+    /// Whether this definition will be evaluated again the next time it is needed, because one it
+    /// depends on changed since.
+    pub fn is_stale(&self, name: &str) -> bool {
+        matches!(
+            self.definitions.get(name),
+            Some(definition) if definition.value.is_none()
+        )
+    }
+
+    /// The definitions that `refs` depend on, transitively, including themselves.
+    fn dependencies(&self, refs: &BTreeSet<Label>) -> BTreeSet<Label> {
+        let mut seen = BTreeSet::new();
+        let mut todo: Vec<&Label> = refs.iter().collect();
+        while let Some(name) = todo.pop() {
+            if seen.insert(name.clone()) {
+                if let Some(definition) = self.definitions.get(name) {
+                    todo.extend(&definition.refs);
+                }
+            }
+        }
+        seen
+    }
+
+    /// Marks the definitions that depend on `name`, transitively, as stale, and returns them.
+    fn invalidate_dependents(&mut self, name: &Label) -> Vec<Label> {
+        let stale: Vec<Label> = self
+            .definitions
+            .iter()
+            .filter(|(_, definition)| {
+                self.dependencies(&definition.refs).contains(name)
+            })
+            .map(|(dependent, _)| dependent.clone())
+            .collect();
+        for dependent in &stale {
+            self.definitions.get_mut(dependent).unwrap().value = None;
+        }
+        stale
+    }
+
+    /// Evaluates the definition again if it is stale.
+    fn evaluate(&mut self, name: &Label) -> Result<(), Error> {
+        let definition = &self.definitions[name];
+        if definition.value.is_some() {
+            return Ok(());
+        }
+        let (expr, refs) = (definition.expr.clone(), definition.refs.clone());
+        let value = self.with_typed(&expr, &refs, |cx, typed| {
+            let value = typed.normalize(cx)?;
+            cx.evaluate(|| value.to_expr(cx))
+        })?;
+        self.definitions.get_mut(name).unwrap().value = Some(value);
+        Ok(())
+    }
+
+    /// Typechecks `expr` with the definitions among `refs` in scope, and passes it to `f`.
+    fn with_typed<T>(
+        &mut self,
+        expr: &Expr,
+        refs: &BTreeSet<Label>,
+        f: impl for<'cx> FnOnce(Ctxt<'cx>, Typed<'cx>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut expr = expr.clone();
+        for name in refs {
+            if self.definitions.contains_key(name) {
+                self.evaluate(name)?;
+                let value = self.definitions[name].value.clone().unwrap();
+                expr = expr.add_let_binding(name.clone(), value);
+            }
+        }
+        let base = ImportLocation::dhall_code_of_unknown_origin();
+        Ctxt::with_new(|cx| {
+            let resolved = Parsed(expr, base).resolve(cx)?;
+            f(cx, resolved.typecheck(cx)?)
+        })
+    }
+}
+
+/// The names of the variables of `expr` that are not bound in it.
+fn free_variables(expr: &Expr) -> BTreeSet<Label> {
+    fn collect(expr: &Expr, bound: &mut Vec<Label>, out: &mut BTreeSet<Label>) {
+        ensure_sufficient_stack(|| {
+            if let ExprKind::Var(V(name, idx)) = expr.kind() {
+                if *idx == bound.iter().filter(|b| *b == name).count() {
+                    out.insert(name.clone());
+                }
+            }
+            expr.kind()
+                .traverse_ref_maybe_binder(|binder, child| {
+                    bound.extend(binder.cloned());
+                    collect(child, bound, out);
+                    if binder.is_some() {
+                        bound.pop();
+                    }
+                    Ok::<_, ()>(())
+                })
+                .unwrap();
+        })
+    }
+    let mut out = BTreeSet::new();
+    collect(expr, &mut Vec::new(), &mut out);
+    out
+}
@@ -1,14 +1,22 @@
 use itertools::Itertools;
+use num_traits::{CheckedAdd, CheckedMul, One, Zero};
 use std::collections::HashMap;
 use std::iter::once;
 
 use crate::operations::{BinOp, OpKind};
 use crate::semantics::{
-    merge_maps, ret_kind, ret_nir, ret_op, ret_ref, Nir, NirKind, Ret, TextLit,
+    merge_maps, ret_kind, ret_nir, ret_op, ret_ref, Hir, HirKind, Nir, NirKind,
+    NzEnv, Ret, TextLit,
 };
-use crate::syntax::{ExprKind, Label, NumKind};
+use crate::syntax::{ExprKind, InterpolatedTextContents, Label, NumKind};
+use crate::Ctxt;
 
-fn normalize_binop<'cx>(o: BinOp, x: Nir<'cx>, y: Nir<'cx>) -> Ret<'cx> {
+fn normalize_binop<'cx>(
+    cx: Ctxt<'cx>,
+    o: BinOp,
+    x: Nir<'cx>,
+    y: Nir<'cx>,
+) -> Ret<'cx> {
     use BinOp::*;
     use NirKind::{EmptyListLit, NEListLit, Num, RecordLit, RecordType};
     use NumKind::{Bool, Natural};
@@ -33,23 +41,35 @@ fn normalize_binop<'cx>(o: BinOp, x: Nir<'cx>, y: Nir<'cx>) -> Ret<'cx> {
         (BoolNE, Num(Bool(x)), Num(Bool(y))) => ret_kind(Num(Bool(x != y))),
         (BoolNE, _, _) if x == y => ret_kind(Num(Bool(false))),
 
-        (NaturalPlus, Num(Natural(0)), _) => ret_nir(y),
-        (NaturalPlus, _, Num(Natural(0))) => ret_nir(x),
-        (NaturalPlus, Num(Natural(x)), Num(Natural(y))) => {
-            ret_kind(Num(Natural(x + y)))
+        (NaturalPlus, Num(Natural(n)), _) if n.is_zero() => ret_nir(y),
+        (NaturalPlus, _, Num(Natural(n))) if n.is_zero() => ret_nir(x),
+        (NaturalPlus, Num(Natural(a)), Num(Natural(b))) => {
+            match CheckedAdd::checked_add(a, b) {
+                Some(n) => ret_kind(Num(Natural(n))),
+                None => natural_overflow(cx, OpKind::BinOp(o, x.clone(), y)),
+            }
+        }
+        (NaturalTimes, Num(Natural(n)), _) if n.is_zero() => {
+            ret_kind(Num(Natural(Zero::zero())))
+        }
+        (NaturalTimes, _, Num(Natural(n))) if n.is_zero() => {
+            ret_kind(Num(Natural(Zero::zero())))
         }
-        (NaturalTimes, Num(Natural(0)), _) => ret_kind(Num(Natural(0))),
-        (NaturalTimes, _, Num(Natural(0))) => ret_kind(Num(Natural(0))),
-        (NaturalTimes, Num(Natural(1)), _) => ret_nir(y),
-        (NaturalTimes, _, Num(Natural(1))) => ret_nir(x),
-        (NaturalTimes, Num(Natural(x)), Num(Natural(y))) => {
-            ret_kind(Num(Natural(x * y)))
+        (NaturalTimes, Num(Natural(n)), _) if n.is_one() => ret_nir(y),
+        (NaturalTimes, _, Num(Natural(n))) if n.is_one() => ret_nir(x),
+        (NaturalTimes, Num(Natural(a)), Num(Natural(b))) => {
+            match CheckedMul::checked_mul(a, b) {
+                Some(n) => ret_kind(Num(Natural(n))),
+                None => natural_overflow(cx, OpKind::BinOp(o, x.clone(), y)),
+            }
         }
 
         (ListAppend, EmptyListLit(_), _) => ret_nir(y),
         (ListAppend, _, EmptyListLit(_)) => ret_nir(x),
         (ListAppend, NEListLit(xs), NEListLit(ys)) => {
-            ret_kind(NEListLit(xs.iter().chain(ys.iter()).cloned().collect()))
+            let mut xs = xs.clone();
+            xs.append(ys.clone());
+            ret_kind(NEListLit(xs))
         }
 
         (TextAppend, NirKind::TextLit(x), _) if x.is_empty() => ret_nir(y),
@@ -88,11 +108,14 @@ fn normalize_binop<'cx>(o: BinOp, x: Nir<'cx>, y: Nir<'cx>) -> Ret<'cx> {
         }
         (RecursiveRecordMerge, RecordLit(kvs1), RecordLit(kvs2)) => {
             let kvs = merge_maps(kvs1, kvs2, |_, v1, v2| {
-                Nir::from_partial_expr(ExprKind::Op(OpKind::BinOp(
-                    RecursiveRecordMerge,
-                    v1.clone(),
-                    v2.clone(),
-                )))
+                Nir::from_partial_expr(
+                    cx,
+                    ExprKind::Op(OpKind::BinOp(
+                        RecursiveRecordMerge,
+                        v1.clone(),
+                        v2.clone(),
+                    )),
+                )
             });
             ret_kind(RecordLit(kvs))
         }
@@ -103,11 +126,14 @@ fn normalize_binop<'cx>(o: BinOp, x: Nir<'cx>, y: Nir<'cx>) -> Ret<'cx> {
                 kts_y,
                 // If the Label exists for both records, then we hit the recursive case.
                 |_, l: &Nir, r: &Nir| {
-                    Nir::from_partial_expr(ExprKind::Op(OpKind::BinOp(
-                        RecursiveRecordTypeMerge,
-                        l.clone(),
-                        r.clone(),
-                    )))
+                    Nir::from_partial_expr(
+                        cx,
+                        ExprKind::Op(OpKind::BinOp(
+                            RecursiveRecordTypeMerge,
+                            l.clone(),
+                            r.clone(),
+                        )),
+                    )
                 },
             );
             ret_kind(RecordType(kts))
@@ -119,6 +145,63 @@ fn normalize_binop<'cx>(o: BinOp, x: Nir<'cx>, y: Nir<'cx>) -> Ret<'cx> {
     }
 }
 
+/// Normalizes a chain of text appends like `a ++ b ++ c`, which parses as `(a ++ b) ++ c`. Once
+/// the result is known to be a text literal, the remaining operands are collected and the text is
+/// built once at the end, instead of copying the text accumulated so far at every `++`. This
+/// keeps templating with long chains of `++` linear in the size of the result.
+pub fn normalize_text_append<'cx>(
+    env: &NzEnv<'cx>,
+    hir: &Hir<'cx>,
+) -> NirKind<'cx> {
+    use InterpolatedTextContents::Expr;
+    // The right operands, last first.
+    let mut operands = Vec::new();
+    let mut head = hir;
+    while let HirKind::Expr(ExprKind::Op(OpKind::BinOp(
+        BinOp::TextAppend,
+        x,
+        y,
+    ))) = head.kind()
+    {
+        operands.push(y);
+        head = x;
+    }
+
+    let mut acc = head.eval(env);
+    while let Some(y) = operands.pop() {
+        let y = y.eval(env);
+        let kind = normalize_binop(env.cx(), BinOp::TextAppend, acc, y);
+        if let NirKind::TextLit(tlit) = kind {
+            let mut chunks: Vec<_> = tlit.iter().cloned().collect();
+            for y in operands.into_iter().rev() {
+                let y = y.eval(env);
+                match y.kind() {
+                    NirKind::TextLit(tlit) => {
+                        chunks.extend(tlit.iter().cloned())
+                    }
+                    _ => chunks.push(Expr(y)),
+                }
+            }
+            return TextLit::new(chunks.into_iter()).into_nirkind();
+        }
+        acc = Nir::from_kind(kind);
+    }
+    acc.kind().clone()
+}
+
+/// Reports `Natural` arithmetic that overflowed, see `Ctxt::natural_overflow`. The operation is
+/// left unevaluated.
+pub(crate) fn natural_overflow<'cx>(
+    cx: Ctxt<'cx>,
+    op: OpKind<Nir<'cx>>,
+) -> Ret<'cx> {
+    cx.natural_overflow(|| {
+        let op = Nir::from_kind(NirKind::Op(op.clone()));
+        op.to_expr(cx, Default::default()).to_string()
+    });
+    ret_op(op)
+}
+
 fn normalize_field<'cx>(v: &Nir<'cx>, field: &Label) -> Ret<'cx> {
     use self::BinOp::{RecursiveRecordMerge, RightBiasedRecordMerge};
     use NirKind::{Op, RecordLit, UnionConstructor, UnionType};
@@ -187,7 +270,10 @@ fn normalize_field<'cx>(v: &Nir<'cx>, field: &Label) -> Ret<'cx> {
     }
 }
 
-pub fn normalize_operation<'cx>(opkind: OpKind<Nir<'cx>>) -> Ret<'cx> {
+pub fn normalize_operation<'cx>(
+    cx: Ctxt<'cx>,
+    opkind: OpKind<Nir<'cx>>,
+) -> Ret<'cx> {
     use self::BinOp::RightBiasedRecordMerge;
     use NirKind::{
         EmptyListLit, EmptyOptionalLit, NEListLit, NEOptionalLit, Num, Op,
@@ -198,7 +284,7 @@ pub fn normalize_operation<'cx>(opkind: OpKind<Nir<'cx>>) -> Ret<'cx> {
 
     match opkind {
         App(v, a) => ret_kind(v.app_to_kind(a)),
-        BinOp(o, x, y) => normalize_binop(o, x, y),
+        BinOp(o, x, y) => normalize_binop(cx, o, x, y),
         BoolIf(b, e1, e2) => {
             match b.kind() {
                 Num(Bool(true)) => ret_nir(e1),
@@ -268,32 +354,41 @@ pub fn normalize_operation<'cx>(opkind: OpKind<Nir<'cx>>) -> Ret<'cx> {
                     .collect(),
             )),
             Op(Projection(v2, _)) => {
-                normalize_operation(Projection(v2.clone(), ls))
+                normalize_operation(cx, Projection(v2.clone(), ls))
             }
             Op(BinOp(RightBiasedRecordMerge, l, r)) => match r.kind() {
                 RecordLit(kvs) => {
                     let r_keys = kvs.keys().cloned().collect();
-                    normalize_operation(BinOp(
-                        RightBiasedRecordMerge,
-                        Nir::from_partial_expr(ExprKind::Op(Projection(
-                            l.clone(),
-                            ls.difference(&r_keys).cloned().collect(),
-                        ))),
-                        Nir::from_partial_expr(ExprKind::Op(Projection(
-                            r.clone(),
-                            ls.intersection(&r_keys).cloned().collect(),
-                        ))),
-                    ))
+                    normalize_operation(
+                        cx,
+                        BinOp(
+                            RightBiasedRecordMerge,
+                            Nir::from_partial_expr(
+                                cx,
+                                ExprKind::Op(Projection(
+                                    l.clone(),
+                                    ls.difference(&r_keys).cloned().collect(),
+                                )),
+                            ),
+                            Nir::from_partial_expr(
+                                cx,
+                                ExprKind::Op(Projection(
+                                    r.clone(),
+                                    ls.intersection(&r_keys).cloned().collect(),
+                                )),
+                            ),
+                        ),
+                    )
                 }
                 _ => ret_op(Projection(v, ls)),
             },
             _ => ret_op(Projection(v, ls)),
         },
         ProjectionByExpr(v, t) => match t.kind() {
-            RecordType(kts) => normalize_operation(Projection(
-                v,
-                kts.keys().cloned().collect(),
-            )),
+            RecordType(kts) => normalize_operation(
+                cx,
+                Projection(v, kts.keys().cloned().collect()),
+            ),
             _ => ret_op(ProjectionByExpr(v, t)),
         },
         With(mut record, labels, expr) => {
@@ -3,13 +3,30 @@ use std::cmp::max;
 use std::collections::HashMap;
 
 use crate::builtins::Builtin;
-use crate::error::{ErrorBuilder, TypeError};
+use crate::error::{ErrorBuilder, Message, TypeError};
 use crate::operations::{BinOp, OpKind};
 use crate::semantics::{
-    merge_maps, mk_span_err, mkerr, Binder, Closure, Hir, HirKind, Nir,
-    NirKind, Tir, TyEnv, Type,
+    merge_maps, mk_span_err, mkerr, note_type_diff, Binder, Closure, Hir,
+    HirKind, Nir, NirKind, Tir, TyEnv, Type,
 };
-use crate::syntax::{Const, ExprKind, Span};
+use crate::syntax::{Const, ExprKind, Label, Span};
+use crate::utils::closest_name;
+
+/// Reports that `name` is not one of `names`, suggesting the closest one if it looks like a typo.
+fn missing_name_err<'a, T>(
+    span: Span,
+    msg: &'static str,
+    name: &Label,
+    names: impl IntoIterator<Item = &'a Label>,
+) -> Result<T, TypeError> {
+    let mut err = ErrorBuilder::new(msg);
+    err.span_err(span, msg);
+    let names = names.into_iter().map(Label::as_ref);
+    if let Some(close) = closest_name(name.as_ref(), names) {
+        err.help(Message::new("DidYouMean").with("name", close));
+    }
+    mkerr(err.build())
+}
 
 fn check_rectymerge(
     span: &Span,
@@ -18,9 +35,15 @@ fn check_rectymerge(
     y: Nir<'_>,
 ) -> Result<(), TypeError> {
     let not_record_err = || match span {
-        Span::DuplicateRecordFieldsSugar(_, r) => {
-            mk_span_err((**r).clone(), "DuplicateFieldName")
-        }
+        Span::DuplicateRecordFieldsSugar(label, l, r) => mkerr(
+            ErrorBuilder::new("DuplicateFieldName")
+                .span_err(
+                    (**r).clone(),
+                    Message::new("DuplicateField").with("name", label),
+                )
+                .span_help((**l).clone(), "FirstDefinedHere")
+                .build(),
+        ),
         _ => mk_span_err(span.clone(), "RecordTypeMergeRequiresRecordType"),
     };
 
@@ -49,7 +72,7 @@ fn typecheck_binop<'cx>(
     r: Tir<'cx, '_>,
 ) -> Result<Type<'cx>, TypeError> {
     let cx = env.cx();
-    let span_err = |msg: &str| mk_span_err(span.clone(), msg);
+    let span_err = |msg: &'static str| mk_span_err(span.clone(), msg);
     use BinOp::*;
     use NirKind::{ListType, RecordType};
 
@@ -95,8 +118,8 @@ fn typecheck_binop<'cx>(
             check_rectymerge(&span, env, l.eval(env), r.eval(env))?;
 
             // A RecordType's type is always a const
-            let xk = l.ty().as_const().unwrap();
-            let yk = r.ty().as_const().unwrap();
+            let xk = l.ty().expect_const(env)?;
+            let yk = r.ty().expect_const(env)?;
             Type::from_const(max(xk, yk))
         }
         ListAppend => {
@@ -157,7 +180,7 @@ fn typecheck_merge<'cx>(
     scrut: &Tir<'cx, '_>,
     type_annot: Option<&Tir<'cx, '_>>,
 ) -> Result<Type<'cx>, TypeError> {
-    let span_err = |msg: &str| mk_span_err(span.clone(), msg);
+    let span_err = |msg: &'static str| mk_span_err(span.clone(), msg);
     use NirKind::{OptionalType, PiClosure, RecordType, UnionType};
 
     let record_type = record.ty();
@@ -186,31 +209,22 @@ fn typecheck_merge<'cx>(
                 PiClosure { closure, annot, .. } => {
                     if variant_type != annot {
                         return mkerr(
-                            ErrorBuilder::new(format!(
-                                "Wrong handler input type"
-                            ))
-                            .span_err(
-                                span,
-                                format!("in this merge expression",),
-                            )
-                            .span_err(
-                                record.span(),
-                                format!(
-                                    "the handler for `{}` expects a value of \
-                                     type: `{}`",
-                                    x,
-                                    annot.to_expr_tyenv(env)
-                                ),
-                            )
-                            .span_err(
-                                scrut.span(),
-                                format!(
-                                    "but the corresponding variant has type: \
-                                     `{}`",
-                                    variant_type.to_expr_tyenv(env)
-                                ),
-                            )
-                            .format(),
+                            ErrorBuilder::new("WrongHandlerInputType")
+                                .span_err(span, "InThisMerge")
+                                .span_err(
+                                    record.span(),
+                                    Message::new("HandlerExpects")
+                                        .with("name", x)
+                                        .with("ty", annot.to_expr_tyenv(env)),
+                                )
+                                .span_err(
+                                    scrut.span(),
+                                    Message::new("VariantHasType").with(
+                                        "ty",
+                                        variant_type.to_expr_tyenv(env),
+                                    ),
+                                )
+                                .build(),
                         );
                     }
 
@@ -221,38 +235,42 @@ fn typecheck_merge<'cx>(
                     }
                 }
                 _ => {
+                    let variant_type = variant_type.to_expr_tyenv(env);
                     return mkerr(
-                        ErrorBuilder::new(format!(
-                            "merge handler is not a function"
-                        ))
-                        .span_err(span, format!("in this merge expression"))
-                        .span_err(
-                            record.span(),
-                            format!(
-                                "the handler for `{}` has type: `{}`",
-                                x,
-                                handler_type.to_expr_tyenv(env)
-                            ),
-                        )
-                        .span_help(
-                            scrut.span(),
-                            format!(
-                                "the corresponding variant has type: `{}`",
-                                variant_type.to_expr_tyenv(env)
-                            ),
-                        )
-                        .help(format!(
-                            "a handler for this variant must be a function \
-                             that takes an input of type: `{}`",
-                            variant_type.to_expr_tyenv(env)
-                        ))
-                        .format(),
-                    )
+                        ErrorBuilder::new("HandlerNotAFunction")
+                            .span_err(span, "InThisMerge")
+                            .span_err(
+                                record.span(),
+                                Message::new("HandlerHasType")
+                                    .with("name", x)
+                                    .with(
+                                        "ty",
+                                        handler_type.to_expr_tyenv(env),
+                                    ),
+                            )
+                            .span_help(
+                                scrut.span(),
+                                Message::new("CorrespondingVariantHasType")
+                                    .with("ty", &variant_type),
+                            )
+                            .help(
+                                Message::new("HandlerMustBeFunction")
+                                    .with("ty", &variant_type),
+                            )
+                            .build(),
+                    );
                 }
             },
             // Union alternative without type
             Some(None) => Type::new_infer_universe(env, handler_type.clone())?,
-            None => return span_err("MergeHandlerMissingVariant"),
+            None => {
+                return missing_name_err(
+                    span,
+                    "MergeHandlerMissingVariant",
+                    x,
+                    variants.keys(),
+                )
+            }
         };
         match &inferred_type {
             None => inferred_type = Some(handler_return_type),
@@ -292,7 +310,7 @@ pub fn typecheck_operation<'cx>(
     opkind: OpKind<Tir<'cx, '_>>,
 ) -> Result<Type<'cx>, TypeError> {
     let cx = env.cx();
-    let span_err = |msg: &str| mk_span_err(span.clone(), msg);
+    let span_err = |msg: &'static str| mk_span_err(span.clone(), msg);
     use NirKind::{ListType, PiClosure, RecordType, UnionType};
     use OpKind::*;
 
@@ -302,47 +320,62 @@ pub fn typecheck_operation<'cx>(
                 // TODO: store Type in closure
                 PiClosure { annot, closure, .. } => {
                     if arg.ty().as_nir() != annot {
+                        let mut err = ErrorBuilder::new("WrongArgumentType");
+                        if note_type_diff(
+                            &mut err,
+                            env,
+                            annot,
+                            arg.ty().as_nir(),
+                        ) {
+                            err.span_err(f.span(), "ExpectsOtherArgumentType")
+                                .span_err(arg.span(), "ArgumentHasOtherType");
+                            return mkerr(err.build());
+                        }
+                        let expected = annot.to_expr_tyenv(env);
+                        let found = arg.ty().to_expr_tyenv(env);
                         return mkerr(
-                            ErrorBuilder::new(format!(
-                                "wrong type of function argument"
-                            ))
-                            .span_err(
-                                f.span(),
-                                format!(
-                                    "this expects an argument of type: {}",
-                                    annot.to_expr_tyenv(env),
-                                ),
-                            )
-                            .span_err(
-                                arg.span(),
-                                format!(
-                                    "but this has type: {}",
-                                    arg.ty().to_expr_tyenv(env),
-                                ),
-                            )
-                            .note(format!(
-                                "expected type `{}`\n   found type `{}`",
-                                annot.to_expr_tyenv(env),
-                                arg.ty().to_expr_tyenv(env),
-                            ))
-                            .format(),
+                            ErrorBuilder::new("WrongArgumentType")
+                                .span_err(
+                                    f.span(),
+                                    Message::new("ExpectsArgumentType")
+                                        .with("ty", &expected),
+                                )
+                                .span_err(
+                                    arg.span(),
+                                    Message::new("ArgumentHasType")
+                                        .with("ty", &found),
+                                )
+                                .note(
+                                    Message::new("ExpectedFoundType")
+                                        .with("expected", expected)
+                                        .with("found", found),
+                                )
+                                .build(),
                         );
                     }
 
                     let arg_nf = arg.eval(env);
-                    Type::new_infer_universe(env, closure.apply(arg_nf))?
+                    let ret = closure.apply(arg_nf);
+                    // A function type lives in the same universe as its output type, except in
+                    // `Sort` where the output could also be a kind. Only then do we need to
+                    // typecheck the output type again.
+                    match f.ty().ty().as_const() {
+                        Some(Const::Sort) | None => {
+                            Type::new_infer_universe(env, ret)?
+                        }
+                        Some(c) => Type::new(ret, c.to_universe()),
+                    }
                 }
-                _ => return mkerr(
-                    ErrorBuilder::new(format!(
-                        "expected function, found `{}`",
-                        f.ty().to_expr_tyenv(env)
-                    ))
-                    .span_err(
-                        f.span(),
-                        format!("function application requires a function",),
+                _ => {
+                    return mkerr(
+                        ErrorBuilder::new(
+                            Message::new("NotAFunction")
+                                .with("ty", f.ty().to_expr_tyenv(env)),
+                        )
+                        .span_err(f.span(), "ApplicationRequiresFunction")
+                        .build(),
                     )
-                    .format(),
-                ),
+                }
             }
         }
         BinOp(o, l, r) => typecheck_binop(env, span, o, l, r)?,
@@ -364,29 +397,23 @@ pub fn typecheck_operation<'cx>(
         }
         ToMap(record, annot) => {
             if record.ty().ty().as_const() != Some(Const::Type) {
-                return span_err("`toMap` only accepts records of type `Type`");
+                return span_err("ToMapRecordKind");
             }
             let record_t = record.ty();
             let kts = match record_t.kind() {
                 RecordType(kts) => kts,
-                _ => {
-                    return span_err("The argument to `toMap` must be a record")
-                }
+                _ => return span_err("ToMapNotRecord"),
             };
 
             if kts.is_empty() {
                 let annot = if let Some(annot) = annot {
                     annot
                 } else {
-                    return span_err(
-                        "`toMap` applied to an empty record requires a type \
-                         annotation",
-                    );
+                    return span_err("ToMapEmptyNeedsAnnotation");
                 };
                 let annot_val = annot.eval_to_type(env)?;
 
-                let err_msg = "The type of `toMap x` must be of the form \
-                               `List { mapKey : Text, mapValue : T }`";
+                let err_msg = "ToMapInvalidAnnotation";
                 let arg = match annot_val.kind() {
                     ListType(t) => t,
                     _ => return span_err(err_msg),
@@ -411,9 +438,7 @@ pub fn typecheck_operation<'cx>(
                 let entry_type = kts.iter().next().unwrap().1.clone();
                 for (_, t) in kts.iter() {
                     if *t != entry_type {
-                        return span_err(
-                            "Every field of the record must have the same type",
-                        );
+                        return span_err("ToMapHeterogenousRecord");
                     }
                 }
 
@@ -429,7 +454,7 @@ pub fn typecheck_operation<'cx>(
                 if let Some(annot) = annot {
                     let annot_val = annot.eval_to_type(env)?;
                     if output_type != annot_val {
-                        return span_err("Annotation mismatch");
+                        return span_err("ToMapAnnotMismatch");
                     }
                 }
                 output_type
@@ -439,7 +464,14 @@ pub fn typecheck_operation<'cx>(
             match scrut.ty().kind() {
                 RecordType(kts) => match kts.get(&x) {
                     Some(val) => Type::new_infer_universe(env, val.clone())?,
-                    None => return span_err("MissingRecordField"),
+                    None => {
+                        return missing_name_err(
+                            span,
+                            "MissingRecordField",
+                            &x,
+                            kts.keys(),
+                        )
+                    }
                 },
                 NirKind::Const(_) => {
                     let scrut = scrut.eval_to_type(env)?;
@@ -453,7 +485,14 @@ pub fn typecheck_operation<'cx>(
                             })
                             .to_type(scrut.ty()),
                             Some(None) => scrut,
-                            None => return span_err("MissingUnionField"),
+                            None => {
+                                return missing_name_err(
+                                    span,
+                                    "MissingUnionField",
+                                    &x,
+                                    kts.keys(),
+                                )
+                            }
                         },
                         _ => return span_err("NotARecord"),
                     }
@@ -471,7 +510,14 @@ pub fn typecheck_operation<'cx>(
             let mut new_kts = HashMap::new();
             for l in labels {
                 match kts.get(&l) {
-                    None => return span_err("ProjectionMissingEntry"),
+                    None => {
+                        return missing_name_err(
+                            span,
+                            "ProjectionMissingEntry",
+                            &l,
+                            kts.keys(),
+                        )
+                    }
                     Some(t) => {
                         new_kts.insert(l.clone(), t.clone());
                     }
@@ -500,7 +546,14 @@ pub fn typecheck_operation<'cx>(
                             return span_err("ProjectionWrongType");
                         }
                     }
-                    None => return span_err("ProjectionMissingEntry"),
+                    None => {
+                        return missing_name_err(
+                            span,
+                            "ProjectionMissingEntry",
+                            l,
+                            rec_kts.keys(),
+                        )
+                    }
                 }
             }
 
@@ -0,0 +1,149 @@
+//! Measures where the time and memory go when loading an expression, to find out whether a slow
+//! configuration is slow because of its imports, typechecking or evaluation.
+//!
+//! The time of each phase is always measured. Memory is only measured if [`CountingAllocator`] is
+//! the global allocator of the program; the figures are estimates, since allocations made by other
+//! threads at the same time are counted too.
+//!
+//! The costs of each import are recorded by a context once
+//! [`Ctxt::profile_imports`](crate::Ctxt::profile_imports) was called.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::semantics::ImportLocation;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// A global allocator that counts the bytes allocated by the program, so that phases can report
+/// their peak memory usage. It forwards the allocations to the system allocator.
+///
+/// # Example
+///
+/// ```rust
+/// use dhall::profile::{CountingAllocator, PhaseCost};
+///
+/// #[global_allocator]
+/// static ALLOCATOR: CountingAllocator = CountingAllocator;
+///
+/// fn main() {
+///     let mut cost = PhaseCost::default();
+///     let bytes = cost.measure(|| vec![0u8; 1000]);
+///     assert_eq!(bytes.len(), 1000);
+///     assert!(cost.peak_memory.unwrap() >= 1000);
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            allocated(layout.size());
+        }
+        ptr
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            allocated(layout.size());
+        }
+        ptr
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            allocated(new_size);
+        }
+        new_ptr
+    }
+}
+
+fn allocated(size: usize) {
+    INSTALLED.store(true, Ordering::Relaxed);
+    let total = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(total, Ordering::Relaxed);
+}
+
+/// The cost of a phase.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseCost {
+    /// The time it took.
+    pub time: Duration,
+    /// The most memory it had allocated at once, in bytes, not counting what was allocated
+    /// before it started. `None` unless [`CountingAllocator`] is the global allocator.
+    pub peak_memory: Option<usize>,
+}
+
+impl PhaseCost {
+    /// Runs `f` and adds its cost to this phase. The time is added, and the peak memory is the
+    /// highest of the two.
+    pub fn measure<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        let start_memory = ALLOCATED.load(Ordering::Relaxed);
+        // Measure the peak of this phase alone, and restore the peak of the enclosing one after.
+        let outer_peak = PEAK.swap(start_memory, Ordering::Relaxed);
+        let start = Instant::now();
+        let ret = f();
+        self.time += start.elapsed();
+        let peak = PEAK.fetch_max(outer_peak, Ordering::Relaxed);
+        if INSTALLED.load(Ordering::Relaxed) {
+            let peak = peak.saturating_sub(start_memory);
+            self.peak_memory =
+                Some(self.peak_memory.map_or(peak, |p| p.max(peak)));
+        }
+        ret
+    }
+}
+
+/// The cost of loading an import, recorded by
+/// [`Ctxt::profile_imports`](crate::Ctxt::profile_imports). The costs of the imports
+/// it contains are included, and also recorded separately.
+#[derive(Debug, Clone)]
+pub struct ImportCost {
+    /// Where it was loaded from, with the names of the HTTP headers sent to fetch it, see
+    /// [`ImportLocation::header_names`].
+    pub location: ImportLocation,
+    /// Whether it was loaded from the on-disk cache, in which case `fetch` is the time it took to
+    /// read the cached expression, and the other phases are empty.
+    pub from_cache: bool,
+    /// Reading or downloading the import and parsing it.
+    pub fetch: PhaseCost,
+    /// Resolving the imports it contains.
+    pub resolve: PhaseCost,
+    pub typecheck: PhaseCost,
+    pub normalize: PhaseCost,
+}
+
+impl ImportCost {
+    pub(crate) fn new(location: ImportLocation) -> Self {
+        ImportCost {
+            location,
+            from_cache: false,
+            fetch: PhaseCost::default(),
+            resolve: PhaseCost::default(),
+            typecheck: PhaseCost::default(),
+            normalize: PhaseCost::default(),
+        }
+    }
+
+    /// The total time it took.
+    pub fn time(&self) -> Duration {
+        self.fetch.time
+            + self.resolve.time
+            + self.typecheck.time
+            + self.normalize.time
+    }
+}
@@ -0,0 +1,177 @@
+//! An interactive session, to embed a Dhall console in a program.
+//!
+//! A [`Session`] evaluates one line of input at a time. A line is one of:
+//!
+//! - an expression, which is evaluated to its normal form;
+//! - `let` bindings without an `in` (e.g. `let x = 1` or `let x : Natural = 1 let y = x + 1`), which
+//!   are added to the session and visible in the following lines;
+//! - `:type <expr>`, which gives the type of an expression;
+//! - `:let <bindings>`, the same as `let <bindings>`;
+//! - `:load <path>`, which runs the lines of a file one after the other. Relative imports in the
+//!   file are resolved from its directory.
+//!
+//! Imports in the other lines are resolved from the current directory.
+//!
+//! # Example
+//!
+//! ```rust
+//! # fn main() -> Result<(), dhall::error::Error> {
+//! use dhall::repl::Session;
+//!
+//! let mut session = Session::new();
+//! assert_eq!(
+//!     session.eval("let double = λ(x : Natural) → x * 2")?,
+//!     "double : ∀(x : Natural) → Natural"
+//! );
+//! assert_eq!(session.eval("double 21")?, "42");
+//! assert_eq!(session.eval(":type double 21")?, "Natural");
+//! # Ok(())
+//! # }
+//! ```
+use std::path::Path;
+
+use crate::error::{Error, ErrorKind};
+use crate::semantics::resolve::ImportLocation;
+use crate::syntax::{parse_expr, Expr, ExprKind, Label, Span};
+use crate::{Ctxt, Parsed, Typed};
+
+/// A REPL session: the bindings made by its previous lines. See the [module docs](self).
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// The normalized values of the bindings, in order.
+    bindings: Vec<(Label, Expr)>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session::default()
+    }
+
+    /// Runs a line of input, and returns what to show for it: the value of an expression, the type
+    /// of each new binding, or the output of the lines of a loaded file. The bindings of a line
+    /// that fails are not added.
+    pub fn eval(&mut self, line: &str) -> Result<String, Error> {
+        self.eval_from(line, &ImportLocation::dhall_code_of_unknown_origin())
+    }
+
+    /// The bindings made so far, with their normalized values. Later bindings shadow earlier ones
+    /// of the same name.
+    pub fn bindings(&self) -> impl Iterator<Item = (&Label, &Expr)> {
+        self.bindings.iter().map(|(label, value)| (label, value))
+    }
+
+    fn eval_from(
+        &mut self,
+        line: &str,
+        base: &ImportLocation,
+    ) -> Result<String, Error> {
+        let line = line.trim();
+        let (command, arg) = match line.strip_prefix(':') {
+            Some(command) => match command.find(char::is_whitespace) {
+                Some(i) => (&command[..i], command[i..].trim()),
+                None => (command, ""),
+            },
+            None if is_let_statement(line) => {
+                return self.add_bindings(line, base)
+            }
+            None => {
+                let expr = parse_expr(line)?;
+                return self.with_typed(expr, base, |cx, typed| {
+                    let value = typed.normalize(cx)?;
+                    cx.evaluate(|| value.to_expr(cx).to_string())
+                });
+            }
+        };
+        match command {
+            "type" if !arg.is_empty() => {
+                let expr = parse_expr(arg)?;
+                self.with_typed(expr, base, |cx, typed| {
+                    Ok(typed.get_type()?.to_expr(cx).to_string())
+                })
+            }
+            "let" if !arg.is_empty() => {
+                self.add_bindings(&format!("let {}", arg), base)
+            }
+            "load" if !arg.is_empty() => self.load(Path::new(arg)),
+            _ => Err(ErrorKind::UnknownReplCommand(line.to_owned()).into()),
+        }
+    }
+
+    /// Runs the lines of a file, stopping at the first that fails. The bindings of the lines
+    /// before it are kept.
+    fn load(&mut self, path: &Path) -> Result<String, Error> {
+        let text = std::fs::read_to_string(path)?;
+        let base = ImportLocation::local_dhall_code(path.to_owned());
+        let mut output = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("--") {
+                continue;
+            }
+            output.push(self.eval_from(line, &base)?);
+        }
+        Ok(output.join("\n"))
+    }
+
+    /// Adds the bindings of `let x = e ...` without the `in`. Each binding is evaluated with the
+    /// previous ones in scope.
+    fn add_bindings(
+        &mut self,
+        line: &str,
+        base: &ImportLocation,
+    ) -> Result<String, Error> {
+        // The body is a placeholder, after a newline in case the line ends with a comment.
+        let mut expr = parse_expr(&format!("{}\nin {{=}}", line))?;
+        let mut new = self.clone();
+        let mut output = Vec::new();
+        while let ExprKind::Let(label, annot, value, body) = expr.kind() {
+            let value = match annot {
+                Some(annot) => Expr::new(
+                    ExprKind::Annot(value.clone(), annot.clone()),
+                    Span::Artificial,
+                ),
+                None => value.clone(),
+            };
+            let (value, ty) = new.with_typed(value, base, |cx, typed| {
+                let ty = typed.get_type()?.to_expr(cx);
+                let value = typed.normalize(cx)?;
+                Ok((cx.evaluate(|| value.to_expr(cx))?, ty))
+            })?;
+            output.push(format!("{} : {}", label, ty));
+            new.bindings.push((label.clone(), value));
+            expr = body.clone();
+        }
+        *self = new;
+        Ok(output.join("\n"))
+    }
+
+    /// Typechecks `expr` with the bindings of the session in scope, and passes it to `f`.
+    fn with_typed<T>(
+        &self,
+        expr: Expr,
+        base: &ImportLocation,
+        f: impl for<'cx> FnOnce(Ctxt<'cx>, Typed<'cx>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let expr =
+            self.bindings
+                .iter()
+                .rev()
+                .fold(expr, |expr, (label, value)| {
+                    expr.add_let_binding(label.clone(), value.clone())
+                });
+        Ctxt::with_new(|cx| {
+            let resolved = Parsed(expr, base.clone()).resolve(cx)?;
+            f(cx, resolved.typecheck(cx)?)
+        })
+    }
+}
+
+/// Whether the line is `let` bindings, which can't be parsed on their own since they lack an `in`.
+fn is_let_statement(line: &str) -> bool {
+    match line.strip_prefix("let") {
+        Some(rest) => {
+            rest.starts_with(char::is_whitespace) && parse_expr(line).is_err()
+        }
+        None => false,
+    }
+}
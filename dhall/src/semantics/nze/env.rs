@@ -1,3 +1,4 @@
+use crate::semantics::nze::vector::Vector;
 use crate::semantics::{AlphaVar, Nir, NirKind};
 use crate::Ctxt;
 
@@ -17,10 +18,12 @@ enum EnvItem<'cx, T> {
     Replaced(Nir<'cx>, T),
 }
 
+/// The values of the variables in scope. It is persistent, so that extending it doesn't copy it:
+/// each value of a `let` holds the environment it was bound in.
 #[derive(Debug, Clone)]
 pub struct ValEnv<'cx, T> {
     cx: Ctxt<'cx>,
-    items: Vec<EnvItem<'cx, T>>,
+    items: Vector<EnvItem<'cx, T>>,
 }
 
 pub type NzEnv<'cx> = ValEnv<'cx, ()>;
@@ -52,23 +55,12 @@ impl<'cx, T: Clone> ValEnv<'cx, T> {
     pub fn new(cx: Ctxt<'cx>) -> Self {
         ValEnv {
             cx,
-            items: Vec::new(),
+            items: Vector::new(),
         }
     }
     pub fn cx(&self) -> Ctxt<'cx> {
         self.cx
     }
-    pub fn discard_types(&self) -> ValEnv<'cx, ()> {
-        let items = self
-            .items
-            .iter()
-            .map(|i| match i {
-                EnvItem::Kept(_) => EnvItem::Kept(()),
-                EnvItem::Replaced(val, _) => EnvItem::Replaced(val.clone(), ()),
-            })
-            .collect();
-        ValEnv { cx: self.cx, items }
-    }
 
     pub fn insert_type(&self, ty: T) -> Self {
         let mut env = self.clone();
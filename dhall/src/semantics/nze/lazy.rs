@@ -3,8 +3,10 @@ use std::cell::Cell;
 use std::fmt::Debug;
 use std::ops::Deref;
 
-pub trait Eval<Tgt> {
-    fn eval(self) -> Tgt;
+pub trait Eval<Tgt>: Sized {
+    /// If the evaluation was interrupted, returns `self` back with the incomplete result, so that
+    /// it can be evaluated again later.
+    fn eval(self) -> Result<Tgt, (Self, Tgt)>;
 }
 
 /// A value which is initialized from a `Src` on the first access.
@@ -13,6 +15,9 @@ pub struct Lazy<Src, Tgt> {
     /// Once `src` is unset and `tgt` is set, we never go back.
     src: Cell<Option<Src>>,
     tgt: OnceCell<Tgt>,
+    /// The incomplete result of an interrupted evaluation. `src` stays set in that case, so that
+    /// the value is evaluated again on the next access.
+    interrupted: OnceCell<Box<Tgt>>,
 }
 
 impl<Src, Tgt> Lazy<Src, Tgt>
@@ -24,6 +29,7 @@ where
         Lazy {
             src: Cell::new(Some(src)),
             tgt: OnceCell::new(),
+            interrupted: OnceCell::new(),
         }
     }
     /// Creates a new lazy value with the given already-initialized value.
@@ -31,25 +37,42 @@ where
         let lazy = Lazy {
             src: Cell::new(None),
             tgt: OnceCell::new(),
+            interrupted: OnceCell::new(),
         };
         let _ = lazy.tgt.set(tgt);
         lazy
     }
 
     pub fn force(&self) -> &Tgt {
-        self.tgt.get_or_init(|| {
-            let src = self.src.take().unwrap();
-            src.eval()
-        })
+        if let Some(tgt) = self.tgt.get() {
+            return tgt;
+        }
+        let src = self.src.take().unwrap();
+        match src.eval() {
+            Ok(tgt) => {
+                let _ = self.tgt.set(tgt);
+                self.tgt.get().unwrap()
+            }
+            Err((src, tgt)) => {
+                self.src.set(Some(src));
+                self.interrupted.get_or_init(|| Box::new(tgt))
+            }
+        }
     }
 
     pub fn get_mut(&mut self) -> &mut Tgt {
         self.force();
-        self.tgt.get_mut().unwrap()
+        match self.tgt.get_mut() {
+            Some(tgt) => tgt,
+            None => self.interrupted.get_mut().unwrap(),
+        }
     }
     pub fn into_inner(self) -> Tgt {
         self.force();
-        self.tgt.into_inner().unwrap()
+        match self.tgt.into_inner() {
+            Some(tgt) => tgt,
+            None => *self.interrupted.into_inner().unwrap(),
+        }
     }
 }
 
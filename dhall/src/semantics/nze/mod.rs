@@ -1,8 +1,22 @@
+//! Normalization, by evaluation.
+//!
+//! A `Hir` is evaluated in an environment (`NzEnv`) that holds the values of its free variables,
+//! giving a `Nir`. Evaluation is lazy: a `Nir` starts as a `Thunk` of an expression and its
+//! environment, and is reduced to weak head normal form (`NirKind`) when it is looked at. Binders
+//! evaluate to a `Closure` that captures the environment, and applying one extends that
+//! environment with the argument; the body is not traversed to substitute it.
+//!
+//! Variables under binders that are not applied are represented by `NzVar`s, which count binders
+//! from the outside in (de Bruijn levels), so values don't have to be shifted when they are moved
+//! under binders. They are converted back to de Bruijn indices when the value is read back into a
+//! `Hir` (`Nir::to_hir`), which fully normalizes it. There is thus no shift or substitution on
+//! expressions anywhere in normalization.
 pub mod env;
 pub mod lazy;
 pub mod nir;
 pub mod normalize;
 pub mod var;
+pub mod vector;
 pub use env::*;
 pub use nir::*;
 pub use normalize::*;
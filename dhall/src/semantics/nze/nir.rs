@@ -4,13 +4,15 @@ use std::rc::Rc;
 use crate::builtins::{Builtin, BuiltinClosure};
 use crate::operations::{BinOp, OpKind};
 use crate::semantics::nze::lazy;
+use crate::semantics::nze::vector::Vector;
 use crate::semantics::{
     apply_any, normalize_hir, normalize_one_layer, squash_textlit, Binder, Hir,
     HirKind, NzEnv, NzVar, TyEnv, Type, Universe, VarEnv,
 };
 use crate::syntax::{
-    Const, Expr, ExprKind, InterpolatedTextContents, Label, NumKind, Span,
+    Const, Expr, ExprKind, InterpolatedTextContents, Label, NumKind, Span, V,
 };
+use crate::utils::ensure_sufficient_stack;
 use crate::{Ctxt, ToExprOptions};
 
 /// Stores a possibly unevaluated value. Gets (partially) normalized on-demand, sharing computation
@@ -28,7 +30,10 @@ pub enum Thunk<'cx> {
     /// A completely unnormalized expression.
     Thunk { env: NzEnv<'cx>, body: Hir<'cx> },
     /// A partially normalized expression that may need to go through `normalize_one_layer`.
-    PartialExpr { expr: ExprKind<Nir<'cx>> },
+    PartialExpr {
+        cx: Ctxt<'cx>,
+        expr: ExprKind<Nir<'cx>>,
+    },
 }
 
 /// An unevaluated subexpression that takes an argument.
@@ -41,8 +46,10 @@ pub enum Closure<'cx> {
 }
 
 /// A text literal with interpolations.
-// Invariant: this must not contain interpolations that are themselves TextLits, and contiguous
-// text values must be merged.
+///
+/// Adjacent text is always merged, empty text is dropped, and interpolated values are never text
+/// literals themselves. So a text value without interpolations is a single chunk, or no chunk at
+/// all if it is empty, and `as_text` returns it.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TextLit<'cx>(Vec<InterpolatedTextContents<Nir<'cx>>>);
 
@@ -79,7 +86,9 @@ pub enum NirKind<'cx> {
     OptionalType(Nir<'cx>),
     // EmptyListLit(t) means `[] : List t`, not `[] : t`
     EmptyListLit(Nir<'cx>),
-    NEListLit(Vec<Nir<'cx>>),
+    /// A persistent vector, so that appending lists shares their elements instead of copying
+    /// them.
+    NEListLit(Vector<Nir<'cx>>),
     ListType(Nir<'cx>),
     RecordLit(HashMap<Label, Nir<'cx>>),
     RecordType(HashMap<Label, Nir<'cx>>),
@@ -91,6 +100,10 @@ pub enum NirKind<'cx> {
     /// Invariant: evaluation must not be able to progress with `normalize_operation`.
     /// This is used when an operation couldn't proceed further, for example because of variables.
     Op(OpKind<Nir<'cx>>),
+    /// What evaluation returns instead of making progress once the running operation has been
+    /// interrupted, see `Ctxt::interruptible`. It is never memoized, and the result of the
+    /// interrupted operation is discarded, so it never escapes.
+    Interrupted,
 }
 
 impl<'cx> Nir<'cx> {
@@ -99,8 +112,8 @@ impl<'cx> Nir<'cx> {
         Nir(Rc::new(lazy::Lazy::new(Thunk::new(env, hir))))
     }
     /// Construct a Nir from a partially normalized expression that's not in WHNF.
-    pub fn from_partial_expr(e: ExprKind<Self>) -> Self {
-        Nir(Rc::new(lazy::Lazy::new(Thunk::from_partial_expr(e))))
+    pub fn from_partial_expr(cx: Ctxt<'cx>, e: ExprKind<Self>) -> Self {
+        Nir(Rc::new(lazy::Lazy::new(Thunk::from_partial_expr(cx, e))))
     }
     /// Make a Nir from a NirKind
     pub fn from_kind(v: NirKind<'cx>) -> Self {
@@ -163,6 +176,10 @@ impl<'cx> Nir<'cx> {
     }
 
     pub fn to_hir(&self, venv: VarEnv) -> Hir<'cx> {
+        ensure_sufficient_stack(|| self.to_hir_inner(venv))
+    }
+
+    fn to_hir_inner(&self, venv: VarEnv) -> Hir<'cx> {
         let map_uniontype =
             |kts: &HashMap<Label, Option<Nir<'cx>>>| -> ExprKind<Hir<'cx>> {
                 ExprKind::UnionType(
@@ -177,6 +194,9 @@ impl<'cx> Nir<'cx> {
             |b| Hir::new(HirKind::Expr(ExprKind::Builtin(b)), Span::Artificial);
 
         let hir = match self.kind() {
+            NirKind::Var(v) if venv.is_outside(*v) => {
+                HirKind::MissingVar(V("_".into(), 0))
+            }
             NirKind::Var(v) => HirKind::Var(venv.lookup(*v)),
             NirKind::AppliedBuiltin(closure) => closure.to_hirkind(venv),
             self_kind => HirKind::Expr(match self_kind {
@@ -272,6 +292,8 @@ impl<'cx> Nir<'cx> {
                 )),
                 NirKind::Assert(x) => ExprKind::Assert(x.to_hir(venv)),
                 NirKind::Op(e) => ExprKind::Op(e.map_ref(|v| v.to_hir(venv))),
+                // Only read back by the interrupted operation, which discards the result.
+                NirKind::Interrupted => ExprKind::Const(Const::Sort),
             }),
         };
 
@@ -293,19 +315,58 @@ impl<'cx> NirKind<'cx> {
     pub fn from_builtin_env(b: Builtin, env: NzEnv<'cx>) -> Self {
         BuiltinClosure::new(b, env)
     }
+
+    /// Roughly how many bytes this value takes, not counting the values it contains. Used to
+    /// enforce `EvalLimits::max_memory`.
+    pub(crate) fn estimated_size(&self) -> usize {
+        use std::mem::size_of;
+        let entries = match self {
+            NirKind::TextLit(t) => {
+                return size_of::<Self>()
+                    + t.iter()
+                        .map(|chunk| match chunk {
+                            InterpolatedTextContents::Text(s) => s.len(),
+                            InterpolatedTextContents::Expr(_) => {
+                                size_of::<Nir>()
+                            }
+                        })
+                        .sum::<usize>()
+            }
+            NirKind::NEListLit(xs) => {
+                return size_of::<Self>() + xs.len() * size_of::<Nir>()
+            }
+            NirKind::RecordLit(kvs) | NirKind::RecordType(kvs) => kvs.len(),
+            NirKind::UnionConstructor(_, kts)
+            | NirKind::UnionLit(_, _, kts)
+            | NirKind::UnionType(kts) => kts.len(),
+            _ => 0,
+        };
+        size_of::<Self>() + entries * size_of::<(Label, Nir)>()
+    }
 }
 
 impl<'cx> Thunk<'cx> {
     fn new(env: NzEnv<'cx>, body: Hir<'cx>) -> Self {
         Thunk::Thunk { env, body }
     }
-    fn from_partial_expr(expr: ExprKind<Nir<'cx>>) -> Self {
-        Thunk::PartialExpr { expr }
+    fn from_partial_expr(cx: Ctxt<'cx>, expr: ExprKind<Nir<'cx>>) -> Self {
+        Thunk::PartialExpr { cx, expr }
     }
-    fn eval(self) -> NirKind<'cx> {
-        match self {
-            Thunk::Thunk { env, body, .. } => normalize_hir(&env, &body),
-            Thunk::PartialExpr { expr } => normalize_one_layer(expr),
+    fn eval(self) -> Result<NirKind<'cx>, (Self, NirKind<'cx>)> {
+        // Forcing a value can force others in turn, e.g. the nested applications that `List/fold`
+        // unfolds to, so this recursion depends on the values rather than on the expression.
+        let (cx, kind) = ensure_sufficient_stack(|| match &self {
+            Thunk::Thunk { env, body, .. } => {
+                (env.cx(), normalize_hir(env, body))
+            }
+            Thunk::PartialExpr { cx, expr } => {
+                (*cx, normalize_one_layer(*cx, expr.clone()))
+            }
+        });
+        if cx.is_interrupted() {
+            Err((self, kind))
+        } else {
+            Ok(kind)
         }
     }
 }
@@ -366,10 +427,17 @@ impl<'cx> TextLit<'cx> {
         TextLit(squash_textlit(elts))
     }
     pub fn interpolate(v: Nir<'cx>) -> Self {
-        TextLit(vec![InterpolatedTextContents::Expr(v)])
+        TextLit::new(std::iter::once(InterpolatedTextContents::Expr(v)))
     }
     pub fn from_text(s: String) -> Self {
-        TextLit(vec![InterpolatedTextContents::Text(s)])
+        TextLit::new(std::iter::once(InterpolatedTextContents::Text(s)))
+    }
+    /// Simplifies a bare interpolation like `"${x}"` to `x`.
+    pub fn into_nirkind(self) -> NirKind<'cx> {
+        match self.as_single_expr() {
+            Some(v) => v.kind().clone(),
+            None => NirKind::TextLit(self),
+        }
     }
 
     pub fn concat(&self, other: &Self) -> Self {
@@ -390,11 +458,15 @@ impl<'cx> TextLit<'cx> {
     }
     /// If there are no interpolations, return the corresponding text value.
     pub fn as_text(&self) -> Option<String> {
+        self.as_str().map(str::to_owned)
+    }
+    /// Like `as_text`, without copying the text.
+    pub fn as_str(&self) -> Option<&str> {
         use InterpolatedTextContents::Text;
         if self.is_empty() {
-            Some(String::new())
+            Some("")
         } else if let [Text(s)] = self.0.as_slice() {
-            Some(s.clone())
+            Some(s)
         } else {
             None
         }
@@ -407,7 +479,7 @@ impl<'cx> TextLit<'cx> {
 }
 
 impl<'cx> lazy::Eval<NirKind<'cx>> for Thunk<'cx> {
-    fn eval(self) -> NirKind<'cx> {
+    fn eval(self) -> Result<NirKind<'cx>, (Self, NirKind<'cx>)> {
         self.eval()
     }
 }
@@ -1,9 +1,13 @@
 use std::collections::HashMap;
 
-use crate::operations::{normalize_operation, OpKind};
+use crate::operations::{
+    normalize_operation, normalize_text_append, BinOp, OpKind,
+};
 use crate::semantics::NzEnv;
 use crate::semantics::{Binder, Closure, Hir, HirKind, Nir, NirKind, TextLit};
 use crate::syntax::{ExprKind, InterpolatedTextContents};
+use crate::trace::{step_kind, StepKind};
+use crate::Ctxt;
 
 pub fn apply_any<'cx>(f: &Nir<'cx>, a: Nir<'cx>) -> NirKind<'cx> {
     match f.kind() {
@@ -96,7 +100,10 @@ pub fn ret_op<'cx>(x: OpKind<Nir<'cx>>) -> Ret<'cx> {
     NirKind::Op(x)
 }
 
-pub fn normalize_one_layer<'cx>(expr: ExprKind<Nir<'cx>>) -> NirKind<'cx> {
+pub fn normalize_one_layer<'cx>(
+    cx: Ctxt<'cx>,
+    expr: ExprKind<Nir<'cx>>,
+) -> NirKind<'cx> {
     use NirKind::{
         Assert, Const, NEListLit, NEOptionalLit, Num, RecordLit, RecordType,
         UnionType,
@@ -114,18 +121,13 @@ pub fn normalize_one_layer<'cx>(expr: ExprKind<Nir<'cx>>) -> NirKind<'cx> {
         ExprKind::Const(c) => ret_kind(Const(c)),
         ExprKind::Num(l) => ret_kind(Num(l)),
         ExprKind::TextLit(elts) => {
-            let tlit = TextLit::new(elts.into_iter());
-            // Simplify bare interpolation
-            if let Some(v) = tlit.as_single_expr() {
-                ret_ref(v)
-            } else {
-                ret_kind(NirKind::TextLit(tlit))
-            }
+            ret_kind(TextLit::new(elts.into_iter()).into_nirkind())
         }
         ExprKind::SomeLit(e) => ret_kind(NEOptionalLit(e)),
         ExprKind::EmptyListLit(t) => {
             let arg = match t.kind() {
                 NirKind::ListType(t) => t.clone(),
+                _ if cx.is_interrupted() => return NirKind::Interrupted,
                 _ => panic!("internal type error"),
             };
             ret_kind(NirKind::EmptyListLit(arg))
@@ -142,7 +144,7 @@ pub fn normalize_one_layer<'cx>(expr: ExprKind<Nir<'cx>>) -> NirKind<'cx> {
         ExprKind::UnionType(kvs) => {
             ret_kind(UnionType(kvs.into_iter().collect()))
         }
-        ExprKind::Op(op) => normalize_operation(op),
+        ExprKind::Op(op) => normalize_operation(cx, op),
         ExprKind::Annot(x, _) => ret_nir(x),
         ExprKind::Assert(x) => ret_kind(Assert(x)),
         ExprKind::Import(..) => {
@@ -153,21 +155,131 @@ pub fn normalize_one_layer<'cx>(expr: ExprKind<Nir<'cx>>) -> NirKind<'cx> {
 
 /// Normalize Hir into WHNF
 pub fn normalize_hir<'cx>(env: &NzEnv<'cx>, hir: &Hir<'cx>) -> NirKind<'cx> {
+    /// Nested operations and `let`s are evaluated from this stack instead of by recursion, so that
+    /// long chains of them don't overflow the call stack.
+    enum Task<'a, 'cx> {
+        /// Push the value of the expression.
+        Eval(NzEnv<'cx>, &'a Hir<'cx>),
+        /// Pop the values of the strict operands of the operation, and push its result.
+        Operation(NzEnv<'cx>, &'a OpKind<Hir<'cx>>),
+        /// Pop the value bound by a `let`, and evaluate its body.
+        LetBody(NzEnv<'cx>, &'a Hir<'cx>),
+    }
+
+    let cx = env.cx();
+    let mut tasks = vec![Task::Eval(env.clone(), hir)];
+    let mut values: Vec<NirKind<'cx>> = Vec::new();
+    while let Some(task) = tasks.pop() {
+        if cx.check_interrupted().is_err() {
+            return NirKind::Interrupted;
+        }
+        let kind = match task {
+            Task::Eval(mut env, mut hir) => loop {
+                match hir.kind() {
+                    HirKind::Import(import) => {
+                        hir = &cx[import].unwrap_result().hir;
+                    }
+                    HirKind::ImportAlternative(alt, left, right) => {
+                        hir = if cx[alt].unwrap_selected() {
+                            left
+                        } else {
+                            right
+                        };
+                    }
+                    HirKind::Expr(ExprKind::Let(_, _, val, body))
+                        if is_operation(val) =>
+                    {
+                        tasks.push(Task::LetBody(env.clone(), body));
+                        tasks.push(Task::Eval(env, val));
+                        break None;
+                    }
+                    HirKind::Expr(ExprKind::Let(_, _, val, body)) => {
+                        env = env.insert_value(val.eval(&env), ());
+                        hir = body;
+                    }
+                    HirKind::Expr(ExprKind::Op(op))
+                        if !matches!(
+                            op,
+                            OpKind::BinOp(BinOp::TextAppend, ..)
+                        ) =>
+                    {
+                        tasks.push(Task::Operation(env.clone(), op));
+                        let strict = strict_operands(op);
+                        let mut strict = strict.iter();
+                        op.map_ref(|operand| {
+                            if *strict.next().unwrap() {
+                                tasks.push(Task::Eval(env.clone(), operand));
+                            }
+                        });
+                        break None;
+                    }
+                    _ => break Some(normalize_hir_layer(&env, hir)),
+                }
+            },
+            Task::Operation(env, op) => {
+                let strict = strict_operands(op);
+                let mut strict = strict.iter();
+                // The strict operands were evaluated last first, so the first one is on top.
+                let op = op.map_ref(|operand| {
+                    if *strict.next().unwrap() {
+                        Nir::from_kind(values.pop().unwrap())
+                    } else {
+                        operand.eval(&env)
+                    }
+                });
+                Some(if cx.is_tracing() {
+                    traced_operation(cx, op)
+                } else {
+                    normalize_operation(cx, op)
+                })
+            }
+            Task::LetBody(env, body) => {
+                let val = Nir::from_kind(values.pop().unwrap());
+                tasks.push(Task::Eval(env.insert_value(val, ()), body));
+                None
+            }
+        };
+        if let Some(kind) = kind {
+            if cx.count_memory(kind.estimated_size()).is_err() {
+                return NirKind::Interrupted;
+            }
+            values.push(kind);
+        }
+    }
+    values.pop().unwrap()
+}
+
+fn is_operation(hir: &Hir<'_>) -> bool {
+    matches!(hir.kind(), HirKind::Expr(ExprKind::Op(_)))
+}
+
+/// Which operands of an operation, in the order `map_ref` visits them, are evaluated before it
+/// instead of lazily. Those are the operands that are operations themselves, except for the
+/// branches of an `if` and the argument of a function, which may not be needed.
+fn strict_operands(op: &OpKind<Hir<'_>>) -> Vec<bool> {
+    let mut strict = Vec::new();
+    op.map_ref(|operand| strict.push(is_operation(operand)));
+    match op {
+        OpKind::App(..) => strict[1] = false,
+        OpKind::BoolIf(..) => {
+            strict[1] = false;
+            strict[2] = false;
+        }
+        _ => {}
+    }
+    strict
+}
+
+/// Evaluates an expression whose operands, if any, are evaluated lazily.
+fn normalize_hir_layer<'cx>(env: &NzEnv<'cx>, hir: &Hir<'cx>) -> NirKind<'cx> {
     match hir.kind() {
         HirKind::MissingVar(..) => unreachable!("ruled out by typechecking"),
-        HirKind::Var(var) => env.lookup_val(*var),
-        HirKind::Import(import) => {
-            let typed = env.cx()[import].unwrap_result();
-            normalize_hir(env, &typed.hir)
-        }
-        HirKind::ImportAlternative(alt, left, right) => {
-            let hir = if env.cx()[alt].unwrap_selected() {
-                left
-            } else {
-                right
-            };
-            normalize_hir(env, hir)
+        HirKind::Import(..)
+        | HirKind::ImportAlternative(..)
+        | HirKind::Expr(ExprKind::Let(..)) => {
+            unreachable!("This case should have been handled in normalize_hir")
         }
+        HirKind::Var(var) => env.lookup_val(*var),
         HirKind::Expr(ExprKind::Lam(binder, annot, body)) => {
             let annot = annot.eval(env);
             NirKind::LamClosure {
@@ -184,16 +296,41 @@ pub fn normalize_hir<'cx>(env: &NzEnv<'cx>, hir: &Hir<'cx>) -> NirKind<'cx> {
                 closure: Closure::new(env, body.clone()),
             }
         }
-        HirKind::Expr(ExprKind::Let(_, _, val, body)) => {
-            let val = val.eval(env);
-            body.eval(env.insert_value(val, ())).kind().clone()
-        }
         HirKind::Expr(ExprKind::Builtin(b)) => {
             NirKind::from_builtin_env(*b, env.clone())
         }
+        HirKind::Expr(ExprKind::Op(OpKind::BinOp(BinOp::TextAppend, x, y))) => {
+            let result = normalize_text_append(env, hir);
+            if env.cx().is_tracing() {
+                let before = NirKind::Op(OpKind::BinOp(
+                    BinOp::TextAppend,
+                    x.eval(env),
+                    y.eval(env),
+                ));
+                env.cx().trace_step(
+                    StepKind::Operation,
+                    before.into_nir(),
+                    result.clone().into_nir(),
+                );
+            }
+            result
+        }
         HirKind::Expr(e) => {
             let e = e.map_ref(|hir| hir.eval(env));
-            normalize_one_layer(e)
+            normalize_one_layer(env.cx(), e)
         }
     }
 }
+
+/// Like `normalize_operation`, but records the step if it reduced anything.
+fn traced_operation<'cx>(cx: Ctxt<'cx>, op: OpKind<Nir<'cx>>) -> NirKind<'cx> {
+    let result = normalize_operation(cx, op.clone());
+    if let Some(kind) = step_kind(&op, &result) {
+        cx.trace_step(
+            kind,
+            NirKind::Op(op).into_nir(),
+            result.clone().into_nir(),
+        );
+    }
+    result
+}
@@ -0,0 +1,367 @@
+use std::fmt::{self, Debug};
+use std::ops::Index;
+use std::rc::Rc;
+
+/// A persistent vector: cloning it and appending to it share the elements instead of copying
+/// them.
+///
+/// It is a balanced tree of chunks, built only from `Rc` and `Vec`. Values stored in a `Ctxt`
+/// can't contain a type with a custom `Drop` impl, since they hold references to the context.
+pub struct Vector<T>(Rc<Node<T>>);
+
+enum Node<T> {
+    Leaf(Vec<T>),
+    /// Invariant: the depths of `left` and `right` differ by at most one.
+    ///
+    /// `right` is declared first so that it is dropped first: environments are vectors whose
+    /// last elements can hold the environment they were pushed on, i.e. the other elements. Those
+    /// are then still held by the vector when they get dropped, instead of each one dropping the
+    /// previous one in turn, which would recurse once per element.
+    Concat {
+        right: Vector<T>,
+        left: Vector<T>,
+        len: usize,
+        depth: usize,
+    },
+}
+
+/// Appending to a chunk at most this long copies it instead of sharing it.
+const MAX_COPIED_LEN: usize = 32;
+
+impl<T> Vector<T> {
+    pub fn new() -> Self {
+        Vector(Rc::new(Node::Leaf(Vec::new())))
+    }
+    pub fn len(&self) -> usize {
+        match &*self.0 {
+            Node::Leaf(xs) => xs.len(),
+            Node::Concat { len, .. } => *len,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn depth(&self) -> usize {
+        match &*self.0 {
+            Node::Leaf(_) => 0,
+            Node::Concat { depth, .. } => *depth,
+        }
+    }
+    fn children(&self) -> (Self, Self) {
+        match &*self.0 {
+            Node::Leaf(_) => unreachable!("a chunk has no children"),
+            Node::Concat { left, right, .. } => (left.clone(), right.clone()),
+        }
+    }
+    fn concat(left: Self, right: Self) -> Self {
+        Vector(Rc::new(Node::Concat {
+            len: left.len() + right.len(),
+            depth: std::cmp::max(left.depth(), right.depth()) + 1,
+            left,
+            right,
+        }))
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        self.get(0)
+    }
+    pub fn back(&self) -> Option<&T> {
+        self.get(self.len().checked_sub(1)?)
+    }
+    pub fn get(&self, mut index: usize) -> Option<&T> {
+        let mut node = self;
+        loop {
+            match &*node.0 {
+                Node::Leaf(xs) => return xs.get(index),
+                Node::Concat { left, right, .. } => {
+                    if index < left.len() {
+                        node = left;
+                    } else {
+                        index -= left.len();
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            front: vec![self],
+            front_chunk: [].iter(),
+            back: vec![self],
+            back_chunk: [].iter(),
+            remaining: self.len(),
+        }
+    }
+
+    /// Appends an element.
+    pub fn push(&mut self, x: T)
+    where
+        T: Clone,
+    {
+        self.append(std::iter::once(x).collect())
+    }
+
+    /// Appends the elements of `other`, sharing them with it.
+    pub fn append(&mut self, other: Self)
+    where
+        T: Clone,
+    {
+        *self = if other.is_empty() {
+            return;
+        } else if self.is_empty() {
+            other
+        } else {
+            match (&*self.0, &*other.0) {
+                (Node::Leaf(xs), Node::Leaf(ys))
+                    if xs.len() + ys.len() <= MAX_COPIED_LEN =>
+                {
+                    xs.iter().chain(ys.iter()).cloned().collect()
+                }
+                _ => Self::join(self.clone(), other),
+            }
+        }
+    }
+
+    /// Concatenates two balanced trees into a balanced tree, like the join of AVL trees.
+    fn join(left: Self, right: Self) -> Self {
+        if left.depth() > right.depth() + 1 {
+            let (l, c) = left.children();
+            let t = Self::join(c, right);
+            if t.depth() <= l.depth() + 1 {
+                Self::concat(l, t)
+            } else {
+                Self::rotate_left(l, t)
+            }
+        } else if right.depth() > left.depth() + 1 {
+            let (c, r) = right.children();
+            let t = Self::join(left, c);
+            if t.depth() <= r.depth() + 1 {
+                Self::concat(t, r)
+            } else {
+                Self::rotate_right(t, r)
+            }
+        } else {
+            Self::concat(left, right)
+        }
+    }
+    /// Balances `left ++ right`, where `right` is two levels deeper than `left`.
+    fn rotate_left(left: Self, right: Self) -> Self {
+        let (rl, rr) = right.children();
+        if rl.depth() > rr.depth() {
+            let (rll, rlr) = rl.children();
+            Self::concat(Self::concat(left, rll), Self::concat(rlr, rr))
+        } else {
+            Self::concat(Self::concat(left, rl), rr)
+        }
+    }
+    /// Balances `left ++ right`, where `left` is two levels deeper than `right`.
+    fn rotate_right(left: Self, right: Self) -> Self {
+        let (ll, lr) = left.children();
+        if lr.depth() > ll.depth() {
+            let (lrl, lrr) = lr.children();
+            Self::concat(Self::concat(ll, lrl), Self::concat(lrr, right))
+        } else {
+            Self::concat(ll, Self::concat(lr, right))
+        }
+    }
+}
+
+impl<T> Clone for Vector<T> {
+    fn clone(&self) -> Self {
+        Vector(self.0.clone())
+    }
+}
+
+impl<T> std::iter::FromIterator<T> for Vector<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Vector(Rc::new(Node::Leaf(iter.into_iter().collect())))
+    }
+}
+
+impl<T> Index<usize> for Vector<T> {
+    type Output = T;
+    fn index(&self, index: usize) -> &T {
+        self.get(index).expect("index out of bounds")
+    }
+}
+
+impl<T: PartialEq> PartialEq for Vector<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+            || (self.len() == other.len() && self.iter().eq(other.iter()))
+    }
+}
+impl<T: Eq> Eq for Vector<T> {}
+
+impl<T: Debug> Debug for Vector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Iterates over the elements of a `Vector` from both ends.
+pub struct Iter<'a, T> {
+    /// The nodes left to visit from the front, the next one last.
+    front: Vec<&'a Vector<T>>,
+    front_chunk: std::slice::Iter<'a, T>,
+    /// The nodes left to visit from the back, the next one last.
+    back: Vec<&'a Vector<T>>,
+    back_chunk: std::slice::Iter<'a, T>,
+    /// Both ends walk the whole tree, so this stops them once they meet.
+    remaining: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(x) = self.front_chunk.next() {
+                self.remaining -= 1;
+                return Some(x);
+            }
+            match &*self.front.pop()?.0 {
+                Node::Leaf(xs) => self.front_chunk = xs.iter(),
+                Node::Concat { left, right, .. } => {
+                    self.front.push(right);
+                    self.front.push(left);
+                }
+            }
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(x) = self.back_chunk.next_back() {
+                self.remaining -= 1;
+                return Some(x);
+            }
+            match &*self.back.pop()?.0 {
+                Node::Leaf(xs) => self.back_chunk = xs.iter(),
+                Node::Concat { left, right, .. } => {
+                    self.back.push(left);
+                    self.back.push(right);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Checks the balance invariant, and that `vector` holds the elements of `model`.
+    fn check(vector: &Vector<usize>, model: &[usize]) {
+        fn check_balanced(vector: &Vector<usize>) {
+            if let Node::Concat { left, right, .. } = &*vector.0 {
+                let (l, r) = (left.depth(), right.depth());
+                assert!(l <= r + 1 && r <= l + 1, "unbalanced: {} {}", l, r);
+                check_balanced(left);
+                check_balanced(right);
+            }
+        }
+        check_balanced(vector);
+        assert_eq!(vector.len(), model.len());
+        for (i, x) in model.iter().enumerate() {
+            assert_eq!(vector.get(i), Some(x));
+        }
+        assert_eq!(vector.get(model.len()), None);
+        assert_eq!(vector.front(), model.first());
+        assert_eq!(vector.back(), model.last());
+        assert!(vector.iter().eq(model.iter()));
+        assert!(vector.iter().rev().eq(model.iter().rev()));
+    }
+
+    /// Builds a vector of `model` by splitting it at random points and appending the halves.
+    fn split(rng: &mut StdRng, model: &[usize]) -> Vector<usize> {
+        if model.len() <= 1 || rng.gen_range(0, 4) == 0 {
+            return model.iter().cloned().collect();
+        }
+        let mid = rng.gen_range(0, model.len() + 1);
+        let mut vector = split(rng, &model[..mid]);
+        vector.append(split(rng, &model[mid..]));
+        vector
+    }
+
+    #[test]
+    fn append_like_vec() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..200 {
+            let len = rng.gen_range(0, 2000);
+            let model: Vec<usize> = (0..len).collect();
+            check(&split(&mut rng, &model), &model);
+        }
+    }
+
+    #[test]
+    fn append_and_prepend_chunks() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..50 {
+            // Appending grows the tree on the right and prepending on the left, which both need
+            // rotations to stay balanced.
+            let mut vector: Vector<usize> = None.into_iter().collect();
+            let mut model = Vec::new();
+            for _ in 0..rng.gen_range(0, 200) {
+                let len = rng.gen_range(0, 2 * MAX_COPIED_LEN);
+                let chunk: Vec<usize> =
+                    (model.len()..model.len() + len).collect();
+                if rng.gen() {
+                    vector.append(chunk.iter().cloned().collect());
+                    model.extend(chunk);
+                } else {
+                    let mut front: Vector<usize> =
+                        chunk.iter().cloned().collect();
+                    front.append(vector);
+                    vector = front;
+                    model.splice(0..0, chunk);
+                }
+                check(&vector, &model);
+            }
+            // Appending a vector to itself shares its nodes on both sides.
+            let shared = vector.clone();
+            vector.append(shared);
+            model.extend(model.clone());
+            check(&vector, &model);
+        }
+    }
+
+    #[test]
+    fn iterate_from_both_ends() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..200 {
+            let len = rng.gen_range(0, 500);
+            let model: Vec<usize> = (0..len).collect();
+            let vector = split(&mut rng, &model);
+            let mut iter = vector.iter();
+            let (mut front, mut back) = (0, len);
+            while front < back {
+                assert_eq!(iter.len(), back - front);
+                if rng.gen() {
+                    assert_eq!(iter.next(), Some(&front));
+                    front += 1;
+                } else {
+                    back -= 1;
+                    assert_eq!(iter.next_back(), Some(&back));
+                }
+            }
+            assert_eq!(iter.next(), None);
+            assert_eq!(iter.next_back(), None);
+        }
+    }
+}
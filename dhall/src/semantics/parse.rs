@@ -1,38 +1,80 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use url::Url;
 
 use crate::error::Error;
-use crate::semantics::resolve::{download_http_text, ImportLocation};
-use crate::syntax::{binary, parse_expr};
+use crate::semantics::resolve::{
+    default_home_dir, download_http_text, resolve_home, ImportLocation,
+};
+#[cfg(feature = "binary")]
+use crate::syntax::binary;
+use crate::syntax::{parse_expr_from, parse_expr_with_limits, ParseLimits};
 use crate::Parsed;
 
 pub fn parse_file(f: &Path) -> Result<Parsed, Error> {
-    let path = crate::resolve::resolve_home(f)?;
+    parse_file_with_limits(f, ParseLimits::default())
+}
+
+pub fn parse_file_with_limits(
+    f: &Path,
+    limits: ParseLimits,
+) -> Result<Parsed, Error> {
+    parse_file_with_home(f, default_home_dir(), limits)
+}
+
+/// Like `parse_file`, with `~` standing for `home`.
+pub(crate) fn parse_file_with_home(
+    f: &Path,
+    home: Option<PathBuf>,
+    limits: ParseLimits,
+) -> Result<Parsed, Error> {
+    let path = resolve_home(f, home)?;
     let text = std::fs::read_to_string(path)?;
-    let expr = parse_expr(&text)?;
+    let origin = f.to_string_lossy();
+    let expr = parse_expr_from(&text, limits, Some(&origin))
+        .map_err(|e| Error::from(e).in_file(&origin))?;
     let root = ImportLocation::local_dhall_code(f.to_owned());
     Ok(Parsed(expr, root))
 }
 
 pub fn parse_remote(url: Url) -> Result<Parsed, Error> {
-    let body = download_http_text(url.clone())?;
-    let expr = parse_expr(&body)?;
-    let root = ImportLocation::remote_dhall_code(url);
+    parse_remote_with_headers(url, &[], None, ParseLimits::default())
+}
+
+pub(crate) fn parse_remote_with_headers(
+    url: Url,
+    headers: &[(String, String)],
+    cors_origin: Option<&str>,
+    limits: ParseLimits,
+) -> Result<Parsed, Error> {
+    let body = download_http_text(url.clone(), headers, cors_origin)?;
+    let expr = parse_expr_from(&body, limits, Some(url.as_str()))
+        .map_err(|e| Error::from(e).in_file(url.as_str()))?;
+    let root =
+        ImportLocation::remote_dhall_code_with_headers(url, headers.to_vec());
     Ok(Parsed(expr, root))
 }
 
 pub fn parse_str(s: &str) -> Result<Parsed, Error> {
-    let expr = parse_expr(s)?;
+    parse_str_with_limits(s, ParseLimits::default())
+}
+
+pub fn parse_str_with_limits(
+    s: &str,
+    limits: ParseLimits,
+) -> Result<Parsed, Error> {
+    let expr = parse_expr_with_limits(s, limits)?;
     let root = ImportLocation::dhall_code_of_unknown_origin();
     Ok(Parsed(expr, root))
 }
 
+#[cfg(feature = "binary")]
 pub fn parse_binary(data: &[u8]) -> Result<Parsed, Error> {
     let expr = binary::decode(data)?;
     let root = ImportLocation::dhall_code_of_unknown_origin();
     Ok(Parsed(expr, root))
 }
 
+#[cfg(feature = "binary")]
 pub fn parse_binary_file(f: &Path) -> Result<Parsed, Error> {
     let data = crate::utils::read_binary_file(f)?;
     let expr = binary::decode(&data)?;
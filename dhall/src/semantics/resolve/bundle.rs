@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use crate::error::Error;
+use crate::semantics::{Hir, HirKind};
+use crate::syntax::ImportTarget;
+use crate::utils::ensure_sufficient_stack;
+use crate::{Ctxt, Parsed};
+
+/// Controls the output of `bundle_with`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BundleOptions {
+    /// Whether to list the integrity hashes of the inlined imports in a comment at the top of the
+    /// output.
+    pub keep_hashes: bool,
+}
+
+/// Inlines all the transitive imports of the file at `path`, and returns a single self-contained
+/// Dhall source file. The result can be evaluated without access to the filesystem or the network,
+/// which is useful to vendor a configuration.
+pub fn bundle(path: &Path) -> Result<String, Error> {
+    bundle_with(path, BundleOptions::default())
+}
+
+/// Like `bundle`, with options.
+pub fn bundle_with(path: &Path, opts: BundleOptions) -> Result<String, Error> {
+    Ctxt::with_new(|cx| {
+        let resolved = Parsed::parse_file(path)?.resolve(cx)?;
+        let mut out = String::new();
+        if opts.keep_hashes {
+            let mut imports = Vec::new();
+            collect_hashed_imports(cx, &resolved.0, &mut imports);
+            for import in imports {
+                out.push_str(&format!("-- {}\n", import));
+            }
+        }
+        out.push_str(&resolved.to_expr(cx).to_string());
+        out.push('\n');
+        Ok(out)
+    })
+}
+
+/// Collects the imports that carry an integrity hash, in order of first appearance, without
+/// duplicates. Only the imports that actually got inlined are considered.
+fn collect_hashed_imports<'cx>(
+    cx: Ctxt<'cx>,
+    hir: &Hir<'cx>,
+    out: &mut Vec<String>,
+) {
+    ensure_sufficient_stack(|| match hir.kind() {
+        HirKind::Import(import) => {
+            let stored = &cx[import];
+            if stored.import.hash.is_some() {
+                // The headers are stored separately, already resolved.
+                let mut import = stored.import.clone();
+                if let ImportTarget::Remote(url) = &mut import.location {
+                    url.headers = None;
+                }
+                let import = import.map_ref(|_| "").to_string();
+                if !out.contains(&import) {
+                    out.push(import);
+                }
+            }
+            collect_hashed_imports(cx, &stored.unwrap_result().hir, out);
+        }
+        HirKind::ImportAlternative(alt, left, right) => {
+            let hir = if cx[alt].unwrap_selected() {
+                left
+            } else {
+                right
+            };
+            collect_hashed_imports(cx, hir, out);
+        }
+        HirKind::Expr(e) => {
+            e.traverse_ref(|hir| {
+                collect_hashed_imports(cx, hir, out);
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        }
+        HirKind::Var(_) | HirKind::MissingVar(_) => {}
+    })
+}
@@ -1,10 +1,14 @@
 use std::env;
-use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::io::Read;
+use std::path::PathBuf;
 
 use crate::error::{CacheError, Error};
+#[cfg(feature = "binary")]
 use crate::parse::parse_binary;
-use crate::syntax::{binary, Hash};
+#[cfg(feature = "binary")]
+use crate::syntax::binary;
+use crate::syntax::Hash;
+use crate::utils::write_file_atomically;
 use crate::{Ctxt, Typed};
 use std::ffi::OsStr;
 use std::fs::File;
@@ -33,57 +37,81 @@ fn default_cache_dir() -> Result<PathBuf, CacheError> {
     Err(CacheError::MissingConfiguration)
 }
 
+/// A store for imports protected by a semantic hash. Lookups are keyed by the hash, and the
+/// values are the binary encoding of the imported expression, so a cache can be shared between
+/// processes or machines, e.g. through a key-value database.
+///
+/// Entries are checked against their hash when read, so implementations need not worry about
+/// corrupted data.
+pub trait Cache {
+    /// Returns the data stored for `hash`, if any.
+    fn get(&self, hash: &Hash) -> std::io::Result<Option<Vec<u8>>>;
+    /// Stores `data` for `hash`, replacing any previous entry.
+    fn put(&self, hash: &Hash, data: &[u8]) -> std::io::Result<()>;
+}
+
+impl std::fmt::Debug for dyn Cache + '_ {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Cache")
+    }
+}
+
+/// The default cache, that stores one file per entry in the standard Dhall cache directory. Entries
+/// are written atomically, so that concurrent readers never see a partially written entry.
 #[derive(Debug, Clone, PartialEq)]
-pub struct Cache {
+pub struct FsCache {
     cache_dir: PathBuf,
 }
 
-impl Cache {
-    pub fn new() -> Result<Cache, Error> {
-        let cache_dir = default_cache_dir()?;
+impl FsCache {
+    /// Uses `$XDG_CACHE_HOME/dhall`, falling back to `~/.cache/dhall`.
+    pub fn new() -> Result<FsCache, Error> {
+        FsCache::with_dir(default_cache_dir()?)
+    }
+
+    /// Uses the given directory, creating it if needed.
+    pub fn with_dir(cache_dir: impl Into<PathBuf>) -> Result<FsCache, Error> {
+        let cache_dir = cache_dir.into();
         if !cache_dir.exists() {
             std::fs::create_dir_all(&cache_dir)
                 .map_err(|e| CacheError::InitialisationError { cause: e })?;
         }
-        Ok(Cache { cache_dir })
+        Ok(FsCache { cache_dir })
     }
 
     fn entry_path(&self, hash: &Hash) -> PathBuf {
         self.cache_dir.join(filename_for_hash(hash))
     }
+}
 
-    pub fn get<'cx>(
-        &self,
-        cx: Ctxt<'cx>,
-        hash: &Hash,
-    ) -> Result<Typed<'cx>, Error> {
+impl Cache for FsCache {
+    fn get(&self, hash: &Hash) -> std::io::Result<Option<Vec<u8>>> {
         let path = self.entry_path(hash);
-        let res = read_cache_file(cx, &path, hash);
-        if res.is_err() && path.exists() {
-            // Delete cache file since it's invalid. We ignore the error.
-            let _ = std::fs::remove_file(&path);
+        if !path.exists() {
+            return Ok(None);
         }
-        res
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        Ok(Some(data))
     }
 
-    pub fn insert<'cx>(
-        &self,
-        cx: Ctxt<'cx>,
-        hash: &Hash,
-        expr: &Typed<'cx>,
-    ) -> Result<(), Error> {
-        let path = self.entry_path(hash);
-        write_cache_file(cx, &path, expr)
+    fn put(&self, hash: &Hash, data: &[u8]) -> std::io::Result<()> {
+        write_file_atomically(&self.entry_path(hash), data)
     }
 }
 
-/// Read a file from the cache, also checking that its hash is valid.
-fn read_cache_file<'cx>(
+/// Read an entry from the cache, also checking that its hash is valid. Returns `None` if there is
+/// no entry for this hash.
+#[cfg(feature = "binary")]
+pub(crate) fn read_from_cache<'cx>(
     cx: Ctxt<'cx>,
-    path: &Path,
+    cache: &dyn Cache,
     hash: &Hash,
-) -> Result<Typed<'cx>, Error> {
-    let data = crate::utils::read_binary_file(path)?;
+) -> Result<Option<Typed<'cx>>, Error> {
+    let data = match cache.get(hash)? {
+        Some(data) => data,
+        None => return Ok(None),
+    };
 
     match hash {
         Hash::SHA256(hash) => {
@@ -94,17 +122,38 @@ fn read_cache_file<'cx>(
         }
     }
 
-    Ok(parse_binary(&data)?.resolve(cx)?.typecheck(cx)?)
+    Ok(Some(parse_binary(&data)?.resolve(cx)?.typecheck(cx)?))
 }
 
-/// Write a file to the cache.
-fn write_cache_file<'cx>(
+/// Write an entry to the cache.
+#[cfg(feature = "binary")]
+pub(crate) fn write_to_cache<'cx>(
     cx: Ctxt<'cx>,
-    path: &Path,
+    cache: &dyn Cache,
+    hash: &Hash,
     expr: &Typed<'cx>,
 ) -> Result<(), Error> {
     let data = binary::encode(&expr.to_expr(cx))?;
-    File::create(path)?.write_all(data.as_slice())?;
+    cache.put(hash, &data)?;
+    Ok(())
+}
+
+// Without the binary encoding, entries can't be read nor written, and the cache is never used.
+#[cfg(not(feature = "binary"))]
+pub(crate) fn read_from_cache<'cx>(
+    _cx: Ctxt<'cx>,
+    _cache: &dyn Cache,
+    _hash: &Hash,
+) -> Result<Option<Typed<'cx>>, Error> {
+    Ok(None)
+}
+#[cfg(not(feature = "binary"))]
+pub(crate) fn write_to_cache<'cx>(
+    _cx: Ctxt<'cx>,
+    _cache: &dyn Cache,
+    _hash: &Hash,
+    _expr: &Typed<'cx>,
+) -> Result<(), Error> {
     Ok(())
 }
 
@@ -114,7 +163,7 @@ fn filename_for_hash(hash: &Hash) -> String {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "binary"))]
 mod test {
     use super::*;
     use crate::syntax::parse_expr;
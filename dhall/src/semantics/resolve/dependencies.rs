@@ -0,0 +1,99 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use crate::semantics::{resolve_home, Hir, HirKind, ImportLocation};
+use crate::syntax::ImportMode;
+use crate::utils::ensure_sufficient_stack;
+use crate::Ctxt;
+
+/// The environment variables and local files that an expression read while resolving its imports,
+/// transitively. See `Resolved::dependencies`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dependencies {
+    pub env_vars: BTreeSet<String>,
+    /// With `~` expanded to the home directory of the context.
+    pub local_paths: BTreeSet<PathBuf>,
+}
+
+impl Dependencies {
+    /// Whether the expression read nothing from its environment.
+    pub fn is_empty(&self) -> bool {
+        self.env_vars.is_empty() && self.local_paths.is_empty()
+    }
+
+    fn add(&mut self, cx: Ctxt<'_>, location: &ImportLocation) {
+        if let Some(name) = location.as_env_var() {
+            self.env_vars.insert(name.to_owned());
+        } else if let Some(path) = location.as_local_path() {
+            let path = resolve_home(path, cx.home_dir())
+                .unwrap_or_else(|_| path.to_owned());
+            self.local_paths.insert(path);
+        }
+    }
+
+    fn extend(&mut self, other: &Dependencies) {
+        self.env_vars.extend(other.env_vars.iter().cloned());
+        self.local_paths.extend(other.local_paths.iter().cloned());
+    }
+}
+
+/// Collects the dependencies of the imports in `hir`, which must have been resolved.
+pub(crate) fn dependencies<'cx>(cx: Ctxt<'cx>, hir: &Hir<'cx>) -> Dependencies {
+    let mut deps = Dependencies::default();
+    collect(cx, hir, false, &mut deps);
+    deps
+}
+
+/// `failed` is whether `hir` is on the left of an import alternative that failed. Its imports may
+/// then have been tried even though they have no result.
+fn collect<'cx>(
+    cx: Ctxt<'cx>,
+    hir: &Hir<'cx>,
+    failed: bool,
+    out: &mut Dependencies,
+) {
+    ensure_sufficient_stack(|| match hir.kind() {
+        HirKind::Import(import) => {
+            let stored = &cx[import];
+            if let Some(headers) = &stored.headers {
+                collect(cx, headers, failed, out);
+            }
+            let location = match stored.base_location.chain(&stored.import) {
+                Ok(location) => location,
+                Err(_) => return,
+            };
+            if location.mode() == ImportMode::Location {
+                return;
+            }
+            match stored.get_resultid() {
+                // Results found in the disk cache from their hash are not in the in-memory
+                // cache; their location was not read.
+                Some(res) if cx.cached_import(&location) == Some(res) => {
+                    out.add(cx, &location);
+                    if let Some(deps) = cx.import_dependencies(&location) {
+                        out.extend(&deps);
+                    }
+                }
+                Some(_) => {}
+                None if failed => out.add(cx, &location),
+                None => {}
+            }
+        }
+        HirKind::ImportAlternative(alt, left, right) => {
+            if cx[alt].unwrap_selected() {
+                collect(cx, left, failed, out);
+            } else {
+                collect(cx, left, true, out);
+                collect(cx, right, failed, out);
+            }
+        }
+        HirKind::Expr(e) => {
+            e.traverse_ref(|hir| {
+                collect(cx, hir, failed, out);
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        }
+        HirKind::Var(_) | HirKind::MissingVar(_) => {}
+    })
+}
@@ -1,7 +1,10 @@
-use std::collections::HashMap;
+use std::rc::Rc;
 
 use crate::error::{Error, ImportError};
-use crate::semantics::{check_hash, AlphaVar, Cache, ImportLocation, VarEnv};
+use crate::semantics::{
+    check_hash, read_from_cache, write_to_cache, AlphaVar, Cache, FsCache,
+    ImportLocation, VarEnv,
+};
 use crate::syntax::{Hash, Label, V};
 use crate::{Ctxt, ImportId, ImportResultId, Typed};
 
@@ -16,9 +19,10 @@ pub type CyclesStack = Vec<ImportLocation>;
 /// Environment for resolving imports
 pub struct ImportEnv<'cx> {
     cx: Ctxt<'cx>,
-    disk_cache: Option<Cache>, // `None` if it failed to initialize
-    mem_cache: HashMap<ImportLocation, ImportResultId<'cx>>,
+    disk_cache: Option<Rc<dyn Cache>>, // `None` if it failed to initialize
     stack: CyclesStack,
+    /// How many of the imports in `stack` are protected by an integrity hash.
+    frozen_depth: usize,
 }
 
 impl NameEnv {
@@ -40,6 +44,10 @@ impl NameEnv {
     pub fn remove_mut(&mut self) {
         self.names.pop();
     }
+    /// The names of the variables in scope, innermost first. Shadowed names are repeated.
+    pub fn names(&self) -> impl Iterator<Item = &Label> + '_ {
+        self.names.iter().rev()
+    }
 
     pub fn unlabel_var(&self, var: &V) -> Option<AlphaVar> {
         let V(name, idx) = var;
@@ -67,11 +75,21 @@ impl NameEnv {
 
 impl<'cx> ImportEnv<'cx> {
     pub fn new(cx: Ctxt<'cx>) -> Self {
+        let disk_cache =
+            FsCache::new().ok().map(|c| Rc::new(c) as Rc<dyn Cache>);
+        ImportEnv::with_cache(cx, disk_cache)
+    }
+
+    /// Uses `cache` instead of the default on-disk cache. `None` disables caching.
+    pub fn with_cache(
+        cx: Ctxt<'cx>,
+        disk_cache: Option<Rc<dyn Cache>>,
+    ) -> Self {
         ImportEnv {
             cx,
-            disk_cache: Cache::new().ok(),
-            mem_cache: Default::default(),
+            disk_cache,
             stack: Default::default(),
+            frozen_depth: 0,
         }
     }
 
@@ -83,7 +101,7 @@ impl<'cx> ImportEnv<'cx> {
         &self,
         location: &ImportLocation,
     ) -> Option<ImportResultId<'cx>> {
-        Some(*self.mem_cache.get(location)?)
+        self.cx.cached_import(location)
     }
 
     pub fn get_from_disk_cache(
@@ -91,8 +109,8 @@ impl<'cx> ImportEnv<'cx> {
         hash: &Option<Hash>,
     ) -> Option<Typed<'cx>> {
         let hash = hash.as_ref()?;
-        let expr = self.disk_cache.as_ref()?.get(self.cx(), hash).ok()?;
-        Some(expr)
+        let cache = self.disk_cache.as_deref()?;
+        read_from_cache(self.cx(), cache, hash).ok()?
     }
 
     pub fn check_hash(
@@ -108,7 +126,7 @@ impl<'cx> ImportEnv<'cx> {
         location: ImportLocation,
         result: ImportResultId<'cx>,
     ) {
-        self.mem_cache.insert(location, result);
+        self.cx.cache_import(location, result);
     }
 
     pub fn write_to_disk_cache(
@@ -116,17 +134,25 @@ impl<'cx> ImportEnv<'cx> {
         hash: &Option<Hash>,
         result: ImportResultId<'cx>,
     ) {
-        if let Some(disk_cache) = self.disk_cache.as_ref() {
+        if let Some(disk_cache) = self.disk_cache.as_deref() {
             if let Some(hash) = hash {
                 let expr = &self.cx()[result];
-                let _ = disk_cache.insert(self.cx(), hash, expr);
+                let _ = write_to_cache(self.cx(), disk_cache, hash, expr);
             }
         }
     }
 
+    /// Whether one of the imports being resolved is protected by an integrity hash. Everything it
+    /// imports is then covered by that hash.
+    pub fn in_frozen_import(&self) -> bool {
+        self.frozen_depth != 0
+    }
+
+    /// `frozen` is whether the import at `location` is protected by an integrity hash.
     pub fn with_cycle_detection(
         &mut self,
         location: ImportLocation,
+        frozen: bool,
         do_resolve: impl FnOnce(&mut Self) -> Result<Typed<'cx>, Error>,
     ) -> Result<Typed<'cx>, Error> {
         if self.stack.contains(&location) {
@@ -136,10 +162,12 @@ impl<'cx> ImportEnv<'cx> {
         }
         // Push the current location on the stack
         self.stack.push(location);
+        self.frozen_depth += frozen as usize;
         // Resolve the import recursively
         // WARNING: do not propagate errors here or the stack will get messed up.
         let result = do_resolve(self);
         // Remove location from the stack.
+        self.frozen_depth -= frozen as usize;
         self.stack.pop().unwrap();
         result
     }
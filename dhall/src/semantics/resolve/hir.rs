@@ -1,6 +1,10 @@
+use std::marker::PhantomData;
+use std::rc::Rc;
+
 use crate::error::TypeError;
 use crate::semantics::{type_with, typecheck, NameEnv, Nir, NzEnv, Tir, TyEnv};
 use crate::syntax::{Expr, ExprKind, Span, V};
+use crate::utils::ensure_sufficient_stack;
 use crate::{Ctxt, ImportAlternativeId, ImportId, ToExprOptions};
 
 /// Stores an alpha-normalized variable.
@@ -24,12 +28,19 @@ pub enum HirKind<'cx> {
 }
 
 // An expression with resolved variables and imports.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Hir<'cx> {
-    kind: Box<HirKind<'cx>>,
+    kind: Rc<HirNode>,
     span: Span,
+    cx: PhantomData<HirKind<'cx>>,
 }
 
+/// The kind of a `Hir`, stored without its lifetime so that it can be dropped without recursing:
+/// a type with a lifetime can't implement `Drop` and be stored in the context it borrows. Only
+/// `Hir::new` builds one, and `Hir::kind` reads it back with the lifetime of its `Hir`, so the ids
+/// it holds never leave their context.
+struct HirNode(HirKind<'static>);
+
 impl AlphaVar {
     pub fn new(idx: usize) -> Self {
         AlphaVar { idx }
@@ -41,14 +52,38 @@ impl AlphaVar {
 
 impl<'cx> Hir<'cx> {
     pub fn new(kind: HirKind<'cx>, span: Span) -> Self {
+        let kind = match kind {
+            HirKind::Var(v) => HirKind::Var(v),
+            HirKind::MissingVar(v) => HirKind::MissingVar(v),
+            HirKind::Import(id) => HirKind::Import(id.relabel()),
+            HirKind::ImportAlternative(id, left, right) => {
+                HirKind::ImportAlternative(
+                    id.relabel(),
+                    left.relabel(),
+                    right.relabel(),
+                )
+            }
+            HirKind::Expr(e) => {
+                HirKind::Expr(e.map_ref(|hir| hir.clone().relabel()))
+            }
+        };
         Hir {
-            kind: Box::new(kind),
+            kind: Rc::new(HirNode(kind)),
             span,
+            cx: PhantomData,
+        }
+    }
+    /// The same expression without the lifetime of its context, to store it in a `HirNode`.
+    fn relabel(self) -> Hir<'static> {
+        Hir {
+            kind: self.kind,
+            span: self.span,
+            cx: PhantomData,
         }
     }
 
     pub fn kind(&self) -> &HirKind<'cx> {
-        &*self.kind
+        &self.kind.0
     }
     pub fn span(&self) -> Span {
         self.span.clone()
@@ -70,7 +105,7 @@ impl<'cx> Hir<'cx> {
     pub fn to_expr_tyenv(&self, env: &TyEnv<'cx>) -> Expr {
         let opts = ToExprOptions { alpha: false };
         let cx = env.cx();
-        let mut env = env.as_nameenv().clone();
+        let mut env = env.to_nameenv();
         hir_to_expr(cx, self, opts, &mut env)
     }
 
@@ -104,6 +139,15 @@ fn hir_to_expr<'cx>(
     hir: &Hir<'cx>,
     opts: ToExprOptions,
     env: &mut NameEnv,
+) -> Expr {
+    ensure_sufficient_stack(|| hir_to_expr_inner(cx, hir, opts, env))
+}
+
+fn hir_to_expr_inner<'cx>(
+    cx: Ctxt<'cx>,
+    hir: &Hir<'cx>,
+    opts: ToExprOptions,
+    env: &mut NameEnv,
 ) -> Expr {
     let kind = match hir.kind() {
         HirKind::Var(v) if opts.alpha => ExprKind::Var(V("_".into(), v.idx())),
@@ -149,7 +193,45 @@ fn hir_to_expr<'cx>(
 
 impl<'cx> std::cmp::PartialEq for Hir<'cx> {
     fn eq(&self, other: &Self) -> bool {
-        self.kind == other.kind
+        self.kind() == other.kind()
     }
 }
 impl<'cx> std::cmp::Eq for Hir<'cx> {}
+
+impl<'cx> std::fmt::Debug for Hir<'cx> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Hir")
+            .field("kind", self.kind())
+            .field("span", &self.span)
+            .finish()
+    }
+}
+
+/// Drops a deeply nested expression without recursing as deeply, using a list of the
+/// sub-expressions left to drop.
+impl Drop for HirNode {
+    fn drop(&mut self) {
+        fn take_children(node: &mut HirNode, todo: &mut Vec<Hir<'static>>) {
+            let kind =
+                std::mem::replace(&mut node.0, HirKind::Var(AlphaVar::new(0)));
+            match kind {
+                HirKind::ImportAlternative(_, left, right) => {
+                    todo.push(left);
+                    todo.push(right);
+                }
+                HirKind::Expr(e) => {
+                    e.map_ref(|hir| todo.push(hir.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        let mut todo = Vec::new();
+        take_children(self, &mut todo);
+        while let Some(hir) = todo.pop() {
+            if let Ok(mut node) = Rc::try_unwrap(hir.kind) {
+                take_children(&mut node, &mut todo);
+            }
+        }
+    }
+}
@@ -1,8 +1,12 @@
+pub mod bundle;
 pub mod cache;
+pub mod dependencies;
 pub mod env;
 pub mod hir;
 pub mod resolve;
+pub use bundle::*;
 pub use cache::*;
+pub use dependencies::*;
 pub use env::*;
 pub use hir::*;
 pub use resolve::*;
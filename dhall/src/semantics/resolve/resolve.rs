@@ -1,35 +1,43 @@
-use itertools::Itertools;
 use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::env;
-use std::path::{Path, PathBuf};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
 use url::Url;
 
 use crate::builtins::Builtin;
-use crate::error::ErrorBuilder;
-use crate::error::{Error, ImportError};
+use crate::error::{Error, ErrorBuilder, ErrorKind, ImportError, Message};
 use crate::operations::{BinOp, OpKind};
-use crate::semantics::{mkerr, Hir, HirKind, ImportEnv, NameEnv, Type};
+use crate::profile::{ImportCost, PhaseCost};
+use crate::semantics::parse;
+use crate::semantics::{
+    dependencies, mk_span_err, mkerr, Cache, Hir, HirKind, ImportEnv, NameEnv,
+    Type,
+};
 use crate::syntax;
 use crate::syntax::{
-    Expr, ExprKind, FilePath, FilePrefix, Hash, ImportMode, ImportTarget, Span,
-    UnspannedExpr, URL,
+    Authority, Expr, ExprKind, FilePath, FilePrefix, Hash, ImportMode,
+    ImportTarget, Label, Scheme, Span, UnspannedExpr, URL,
 };
+use crate::utils::ensure_sufficient_stack;
 use crate::{
-    Ctxt, ImportAlternativeId, ImportId, ImportResultId, Parsed, Resolved,
-    Typed,
+    Ctxt, HeaderForwarding, ImportAlternativeId, ImportId, ImportResultId,
+    Parsed, Resolved, Typed,
 };
 
 // TODO: evaluate import headers
 pub type Import = syntax::Import<()>;
+type RemoteURL = syntax::URL<()>;
 
 /// The location of some data, usually some dhall code.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum ImportLocationKind {
     /// Local file
     Local(PathBuf),
-    /// Remote file
-    Remote(Url),
+    /// Remote file, as written in the source, and parsed for fetching. The written form is what
+    /// identifies it, e.g. for `as Location`, since parsing re-encodes some characters.
+    Remote(Box<RemoteURL>, Url),
     /// Environment variable
     Env(String),
     /// Data without a location; chaining will start from current directory.
@@ -39,10 +47,38 @@ enum ImportLocationKind {
 }
 
 /// The location of some data.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone)]
 pub struct ImportLocation {
     kind: ImportLocationKind,
     mode: ImportMode,
+    /// The HTTP headers sent to fetch a remote location. They don't change what the location
+    /// points to, so they are ignored when comparing locations.
+    headers: Headers,
+}
+
+/// HTTP headers. Only their names are shown, since the values often hold credentials.
+#[derive(Clone, Default)]
+struct Headers(Vec<(String, String)>);
+
+impl PartialEq for ImportLocation {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.mode == other.mode
+    }
+}
+impl Eq for ImportLocation {}
+impl std::hash::Hash for ImportLocation {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+        self.mode.hash(state);
+    }
+}
+
+impl fmt::Debug for Headers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|(name, _)| name))
+            .finish()
+    }
 }
 
 impl ImportLocationKind {
@@ -52,77 +88,99 @@ impl ImportLocationKind {
         path: &FilePath,
     ) -> Result<Self, Error> {
         Ok(match self {
+            // Only forbidden by the referential sanity check, see `check_sanity`.
+            ImportLocationKind::Remote(..)
+                if matches!(
+                    prefix,
+                    FilePrefix::Absolute | FilePrefix::Home
+                ) =>
+            {
+                ImportLocationKind::Missing.chain_local(prefix, path)?
+            }
             ImportLocationKind::Local(..)
             | ImportLocationKind::Env(..)
             | ImportLocationKind::Missing => {
-                let dir = match self {
+                let (root, mut dir) = match self {
                     ImportLocationKind::Local(path) => {
-                        path.parent().unwrap().to_owned()
+                        split_local_path(path.parent().unwrap())
                     }
                     ImportLocationKind::Env(..)
-                    | ImportLocationKind::Missing => std::env::current_dir()?,
+                    | ImportLocationKind::Missing => {
+                        split_local_path(&std::env::current_dir()?)
+                    }
                     _ => unreachable!(),
                 };
-                let mut dir: Vec<String> = dir
-                    .components()
-                    .map(|component| {
-                        component.as_os_str().to_string_lossy().into_owned()
-                    })
-                    .collect();
-                let root = match prefix {
-                    FilePrefix::Here => dir,
+                let (root, dir) = match prefix {
+                    FilePrefix::Here => (root, dir),
                     FilePrefix::Parent => {
                         dir.push("..".to_string());
-                        dir
+                        (root, dir)
                     }
-                    FilePrefix::Absolute => vec![],
-                    FilePrefix::Home => vec![],
+                    FilePrefix::Absolute => (PathBuf::from("/"), vec![]),
+                    FilePrefix::Home => (PathBuf::from("~"), vec![]),
                 };
-                let path: Vec<_> = root
+                let path: Vec<_> = dir
                     .into_iter()
                     .chain(path.file_path.iter().cloned())
                     .collect();
                 let path =
                     (FilePath { file_path: path }).canonicalize().file_path;
-                let prefix = match prefix {
-                    FilePrefix::Here | FilePrefix::Parent => ".",
-                    FilePrefix::Absolute => "/",
-                    FilePrefix::Home => "~",
-                };
-                let path =
-                    Some(prefix.to_string()).into_iter().chain(path).collect();
+                let mut root = root;
+                root.extend(path);
+                let path = root;
                 ImportLocationKind::Local(path)
             }
-            ImportLocationKind::Remote(url) => {
-                let mut url = url.clone();
-                match prefix {
-                    FilePrefix::Here => {}
-                    FilePrefix::Parent => {
-                        url = url.join("..")?;
-                    }
-                    FilePrefix::Absolute => panic!("error"),
-                    FilePrefix::Home => panic!("error"),
+            ImportLocationKind::Remote(url, _) => {
+                // Relative to the directory of the file, like local paths.
+                let mut dir = url.path.file_path.clone();
+                dir.pop();
+                if prefix == FilePrefix::Parent {
+                    dir.push("..".to_owned());
                 }
-                url = url.join(&path.file_path.join("/"))?;
-                ImportLocationKind::Remote(url)
+                // Local path components are literal, so we must escape those characters
+                // that would be interpreted by the URL parser.
+                let path = path.file_path.iter().map(|c| {
+                    percent_encoding::utf8_percent_encode(c, NOT_PCHAR)
+                        .to_string()
+                });
+                let file_path = dir.into_iter().chain(path).collect();
+                let url = URL {
+                    path: (FilePath { file_path }).canonicalize(),
+                    query: None,
+                    ..(**url).clone()
+                };
+                let parsed = Url::parse(&url.to_string())?;
+                ImportLocationKind::Remote(Box::new(url), parsed)
             }
             ImportLocationKind::NoImport => unreachable!(),
         })
     }
 
-    fn fetch_dhall(&self) -> Result<Parsed, Error> {
+    fn fetch_dhall(
+        &self,
+        cx: Ctxt<'_>,
+        span: &Span,
+        headers: &[(String, String)],
+        cors_origin: Option<&str>,
+    ) -> Result<Parsed, Error> {
         Ok(match self {
-            ImportLocationKind::Local(path) => Parsed::parse_file(path)?,
-            ImportLocationKind::Remote(url) => {
-                Parsed::parse_remote(url.clone())?
-            }
-            ImportLocationKind::Env(var_name) => {
-                let val = match env::var(var_name) {
-                    Ok(val) => val,
-                    Err(_) => return Err(ImportError::MissingEnvVar.into()),
-                };
-                Parsed::parse_str(&val)?
+            ImportLocationKind::Local(path) => parse::parse_file_with_home(
+                path,
+                cx.home_dir(),
+                cx.parse_limits(),
+            )?,
+            ImportLocationKind::Remote(_, url) => {
+                parse::parse_remote_with_headers(
+                    url.clone(),
+                    headers,
+                    cors_origin,
+                    cx.parse_limits(),
+                )?
             }
+            ImportLocationKind::Env(var_name) => parse::parse_str_with_limits(
+                &env_var(var_name, span)?,
+                cx.parse_limits(),
+            )?,
             ImportLocationKind::Missing => {
                 return Err(ImportError::Missing.into())
             }
@@ -130,17 +188,22 @@ impl ImportLocationKind {
         })
     }
 
-    fn fetch_text(&self) -> Result<String, Error> {
+    fn fetch_text(
+        &self,
+        cx: Ctxt<'_>,
+        span: &Span,
+        headers: &[(String, String)],
+        cors_origin: Option<&str>,
+    ) -> Result<String, Error> {
         Ok(match self {
             ImportLocationKind::Local(path) => {
-                let path = resolve_home(path)?;
+                let path = resolve_home(path, cx.home_dir())?;
                 std::fs::read_to_string(path)?
             }
-            ImportLocationKind::Remote(url) => download_http_text(url.clone())?,
-            ImportLocationKind::Env(var_name) => match env::var(var_name) {
-                Ok(val) => val,
-                Err(_) => return Err(ImportError::MissingEnvVar.into()),
-            },
+            ImportLocationKind::Remote(_, url) => {
+                download_http_text(url.clone(), headers, cors_origin)?
+            }
+            ImportLocationKind::Env(var_name) => env_var(var_name, span)?,
             ImportLocationKind::Missing => {
                 return Err(ImportError::Missing.into())
             }
@@ -151,9 +214,9 @@ impl ImportLocationKind {
     fn to_location(&self) -> Expr {
         let (field_name, arg) = match self {
             ImportLocationKind::Local(path) => {
-                ("Local", Some(path.to_string_lossy().into_owned()))
+                ("Local", Some(local_path_to_dhall(path)))
             }
-            ImportLocationKind::Remote(url) => {
+            ImportLocationKind::Remote(url, _) => {
                 ("Remote", Some(url.to_string()))
             }
             ImportLocationKind::Env(name) => {
@@ -181,32 +244,76 @@ impl ImportLocation {
         ImportLocation {
             kind: ImportLocationKind::Missing,
             mode: ImportMode::Code,
+            headers: Headers::default(),
         }
     }
     pub fn dhall_code_without_imports() -> Self {
         ImportLocation {
             kind: ImportLocationKind::NoImport,
             mode: ImportMode::Code,
+            headers: Headers::default(),
         }
     }
     pub fn local_dhall_code(path: PathBuf) -> Self {
         ImportLocation {
             kind: ImportLocationKind::Local(path),
             mode: ImportMode::Code,
+            headers: Headers::default(),
         }
     }
     pub fn remote_dhall_code(url: Url) -> Self {
+        Self::remote_dhall_code_with_headers(url, Vec::new())
+    }
+    /// A remote file fetched with the given HTTP headers.
+    pub(crate) fn remote_dhall_code_with_headers(
+        url: Url,
+        headers: Vec<(String, String)>,
+    ) -> Self {
         ImportLocation {
-            kind: ImportLocationKind::Remote(url),
+            kind: ImportLocationKind::Remote(Box::new(url_to_dhall(&url)), url),
             mode: ImportMode::Code,
+            headers: Headers(headers),
+        }
+    }
+
+    /// The file this points to, if it is a local file.
+    pub fn as_local_path(&self) -> Option<&Path> {
+        match &self.kind {
+            ImportLocationKind::Local(path) => Some(path),
+            _ => None,
         }
     }
+    /// The URL this points to, if it is a remote file.
+    pub fn as_remote_url(&self) -> Option<&Url> {
+        match &self.kind {
+            ImportLocationKind::Remote(_, url) => Some(url),
+            _ => None,
+        }
+    }
+    /// The name of the environment variable this points to, if any.
+    pub fn as_env_var(&self) -> Option<&str> {
+        match &self.kind {
+            ImportLocationKind::Env(name) => Some(name),
+            _ => None,
+        }
+    }
+    /// How the data at this location is interpreted.
+    pub fn mode(&self) -> ImportMode {
+        self.mode
+    }
+    /// The names of the HTTP headers sent to fetch this location, including those forwarded
+    /// from the file that imported it. Empty unless it is a remote file that was fetched.
+    pub fn header_names(&self) -> impl Iterator<Item = &str> {
+        self.headers.0.iter().map(|(name, _)| name.as_str())
+    }
 
     /// Given an import pointing to `target` found in the current location, compute the next
-    /// location, or error if not allowed.
-    /// `sanity_check` indicates whether to check if that location is allowed to be referenced,
-    /// for example to prevent a remote file from reading an environment variable.
-    fn chain(&self, import: &Import) -> Result<ImportLocation, Error> {
+    /// location, or error if not allowed. Whether that location may be referenced from here is
+    /// checked separately, see `check_sanity`.
+    pub(crate) fn chain(
+        &self,
+        import: &Import,
+    ) -> Result<ImportLocation, Error> {
         // Makes no sense to chain an import if the current file is not a dhall file.
         assert!(matches!(self.mode, ImportMode::Code));
         if matches!(self.kind, ImportLocationKind::NoImport) {
@@ -218,26 +325,15 @@ impl ImportLocation {
                 self.kind.chain_local(*prefix, path)?
             }
             ImportTarget::Remote(remote) => {
-                if matches!(self.kind, ImportLocationKind::Remote(..))
-                    && !matches!(import.mode, ImportMode::Location)
-                {
-                    // TODO: allow if CORS check passes
-                    return Err(ImportError::SanityCheck.into());
-                }
-                let mut url = Url::parse(&format!(
-                    "{}://{}",
-                    remote.scheme, remote.authority
-                ))?;
-                url.set_path(&remote.path.file_path.iter().join("/"));
-                url.set_query(remote.query.as_ref().map(String::as_ref));
-                ImportLocationKind::Remote(url)
+                // The headers are stored separately, already resolved.
+                let url = URL {
+                    headers: None,
+                    ..remote.clone()
+                };
+                let parsed = Url::parse(&url.to_string())?;
+                ImportLocationKind::Remote(Box::new(url), parsed)
             }
             ImportTarget::Env(var_name) => {
-                if matches!(self.kind, ImportLocationKind::Remote(..))
-                    && !matches!(import.mode, ImportMode::Location)
-                {
-                    return Err(ImportError::SanityCheck.into());
-                }
                 ImportLocationKind::Env(var_name.clone())
             }
             ImportTarget::Missing => ImportLocationKind::Missing,
@@ -245,28 +341,74 @@ impl ImportLocation {
         Ok(ImportLocation {
             kind,
             mode: import.mode,
+            headers: Headers::default(),
         })
     }
 
-    /// Fetches the expression corresponding to this location.
+    /// The referential sanity check of the standard: a remote file can't read local files or
+    /// environment variables, e.g. to send them elsewhere. It can still refer to them with `as
+    /// Location`. Returns the origin that `target` must allow, if it is a remote file of another
+    /// origin (see `download_http_text`).
+    pub(crate) fn check_sanity(
+        &self,
+        target: &ImportLocation,
+    ) -> Result<Option<String>, Error> {
+        let origin = match &self.kind {
+            ImportLocationKind::Remote(_, url) => url.origin(),
+            _ => return Ok(None),
+        };
+        if target.mode == ImportMode::Location {
+            return Ok(None);
+        }
+        match &target.kind {
+            ImportLocationKind::Local(..) | ImportLocationKind::Env(..) => {
+                Err(ImportError::SanityCheck.into())
+            }
+            ImportLocationKind::Remote(_, url) if url.origin() != origin => {
+                Ok(Some(origin.ascii_serialization()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Fetches the expression corresponding to this location. The headers are only used for
+    /// remote locations, and so is `cors_origin`: the origin that the response must allow, if any.
     fn fetch<'cx>(
         &self,
         env: &mut ImportEnv<'cx>,
         span: Span,
+        cors_origin: Option<&str>,
     ) -> Result<Typed<'cx>, Error> {
         let cx = env.cx();
+        let headers = &self.headers.0;
+        let mut cost = ImportCost::new(self.clone());
         let typed = match self.mode {
             ImportMode::Code => {
-                let parsed = self.kind.fetch_dhall()?;
-                let typed = parsed.resolve_with_env(env)?.typecheck(cx)?;
+                let parsed = cost.fetch.measure(|| {
+                    self.kind.fetch_dhall(cx, &span, headers, cors_origin)
+                })?;
+                let resolved =
+                    cost.resolve.measure(|| parsed.resolve_with_env(env))?;
+                cx.store_import_source(self, &resolved.0);
+                cx.store_import_dependencies(
+                    self,
+                    dependencies(cx, &resolved.0),
+                );
+                let typed =
+                    cost.typecheck.measure(|| resolved.typecheck(cx))?;
                 Typed {
                     // TODO: manage to keep the Nir around. Will need fixing variables.
-                    hir: typed.normalize(cx).to_hir(),
+                    hir: cost.normalize.measure(|| {
+                        let value = typed.normalize(cx)?;
+                        cx.evaluate(|| value.to_hir())
+                    })?,
                     ty: typed.ty,
                 }
             }
             ImportMode::RawText => {
-                let text = self.kind.fetch_text()?;
+                let text = cost.fetch.measure(|| {
+                    self.kind.fetch_text(cx, &span, headers, cors_origin)
+                })?;
                 Typed {
                     hir: Hir::new(
                         HirKind::Expr(ExprKind::TextLit(text.into())),
@@ -284,25 +426,118 @@ impl ImportLocation {
                     .unwrap()
             }
         };
+        cx.record_import_cost(cost);
         Ok(typed)
     }
 }
 
+/// The characters that can't appear as is in a URL path segment.
+const NOT_PCHAR: &percent_encoding::AsciiSet =
+    &percent_encoding::NON_ALPHANUMERIC
+        .remove(b'-')
+        .remove(b'.')
+        .remove(b'_')
+        .remove(b'~')
+        .remove(b'!')
+        .remove(b'$')
+        .remove(b'&')
+        .remove(b'\'')
+        .remove(b'*')
+        .remove(b'+')
+        .remove(b';')
+        .remove(b'=')
+        .remove(b':')
+        .remove(b'@');
+
+/// The Dhall form of a URL, keeping its path and query as they are encoded.
+fn url_to_dhall(url: &Url) -> RemoteURL {
+    let userinfo = match (url.username(), url.password()) {
+        ("", None) => None,
+        (user, None) => Some(user.to_owned()),
+        (user, Some(password)) => Some(format!("{}:{}", user, password)),
+    };
+    URL {
+        scheme: if url.scheme() == "http" {
+            Scheme::HTTP
+        } else {
+            Scheme::HTTPS
+        },
+        authority: Authority {
+            userinfo,
+            host: url.host_str().unwrap_or_default().to_owned(),
+            port: url.port().map(|port| port.to_string()),
+        },
+        path: FilePath {
+            file_path: url.path()[1..].split('/').map(String::from).collect(),
+        },
+        query: url.query().map(String::from),
+        headers: None,
+    }
+}
+
 fn mkexpr(kind: UnspannedExpr) -> Expr {
     Expr::new(kind, Span::Artificial)
 }
 
-// TODO: error handling
+/// Reads the environment variable imported at `span`.
+fn env_var(name: &str, span: &Span) -> Result<String, Error> {
+    env::var(name).map_err(|_| {
+        ImportError::MissingEnvVar {
+            name: name.to_owned(),
+            span: span.byte_range(),
+        }
+        .into()
+    })
+}
+
+/// Downloads the text at `url`. If `cors_origin` is set, the response must allow that origin with
+/// its `Access-Control-Allow-Origin` header, like browsers require of cross-origin requests.
 #[cfg(all(not(target_arch = "wasm32"), feature = "reqwest"))]
-pub(crate) fn download_http_text(url: Url) -> Result<String, Error> {
-    Ok(reqwest::blocking::get(url).unwrap().text().unwrap())
+pub(crate) fn download_http_text(
+    url: Url,
+    headers: &[(String, String)],
+    cors_origin: Option<&str>,
+) -> Result<String, Error> {
+    let failed = |url: &Url, reason: String| -> Error {
+        ImportError::DownloadFailed {
+            url: url.clone(),
+            reason,
+        }
+        .into()
+    };
+    let mut request = reqwest::blocking::Client::new().get(url.clone());
+    for (name, value) in headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    let response = request.send().map_err(|e| failed(&url, e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(failed(&url, response.status().to_string()));
+    }
+    if let Some(origin) = cors_origin {
+        let allowed = response
+            .headers()
+            .get(reqwest::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .and_then(|value| value.to_str().ok());
+        if allowed != Some("*") && allowed != Some(origin) {
+            return Err(ImportError::SanityCheck.into());
+        }
+    }
+    response.text().map_err(|e| failed(&url, e.to_string()))
 }
 #[cfg(all(not(target_arch = "wasm32"), not(feature = "reqwest")))]
-pub(crate) fn download_http_text(_url: Url) -> Result<String, Error> {
+pub(crate) fn download_http_text(
+    _url: Url,
+    _headers: &[(String, String)],
+    _cors_origin: Option<&str>,
+) -> Result<String, Error> {
     panic!("Remote imports are disabled in this build of dhall-rust")
 }
 #[cfg(target_arch = "wasm32")]
-pub(crate) fn download_http_text(_url: Url) -> Result<String, Error> {
+pub(crate) fn download_http_text(
+    _url: Url,
+    _headers: &[(String, String)],
+    _cors_origin: Option<&str>,
+) -> Result<String, Error> {
     panic!("Remote imports are not supported on wasm yet")
 }
 
@@ -316,6 +551,7 @@ fn make_aslocation_uniontype() -> Expr {
     mkexpr(ExprKind::UnionType(union))
 }
 
+#[cfg(feature = "binary")]
 pub fn check_hash<'cx>(
     cx: Ctxt<'cx>,
     import: ImportId<'cx>,
@@ -329,19 +565,39 @@ pub fn check_hash<'cx>(
         let actual_hash = expr.sha256_hash()?;
         if hash[..] != actual_hash[..] {
             mkerr(
-                ErrorBuilder::new("hash mismatch")
-                    .span_err(import.span.clone(), "hash mismatch")
-                    .note(format!("Expected sha256:{}", hex::encode(hash)))
-                    .note(format!(
-                        "Found    sha256:{}",
-                        hex::encode(actual_hash)
-                    ))
-                    .format(),
+                ErrorBuilder::new("HashMismatch")
+                    .span_err(import.span.clone(), "HashMismatch")
+                    .note(
+                        Message::new("ExpectedHash")
+                            .with("hash", hex::encode(hash)),
+                    )
+                    .note(
+                        Message::new("FoundHash")
+                            .with("hash", hex::encode(actual_hash)),
+                    )
+                    .build(),
             )?
         }
     }
     Ok(())
 }
+#[cfg(not(feature = "binary"))]
+pub fn check_hash<'cx>(
+    cx: Ctxt<'cx>,
+    import: ImportId<'cx>,
+    _result: ImportResultId<'cx>,
+) -> Result<(), Error> {
+    let import = &cx[import];
+    match (import.import.mode, &import.import.hash) {
+        (ImportMode::Code, Some(Hash::SHA256(_))) => {
+            Err(ImportError::UncheckableHash {
+                span: import.span.byte_range(),
+            }
+            .into())
+        }
+        _ => Ok(()),
+    }
+}
 
 /// Desugar the first level of the expression.
 fn desugar(expr: &Expr) -> Cow<'_, Expr> {
@@ -380,11 +636,39 @@ fn fetch_import<'cx>(
     let cx = env.cx();
     let import = &cx[import_id].import;
     let span = cx[import_id].span.clone();
-    let location = cx[import_id].base_location.chain(import)?;
+    let base_location = &cx[import_id].base_location;
+    let location = base_location.chain(import)?;
+    let cors_origin = if cx.referential_sanity_check() {
+        base_location.check_sanity(&location)?
+    } else {
+        None
+    };
+
+    if let Some(url) = location.as_remote_url() {
+        if cx.requires_frozen_remote_imports()
+            && import.hash.is_none()
+            && import.mode != ImportMode::Location
+            && !env.in_frozen_import()
+        {
+            return Err(ImportError::UnfrozenRemoteImport {
+                url: url.clone(),
+                span: span.byte_range(),
+            }
+            .into());
+        }
+    }
 
     // If the hash is in the on-disk cache, return
     // the cached contents.
-    if let Some(typed) = env.get_from_disk_cache(&import.hash) {
+    let mut cache_read = PhaseCost::default();
+    if let Some(typed) =
+        cache_read.measure(|| env.get_from_disk_cache(&import.hash))
+    {
+        cx.record_import_cost(ImportCost {
+            from_cache: true,
+            fetch: cache_read,
+            ..ImportCost::new(location)
+        });
         // No need to check the hash, it was checked before reading the file.
         // We also don't write to the in-memory cache, because the location might be completely
         // unrelated to the cached file (e.g. `missing sha256:...` is valid).
@@ -401,15 +685,48 @@ fn fetch_import<'cx>(
     } else {
         // Resolve this import, making sure that recursive imports don't cycle back to the
         // current one.
-        let res = env.with_cycle_detection(location.clone(), |env| {
-            location.fetch(env, span.clone())
+        let frozen = import.hash.is_some();
+        let res = env.with_cycle_detection(location.clone(), frozen, |env| {
+            let stored = &cx[import_id];
+            let headers = match &stored.headers {
+                Some(headers) => eval_headers(cx, headers)?,
+                // Relative imports of a remote file come from the same origin, so the standard
+                // has them sent with the same headers as that file.
+                None if cx.header_forwarding()
+                    == HeaderForwarding::SameOrigin
+                    && matches!(import.location, ImportTarget::Local(..)) =>
+                {
+                    stored.base_location.headers.0.clone()
+                }
+                None => Vec::new(),
+            };
+            let location = ImportLocation {
+                headers: Headers(headers),
+                ..location.clone()
+            };
+            location.fetch(env, span.clone(), cors_origin.as_deref())
         });
         let typed = match res {
             Ok(typed) => typed,
+            Err(_) if cx.is_cancelled() => {
+                return Err(ErrorKind::Cancelled.into())
+            }
+            // Keep these structured, so that callers can react to them.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::Resolve(ImportError::MissingEnvVar { .. })
+                        | ErrorKind::Resolve(
+                            ImportError::UnfrozenRemoteImport { .. }
+                        )
+                ) =>
+            {
+                return Err(e)
+            }
             Err(e) => mkerr(
-                ErrorBuilder::new("error")
-                    .span_err(span.clone(), e.to_string())
-                    .format(),
+                ErrorBuilder::new("ImportFailed")
+                    .span_err(span.clone(), Message::from_error(e))
+                    .build(),
             )?,
         };
 
@@ -426,6 +743,54 @@ fn fetch_import<'cx>(
     Ok(res_id)
 }
 
+/// Evaluates the `using` expression of a remote import to a list of HTTP headers. It must have
+/// type `List { mapKey : Text, mapValue : Text }`, or the older `List { header : Text, value : Text
+/// }`.
+fn eval_headers<'cx>(
+    cx: Ctxt<'cx>,
+    headers: &Hir<'cx>,
+) -> Result<Vec<(String, String)>, Error> {
+    let typed = Resolved(headers.clone()).typecheck(cx)?;
+    let ty = typed.get_type()?.to_expr(cx);
+    let (key, value) = match &["mapKey", "header"]
+        .iter()
+        .zip(&["mapValue", "value"])
+        .find(|(key, value)| {
+            let expected =
+                format!("List {{ {} : Text, {} : Text }}", key, value);
+            syntax::parse_expr(&expected).unwrap() == ty
+        }) {
+        Some((key, value)) => (**key, **value),
+        None => mk_span_err(
+            headers.span(),
+            Message::new("InvalidHeaders").with("found", ty),
+        )?,
+    };
+
+    let list = typed.normalize(cx)?;
+    let entries = match cx.evaluate(|| list.to_expr(cx))?.kind() {
+        ExprKind::NEListLit(entries) => entries.clone(),
+        _ => Vec::new(),
+    };
+    let text = |e: &Expr| match e.kind() {
+        ExprKind::TextLit(t) if t.tail().is_empty() => {
+            Some(t.head().to_owned())
+        }
+        _ => None,
+    };
+    let mut result = Vec::new();
+    for entry in &entries {
+        if let ExprKind::RecordLit(kvs) = entry.kind() {
+            let k = kvs.get(&Label::from(key)).and_then(text);
+            let v = kvs.get(&Label::from(value)).and_then(text);
+            if let (Some(k), Some(v)) = (k, v) {
+                result.push((k, v));
+            }
+        }
+    }
+    Ok(result)
+}
+
 /// Part of a tree of imports.
 #[derive(Debug, Clone, Copy)]
 pub enum ImportNode<'cx> {
@@ -441,6 +806,18 @@ fn traverse_accumulate<'cx>(
     nodes: &mut Vec<ImportNode<'cx>>,
     base_location: &ImportLocation,
     expr: &Expr,
+) -> Hir<'cx> {
+    ensure_sufficient_stack(|| {
+        traverse_accumulate_inner(env, name_env, nodes, base_location, expr)
+    })
+}
+
+fn traverse_accumulate_inner<'cx>(
+    env: &mut ImportEnv<'cx>,
+    name_env: &mut NameEnv,
+    nodes: &mut Vec<ImportNode<'cx>>,
+    base_location: &ImportLocation,
+    expr: &Expr,
 ) -> Hir<'cx> {
     let cx = env.cx();
     let expr = desugar(expr);
@@ -471,33 +848,43 @@ fn traverse_accumulate<'cx>(
             nodes.push(ImportNode::Alternative(alt));
             HirKind::ImportAlternative(alt, l, r)
         }
-        kind => {
-            let kind = kind.map_ref_maybe_binder(|l, e| {
-                if let Some(l) = l {
-                    name_env.insert_mut(l);
-                }
-                let hir =
-                    traverse_accumulate(env, name_env, nodes, base_location, e);
-                if l.is_some() {
-                    name_env.remove_mut();
-                }
-                hir
-            });
-            match kind {
-                ExprKind::Import(import) => {
-                    // TODO: evaluate import headers
-                    let import = import.map_ref(|_| ());
-                    let import_id = cx.push_import(
-                        base_location.clone(),
-                        import,
-                        expr.span(),
-                    );
-                    nodes.push(ImportNode::Import(import_id));
-                    HirKind::Import(import_id)
-                }
-                kind => HirKind::Expr(kind),
-            }
+        ExprKind::Import(import) => {
+            // Headers can't refer to bound variables, so they are resolved in an empty
+            // environment. Their imports come before the import itself.
+            let headers = match &import.location {
+                ImportTarget::Remote(URL {
+                    headers: Some(headers),
+                    ..
+                }) => Some(traverse_accumulate(
+                    env,
+                    &mut NameEnv::new(),
+                    nodes,
+                    base_location,
+                    headers,
+                )),
+                _ => None,
+            };
+            let import = import.map_ref(|_| ());
+            let import_id = cx.push_import(
+                base_location.clone(),
+                import,
+                headers,
+                expr.span(),
+            );
+            nodes.push(ImportNode::Import(import_id));
+            HirKind::Import(import_id)
         }
+        kind => HirKind::Expr(kind.map_ref_maybe_binder(|l, e| {
+            if let Some(l) = l {
+                name_env.insert_mut(l);
+            }
+            let hir =
+                traverse_accumulate(env, name_env, nodes, base_location, e);
+            if l.is_some() {
+                name_env.remove_mut();
+            }
+            hir
+        })),
     };
     Hir::new(kind, expr.span())
 }
@@ -508,6 +895,9 @@ fn resolve_nodes<'cx>(
     nodes: &[ImportNode<'cx>],
 ) -> Result<(), Error> {
     for &node in nodes {
+        if env.cx().is_cancelled() {
+            return Err(ErrorKind::Cancelled.into());
+        }
         match node {
             ImportNode::Import(import) => {
                 let res_id = fetch_import(env, import)?;
@@ -555,6 +945,16 @@ pub fn resolve<'cx>(
     parsed.resolve_with_env(&mut ImportEnv::new(cx))
 }
 
+/// Like `resolve`, but stores hashed imports in `cache` instead of the default on-disk cache. `None`
+/// disables caching.
+pub fn resolve_with_cache<'cx>(
+    cx: Ctxt<'cx>,
+    parsed: Parsed,
+    cache: Option<Rc<dyn Cache>>,
+) -> Result<Resolved<'cx>, Error> {
+    parsed.resolve_with_env(&mut ImportEnv::with_cache(cx, cache))
+}
+
 /// Resolves names, and errors if we find any imports.
 pub fn skip_resolve<'cx>(
     cx: Ctxt<'cx>,
@@ -617,14 +1017,57 @@ impl Canonicalize for FilePath {
     }
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-pub(crate) fn resolve_home(path: impl AsRef<Path>) -> Result<PathBuf, Error> {
+/// Splits a local path into its root, e.g. `.`, `~`, `/`, `C:\` or `\\server\share\`, and the
+/// components that follow, which are handled like the components of a Dhall path. This way `..`
+/// never eats into the root, and the root keeps the syntax of the platform.
+fn split_local_path(path: &Path) -> (PathBuf, Vec<String>) {
+    let mut root = PathBuf::new();
+    let mut components = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => root.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => components.push("..".to_string()),
+            Component::Normal(c) => {
+                components.push(c.to_string_lossy().into_owned())
+            }
+        }
+    }
+    if root.as_os_str().is_empty() {
+        if components.first().map(String::as_str) == Some("~") {
+            components.remove(0);
+            root.push("~");
+        } else {
+            root.push(".");
+        }
+    }
+    (root, components)
+}
+
+/// Prints a local path with forward slashes, like a Dhall import, whatever the platform.
+fn local_path_to_dhall(path: &Path) -> String {
+    let (root, components) = split_local_path(path);
+    let mut root = root.to_string_lossy().into_owned();
+    if cfg!(windows) {
+        root = root.replace('\\', "/");
+    }
+    if !root.ends_with('/') {
+        root.push('/');
+    }
+    root + &components.join("/")
+}
+
+/// Replaces a leading `~` in `path` with `home`.
+pub(crate) fn resolve_home(
+    path: impl AsRef<Path>,
+    home: Option<PathBuf>,
+) -> Result<PathBuf, Error> {
     let mut f = PathBuf::new();
 
     match path.as_ref().strip_prefix("~") {
         Ok(rest) => {
-            let home = home::home_dir()
-                .ok_or_else(|| Error::from(ImportError::MissingHome))?;
+            let home =
+                home.ok_or_else(|| Error::from(ImportError::MissingHome))?;
             f.push(home);
             f.push(rest);
         }
@@ -636,9 +1079,14 @@ pub(crate) fn resolve_home(path: impl AsRef<Path>) -> Result<PathBuf, Error> {
     Ok(f)
 }
 
+/// The home directory of the current user.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn default_home_dir() -> Option<PathBuf> {
+    home::home_dir()
+}
 #[cfg(target_arch = "wasm32")]
-pub(crate) fn resolve_home(_path: impl AsRef<Path>) -> Result<PathBuf, Error> {
-    panic!("Imports relative to home are not supported on wasm yet");
+pub(crate) fn default_home_dir() -> Option<PathBuf> {
+    None
 }
 
 impl<SE: Copy> Canonicalize for ImportTarget<SE> {
@@ -659,3 +1107,41 @@ impl<SE: Copy> Canonicalize for ImportTarget<SE> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chain(from: &str, import: &str) -> String {
+        let import = match syntax::parse_expr(import).unwrap().kind() {
+            ExprKind::Import(import) => import.map_ref(|_| ()),
+            _ => unreachable!(),
+        };
+        let location = ImportLocation::local_dhall_code(PathBuf::from(from))
+            .chain(&import)
+            .unwrap();
+        local_path_to_dhall(location.as_local_path().unwrap())
+    }
+
+    #[test]
+    fn chain_local_paths() {
+        assert_eq!(chain("./a/b.dhall", "./c.dhall"), "./a/c.dhall");
+        assert_eq!(chain("a/b.dhall", "../../c.dhall"), "./../c.dhall");
+        assert_eq!(chain("/a/b.dhall", "../../c.dhall"), "/../c.dhall");
+        assert_eq!(chain("~/a/b.dhall", "../c.dhall"), "~/c.dhall");
+        assert_eq!(chain("./a/b.dhall", "~/c.dhall"), "~/c.dhall");
+        assert_eq!(chain("~/a/b.dhall", "/c/d.dhall"), "/c/d.dhall");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn chain_windows_paths() {
+        assert_eq!(chain(r"C:\a\b.dhall", "./c/d.dhall"), "C:/a/c/d.dhall");
+        assert_eq!(chain(r"C:\a\b.dhall", "../../c.dhall"), "C:/../c.dhall");
+        assert_eq!(
+            chain(r"\\server\share\a\b.dhall", "../c.dhall"),
+            "//server/share/c.dhall"
+        );
+        assert_eq!(chain(r".\a\b.dhall", "./c.dhall"), "./a/c.dhall");
+    }
+}
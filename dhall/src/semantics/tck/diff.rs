@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::error::{ErrorBuilder, Message};
+use crate::semantics::{Nir, NirKind, TyEnv};
+use crate::syntax::Label;
+
+/// If `expected` and `found` are both record types or both union types, adds a note to `err` for
+/// each field that is missing, unexpected, or of the wrong type, and returns `true`. Otherwise
+/// does nothing and returns `false`, and the types should be printed in full.
+///
+/// Nested records and unions are compared field by field too, so that a mismatch deep inside a
+/// large schema is reported as e.g. ``field `server.port`: expected `Natural`, found `Text` ``.
+pub(crate) fn note_type_diff<'cx>(
+    err: &mut ErrorBuilder,
+    env: &TyEnv<'cx>,
+    expected: &Nir<'cx>,
+    found: &Nir<'cx>,
+) -> bool {
+    if entries(expected).is_none() || entries(found).is_none() {
+        return false;
+    }
+    let mut notes = Vec::new();
+    diff(env, "", expected, found, &mut notes);
+    for note in notes {
+        err.note(note);
+    }
+    true
+}
+
+/// The entries of a record type, or the alternatives of a union type, together with what they
+/// are called in messages.
+fn entries<'a, 'cx>(
+    ty: &'a Nir<'cx>,
+) -> Option<(BTreeMap<&'a Label, Option<&'a Nir<'cx>>>, &'static str)> {
+    match ty.kind() {
+        NirKind::RecordType(kts) => {
+            Some((kts.iter().map(|(k, t)| (k, Some(t))).collect(), "field"))
+        }
+        NirKind::UnionType(kts) => Some((
+            kts.iter().map(|(k, t)| (k, t.as_ref())).collect(),
+            "alternative",
+        )),
+        _ => None,
+    }
+}
+
+fn diff<'cx>(
+    env: &TyEnv<'cx>,
+    prefix: &str,
+    expected: &Nir<'cx>,
+    found: &Nir<'cx>,
+    notes: &mut Vec<Message>,
+) {
+    let show = |ty: Option<&Nir<'cx>>| match ty {
+        Some(ty) => Message::new("Code").with("code", ty.to_expr_tyenv(env)),
+        None => Message::new("NoPayload"),
+    };
+    let show_entry = |name: &str, ty: Option<&Nir<'cx>>| match ty {
+        Some(ty) => format!("{} : {}", name, ty.to_expr_tyenv(env)),
+        None => name.to_string(),
+    };
+
+    let (expected, kind) = entries(expected).unwrap();
+    let (found, found_kind) = entries(found).unwrap();
+    if kind != found_kind {
+        let id = if kind == "field" {
+            "ExpectedRecordType"
+        } else {
+            "ExpectedUnionType"
+        };
+        notes.push(Message::new(id).with("prefix", prefix));
+        return;
+    }
+    let is_field = kind == "field";
+    let names: BTreeSet<&Label> =
+        expected.keys().chain(found.keys()).copied().collect();
+    for name in names {
+        let path = format!("{}{}", prefix, name);
+        match (expected.get(name), found.get(name)) {
+            (Some(e), None) => {
+                let id = if is_field {
+                    "MissingField"
+                } else {
+                    "MissingAlternative"
+                };
+                notes
+                    .push(Message::new(id).with("entry", show_entry(&path, *e)))
+            }
+            (None, Some(f)) => {
+                let id = if is_field {
+                    "UnexpectedField"
+                } else {
+                    "UnexpectedAlternative"
+                };
+                notes
+                    .push(Message::new(id).with("entry", show_entry(&path, *f)))
+            }
+            (Some(e), Some(f)) if e != f => match (e, f) {
+                (Some(e), Some(f))
+                    if entries(e).is_some() && entries(f).is_some() =>
+                {
+                    let prefix = format!("{}.", path);
+                    diff(env, &prefix, e, f, notes)
+                }
+                _ => {
+                    let id = if is_field {
+                        "FieldTypeMismatch"
+                    } else {
+                        "AlternativeTypeMismatch"
+                    };
+                    notes.push(
+                        Message::new(id)
+                            .with("path", &path)
+                            .with_message("expected", show(*e))
+                            .with_message("found", show(*f)),
+                    )
+                }
+            },
+            _ => {}
+        }
+    }
+}
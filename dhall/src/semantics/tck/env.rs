@@ -1,3 +1,4 @@
+use crate::semantics::nze::vector::Vector;
 use crate::semantics::{AlphaVar, NameEnv, Nir, NzEnv, NzVar, Type, ValEnv};
 use crate::syntax::Label;
 use crate::Ctxt;
@@ -6,14 +7,25 @@ use crate::Ctxt;
 #[derive(Debug, Clone, Copy, Default)]
 pub struct VarEnv {
     size: usize,
+    /// Whether variables bound outside of this environment are allowed, see `lenient`.
+    lenient: bool,
 }
 
-/// Environment for typing expressions.
+/// The size of a lenient environment when it's created. Variables from real environments are
+/// below it.
+const LENIENT_BASE: usize = usize::MAX / 2;
+
+/// Environment for typing expressions. It is persistent like `ValEnv`, so that typing each binder
+/// of a long chain doesn't copy the whole environment.
 #[derive(Debug, Clone)]
 pub struct TyEnv<'cx> {
     cx: Ctxt<'cx>,
-    names: NameEnv,
+    /// The names of the variables in scope, outermost first.
+    names: Vector<Label>,
     items: ValEnv<'cx, Type<'cx>>,
+    /// The same items without their types, kept alongside so that evaluating in this environment
+    /// doesn't need to rebuild it.
+    values: NzEnv<'cx>,
 }
 
 impl VarEnv {
@@ -21,7 +33,18 @@ impl VarEnv {
         VarEnv::default()
     }
     pub fn from_size(size: usize) -> Self {
-        VarEnv { size }
+        VarEnv {
+            size,
+            lenient: false,
+        }
+    }
+    /// An empty environment to show values out of their context: variables bound outside of it
+    /// become `MissingVar`s instead of causing a panic.
+    pub fn lenient() -> Self {
+        VarEnv {
+            size: LENIENT_BASE,
+            lenient: true,
+        }
     }
     pub fn size(self) -> usize {
         self.size
@@ -29,8 +52,17 @@ impl VarEnv {
     pub fn insert(self) -> Self {
         VarEnv {
             size: self.size + 1,
+            ..self
         }
     }
+    /// Whether this environment is lenient and `var` is bound outside of it.
+    pub fn is_outside(self, var: NzVar) -> bool {
+        self.lenient
+            && match var {
+                NzVar::Bound(i) => i < LENIENT_BASE,
+                NzVar::Fresh(_) => true,
+            }
+    }
     pub fn lookup(self, var: NzVar) -> AlphaVar {
         self.lookup_fallible(var).unwrap()
     }
@@ -44,35 +76,50 @@ impl<'cx> TyEnv<'cx> {
     pub fn new(cx: Ctxt<'cx>) -> Self {
         TyEnv {
             cx,
-            names: NameEnv::new(),
+            names: Vector::new(),
             items: ValEnv::new(cx),
+            values: NzEnv::new(cx),
         }
     }
     pub fn cx(&self) -> Ctxt<'cx> {
         self.cx
     }
     pub fn as_varenv(&self) -> VarEnv {
-        self.names.as_varenv()
+        VarEnv::from_size(self.names.len())
     }
     pub fn to_nzenv(&self) -> NzEnv<'cx> {
-        self.items.discard_types()
+        self.values.clone()
     }
-    pub fn as_nameenv(&self) -> &NameEnv {
-        &self.names
+    /// The names of the variables in scope, innermost first. Shadowed names are repeated.
+    pub fn names(&self) -> impl Iterator<Item = &Label> + '_ {
+        self.names.iter().rev()
+    }
+    pub fn to_nameenv(&self) -> NameEnv {
+        let mut env = NameEnv::new();
+        for x in self.names.iter() {
+            env.insert_mut(x);
+        }
+        env
     }
 
     pub fn insert_type(&self, x: &Label, ty: Type<'cx>) -> Self {
+        let mut names = self.names.clone();
+        names.push(x.clone());
         TyEnv {
             cx: self.cx,
-            names: self.names.insert(x),
+            names,
             items: self.items.insert_type(ty),
+            values: self.values.insert_type(()),
         }
     }
     pub fn insert_value(&self, x: &Label, e: Nir<'cx>, ty: Type<'cx>) -> Self {
+        let mut names = self.names.clone();
+        names.push(x.clone());
         TyEnv {
             cx: self.cx,
-            names: self.names.insert(x),
-            items: self.items.insert_value(e, ty),
+            names,
+            items: self.items.insert_value(e.clone(), ty),
+            values: self.values.insert_value(e, ()),
         }
     }
     pub fn lookup(&self, var: AlphaVar) -> Type<'cx> {
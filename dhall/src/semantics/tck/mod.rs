@@ -1,6 +1,8 @@
+mod diff;
 pub mod env;
 pub mod tir;
 pub mod typecheck;
+pub(crate) use diff::note_type_diff;
 pub use env::*;
 pub use tir::*;
 pub use typecheck::*;
@@ -1,5 +1,5 @@
 use crate::builtins::Builtin;
-use crate::error::{ErrorBuilder, TypeError};
+use crate::error::{ErrorBuilder, Message, TypeError};
 use crate::semantics::{mkerr, Hir, Nir, NirKind, NzEnv, TyEnv, VarEnv};
 use crate::syntax::{Const, Expr, Span};
 use crate::Ctxt;
@@ -55,14 +55,8 @@ impl<'cx> Type<'cx> {
         env: &TyEnv<'cx>,
         val: Nir<'cx>,
     ) -> Result<Self, TypeError> {
-        let c = val.to_hir(env.as_varenv()).typecheck(env)?.ty().as_const();
-        let u = match c {
-            Some(c) => c.to_universe(),
-            None => unreachable!(
-                "internal type error: this is not a type: {:?}",
-                val
-            ),
-        };
+        let ty = val.to_hir(env.as_varenv()).typecheck(env)?.ty().clone();
+        let u = ty.expect_const(env)?.to_universe();
         Ok(Type::new(val, u))
     }
     pub fn from_const(c: Const) -> Self {
@@ -95,6 +89,18 @@ impl<'cx> Type<'cx> {
     pub fn as_const(&self) -> Option<Const> {
         self.val.as_const()
     }
+    /// Get the constant this type is known to be, e.g. because it is the type of a type. It may
+    /// only be missing if the typechecking that computed it was interrupted.
+    pub fn expect_const(&self, env: &TyEnv<'cx>) -> Result<Const, TypeError> {
+        match (self.as_const(), env.cx().interruption()) {
+            (Some(c), _) => Ok(c),
+            (None, Some(x)) => Err(TypeError::new(x.into())),
+            (None, None) => unreachable!(
+                "internal type error: this is not a type: {:?}",
+                self.val
+            ),
+        }
+    }
     pub fn kind(&self) -> &NirKind<'cx> {
         self.val.kind()
     }
@@ -139,22 +145,17 @@ impl<'cx, 'hir> Tir<'cx, 'hir> {
     pub fn ensure_is_type(&self, env: &TyEnv<'cx>) -> Result<(), TypeError> {
         if self.ty().as_const().is_none() {
             return mkerr(
-                ErrorBuilder::new(format!(
-                    "Expected a type, found: `{}`",
-                    self.to_expr_tyenv(env),
-                ))
+                ErrorBuilder::new(
+                    Message::new("ExpectedType")
+                        .with("found", self.to_expr_tyenv(env)),
+                )
                 .span_err(
                     self.span(),
-                    format!(
-                        "this has type: `{}`",
-                        self.ty().to_expr_tyenv(env)
-                    ),
+                    Message::new("ThisHasType")
+                        .with("ty", self.ty().to_expr_tyenv(env)),
                 )
-                .help(format!(
-                    "An expression in type position must have type `Type`, \
-                     `Kind` or `Sort`",
-                ))
-                .format(),
+                .help("TypePositionHelp")
+                .build(),
             );
         }
         Ok(())
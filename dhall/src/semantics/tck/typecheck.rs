@@ -1,10 +1,16 @@
 use std::cmp::max;
+use std::rc::Rc;
 
 use crate::builtins::{type_of_builtin, Builtin};
-use crate::error::{ErrorBuilder, TypeError, TypeMessage};
+use crate::error::{ErrorBuilder, Message, TypeError, TypeMessage};
 use crate::operations::typecheck_operation;
-use crate::semantics::{Hir, HirKind, Nir, NirKind, Tir, TyEnv, Type};
-use crate::syntax::{Const, ExprKind, InterpolatedTextContents, NumKind, Span};
+use crate::semantics::{
+    note_type_diff, Hir, HirKind, Nir, NirKind, Tir, TyEnv, Type,
+};
+use crate::syntax::{
+    Const, ExprKind, InterpolatedTextContents, NumKind, Span, V,
+};
+use crate::utils::closest_name;
 use crate::Ctxt;
 
 fn function_check(a: Const, b: Const) -> Const {
@@ -15,16 +21,16 @@ fn function_check(a: Const, b: Const) -> Const {
     }
 }
 
-pub fn mkerr<T, S: ToString>(msg: S) -> Result<T, TypeError> {
-    Err(TypeError::new(TypeMessage::Custom(msg.to_string())))
+pub fn mkerr<T, S: Into<TypeMessage>>(msg: S) -> Result<T, TypeError> {
+    Err(TypeError::new(msg.into()))
 }
 
-pub fn mk_span_err<T, S: ToString>(span: Span, msg: S) -> Result<T, TypeError> {
-    mkerr(
-        ErrorBuilder::new(msg.to_string())
-            .span_err(span, msg.to_string())
-            .format(),
-    )
+pub fn mk_span_err<T, S: Into<Message>>(
+    span: Span,
+    msg: S,
+) -> Result<T, TypeError> {
+    let msg = msg.into();
+    mkerr(ErrorBuilder::new(msg.clone()).span_err(span, msg).build())
 }
 
 /// When all sub-expressions have been typed, check the remaining toplevel
@@ -35,7 +41,7 @@ fn type_one_layer<'cx>(
     span: Span,
 ) -> Result<Type<'cx>, TypeError> {
     let cx = env.cx();
-    let span_err = |msg: &str| mk_span_err(span.clone(), msg);
+    let span_err = |msg: &'static str| mk_span_err(span.clone(), msg);
 
     Ok(match ekind {
         ExprKind::Import(..) => {
@@ -61,10 +67,19 @@ fn type_one_layer<'cx>(
                 NumKind::Double(_) => Builtin::Double,
             },
         ),
-        ExprKind::Builtin(b) => {
-            let t_hir = type_of_builtin(cx, b);
-            typecheck(cx, &t_hir)?.eval_to_type(env)?
-        }
+        ExprKind::Builtin(b) => match cx.cached_builtin_type(b) {
+            Some(t) => t,
+            None => {
+                // The type is closed, so it can be evaluated in any environment. It is cached, so
+                // it must be computed completely.
+                let t_hir = type_of_builtin(cx, b);
+                let t = cx.uninterruptible(|| {
+                    typecheck(cx, &t_hir)?.eval_to_type(&TyEnv::new(cx))
+                })?;
+                cx.cache_builtin_type(b, t.clone());
+                t
+            }
+        },
         ExprKind::TextLit(interpolated) => {
             let text_type = Type::from_builtin(cx, Builtin::Text);
             for contents in interpolated.iter() {
@@ -182,110 +197,230 @@ pub fn type_with<'cx, 'hir>(
     hir: &'hir Hir<'cx>,
     annot: Option<Type<'cx>>,
 ) -> Result<Tir<'cx, 'hir>, TypeError> {
-    let tir = match hir.kind() {
-        HirKind::Var(var) => Tir::from_hir(hir, env.lookup(*var)),
-        HirKind::MissingVar(var) => mkerr(
-            ErrorBuilder::new(format!("unbound variable `{}`", var))
-                .span_err(hir.span(), "not found in this scope")
-                .format(),
-        )?,
-        HirKind::Import(import) => {
-            let typed = env.cx()[import].unwrap_result();
-            Tir::from_hir(hir, typed.ty.clone())
-        }
-        HirKind::ImportAlternative(alt, left, right) => {
-            let hir = if env.cx()[alt].unwrap_selected() {
-                left
-            } else {
-                right
-            };
-            return type_with(env, hir, annot);
-        }
-        HirKind::Expr(ExprKind::Var(_)) => {
-            unreachable!("Hir should contain no unresolved variables")
-        }
-        HirKind::Expr(ExprKind::Const(Const::Sort)) => {
-            return mk_span_err(hir.span(), "Sort does not have a type")
-        }
-        HirKind::Expr(ExprKind::Annot(x, t)) => {
-            let t = match t.kind() {
-                HirKind::Expr(ExprKind::Const(Const::Sort)) => {
-                    Type::from_const(Const::Sort)
-                }
-                _ => type_with(env, t, None)?.eval_to_type(env)?,
-            };
-            type_with(env, x, Some(t))?
-        }
-
-        HirKind::Expr(ExprKind::Lam(binder, annot, body)) => {
-            let annot = type_with(env, annot, None)?;
-            let annot_nf = annot.eval_to_type(env)?;
-            let body_env = env.insert_type(binder, annot_nf);
-            let body = type_with(&body_env, body, None)?;
-
-            let u_annot = annot.ty().as_const().unwrap();
-            let u_body = match body.ty().ty().as_const() {
-                Some(k) => k,
-                _ => return mk_span_err(hir.span(), "Invalid output type"),
-            };
-            let u = function_check(u_annot, u_body).to_universe();
-            let ty_hir = Hir::new(
-                HirKind::Expr(ExprKind::Pi(
-                    binder.clone(),
-                    annot.to_hir(),
-                    body.ty().to_hir(body_env.as_varenv()),
-                )),
-                hir.span(),
-            );
-            let ty = Type::new(ty_hir.eval(env), u);
-
-            Tir::from_hir(hir, ty)
-        }
-        HirKind::Expr(ExprKind::Pi(binder, annot, body)) => {
-            let annot = type_with(env, annot, None)?;
-            let annot_val = annot.eval_to_type(env)?;
-            let body_env = env.insert_type(binder, annot_val);
-            let body = type_with(&body_env, body, None)?;
-            body.ensure_is_type(env)?;
-
-            let ks = annot.ty().as_const().unwrap();
-            let kt = body.ty().as_const().unwrap();
-            let ty = Type::from_const(function_check(ks, kt));
-            Tir::from_hir(hir, ty)
-        }
-        HirKind::Expr(ExprKind::Let(binder, annot, val, body)) => {
-            let val_annot = annot
-                .as_ref()
-                .map(|t| type_with(env, t, None)?.eval_to_type(env))
-                .transpose()?;
-            let val = type_with(env, &val, val_annot)?;
-            let val_nf = val.eval(env);
-            let body_env = env.insert_value(&binder, val_nf, val.ty().clone());
-            let body = type_with(&body_env, body, None)?;
-            let ty = body.ty().clone();
-            Tir::from_hir(hir, ty)
-        }
-        HirKind::Expr(ekind) => {
-            let ekind = ekind.traverse_ref(|e| type_with(env, e, None))?;
-            let ty = type_one_layer(env, ekind, hir.span())?;
-            Tir::from_hir(hir, ty)
-        }
-    };
+    /// Subexpressions are typed from this stack instead of by recursion, so that deeply nested
+    /// expressions don't overflow the call stack. Typing an expression pushes its `Tir` on `tirs`.
+    enum Task<'cx, 'hir> {
+        /// Type the expression, and check it against the annotation if there is one.
+        Check(Rc<TyEnv<'cx>>, &'hir Hir<'cx>, Option<Type<'cx>>),
+        /// Check the last typed expression against the annotation.
+        Annotation(Rc<TyEnv<'cx>>, &'hir Hir<'cx>, Type<'cx>),
+        /// Pop the typed subexpressions of the expression, and type it.
+        Layer(Rc<TyEnv<'cx>>, &'hir Hir<'cx>, &'hir ExprKind<Hir<'cx>>),
+        /// Pop the typed annotation `t` of `x : t`, and check `x` against it.
+        Annot(Rc<TyEnv<'cx>>, &'hir Hir<'cx>),
+        /// Type the body of a `λ` or `∀` whose annotation was typed last.
+        Binder(Rc<TyEnv<'cx>>, &'hir Hir<'cx>),
+        /// Pop the typed annotation and body of a `λ` or `∀`, and type it.
+        BinderDone(Rc<TyEnv<'cx>>, Rc<TyEnv<'cx>>, &'hir Hir<'cx>),
+        /// Pop the typed annotation of a `let`, and check its value against it.
+        LetValue(Rc<TyEnv<'cx>>, &'hir Hir<'cx>),
+        /// Pop the typed value of a `let`, and type its body.
+        LetBody(Rc<TyEnv<'cx>>, &'hir Hir<'cx>),
+        /// Pop the typed body of a `let`, and type it.
+        LetDone(&'hir Hir<'cx>),
+    }
 
-    if let Some(annot) = annot {
-        if *tir.ty() != annot {
-            return mk_span_err(
-                hir.span(),
-                &format!(
-                    "annot mismatch: {} != {}",
-                    tir.ty().to_expr_tyenv(env),
-                    annot.to_expr_tyenv(env)
-                ),
-            );
+    let mut tasks = vec![Task::Check(Rc::new(env.clone()), hir, annot)];
+    let mut tirs: Vec<Tir<'cx, 'hir>> = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Check(env, mut hir, annot) => {
+                env.cx()
+                    .check_interrupted()
+                    .map_err(|x| TypeError::new(x.into()))?;
+                while let HirKind::ImportAlternative(alt, left, right) =
+                    hir.kind()
+                {
+                    hir = if env.cx()[alt].unwrap_selected() {
+                        left
+                    } else {
+                        right
+                    };
+                }
+                if let Some(annot) = annot {
+                    tasks.push(Task::Annotation(env.clone(), hir, annot));
+                }
+                let tir = match hir.kind() {
+                    HirKind::Var(var) => Tir::from_hir(hir, env.lookup(*var)),
+                    HirKind::MissingVar(var) => {
+                        let mut err = ErrorBuilder::new(
+                            Message::new("UnboundVariable").with("name", var),
+                        );
+                        err.span_err(hir.span(), "NotInScope");
+                        let V(name, _) = var;
+                        let names = env.names().map(|l| l.as_ref());
+                        if let Some(close) = closest_name(name.as_ref(), names)
+                        {
+                            err.help(
+                                Message::new("DidYouMean").with("name", close),
+                            );
+                        }
+                        mkerr(err.build())?
+                    }
+                    HirKind::Import(import) => {
+                        let typed = env.cx()[import].unwrap_result();
+                        Tir::from_hir(hir, typed.ty.clone())
+                    }
+                    HirKind::ImportAlternative(..) => unreachable!(),
+                    HirKind::Expr(ExprKind::Var(_)) => {
+                        unreachable!(
+                            "Hir should contain no unresolved variables"
+                        )
+                    }
+                    HirKind::Expr(ExprKind::Const(Const::Sort)) => {
+                        return mk_span_err(hir.span(), "SortHasNoType")
+                    }
+                    HirKind::Expr(ExprKind::Annot(x, t)) => {
+                        match t.kind() {
+                            HirKind::Expr(ExprKind::Const(Const::Sort)) => {
+                                let t = Type::from_const(Const::Sort);
+                                tasks.push(Task::Check(env, x, Some(t)));
+                            }
+                            _ => {
+                                tasks.push(Task::Annot(env.clone(), x));
+                                tasks.push(Task::Check(env, t, None));
+                            }
+                        }
+                        continue;
+                    }
+                    HirKind::Expr(ExprKind::Lam(_, annot, _))
+                    | HirKind::Expr(ExprKind::Pi(_, annot, _)) => {
+                        tasks.push(Task::Binder(env.clone(), hir));
+                        tasks.push(Task::Check(env, annot, None));
+                        continue;
+                    }
+                    HirKind::Expr(ExprKind::Let(_, annot, val, _)) => {
+                        tasks.push(Task::LetBody(env.clone(), hir));
+                        match annot {
+                            Some(annot) => {
+                                tasks.push(Task::LetValue(env.clone(), val));
+                                tasks.push(Task::Check(env, annot, None));
+                            }
+                            None => tasks.push(Task::Check(env, val, None)),
+                        }
+                        continue;
+                    }
+                    HirKind::Expr(ekind) => {
+                        tasks.push(Task::Layer(env.clone(), hir, ekind));
+                        let mut subexprs = Vec::new();
+                        ekind.map_ref(|e| subexprs.push(e));
+                        // Pushed last first, so that they are typed in order.
+                        for e in subexprs.into_iter().rev() {
+                            tasks.push(Task::Check(env.clone(), e, None));
+                        }
+                        continue;
+                    }
+                };
+                tirs.push(tir);
+            }
+            Task::Annotation(env, hir, annot) => {
+                let tir = tirs.last().unwrap();
+                if *tir.ty() != annot {
+                    let mut err = ErrorBuilder::new("AnnotMismatch");
+                    if note_type_diff(
+                        &mut err,
+                        &env,
+                        annot.as_nir(),
+                        tir.ty().as_nir(),
+                    ) {
+                        err.span_err(hir.span(), "AnnotMismatchLabel");
+                        return mkerr(err.build());
+                    }
+                    return mk_span_err(
+                        hir.span(),
+                        Message::new("AnnotMismatchTypes")
+                            .with("found", tir.ty().to_expr_tyenv(&env))
+                            .with("expected", annot.to_expr_tyenv(&env)),
+                    );
+                }
+            }
+            Task::Layer(env, hir, ekind) => {
+                let mut subexprs = Vec::new();
+                ekind.map_ref(|e| subexprs.push(e));
+                let mut sub_tirs =
+                    tirs.split_off(tirs.len() - subexprs.len()).into_iter();
+                let ekind = ekind.map_ref(|_| sub_tirs.next().unwrap());
+                let ty = type_one_layer(&env, ekind, hir.span())?;
+                tirs.push(Tir::from_hir(hir, ty));
+            }
+            Task::Annot(env, x) => {
+                let t = tirs.pop().unwrap().eval_to_type(&env)?;
+                tasks.push(Task::Check(env, x, Some(t)));
+            }
+            Task::Binder(env, hir) => {
+                let (binder, body) = match hir.kind() {
+                    HirKind::Expr(ExprKind::Lam(binder, _, body))
+                    | HirKind::Expr(ExprKind::Pi(binder, _, body)) => {
+                        (binder, body)
+                    }
+                    _ => unreachable!(),
+                };
+                let annot_nf = tirs.last().unwrap().eval_to_type(&env)?;
+                let body_env = Rc::new(env.insert_type(binder, annot_nf));
+                tasks.push(Task::BinderDone(env, body_env.clone(), hir));
+                tasks.push(Task::Check(body_env, body, None));
+            }
+            Task::BinderDone(env, body_env, hir) => {
+                let body = tirs.pop().unwrap();
+                let annot = tirs.pop().unwrap();
+                let ty = match hir.kind() {
+                    HirKind::Expr(ExprKind::Lam(binder, ..)) => {
+                        let u_annot = annot.ty().expect_const(&env)?;
+                        let u_body = match body.ty().ty().as_const() {
+                            Some(k) => k,
+                            _ => {
+                                return mk_span_err(
+                                    hir.span(),
+                                    "InvalidOutputType",
+                                )
+                            }
+                        };
+                        let u = function_check(u_annot, u_body).to_universe();
+                        let ty_hir = Hir::new(
+                            HirKind::Expr(ExprKind::Pi(
+                                binder.clone(),
+                                annot.to_hir(),
+                                body.ty().to_hir(body_env.as_varenv()),
+                            )),
+                            hir.span(),
+                        );
+                        Type::new(ty_hir.eval(&*env), u)
+                    }
+                    HirKind::Expr(ExprKind::Pi(..)) => {
+                        body.ensure_is_type(&env)?;
+                        let ks = annot.ty().expect_const(&env)?;
+                        let kt = body.ty().expect_const(&env)?;
+                        Type::from_const(function_check(ks, kt))
+                    }
+                    _ => unreachable!(),
+                };
+                tirs.push(Tir::from_hir(hir, ty));
+            }
+            Task::LetValue(env, val) => {
+                let t = tirs.pop().unwrap().eval_to_type(&env)?;
+                tasks.push(Task::Check(env, val, Some(t)));
+            }
+            Task::LetBody(env, hir) => {
+                let (binder, body) = match hir.kind() {
+                    HirKind::Expr(ExprKind::Let(binder, _, _, body)) => {
+                        (binder, body)
+                    }
+                    _ => unreachable!(),
+                };
+                let val = tirs.pop().unwrap();
+                let val_nf = val.eval(&*env);
+                let body_env =
+                    env.insert_value(binder, val_nf, val.ty().clone());
+                tasks.push(Task::LetDone(hir));
+                tasks.push(Task::Check(Rc::new(body_env), body, None));
+            }
+            Task::LetDone(hir) => {
+                let ty = tirs.pop().unwrap().ty().clone();
+                tirs.push(Tir::from_hir(hir, ty));
+            }
         }
     }
-
-    Ok(tir)
+    Ok(tirs.pop().unwrap())
 }
 
 /// Typecheck an expression and return the expression annotated with its type if type-checking
@@ -294,7 +429,7 @@ pub fn typecheck<'cx, 'hir>(
     cx: Ctxt<'cx>,
     hir: &'hir Hir<'cx>,
 ) -> Result<Tir<'cx, 'hir>, TypeError> {
-    type_with(&TyEnv::new(cx), hir, None)
+    interruptible(cx, || type_with(&TyEnv::new(cx), hir, None))
 }
 
 /// Like `typecheck`, but additionally checks that the expression's type matches the provided type.
@@ -304,5 +439,115 @@ pub fn typecheck_with<'cx, 'hir>(
     ty: &Hir<'cx>,
 ) -> Result<Tir<'cx, 'hir>, TypeError> {
     let ty = typecheck(cx, ty)?.eval_to_type(&TyEnv::new(cx))?;
-    type_with(&TyEnv::new(cx), hir, Some(ty))
+    interruptible(cx, || type_with(&TyEnv::new(cx), hir, Some(ty)))
+}
+
+/// Typecheck an expression and add its inferred type as annotations, like `dhall type
+/// --annotate`: the `let`-bindings of the outermost `let` block that have no annotation get one,
+/// and the body of the block (or the whole expression if there is no block) is annotated with its
+/// type.
+pub fn annotate<'cx>(
+    cx: Ctxt<'cx>,
+    hir: &Hir<'cx>,
+) -> Result<Hir<'cx>, TypeError> {
+    interruptible(cx, || annotate_with(&TyEnv::new(cx), hir))
+}
+
+fn annotate_with<'cx>(
+    env: &TyEnv<'cx>,
+    hir: &Hir<'cx>,
+) -> Result<Hir<'cx>, TypeError> {
+    // The bindings of the block with their annotations, outermost first. They are collected in a
+    // loop rather than by recursion, since a block can be arbitrarily long.
+    let mut bindings = Vec::new();
+    let mut env = env.clone();
+    let mut hir = hir;
+    while let HirKind::Expr(ExprKind::Let(binder, annot, val, body)) =
+        hir.kind()
+    {
+        let val_annot = annot
+            .as_ref()
+            .map(|t| type_with(&env, t, None)?.eval_to_type(&env))
+            .transpose()?;
+        let val_tir = type_with(&env, val, val_annot)?;
+        let annot = match annot {
+            Some(annot) => Some(annot.clone()),
+            // `Sort` can't be written as the annotation of a `let`.
+            None if val_tir.ty().as_const() == Some(Const::Sort) => None,
+            None => Some(val_tir.ty().to_hir(env.as_varenv())),
+        };
+        let val_nf = val_tir.eval(&env);
+        env = env.insert_value(binder, val_nf, val_tir.ty().clone());
+        bindings.push((hir.span(), binder, annot, val));
+        hir = body;
+    }
+
+    let mut annotated = match hir.kind() {
+        HirKind::Expr(ExprKind::Annot(..)) => {
+            type_with(&env, hir, None)?;
+            hir.clone()
+        }
+        _ => {
+            let ty = type_with(&env, hir, None)?.ty().to_hir(env.as_varenv());
+            Hir::new(
+                HirKind::Expr(ExprKind::Annot(hir.clone(), ty)),
+                hir.span(),
+            )
+        }
+    };
+    for (span, binder, annot, val) in bindings.into_iter().rev() {
+        let kind = ExprKind::Let(binder.clone(), annot, val.clone(), annotated);
+        annotated = Hir::new(HirKind::Expr(kind), span);
+    }
+    Ok(annotated)
+}
+
+/// Reports a cancellation of the context, or an exceeded memory limit, instead of the result of
+/// `f`.
+fn interruptible<'cx, T>(
+    cx: Ctxt<'cx>,
+    f: impl FnOnce() -> Result<T, TypeError>,
+) -> Result<T, TypeError> {
+    cx.interruptible(f)
+        .unwrap_or_else(|x| Err(TypeError::new(TypeMessage::from(x))))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::Error;
+    use crate::Parsed;
+
+    #[test]
+    fn annotate_with_inferred_types() {
+        let annotate = |s: &str| {
+            Ctxt::with_new(|cx| {
+                let resolved =
+                    Parsed::parse_str(s).unwrap().skip_resolve(cx)?;
+                Ok::<_, Error>(resolved.annotate(cx)?.to_string())
+            })
+        };
+        assert_eq!(annotate("1 + 2").unwrap(), "1 + 2 : Natural");
+        assert_eq!(
+            annotate(
+                "let T = Natural let x : T = 1 let f = λ(n : T) → [n] in f x"
+            )
+            .unwrap(),
+            "let T : Type = Natural let x : T = 1 \
+             let f : ∀(n : Natural) → List Natural = λ(n : T) → [n] \
+             in f x : List Natural"
+        );
+        // `Sort` can't annotate a binding, and existing annotations are kept.
+        assert_eq!(
+            annotate("let k = Kind in { x = 1 } : { x : Natural }").unwrap(),
+            "let k = Kind in { x = 1 } : { x : Natural }"
+        );
+        // The result parses and typechecks again.
+        let annotated =
+            annotate("let a = [1, 2] in List/length Natural a").unwrap();
+        assert!(Ctxt::with_new(|cx| Parsed::parse_str(&annotated)
+            .and_then(|p| Ok(p.skip_resolve(cx)?.typecheck(cx)?))
+            .is_ok()));
+        assert!(annotate("1 + True").is_err());
+    }
 }
@@ -0,0 +1,87 @@
+//! A content-addressed store of normalized expressions, so that build tools can memoize evaluated
+//! configurations across processes.
+//!
+//! Expressions are keyed by their semantic hash, which is the hash that a `sha256:` integrity
+//! check on an import expects. Any [`Cache`] can be used as the backend. Entries have the same
+//! format as the import cache, so a store over the cache directory also makes its expressions
+//! available to imports protected by their hash.
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::error::{CacheError, Error};
+use crate::semantics::{Cache, FsCache};
+use crate::syntax::{binary, Expr, Hash};
+use crate::utils::sha256_hash;
+use crate::{Ctxt, Parsed};
+
+/// A content-addressed store of normalized expressions.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> Result<(), dhall::error::Error> {
+/// use dhall::store::Store;
+/// use dhall::syntax::parse_expr;
+///
+/// let dir = std::env::temp_dir().join("dhall-store-doctest");
+/// let store = Store::with_dir(&dir)?;
+/// let hash = store.put(&parse_expr("{ port = 8000 + 80 }")?)?;
+/// // Equivalent expressions have the same hash.
+/// assert_eq!(store.put(&parse_expr("{ port = 8080 }")?)?, hash);
+///
+/// let stored = store.get(&hash)?.unwrap();
+/// assert_eq!(stored.to_string(), "{ port = 8080 }");
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Store {
+    backend: Rc<dyn Cache>,
+}
+
+impl Store {
+    /// Uses the given backend.
+    pub fn new(backend: Rc<dyn Cache>) -> Self {
+        Store { backend }
+    }
+
+    /// Stores one file per entry in `dir`, creating it if needed.
+    pub fn with_dir(dir: impl Into<PathBuf>) -> Result<Self, Error> {
+        Ok(Store::new(Rc::new(FsCache::with_dir(dir)?)))
+    }
+
+    /// Typechecks and normalizes `expr`, stores its normal form and returns its semantic hash.
+    ///
+    /// `expr` must not contain imports: resolve them first, e.g. with `Resolved::to_expr`.
+    pub fn put(&self, expr: &Expr) -> Result<Hash, Error> {
+        let normal_form = Ctxt::with_new(|cx| -> Result<Expr, Error> {
+            let typed = Parsed::from_expr_without_imports(expr.clone())
+                .skip_resolve(cx)?
+                .typecheck(cx)?;
+            let value = typed.normalize(cx)?;
+            cx.evaluate(|| value.to_expr_alpha(cx))
+        })?;
+        let data = binary::encode(&normal_form)?;
+        let hash = Hash::SHA256(sha256_hash(&data));
+        self.backend.put(&hash, &data)?;
+        Ok(hash)
+    }
+
+    /// Returns the normal form stored for `hash`, or `None` if there is no such entry. Variables
+    /// are all named `_` in it, like in the expression that was hashed.
+    pub fn get(&self, hash: &Hash) -> Result<Option<Expr>, Error> {
+        let data = match self.backend.get(hash)? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        match hash {
+            Hash::SHA256(hash) => {
+                if hash[..] != sha256_hash(&data)[..] {
+                    return Err(CacheError::CacheHashInvalid.into());
+                }
+            }
+        }
+        Ok(Some(binary::decode(&data)?))
+    }
+}
@@ -1,14 +1,22 @@
 use std::collections::BTreeMap;
 
 use crate::builtins::Builtin;
+#[cfg(feature = "binary")]
 use crate::error::Error;
 use crate::operations::OpKind;
 use crate::semantics::Universe;
 use crate::syntax::visitor;
 use crate::syntax::*;
 
+#[cfg(not(feature = "bigint"))]
 pub type Integer = i64;
+#[cfg(not(feature = "bigint"))]
 pub type Natural = u64;
+/// With the `bigint` feature, numbers are arbitrary-precision like the standard requires.
+#[cfg(feature = "bigint")]
+pub type Integer = num_bigint::BigInt;
+#[cfg(feature = "bigint")]
+pub type Natural = num_bigint::BigUint;
 pub type Double = NaiveDouble;
 
 /// Double with bitwise equality
@@ -38,7 +46,7 @@ impl Const {
 pub struct V(pub Label, pub usize);
 
 // Each node carries an annotation.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Expr {
     kind: Box<ExprKind<Expr>>,
     span: Span,
@@ -178,6 +186,7 @@ impl Expr {
     }
 
     // Compute the sha256 hash of the binary form of the expression.
+    #[cfg(feature = "binary")]
     pub fn sha256_hash(&self) -> Result<Box<[u8]>, Error> {
         let data = binary::encode(self)?;
         Ok(crate::utils::sha256_hash(&data))
@@ -187,6 +196,209 @@ impl Expr {
     pub fn add_let_binding(self, label: Label, value: Expr) -> Expr {
         Expr::new(ExprKind::Let(label, None, value, self), Span::Artificial)
     }
+
+    /// Apply the expression, assumed to be a function, to the given argument
+    pub fn apply_to(self, arg: Expr) -> Expr {
+        Expr::new(ExprKind::Op(OpKind::App(self, arg)), Span::Artificial)
+    }
+
+    /// Select the given field of the expression, assumed to be a record
+    pub fn select_field(self, label: Label) -> Expr {
+        Expr::new(ExprKind::Op(OpKind::Field(self, label)), Span::Artificial)
+    }
+
+    /// Returns the same expression in a canonical form, for stable diffs and content-addressed
+    /// storage: source locations are dropped, `.` and `..` components of import paths are
+    /// resolved as far as possible, and NaNs are canonicalized. Record and union entries are
+    /// always kept sorted, and the syntax tree doesn't record parentheses, so there is nothing to
+    /// do for those.
+    pub fn canonicalize(&self) -> Expr {
+        crate::utils::ensure_sufficient_stack(|| {
+            let kind = match self.kind().map_ref(Expr::canonicalize) {
+                ExprKind::Num(NumKind::Double(x)) => {
+                    ExprKind::Num(NumKind::Double(x.canonicalize()))
+                }
+                ExprKind::Import(mut import) => {
+                    import.location =
+                        canonicalize_import_target(import.location);
+                    ExprKind::Import(import)
+                }
+                kind => kind,
+            };
+            Expr::new(kind, Span::Artificial)
+        })
+    }
+
+    /// Adds `delta` to the index of the variables named like `var` whose index is at least that of
+    /// `var`, as the standard's `↑(delta, var, self)` does. Binders of that name are taken into
+    /// account: under one, the minimal index is one more. Returns `None` if an index would become
+    /// negative.
+    ///
+    /// Normalization doesn't need this; it is for tools that rewrite expressions.
+    pub fn shift(&self, delta: isize, var: &V) -> Option<Expr> {
+        crate::utils::ensure_sufficient_stack(|| {
+            let V(name, min) = var;
+            let kind = match self.kind() {
+                ExprKind::Var(V(x, n)) if x == name && n >= min => {
+                    let n = (*n as isize).checked_add(delta)?;
+                    if n < 0 {
+                        return None;
+                    }
+                    ExprKind::Var(V(x.clone(), n as usize))
+                }
+                kind => kind
+                    .traverse_ref_maybe_binder(|binder, e| {
+                        let min = match binder {
+                            Some(binder) if binder == name => min + 1,
+                            _ => *min,
+                        };
+                        e.shift(delta, &V(name.clone(), min)).ok_or(())
+                    })
+                    .ok()?,
+            };
+            Some(Expr::new(kind, self.span()))
+        })
+    }
+
+    /// Replaces the variable `var` with `val`, and removes it from the scope: the variables of
+    /// the same name with a higher index are decremented. This is the standard's
+    /// `↑(-1, var, self[var := ↑(1, var, val)])`, i.e. what beta-reducing `(λ(x : T) → self) val`
+    /// does to the body, with `var` being `x@0`. `val` is shifted as needed when it goes under
+    /// binders, so its free variables keep referring to the same binders.
+    ///
+    /// Normalization doesn't need this; it is for tools that rewrite expressions.
+    pub fn subst_shift(&self, var: &V, val: &Expr) -> Expr {
+        let mut val = ShiftedValue {
+            val,
+            binders: Vec::new(),
+        };
+        self.subst_shift_under(var, &mut val)
+            .unwrap_or_else(|| self.clone())
+    }
+
+    /// `subst_shift` under the binders of `val`. Returns `None` if `self` contains no variable
+    /// that changes, i.e. named like `var` with an index at least that of `var`.
+    fn subst_shift_under(
+        &self,
+        var: &V,
+        val: &mut ShiftedValue<'_>,
+    ) -> Option<Expr> {
+        crate::utils::ensure_sufficient_stack(|| {
+            let V(name, idx) = var;
+            let kind = match self.kind() {
+                ExprKind::Var(V(x, n)) if x == name && n == idx => {
+                    return Some(val.get())
+                }
+                ExprKind::Var(V(x, n)) if x == name && n > idx => {
+                    ExprKind::Var(V(x.clone(), n - 1))
+                }
+                kind => {
+                    let mut children = Vec::new();
+                    kind.map_ref_maybe_binder(|binder, e| match binder {
+                        Some(binder) => {
+                            let idx =
+                                if binder == name { idx + 1 } else { *idx };
+                            val.binders.push((binder.clone(), None));
+                            let var = V(name.clone(), idx);
+                            children.push(e.subst_shift_under(&var, val));
+                            val.binders.pop();
+                        }
+                        None => children.push(e.subst_shift_under(var, val)),
+                    });
+                    if children.iter().all(Option::is_none) {
+                        return None;
+                    }
+                    let mut children = children.into_iter();
+                    kind.map_ref(|e| {
+                        children.next().unwrap().unwrap_or_else(|| e.clone())
+                    })
+                }
+            };
+            Some(Expr::new(kind, self.span()))
+        })
+    }
+}
+
+/// The value that `subst_shift` puts in place of the variable, shifted under the binders it goes
+/// through. It is only shifted where the variable occurs, once per binder.
+struct ShiftedValue<'a> {
+    val: &'a Expr,
+    /// The binders crossed so far, outermost first, each with the value shifted under it once it
+    /// was needed.
+    binders: Vec<(Label, Option<Expr>)>,
+}
+
+impl ShiftedValue<'_> {
+    /// The value under all the binders crossed so far.
+    fn get(&mut self) -> Expr {
+        let shifted = self
+            .binders
+            .iter()
+            .rposition(|(_, val)| val.is_some())
+            .map_or(0, |i| i + 1);
+        for i in shifted..self.binders.len() {
+            let val = match i {
+                0 => self.val,
+                _ => self.binders[i - 1].1.as_ref().unwrap(),
+            };
+            let binder = V(self.binders[i].0.clone(), 0);
+            let val = val.shift(1, &binder).unwrap();
+            self.binders[i].1 = Some(val);
+        }
+        match self.binders.last() {
+            Some((_, val)) => val.clone().unwrap(),
+            None => self.val.clone(),
+        }
+    }
+}
+
+fn canonicalize_import_target<SE>(
+    target: ImportTarget<SE>,
+) -> ImportTarget<SE> {
+    use crate::semantics::Canonicalize;
+    match target {
+        ImportTarget::Local(prefix, path) => {
+            let mut path = path.canonicalize();
+            // `./../a` is `../a`.
+            if prefix == FilePrefix::Here
+                && path.file_path.first().map(String::as_str) == Some("..")
+            {
+                path.file_path.remove(0);
+                ImportTarget::Local(FilePrefix::Parent, path)
+            } else {
+                ImportTarget::Local(prefix, path)
+            }
+        }
+        ImportTarget::Remote(mut url) => {
+            url.path = url.path.canonicalize();
+            ImportTarget::Remote(url)
+        }
+        target => target,
+    }
+}
+
+/// The length of a list, or an index in it, as a `Natural`.
+pub(crate) fn natural_from_usize(n: usize) -> Natural {
+    #[cfg(feature = "bigint")]
+    return Natural::from(n);
+    #[cfg(not(feature = "bigint"))]
+    return n as Natural;
+}
+
+/// Returns `None` if the natural doesn't fit in an `Integer`.
+pub(crate) fn natural_to_integer(n: &Natural) -> Option<Integer> {
+    #[cfg(feature = "bigint")]
+    return Some(Integer::from(n.clone()));
+    #[cfg(not(feature = "bigint"))]
+    return std::convert::TryFrom::try_from(*n).ok();
+}
+
+/// Returns `None` if the integer is negative.
+pub(crate) fn integer_to_natural(n: &Integer) -> Option<Natural> {
+    #[cfg(feature = "bigint")]
+    return n.to_biguint();
+    #[cfg(not(feature = "bigint"))]
+    return std::convert::TryFrom::try_from(*n).ok();
 }
 
 // Empty enum to indicate that no error can occur
@@ -198,9 +410,24 @@ pub(crate) fn trivial_result<T>(x: Result<T, X>) -> T {
     }
 }
 
+impl NaiveDouble {
+    /// Replaces any NaN, whatever its sign and payload, with the canonical NaN. The standard only
+    /// has one NaN, which is encoded as `0xf97e00` in CBOR. Other values, including `-0.0`, are
+    /// left unchanged.
+    pub fn canonicalize(self) -> Self {
+        if self.0.is_nan() {
+            NaiveDouble(f64::NAN)
+        } else {
+            self
+        }
+    }
+}
+
+/// Compares the bits of the canonical value, so that `-0.0` and `0.0` differ but all NaNs are
+/// equal, like their binary encodings.
 impl PartialEq for NaiveDouble {
     fn eq(&self, other: &Self) -> bool {
-        self.0.to_bits() == other.0.to_bits()
+        self.canonicalize().0.to_bits() == other.canonicalize().0.to_bits()
     }
 }
 
@@ -211,7 +438,7 @@ impl std::hash::Hash for NaiveDouble {
     where
         H: std::hash::Hasher,
     {
-        self.0.to_bits().hash(state)
+        self.canonicalize().0.to_bits().hash(state)
     }
 }
 
@@ -233,6 +460,24 @@ impl From<Label> for V {
     }
 }
 
+impl Clone for Expr {
+    fn clone(&self) -> Self {
+        crate::utils::ensure_sufficient_stack(|| Expr {
+            kind: self.kind.clone(),
+            span: self.span.clone(),
+        })
+    }
+}
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        // Dropping a deeply nested expression recurses as deeply.
+        let kind =
+            std::mem::replace(&mut *self.kind, ExprKind::Const(Const::Type));
+        crate::utils::ensure_sufficient_stack(move || drop(kind));
+    }
+}
+
 impl std::cmp::PartialEq for Expr {
     fn eq(&self, other: &Self) -> bool {
         self.kind == other.kind
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use crate::syntax::trivial_result;
 
 /// The beginning of a file path which anchors subsequent path components
@@ -30,12 +32,21 @@ pub enum ImportTarget<SubExpr> {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct URL<SubExpr> {
     pub scheme: Scheme,
-    pub authority: String,
+    pub authority: Authority,
     pub path: FilePath,
     pub query: Option<String>,
     pub headers: Option<SubExpr>,
 }
 
+/// The authority of a URL, i.e. `user:pass@host:port`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Authority {
+    pub userinfo: Option<String>,
+    /// A domain name, an IPv4 address, or an IP literal in brackets like `[::1]`
+    pub host: String,
+    pub port: Option<String>,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Scheme {
     HTTP,
@@ -63,7 +74,53 @@ pub struct Import<SubExpr> {
     pub hash: Option<Hash>,
 }
 
+impl Authority {
+    /// Splits an authority string into its components. Returns `None` if an IP literal is not
+    /// terminated.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (userinfo, rest) = match s.find('@') {
+            Some(i) => (Some(s[..i].to_owned()), &s[i + 1..]),
+            None => (None, s),
+        };
+        let host_end = if rest.starts_with('[') {
+            rest.find(']')? + 1
+        } else {
+            rest.find(':').unwrap_or(rest.len())
+        };
+        let (host, port) = rest.split_at(host_end);
+        let port = match port.strip_prefix(':') {
+            Some(port) => Some(port.to_owned()),
+            None if port.is_empty() => None,
+            None => return None,
+        };
+        Some(Authority {
+            userinfo,
+            host: host.to_owned(),
+            port,
+        })
+    }
+}
+
 impl<SE> URL<SE> {
+    /// The path segments, percent-decoded.
+    pub fn path_segments(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        self.path.file_path.iter().map(|c| {
+            percent_encoding::percent_decode_str(c).decode_utf8_lossy()
+        })
+    }
+    /// The key-value pairs of the query string, decoded as
+    /// `application/x-www-form-urlencoded`. Empty if there is no query.
+    pub fn query_pairs(
+        &self,
+    ) -> impl Iterator<Item = (Cow<'_, str>, Cow<'_, str>)> {
+        url::form_urlencoded::parse(
+            self.query
+                .as_ref()
+                .map(String::as_bytes)
+                .unwrap_or_default(),
+        )
+    }
+
     pub fn traverse_ref<'a, Err, SE2>(
         &'a self,
         f: impl FnOnce(&'a SE) -> Result<SE2, Err>,
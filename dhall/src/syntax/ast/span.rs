@@ -1,9 +1,14 @@
+use std::ops::Range;
 use std::rc::Rc;
 
+use crate::syntax::Label;
+
 /// A location in the source text
 #[derive(Debug, Clone)]
 pub struct ParsedSpan {
     input: Rc<str>,
+    /// The file or URL the input was read from, if any.
+    origin: Option<Rc<str>>,
     /// # Safety
     ///
     /// Must be a valid character boundary index into `input`.
@@ -19,7 +24,7 @@ pub enum Span {
     /// A location in the source text
     Parsed(ParsedSpan),
     /// Desugarings
-    DuplicateRecordFieldsSugar(Box<Span>, Box<Span>),
+    DuplicateRecordFieldsSugar(Label, Box<Span>, Box<Span>),
     DottedFieldSugar,
     RecordPunSugar,
     /// For expressions obtained from decoding binary
@@ -32,6 +37,23 @@ impl ParsedSpan {
     pub fn to_input(&self) -> String {
         self.input.to_string()
     }
+    /// The file path or URL the input was read from, if it was read from one.
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+    /// The range of bytes of the input this points to.
+    pub fn byte_range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+    /// The line and column, both starting at 1, where the span starts. Columns count characters.
+    pub fn start_line_col(&self) -> (usize, usize) {
+        let before = &self.input[..self.start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
     /// Convert to a char range for consumption by annotate_snippets.
     /// This compensates for  https://github.com/rust-lang/annotate-snippets-rs/issues/24
     pub fn as_char_range(&self) -> (usize, usize) {
@@ -43,14 +65,36 @@ impl ParsedSpan {
 }
 
 impl Span {
-    pub fn make(input: Rc<str>, sp: pest::Span) -> Self {
+    pub fn make(
+        input: Rc<str>,
+        origin: Option<Rc<str>>,
+        sp: pest::Span,
+    ) -> Self {
         Span::Parsed(ParsedSpan {
             input,
+            origin,
             start: sp.start(),
             end: sp.end(),
         })
     }
 
+    /// The range of bytes of the input this points to, if it points to an input location.
+    pub fn byte_range(&self) -> Option<Range<usize>> {
+        match self {
+            Span::Parsed(x) => Some(x.byte_range()),
+            _ => None,
+        }
+    }
+
+    /// The line and column, both starting at 1, where the span starts, if it points to an input
+    /// location. Columns count characters.
+    pub fn start_line_col(&self) -> Option<(usize, usize)> {
+        match self {
+            Span::Parsed(x) => Some(x.start_line_col()),
+            _ => None,
+        }
+    }
+
     /// Takes the union of the two spans, i.e. the range of input covered by the two spans plus any
     /// input between them. Assumes that the spans come from the same input. Fails if one of the
     /// spans does not point to an input location.
@@ -61,6 +105,7 @@ impl Span {
             (Parsed(x), Parsed(y)) if Rc::ptr_eq(&x.input, &y.input) => {
                 Parsed(ParsedSpan {
                     input: x.input.clone(),
+                    origin: x.origin.clone(),
                     start: min(x.start, y.start),
                     end: max(x.end, y.end),
                 })
@@ -1,38 +1,78 @@
-use itertools::Itertools;
-use serde::de;
-use std::collections::BTreeMap;
-use std::fmt;
-use std::iter::FromIterator;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
 
 use crate::error::DecodeError;
 use crate::operations::OpKind;
 use crate::syntax;
 use crate::syntax::{
-    Expr, ExprKind, FilePath, FilePrefix, Hash, ImportMode, ImportTarget,
-    Integer, InterpolatedText, Label, Natural, NumKind, Scheme, Span,
-    UnspannedExpr, URL, V,
+    Authority, Expr, ExprKind, FilePath, FilePrefix, Hash, ImportMode,
+    ImportTarget, Integer, InterpolatedText, Label, Natural, NumKind, Scheme,
+    Span, UnspannedExpr, URL, V,
 };
+use crate::utils::ensure_sufficient_stack;
 type DecodedExpr = Expr;
 
+/// Decodes an expression. Expressions encoded by older versions of the standard are accepted as
+/// long as they can be expressed today: version tags are dropped and removed builtins are replaced
+/// with their definition. Otherwise this fails with `DecodeError::UnsupportedEncodingVersion`.
+///
+/// The data is decoded in a single pass, straight into an expression: there is no intermediate
+/// tree of CBOR values, and labels and text are not copied more than once.
 pub fn decode(data: &[u8]) -> Result<DecodedExpr, DecodeError> {
-    match serde_cbor::de::from_slice(data) {
-        Ok(v) => cbor_value_to_dhall(&v),
-        Err(e) => Err(DecodeError::CBORError(e)),
+    let mut reader = Reader { data, pos: 0 };
+    skip_version_tag(&mut reader)?;
+    let expr = reader.expr()?;
+    if reader.pos != data.len() {
+        return Err(cbor_error("trailing data"));
     }
+    Ok(expr)
 }
 
-/// An enum that can encode most CBOR values.
-#[derive(Clone, Debug, PartialEq)]
-pub enum Value {
-    Null,
-    Bool(bool),
-    U64(u64),
-    I64(i64),
-    F64(f64),
-    String(String),
-    Array(Vec<Value>),
-    Object(BTreeMap<String, Value>),
-    Bytes(Vec<u8>),
+/// Before version tags were dropped in standard 5.0.0, expressions were encoded as
+/// `[version, e]`, where `e` is encoded like today.
+const SUPPORTED_VERSION_TAGS: &[&str] =
+    &["1.0", "1.1", "2.0.0", "3.0.0", "4.0.0", "5.0.0"];
+
+/// Moves `reader` to the expression inside the version tag, if there is one.
+fn skip_version_tag(reader: &mut Reader<'_>) -> Result<(), DecodeError> {
+    let is_version = |s: &str| {
+        s.contains('.') && s.chars().all(|c| c.is_ascii_digit() || c == '.')
+    };
+    let mut lookahead = reader.clone();
+    if let Item::Array(2) = lookahead.next()? {
+        // Labels can't start with a digit, so this can't be a variable.
+        match lookahead.next()? {
+            Item::Text(version) if is_version(version) => {
+                if !SUPPORTED_VERSION_TAGS.contains(&version) {
+                    return Err(DecodeError::UnsupportedEncodingVersion(
+                        format!("version tag {}", version),
+                    ));
+                }
+                *reader = lookahead;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The definitions of the builtins that were removed from the standard.
+fn removed_builtin(name: &str) -> Option<Expr> {
+    let definition = match name {
+        "Optional/fold" => {
+            "λ(a : Type) → λ(o : Optional a) → λ(optional : Type) → \
+             λ(some : a → optional) → λ(none : optional) → \
+             merge { Some = some, None = none } o"
+        }
+        "Optional/build" => {
+            "λ(a : Type) → \
+             λ(build : ∀(optional : Type) → ∀(some : a → optional) → \
+                       ∀(none : optional) → optional) → \
+             build (Optional a) (λ(x : a) → Some x) (None a)"
+        }
+        _ => return None,
+    };
+    Some(syntax::parse_expr(definition).unwrap().canonicalize())
 }
 
 // Should probably rename this
@@ -40,98 +80,262 @@ fn rc(x: UnspannedExpr) -> Expr {
     Expr::new(x, Span::Decoded)
 }
 
-fn cbor_value_to_dhall(data: &Value) -> Result<DecodedExpr, DecodeError> {
-    use crate::builtins::Builtin;
-    use crate::operations::BinOp;
-    use syntax::Const;
-    use ExprKind::*;
-    use OpKind::*;
-    use Value::*;
-    Ok(rc(match data {
-        String(s) => match Builtin::parse(s) {
-            Some(b) => ExprKind::Builtin(b),
-            None => match s.as_str() {
-                "True" => Num(NumKind::Bool(true)),
-                "False" => Num(NumKind::Bool(false)),
-                "Type" => Const(Const::Type),
-                "Kind" => Const(Const::Kind),
-                "Sort" => Const(Const::Sort),
-                _ => {
-                    return Err(DecodeError::WrongFormatError(
-                        "builtin".to_owned(),
+fn cbor_error(msg: &str) -> DecodeError {
+    DecodeError::CBORError(serde::de::Error::custom(msg))
+}
+
+fn format_error(msg: &str) -> DecodeError {
+    DecodeError::WrongFormatError(msg.to_owned())
+}
+
+#[cfg(not(feature = "bigint"))]
+fn bignum_unsupported(tag: u64) -> DecodeError {
+    DecodeError::WrongFormatError(format!(
+        "bignum (tag {}) is too large without the `bigint` feature",
+        tag
+    ))
+}
+
+/// The head of a CBOR data item. Strings borrow from the input; arrays and maps only give their
+/// length, and their elements are read next.
+#[derive(Debug, Clone, Copy)]
+enum Item<'a> {
+    UInt(u64),
+    /// The integer `-1 - n`.
+    NegInt(u64),
+    Bytes(&'a [u8]),
+    Text(&'a str),
+    Array(usize),
+    /// The number of key-value pairs.
+    Map(usize),
+    /// A bignum tag (2 or 3); other tags are skipped.
+    Tag(u64),
+    Bool(bool),
+    Null,
+    Float(f64),
+}
+
+/// Reads CBOR data items one at a time.
+#[derive(Debug, Clone)]
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: u64) -> Result<&'a [u8], DecodeError> {
+        let remaining = self.data.len() - self.pos;
+        if n > remaining as u64 {
+            return Err(cbor_error("unexpected end of data"));
+        }
+        let bytes = &self.data[self.pos..self.pos + n as usize];
+        self.pos += n as usize;
+        Ok(bytes)
+    }
+
+    fn uint_of_size(&mut self, size: usize) -> Result<u64, DecodeError> {
+        let bytes = self.take(size as u64)?;
+        Ok(bytes.iter().fold(0, |acc, b| (acc << 8) | u64::from(*b)))
+    }
+
+    /// A number of elements that follow. Each takes at least a byte, so this can't exceed the
+    /// remaining data; checking it avoids allocating for bogus lengths.
+    fn length(&self, n: u64) -> Result<usize, DecodeError> {
+        if n > (self.data.len() - self.pos) as u64 {
+            return Err(cbor_error("unexpected end of data"));
+        }
+        Ok(n as usize)
+    }
+
+    fn next(&mut self) -> Result<Item<'a>, DecodeError> {
+        loop {
+            let initial = self.uint_of_size(1)? as u8;
+            let (major, info) = (initial >> 5, initial & 0x1f);
+            if major == 7 {
+                return Ok(match info {
+                    20 => Item::Bool(false),
+                    21 => Item::Bool(true),
+                    22 | 23 => Item::Null,
+                    25 => Item::Float(f16_to_f64(self.uint_of_size(2)? as u16)),
+                    26 => Item::Float(
+                        f32::from_bits(self.uint_of_size(4)? as u32).into(),
+                    ),
+                    27 => Item::Float(f64::from_bits(self.uint_of_size(8)?)),
+                    _ => return Err(cbor_error("unsupported simple value")),
+                });
+            }
+            let arg = match info {
+                0..=23 => u64::from(info),
+                24 => self.uint_of_size(1)?,
+                25 => self.uint_of_size(2)?,
+                26 => self.uint_of_size(4)?,
+                27 => self.uint_of_size(8)?,
+                31 => {
+                    return Err(cbor_error(
+                        "indefinite-length items are not supported",
                     ))
                 }
-            },
-        },
-        U64(n) => Var(V(Label::from("_"), *n as usize)),
-        F64(x) => Num(NumKind::Double((*x).into())),
-        Bool(b) => Num(NumKind::Bool(*b)),
-        Array(vec) => match vec.as_slice() {
-            [String(l), U64(n)] => {
-                if l.as_str() == "_" {
-                    return Err(DecodeError::WrongFormatError(
-                        "`_` variable was encoded incorrectly".to_owned(),
-                    ));
+                _ => return Err(cbor_error("invalid additional information")),
+            };
+            return Ok(match major {
+                0 => Item::UInt(arg),
+                1 => Item::NegInt(arg),
+                2 => Item::Bytes(self.take(arg)?),
+                3 => match std::str::from_utf8(self.take(arg)?) {
+                    Ok(s) => Item::Text(s),
+                    Err(_) => return Err(cbor_error("invalid UTF-8 in text")),
+                },
+                4 => Item::Array(self.length(arg)?),
+                5 => Item::Map(self.length(arg)?),
+                _ if arg == 2 || arg == 3 => Item::Tag(arg),
+                // Other tags don't change the meaning of the item they tag.
+                _ => continue,
+            });
+        }
+    }
+
+    fn text(&mut self, what: &str) -> Result<&'a str, DecodeError> {
+        match self.next()? {
+            Item::Text(s) => Ok(s),
+            _ => Err(format_error(what)),
+        }
+    }
+
+    fn uint(&mut self, what: &str) -> Result<u64, DecodeError> {
+        match self.next()? {
+            Item::UInt(n) => Ok(n),
+            _ => Err(format_error(what)),
+        }
+    }
+
+    fn label(&mut self, what: &str) -> Result<Label, DecodeError> {
+        Ok(Label::from(self.text(what)?))
+    }
+
+    fn expr(&mut self) -> Result<DecodedExpr, DecodeError> {
+        let head = self.next()?;
+        self.expr_from(head)
+    }
+
+    /// Decodes an expression, or returns `None` if it is null.
+    fn opt_expr(&mut self) -> Result<Option<DecodedExpr>, DecodeError> {
+        match self.next()? {
+            Item::Null => Ok(None),
+            head => Ok(Some(self.expr_from(head)?)),
+        }
+    }
+
+    /// Decodes the expression whose first item has already been read.
+    fn expr_from(
+        &mut self,
+        head: Item<'a>,
+    ) -> Result<DecodedExpr, DecodeError> {
+        use crate::builtins::Builtin;
+        use syntax::Const;
+        use ExprKind::*;
+        ensure_sufficient_stack(|| {
+            Ok(rc(match head {
+                Item::Text(s) => match Builtin::parse(s) {
+                    Some(b) => ExprKind::Builtin(b),
+                    None => match s {
+                        "True" => Num(NumKind::Bool(true)),
+                        "False" => Num(NumKind::Bool(false)),
+                        "Type" => Const(Const::Type),
+                        "Kind" => Const(Const::Kind),
+                        "Sort" => Const(Const::Sort),
+                        _ => match removed_builtin(s) {
+                            Some(definition) => return Ok(definition),
+                            None => return Err(format_error("builtin")),
+                        },
+                    },
+                },
+                Item::UInt(n) => Var(V(Label::from("_"), n as usize)),
+                Item::Float(x) => Num(NumKind::Double(x.into())),
+                Item::Bool(b) => Num(NumKind::Bool(b)),
+                Item::Array(0) => return Err(format_error("empty array")),
+                Item::Array(len) => match self.next()? {
+                    Item::Text(l) => {
+                        if len != 2 {
+                            return Err(format_error("variable"));
+                        }
+                        if l == "_" {
+                            return Err(format_error(
+                                "`_` variable was encoded incorrectly",
+                            ));
+                        }
+                        let n = self.uint("variable")?;
+                        Var(V(Label::from(l), n as usize))
+                    }
+                    Item::UInt(label) => return self.labelled(label, len - 1),
+                    _ => return Err(format_error("array")),
+                },
+                item => {
+                    return Err(DecodeError::WrongFormatError(format!(
+                        "{:?}",
+                        item
+                    )))
                 }
-                let l = Label::from(l.as_str());
-                Var(V(l, *n as usize))
-            }
-            [U64(0), f, args @ ..] => {
-                if args.is_empty() {
-                    return Err(DecodeError::WrongFormatError(
-                        "Function application must have at least one argument"
-                            .to_owned(),
+            }))
+        })
+    }
+
+    /// Decodes the `n` elements that follow `label` in an array.
+    fn labelled(
+        &mut self,
+        label: u64,
+        n: usize,
+    ) -> Result<DecodedExpr, DecodeError> {
+        use crate::builtins::Builtin;
+        use crate::operations::BinOp;
+        use ExprKind::*;
+        use OpKind::*;
+        let wrong_length =
+            || DecodeError::WrongFormatError(format!("label {}", label));
+        Ok(rc(match (label, n) {
+            (0, _) => {
+                if n < 2 {
+                    return Err(format_error(
+                        "Function application must have at least one argument",
                     ));
                 }
-                let mut f = cbor_value_to_dhall(&f)?;
-                for a in args {
-                    let a = cbor_value_to_dhall(&a)?;
+                let mut f = self.expr()?;
+                for _ in 1..n {
+                    let a = self.expr()?;
                     f = rc(Op(App(f, a)))
                 }
                 return Ok(f);
             }
-            [U64(1), x, y] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let y = cbor_value_to_dhall(&y)?;
-                Lam(Label::from("_"), x, y)
-            }
-            [U64(1), String(l), x, y] => {
-                if l.as_str() == "_" {
-                    return Err(DecodeError::WrongFormatError(
-                        "`_` variable was encoded incorrectly".to_owned(),
-                    ));
+            (1, 2) | (2, 2) => {
+                let x = self.expr()?;
+                let y = self.expr()?;
+                if label == 1 {
+                    Lam(Label::from("_"), x, y)
+                } else {
+                    Pi(Label::from("_"), x, y)
                 }
-                let x = cbor_value_to_dhall(&x)?;
-                let y = cbor_value_to_dhall(&y)?;
-                let l = Label::from(l.as_str());
-                Lam(l, x, y)
-            }
-            [U64(2), x, y] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let y = cbor_value_to_dhall(&y)?;
-                Pi(Label::from("_"), x, y)
             }
-            [U64(2), String(l), x, y] => {
-                if l.as_str() == "_" {
-                    return Err(DecodeError::WrongFormatError(
-                        "`_` variable was encoded incorrectly".to_owned(),
+            (1, 3) | (2, 3) => {
+                let l = self.text("binder")?;
+                if l == "_" {
+                    return Err(format_error(
+                        "`_` variable was encoded incorrectly",
                     ));
                 }
-                let x = cbor_value_to_dhall(&x)?;
-                let y = cbor_value_to_dhall(&y)?;
-                let l = Label::from(l.as_str());
-                Pi(l, x, y)
-            }
-            [U64(3), U64(13), x, y] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let y = cbor_value_to_dhall(&y)?;
-                Op(Completion(x, y))
+                let l = Label::from(l);
+                let x = self.expr()?;
+                let y = self.expr()?;
+                if label == 1 {
+                    Lam(l, x, y)
+                } else {
+                    Pi(l, x, y)
+                }
             }
-            [U64(3), U64(n), x, y] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let y = cbor_value_to_dhall(&y)?;
+            (3, 3) => {
+                let op = self.uint("binop")?;
+                let x = self.expr()?;
+                let y = self.expr()?;
                 use BinOp::*;
-                let op = match n {
+                let op = match op {
                     0 => BoolOr,
                     1 => BoolAnd,
                     2 => BoolEQ,
@@ -145,510 +349,353 @@ fn cbor_value_to_dhall(data: &Value) -> Result<DecodedExpr, DecodeError> {
                     10 => RecursiveRecordTypeMerge,
                     11 => ImportAlt,
                     12 => Equivalence,
-                    _ => {
-                        return Err(DecodeError::WrongFormatError(
-                            "binop".to_owned(),
-                        ))
-                    }
+                    13 => return Ok(rc(Op(Completion(x, y)))),
+                    _ => return Err(format_error("binop")),
                 };
                 Op(BinOp(op, x, y))
             }
-            [U64(4), t] => {
-                let t = cbor_value_to_dhall(&t)?;
+            (4, 1) => {
+                let t = self.expr()?;
                 EmptyListLit(rc(Op(App(
                     rc(ExprKind::Builtin(Builtin::List)),
                     t,
                 ))))
             }
-            [U64(4), Null, rest @ ..] => {
-                let rest = rest
-                    .iter()
-                    .map(cbor_value_to_dhall)
+            (4, _) if n >= 2 => {
+                if !matches!(self.next()?, Item::Null) {
+                    return Err(format_error("list"));
+                }
+                let xs = (1..n)
+                    .map(|_| self.expr())
                     .collect::<Result<Vec<_>, _>>()?;
-                NEListLit(rest)
-            }
-            [U64(5), Null, x] => {
-                let x = cbor_value_to_dhall(&x)?;
-                SomeLit(x)
+                NEListLit(xs)
             }
             // Old-style optional literals
-            [U64(5), t] => {
-                let t = cbor_value_to_dhall(&t)?;
+            (5, 1) => {
+                let t = self.expr()?;
                 Op(App(rc(ExprKind::Builtin(Builtin::OptionalNone)), t))
             }
-            [U64(5), t, x] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let t = cbor_value_to_dhall(&t)?;
-                Annot(
-                    rc(SomeLit(x)),
-                    rc(Op(App(rc(ExprKind::Builtin(Builtin::Optional)), t))),
-                )
-            }
-            [U64(6), x, y] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let y = cbor_value_to_dhall(&y)?;
-                Op(Merge(x, y, None))
-            }
-            [U64(6), x, y, z] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let y = cbor_value_to_dhall(&y)?;
-                let z = cbor_value_to_dhall(&z)?;
-                Op(Merge(x, y, Some(z)))
-            }
-            [U64(7), Object(map)] => {
-                let map = cbor_map_to_dhall_map(map)?;
-                RecordType(map)
-            }
-            [U64(8), Object(map)] => {
-                let map = cbor_map_to_dhall_map(map)?;
-                RecordLit(map)
-            }
-            [U64(9), x, String(l)] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let l = Label::from(l.as_str());
+            (5, 2) => match self.opt_expr()? {
+                None => SomeLit(self.expr()?),
+                Some(t) => {
+                    let x = self.expr()?;
+                    Annot(
+                        rc(SomeLit(x)),
+                        rc(Op(App(
+                            rc(ExprKind::Builtin(Builtin::Optional)),
+                            t,
+                        ))),
+                    )
+                }
+            },
+            (6, 2) | (6, 3) => {
+                let x = self.expr()?;
+                let y = self.expr()?;
+                let z = if n == 3 { Some(self.expr()?) } else { None };
+                Op(Merge(x, y, z))
+            }
+            (7, 1) => RecordType(self.map(Self::expr)?),
+            (8, 1) => RecordLit(self.map(Self::expr)?),
+            (9, 2) => {
+                let x = self.expr()?;
+                let l = self.label("field")?;
                 Op(Field(x, l))
             }
-            [U64(10), x, Array(arr)] => {
-                let x = cbor_value_to_dhall(&x)?;
-                if let [y] = arr.as_slice() {
-                    let y = cbor_value_to_dhall(&y)?;
-                    Op(ProjectionByExpr(x, y))
-                } else {
-                    return Err(DecodeError::WrongFormatError(
-                        "projection-by-expr".to_owned(),
-                    ));
+            (10, _) if n >= 1 => {
+                let x = self.expr()?;
+                let mut labels = BTreeSet::new();
+                for _ in 1..n {
+                    match self.next()? {
+                        Item::Array(1) if n == 2 => {
+                            let y = self.expr()?;
+                            return Ok(rc(Op(ProjectionByExpr(x, y))));
+                        }
+                        Item::Text(l) => {
+                            labels.insert(Label::from(l));
+                        }
+                        _ => return Err(format_error("projection")),
+                    }
                 }
-            }
-            [U64(10), x, rest @ ..] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let labels = rest
-                    .iter()
-                    .map(|s| match s {
-                        String(s) => Ok(Label::from(s.as_str())),
-                        _ => Err(DecodeError::WrongFormatError(
-                            "projection".to_owned(),
-                        )),
-                    })
-                    .collect::<Result<_, _>>()?;
                 Op(Projection(x, labels))
             }
-            [U64(11), Object(map)] => {
-                let map = cbor_map_to_dhall_opt_map(map)?;
-                UnionType(map)
-            }
-            [U64(12), ..] => {
-                return Err(DecodeError::WrongFormatError(
-                    "Union literals are not supported anymore".to_owned(),
+            (11, 1) => UnionType(self.map(Self::opt_expr)?),
+            (12, _) => {
+                return Err(DecodeError::UnsupportedEncodingVersion(
+                    "union literal".to_owned(),
                 ))
             }
-            [U64(14), x, y, z] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let y = cbor_value_to_dhall(&y)?;
-                let z = cbor_value_to_dhall(&z)?;
+            (14, 3) => {
+                let x = self.expr()?;
+                let y = self.expr()?;
+                let z = self.expr()?;
                 Op(BoolIf(x, y, z))
             }
-            [U64(15), U64(x)] => Num(NumKind::Natural(*x as Natural)),
-            [U64(16), U64(x)] => Num(NumKind::Integer(*x as Integer)),
-            [U64(16), I64(x)] => Num(NumKind::Integer(*x as Integer)),
-            [U64(18), String(first), rest @ ..] => {
-                TextLit(InterpolatedText::from((
-                    first.clone(),
-                    rest.iter()
-                        .tuples()
-                        .map(|(x, y)| {
-                            let x = cbor_value_to_dhall(&x)?;
-                            let y = match y {
-                                String(s) => s.clone(),
-                                _ => {
-                                    return Err(DecodeError::WrongFormatError(
-                                        "text".to_owned(),
-                                    ))
-                                }
-                            };
-                            Ok((x, y))
-                        })
-                        .collect::<Result<_, _>>()?,
-                )))
-            }
-            [U64(19), t] => {
-                let t = cbor_value_to_dhall(&t)?;
-                Assert(t)
-            }
-            [U64(24), hash, U64(mode), U64(scheme), rest @ ..] => {
-                let mode = match mode {
-                    0 => ImportMode::Code,
-                    1 => ImportMode::RawText,
-                    2 => ImportMode::Location,
-                    _ => {
-                        return Err(DecodeError::WrongFormatError(format!(
-                            "import/mode/unknown_mode: {:?}",
-                            mode
-                        )))
-                    }
-                };
-                let hash = match hash {
-                    Null => None,
-                    Bytes(bytes) => match bytes.as_slice() {
-                        [18, 32, rest @ ..] => {
-                            Some(Hash::SHA256(rest.to_vec().into()))
-                        }
-                        _ => {
-                            return Err(DecodeError::WrongFormatError(format!(
-                                "import/hash/unknown_multihash: {:?}",
-                                bytes
-                            )))
-                        }
-                    },
-                    _ => {
-                        return Err(DecodeError::WrongFormatError(
-                            "import/hash/should_be_bytes".to_owned(),
-                        ))
-                    }
-                };
-                let mut rest = rest.iter();
-                let location = match scheme {
-                    0 | 1 => {
-                        let scheme = match scheme {
-                            0 => Scheme::HTTP,
-                            _ => Scheme::HTTPS,
-                        };
-                        let headers = match rest.next() {
-                            Some(Null) => None,
-                            Some(x) => {
-                                let x = cbor_value_to_dhall(&x)?;
-                                Some(x)
-                            }
-                            _ => {
-                                return Err(DecodeError::WrongFormatError(
-                                    "import/remote/headers".to_owned(),
-                                ))
-                            }
-                        };
-                        let authority = match rest.next() {
-                            Some(String(s)) => s.to_owned(),
-                            _ => {
-                                return Err(DecodeError::WrongFormatError(
-                                    "import/remote/authority".to_owned(),
-                                ))
-                            }
-                        };
-                        let query = match rest.next_back() {
-                            Some(Null) => None,
-                            Some(String(s)) => Some(s.to_owned()),
-                            _ => {
-                                return Err(DecodeError::WrongFormatError(
-                                    "import/remote/query".to_owned(),
-                                ))
-                            }
-                        };
-                        let file_path = rest
-                            .map(|s| match s.as_string() {
-                                Some(s) => Ok(s.clone()),
-                                None => Err(DecodeError::WrongFormatError(
-                                    "import/remote/path".to_owned(),
-                                )),
-                            })
-                            .collect::<Result<_, _>>()?;
-                        let path = FilePath { file_path };
-                        ImportTarget::Remote(URL {
-                            scheme,
-                            authority,
-                            path,
-                            query,
-                            headers,
-                        })
-                    }
-                    2 | 3 | 4 | 5 => {
-                        let prefix = match scheme {
-                            2 => FilePrefix::Absolute,
-                            3 => FilePrefix::Here,
-                            4 => FilePrefix::Parent,
-                            5 => FilePrefix::Home,
-                            _ => {
-                                return Err(DecodeError::WrongFormatError(
-                                    "import/local/prefix".to_owned(),
-                                ))
-                            }
-                        };
-                        let file_path = rest
-                            .map(|s| match s.as_string() {
-                                Some(s) => Ok(s.clone()),
-                                None => Err(DecodeError::WrongFormatError(
-                                    "import/local/path".to_owned(),
-                                )),
-                            })
-                            .collect::<Result<_, _>>()?;
-                        let path = FilePath { file_path };
-                        ImportTarget::Local(prefix, path)
-                    }
-                    6 => {
-                        let env = match rest.next() {
-                            Some(String(s)) => s.to_owned(),
-                            _ => {
-                                return Err(DecodeError::WrongFormatError(
-                                    "import/env".to_owned(),
-                                ))
-                            }
-                        };
-                        ImportTarget::Env(env)
-                    }
-                    7 => ImportTarget::Missing,
-                    _ => {
-                        return Err(DecodeError::WrongFormatError(
-                            "import/type".to_owned(),
-                        ))
-                    }
-                };
-                Import(syntax::Import {
-                    mode,
-                    hash,
-                    location,
-                })
-            }
-            [U64(25), bindings @ ..] => {
-                let mut tuples = bindings.iter().tuples();
-                let bindings = (&mut tuples)
-                    .map(|(x, t, v)| {
-                        let x = x.as_string().ok_or_else(|| {
-                            DecodeError::WrongFormatError(
-                                "let/label".to_owned(),
-                            )
-                        })?;
-                        let x = Label::from(x.as_str());
-                        let t = match t {
-                            Null => None,
-                            t => Some(cbor_value_to_dhall(&t)?),
-                        };
-                        let v = cbor_value_to_dhall(&v)?;
+            (15, 1) => Num(NumKind::Natural(self.natural()?)),
+            (16, 1) => Num(NumKind::Integer(self.integer()?)),
+            (18, _) if n % 2 == 1 => {
+                let first = self.text("text")?.to_owned();
+                let rest = (0..n / 2)
+                    .map(|_| {
+                        let x = self.expr()?;
+                        let y = self.text("text")?.to_owned();
+                        Ok((x, y))
+                    })
+                    .collect::<Result<_, _>>()?;
+                TextLit(InterpolatedText::from((first, rest)))
+            }
+            (19, 1) => Assert(self.expr()?),
+            (24, _) if n >= 3 => Import(self.import(n - 3)?),
+            (25, _) if n >= 4 && n % 3 == 1 => {
+                let bindings = (0..n / 3)
+                    .map(|_| {
+                        let x = self.label("let/label")?;
+                        let t = self.opt_expr()?;
+                        let v = self.expr()?;
                         Ok((x, t, v))
                     })
                     .collect::<Result<Vec<_>, _>>()?;
-                let expr = tuples.into_buffer().next().ok_or_else(|| {
-                    DecodeError::WrongFormatError("let/expr".to_owned())
-                })?;
-                let expr = cbor_value_to_dhall(expr)?;
+                let expr = self.expr()?;
                 return Ok(bindings
                     .into_iter()
                     .rev()
                     .fold(expr, |acc, (x, t, v)| rc(Let(x, t, v, acc))));
             }
-            [U64(26), x, y] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let y = cbor_value_to_dhall(&y)?;
+            (26, 2) => {
+                let x = self.expr()?;
+                let y = self.expr()?;
                 Annot(x, y)
             }
-            [U64(27), x] => {
-                let x = cbor_value_to_dhall(&x)?;
-                Op(ToMap(x, None))
-            }
-            [U64(27), x, y] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let y = cbor_value_to_dhall(&y)?;
-                Op(ToMap(x, Some(y)))
-            }
-            [U64(28), x] => {
-                let x = cbor_value_to_dhall(&x)?;
-                EmptyListLit(x)
-            }
-            [U64(29), x, labels, y] => {
-                let x = cbor_value_to_dhall(&x)?;
-                let y = cbor_value_to_dhall(&y)?;
-                let labels = match labels {
-                    Array(labels) => labels
-                        .iter()
-                        .map(|s| match s {
-                            String(s) => Ok(Label::from(s.as_str())),
-                            _ => Err(DecodeError::WrongFormatError(
-                                "with".to_owned(),
-                            )),
-                        })
+            (27, 1) | (27, 2) => {
+                let x = self.expr()?;
+                let y = if n == 2 { Some(self.expr()?) } else { None };
+                Op(ToMap(x, y))
+            }
+            (28, 1) => EmptyListLit(self.expr()?),
+            (29, 3) => {
+                let x = self.expr()?;
+                let labels = match self.next()? {
+                    Item::Array(k) => (0..k)
+                        .map(|_| self.label("with"))
                         .collect::<Result<_, _>>()?,
-                    _ => {
-                        return Err(DecodeError::WrongFormatError(
-                            "with".to_owned(),
-                        ))
-                    }
+                    _ => return Err(format_error("with")),
                 };
+                let y = self.expr()?;
                 Op(With(x, labels, y))
             }
-            _ => {
-                return Err(DecodeError::WrongFormatError(format!(
-                    "{:?}",
-                    data
-                )))
-            }
-        },
-        _ => return Err(DecodeError::WrongFormatError(format!("{:?}", data))),
-    }))
-}
-
-fn cbor_map_to_dhall_map<'a, T>(
-    map: impl IntoIterator<Item = (&'a String, &'a Value)>,
-) -> Result<T, DecodeError>
-where
-    T: FromIterator<(Label, DecodedExpr)>,
-{
-    map.into_iter()
-        .map(|(k, v)| -> Result<(_, _), _> {
-            let v = cbor_value_to_dhall(v)?;
-            Ok((Label::from(k.as_ref()), v))
-        })
-        .collect::<Result<_, _>>()
-}
+            _ => return Err(wrong_length()),
+        }))
+    }
 
-fn cbor_map_to_dhall_opt_map<'a, T>(
-    map: impl IntoIterator<Item = (&'a String, &'a Value)>,
-) -> Result<T, DecodeError>
-where
-    T: FromIterator<(Label, Option<DecodedExpr>)>,
-{
-    map.into_iter()
-        .map(|(k, v)| -> Result<(_, _), _> {
-            let v = match v {
-                Value::Null => None,
-                _ => Some(cbor_value_to_dhall(v)?),
-            };
-            Ok((Label::from(k.as_ref()), v))
-        })
-        .collect::<Result<_, _>>()
-}
+    /// Decodes a map from labels to the values read by `value`.
+    fn map<T>(
+        &mut self,
+        mut value: impl FnMut(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<BTreeMap<Label, T>, DecodeError> {
+        let len = match self.next()? {
+            Item::Map(len) => len,
+            _ => return Err(format_error("map")),
+        };
+        (0..len)
+            .map(|_| {
+                let k = self.label("map key")?;
+                Ok((k, value(self)?))
+            })
+            .collect()
+    }
 
-impl Value {
-    pub fn as_string(&self) -> Option<&String> {
-        if let Value::String(v) = self {
-            Some(v)
-        } else {
-            None
+    fn natural(&mut self) -> Result<Natural, DecodeError> {
+        match self.next()? {
+            Item::UInt(x) => Ok(Natural::from(x)),
+            #[cfg(feature = "bigint")]
+            Item::Tag(tag) => match self.bignum(tag)?.to_biguint() {
+                Some(n) => Ok(n),
+                None => Err(format_error("invalid natural literal")),
+            },
+            #[cfg(not(feature = "bigint"))]
+            Item::Tag(tag) => Err(bignum_unsupported(tag)),
+            _ => Err(format_error("natural literal")),
         }
     }
-}
-
-impl<'de> de::Deserialize<'de> for Value {
-    #[inline]
-    fn deserialize<D>(deserializer: D) -> Result<Value, D::Error>
-    where
-        D: de::Deserializer<'de>,
-    {
-        struct ValueVisitor;
-
-        impl<'de> de::Visitor<'de> for ValueVisitor {
-            type Value = Value;
-
-            fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-                fmt.write_str("any valid CBOR value")
-            }
-
-            #[inline]
-            fn visit_str<E>(self, value: &str) -> Result<Value, E>
-            where
-                E: de::Error,
-            {
-                self.visit_string(String::from(value))
-            }
-
-            #[inline]
-            fn visit_string<E>(self, value: String) -> Result<Value, E>
-            where
-                E: de::Error,
-            {
-                Ok(Value::String(value))
-            }
-            #[inline]
-            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                self.visit_byte_buf(v.to_owned())
-            }
 
-            #[inline]
-            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                Ok(Value::Bytes(v))
-            }
-
-            #[inline]
-            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                Ok(Value::U64(v))
-            }
-
-            #[inline]
-            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                Ok(Value::I64(v))
-            }
-
-            #[inline]
-            fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                Ok(Value::Bool(v))
-            }
+    fn integer(&mut self) -> Result<Integer, DecodeError> {
+        let too_large = || format_error("integer literal is too large");
+        match self.next()? {
+            Item::UInt(x) => Integer::try_from(x).map_err(|_| too_large()),
+            #[cfg(not(feature = "bigint"))]
+            Item::NegInt(x) => match Integer::try_from(x) {
+                Ok(x) => Ok(-1 - x),
+                Err(_) => Err(too_large()),
+            },
+            #[cfg(feature = "bigint")]
+            Item::NegInt(x) => Ok(Integer::from(-1 - i128::from(x))),
+            #[cfg(feature = "bigint")]
+            Item::Tag(tag) => self.bignum(tag),
+            #[cfg(not(feature = "bigint"))]
+            Item::Tag(tag) => Err(bignum_unsupported(tag)),
+            _ => Err(format_error("integer literal")),
+        }
+    }
 
-            #[inline]
-            fn visit_none<E>(self) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                self.visit_unit()
-            }
+    /// Decodes a CBOR bignum, as described in RFC 7049 section 2.4.2.
+    #[cfg(feature = "bigint")]
+    fn bignum(&mut self, tag: u64) -> Result<Integer, DecodeError> {
+        use num_bigint::Sign;
+        let bytes = match self.next()? {
+            Item::Bytes(bytes) => bytes,
+            _ => return Err(format_error("bignum")),
+        };
+        let n = Integer::from_bytes_be(Sign::Plus, bytes);
+        Ok(if tag == 2 { n } else { -n - 1 })
+    }
 
-            #[inline]
-            fn visit_unit<E>(self) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                Ok(Value::Null)
+    /// Decodes an import, given the number of elements after its scheme.
+    fn import(
+        &mut self,
+        rest: usize,
+    ) -> Result<syntax::Import<DecodedExpr>, DecodeError> {
+        let hash = match self.next()? {
+            Item::Null => None,
+            Item::Bytes(bytes) => match bytes {
+                [18, 32, rest @ ..] => Some(Hash::SHA256(rest.to_vec().into())),
+                _ => {
+                    return Err(DecodeError::WrongFormatError(format!(
+                        "import/hash/unknown_multihash: {:?}",
+                        bytes
+                    )))
+                }
+            },
+            _ => return Err(format_error("import/hash/should_be_bytes")),
+        };
+        let mode = match self.uint("import/mode")? {
+            0 => ImportMode::Code,
+            1 => ImportMode::RawText,
+            2 => ImportMode::Location,
+            mode => {
+                return Err(DecodeError::WrongFormatError(format!(
+                    "import/mode/unknown_mode: {:?}",
+                    mode
+                )))
             }
-
-            #[inline]
-            fn visit_seq<V>(
-                self,
-                mut visitor: V,
-            ) -> Result<Self::Value, V::Error>
-            where
-                V: de::SeqAccess<'de>,
-            {
-                let mut vec = Vec::new();
-
-                while let Some(elem) = visitor.next_element()? {
-                    vec.push(elem);
+        };
+        let location = match self.uint("import/type")? {
+            scheme @ 0..=1 => {
+                if rest < 3 {
+                    return Err(format_error("import/remote"));
                 }
-
-                Ok(Value::Array(vec))
+                let scheme = match scheme {
+                    0 => Scheme::HTTP,
+                    _ => Scheme::HTTPS,
+                };
+                let headers = self.opt_expr()?;
+                let authority = self.text("import/remote/authority")?;
+                let authority = Authority::parse(authority)
+                    .ok_or_else(|| format_error("import/remote/authority"))?;
+                let file_path = (0..rest - 3)
+                    .map(|_| Ok(self.text("import/remote/path")?.to_owned()))
+                    .collect::<Result<_, _>>()?;
+                let query = match self.next()? {
+                    Item::Null => None,
+                    Item::Text(s) => Some(s.to_owned()),
+                    _ => return Err(format_error("import/remote/query")),
+                };
+                ImportTarget::Remote(URL {
+                    scheme,
+                    authority,
+                    path: FilePath { file_path },
+                    query,
+                    headers,
+                })
             }
+            scheme @ 2..=5 => {
+                let prefix = match scheme {
+                    2 => FilePrefix::Absolute,
+                    3 => FilePrefix::Here,
+                    4 => FilePrefix::Parent,
+                    _ => FilePrefix::Home,
+                };
+                let file_path = (0..rest)
+                    .map(|_| Ok(self.text("import/local/path")?.to_owned()))
+                    .collect::<Result<_, _>>()?;
+                ImportTarget::Local(prefix, FilePath { file_path })
+            }
+            6 if rest == 1 => {
+                ImportTarget::Env(self.text("import/env")?.to_owned())
+            }
+            7 if rest == 0 => ImportTarget::Missing,
+            _ => return Err(format_error("import/type")),
+        };
+        Ok(syntax::Import {
+            mode,
+            hash,
+            location,
+        })
+    }
+}
 
-            #[inline]
-            fn visit_map<V>(self, mut visitor: V) -> Result<Value, V::Error>
-            where
-                V: de::MapAccess<'de>,
-            {
-                let mut values = BTreeMap::new();
-
-                while let Some((key, value)) = visitor.next_entry()? {
-                    values.insert(key, value);
-                }
+/// Converts a half-precision float.
+fn f16_to_f64(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let mantissa = f64::from(bits & 0x3ff);
+    sign * match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
 
-                Ok(Value::Object(values))
-            }
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::binary::encode;
+    use crate::syntax::parse_expr;
 
-            #[inline]
-            fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                Ok(Value::F64(v))
-            }
+    /// Every kind of expression decodes back to itself, and malformed data fails cleanly.
+    #[test]
+    fn roundtrip() {
+        let exprs = [
+            "λ(x : Natural) → λ(_ : Bool) → x@1 + _ * x",
+            "∀(a : Type) → a → List a",
+            "let x = 1 let y : Natural = x in [x, y] # ([] : List Natural)",
+            "{ a = Some 1, b = None Bool, c = { d = -2, e = +3 } }",
+            "{ a : Bool, `b c` : Text } ⩓ { d : Double }",
+            "< A : Natural | B >.A 1 ? < C >.C",
+            "merge { A = λ(n : Natural) → n, B = 0 } x : Natural",
+            "toMap { a = 1 } : List { mapKey : Text, mapValue : Natural }",
+            "if True && False || x == y != z then 1.5 else -0.0",
+            "[1.0e300, 1.0e-3, 65504.0, Infinity, -Infinity, NaN]",
+            "\"a${x}b${Natural/show 18446744073709551615}c\" ++ ''\n  d\n  ''",
+            "r.{ a, b } ⫽ r.({ c : Bool }) ∧ r.a.b",
+            "(r with a.b = 1) === T::{ c = 2 }",
+            "assert : Natural/even 2 ≡ True",
+            "./a/b.dhall sha256:0000000000000000000000000000000000000000000000000000000000000000",
+            "../a as Text ? ~/b as Location ? /c/d ? env:HOME ? missing",
+            "https://user@example.com:8080/a/b?c=d using { x = 1 }",
+            "-9223372036854775808 + Integer/toDouble +9223372036854775807",
+        ];
+        for src in &exprs {
+            let e = parse_expr(src).unwrap();
+            let data = encode(&e).unwrap();
+            assert_eq!(decode(&data).unwrap(), e, "{}", src);
+            for i in 0..data.len() {
+                assert!(
+                    decode(&data[..i]).is_err(),
+                    "{} truncated at {}",
+                    src,
+                    i
+                );
+            }
+            let mut longer = data.clone();
+            longer.push(0);
+            assert!(decode(&longer).is_err());
         }
-
-        deserializer.deserialize_any(ValueVisitor)
+        // Huge lengths don't make the decoder allocate.
+        assert!(decode(&[
+            0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff
+        ])
+        .is_err());
+        // Indefinite-length arrays are not supported.
+        assert!(decode(&[0x9f, 0x19, 0xff]).is_err());
     }
 }
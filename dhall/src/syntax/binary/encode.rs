@@ -10,8 +10,29 @@ use crate::syntax::{
     Scheme, V,
 };
 
+/// Controls the binary encoding of expressions, see `encode_with`.
+///
+/// NaNs are always encoded as the canonical NaN, whatever their sign and payload, as the standard
+/// requires (see `NaiveDouble::canonicalize`). Anything else would not be valid Dhall.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct EncodeOptions {
+    /// Whether to encode `-0.0` as `0.0`. The standard considers them different values, so this
+    /// changes the hash of expressions that contain `-0.0` and is off by default. It is meant for
+    /// interoperating with tools that don't distinguish them.
+    pub normalize_negative_zero: bool,
+}
+
+/// Encodes an expression in the standard binary format. This is the encoding used for hashing.
 pub fn encode(expr: &Expr) -> Result<Vec<u8>, EncodeError> {
-    serde_cbor::ser::to_vec(&Serialize::Expr(expr))
+    encode_with(expr, EncodeOptions::default())
+}
+
+/// Like `encode`, with options.
+pub fn encode_with(
+    expr: &Expr,
+    opts: EncodeOptions,
+) -> Result<Vec<u8>, EncodeError> {
+    serde_cbor::ser::to_vec(&Serialize::Expr(expr, opts))
         .map_err(EncodeError::CBORError)
 }
 
@@ -21,10 +42,12 @@ enum Serialize<'a> {
     Label(&'a Label),
     Text(String),
     Bytes(Vec<u8>),
+    Natural(&'a syntax::Natural),
+    Integer(&'a syntax::Integer),
 
-    Expr(&'a Expr),
-    RecordMap(&'a BTreeMap<Label, Expr>),
-    UnionMap(&'a BTreeMap<Label, Option<Expr>>),
+    Expr(&'a Expr, EncodeOptions),
+    RecordMap(&'a BTreeMap<Label, Expr>, EncodeOptions),
+    UnionMap(&'a BTreeMap<Label, Option<Expr>>, EncodeOptions),
 }
 
 macro_rules! count {
@@ -44,7 +67,11 @@ macro_rules! ser_seq {
     }};
 }
 
-fn serialize_subexpr<S>(ser: S, e: &Expr) -> Result<S::Ok, S::Error>
+fn serialize_subexpr<S>(
+    ser: S,
+    e: &Expr,
+    opts: EncodeOptions,
+) -> Result<S::Ok, S::Error>
 where
     S: serde::ser::Serializer,
 {
@@ -54,9 +81,7 @@ where
     use OpKind::*;
 
     use self::Serialize::{RecordMap, UnionMap};
-    fn expr(x: &Expr) -> self::Serialize<'_> {
-        self::Serialize::Expr(x)
-    }
+    let expr = |x| self::Serialize::Expr(x, opts);
     fn label(x: &Label) -> self::Serialize<'_> {
         self::Serialize::Label(x)
     }
@@ -67,11 +92,15 @@ where
         Const(c) => ser.serialize_str(&c.to_string()),
         Builtin(b) => ser.serialize_str(&b.to_string()),
         Num(Bool(b)) => ser.serialize_bool(*b),
-        Num(Natural(n)) => ser_seq!(ser; tag(15), n),
-        Num(Integer(n)) => ser_seq!(ser; tag(16), n),
+        Num(Natural(n)) => ser_seq!(ser; tag(15), Serialize::Natural(n)),
+        Num(Integer(n)) => ser_seq!(ser; tag(16), Serialize::Integer(n)),
         Num(Double(n)) => {
-            let n: f64 = (*n).into();
-            ser.serialize_f64(n)
+            let n: f64 = n.canonicalize().into();
+            if opts.normalize_negative_zero && n == 0.0 {
+                ser.serialize_f64(0.0)
+            } else {
+                ser.serialize_f64(n)
+            }
         }
         Op(BoolIf(x, y, z)) => {
             ser_seq!(ser; tag(14), expr(x), expr(y), expr(z))
@@ -134,9 +163,9 @@ where
                 Text(x) => Serialize::Text(x),
             })))
         }
-        RecordType(map) => ser_seq!(ser; tag(7), RecordMap(map)),
-        RecordLit(map) => ser_seq!(ser; tag(8), RecordMap(map)),
-        UnionType(map) => ser_seq!(ser; tag(11), UnionMap(map)),
+        RecordType(map) => ser_seq!(ser; tag(7), RecordMap(map, opts)),
+        RecordLit(map) => ser_seq!(ser; tag(8), RecordMap(map, opts)),
+        UnionType(map) => ser_seq!(ser; tag(11), UnionMap(map, opts)),
         Op(Field(x, l)) => ser_seq!(ser; tag(9), expr(x), label(l)),
         Op(BinOp(op, x, y)) => {
             use self::BinOp::*;
@@ -178,11 +207,15 @@ where
             let ls: Vec<_> = ls.iter().map(label).collect();
             ser_seq!(ser; tag(29), expr(x), ls, expr(y))
         }
-        Import(import) => serialize_import(ser, import),
+        Import(import) => serialize_import(ser, import, opts),
     }
 }
 
-fn serialize_import<S>(ser: S, import: &Import<Expr>) -> Result<S::Ok, S::Error>
+fn serialize_import<S>(
+    ser: S,
+    import: &Import<Expr>,
+    opts: EncodeOptions,
+) -> Result<S::Ok, S::Error>
 where
     S: serde::ser::Serializer,
 {
@@ -235,11 +268,10 @@ where
         ImportTarget::Remote(url) => {
             match &url.headers {
                 None => ser_seq.serialize_element(&Null)?,
-                Some(e) => {
-                    ser_seq.serialize_element(&self::Serialize::Expr(e))?
-                }
+                Some(e) => ser_seq
+                    .serialize_element(&self::Serialize::Expr(e, opts))?,
             };
-            ser_seq.serialize_element(&url.authority)?;
+            ser_seq.serialize_element(&url.authority.to_string())?;
             for p in url.path.file_path.iter() {
                 ser_seq.serialize_element(&p)?;
             }
@@ -274,14 +306,66 @@ impl<'a> serde::ser::Serialize for Serialize<'a> {
             Label(v) => ser.serialize_str(v.as_ref()),
             Text(v) => ser.serialize_str(v),
             Bytes(v) => ser.serialize_bytes(v),
+            Natural(n) => serialize_natural(ser, n),
+            Integer(n) => serialize_integer(ser, n),
 
-            Expr(e) => serialize_subexpr(ser, e),
-            RecordMap(map) => {
-                ser.collect_map(map.iter().map(|(k, v)| (Label(k), Expr(v))))
-            }
-            UnionMap(map) => ser.collect_map(
-                map.iter().map(|(k, v)| (Label(k), v.as_ref().map(Expr))),
+            Expr(e, opts) => serialize_subexpr(ser, e, *opts),
+            RecordMap(map, opts) => ser.collect_map(
+                map.iter().map(|(k, v)| (Label(k), Expr(v, *opts))),
             ),
+            UnionMap(map, opts) => {
+                ser.collect_map(map.iter().map(|(k, v)| {
+                    (Label(k), v.as_ref().map(|v| Expr(v, *opts)))
+                }))
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "bigint"))]
+fn serialize_natural<S>(ser: S, n: &syntax::Natural) -> Result<S::Ok, S::Error>
+where
+    S: serde::ser::Serializer,
+{
+    ser.serialize_u64(*n)
+}
+
+#[cfg(not(feature = "bigint"))]
+fn serialize_integer<S>(ser: S, n: &syntax::Integer) -> Result<S::Ok, S::Error>
+where
+    S: serde::ser::Serializer,
+{
+    ser.serialize_i64(*n)
+}
+
+#[cfg(feature = "bigint")]
+fn serialize_natural<S>(ser: S, n: &syntax::Natural) -> Result<S::Ok, S::Error>
+where
+    S: serde::ser::Serializer,
+{
+    serialize_integer(ser, &syntax::Integer::from(n.clone()))
+}
+
+/// Numbers that don't fit in a CBOR integer are encoded as bignums (CBOR tags 2 and 3).
+#[cfg(feature = "bigint")]
+fn serialize_integer<S>(ser: S, n: &syntax::Integer) -> Result<S::Ok, S::Error>
+where
+    S: serde::ser::Serializer,
+{
+    use num_traits::{Signed, ToPrimitive};
+    use serde_cbor::tags::Tagged;
+    const LIMIT: i128 = 1 << 64;
+    match n.to_i128() {
+        Some(x) if -LIMIT <= x && x < LIMIT => ser.serialize_i128(x),
+        _ if n.is_negative() => {
+            let bytes = (-n - 1u8).magnitude().to_bytes_be();
+            let tagged = Tagged::new(Some(3), Serialize::Bytes(bytes));
+            serde::ser::Serialize::serialize(&tagged, ser)
+        }
+        _ => {
+            let bytes = n.magnitude().to_bytes_be();
+            let tagged = Tagged::new(Some(2), Serialize::Bytes(bytes));
+            serde::ser::Serialize::serialize(&tagged, ser)
         }
     }
 }
@@ -1,4 +1,4 @@
 mod decode;
 mod encode;
 pub use decode::decode;
-pub use encode::encode;
+pub use encode::{encode, encode_with, EncodeOptions};
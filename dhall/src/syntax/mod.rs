@@ -8,7 +8,9 @@
 mod ast;
 pub use crate::syntax::ast::visitor;
 pub use crate::syntax::ast::*;
+pub use crate::syntax::text::format::*;
 pub use crate::syntax::text::parser::*;
 pub use crate::syntax::text::printer::*;
+#[cfg(feature = "binary")]
 pub mod binary;
 pub mod text;
@@ -0,0 +1,355 @@
+use crate::operations::{BinOp, OpKind};
+use crate::syntax::text::printer::{quote_label, PhasedExpr, PrintPhase};
+use crate::syntax::{Expr, ExprKind, PrintOptions};
+use crate::utils::ensure_sufficient_stack;
+
+/// Controls how `Expr::format` lays out an expression.
+#[derive(Debug, Copy, Clone)]
+pub struct FormatOptions {
+    /// The width that lines should not exceed, in characters. Expressions are broken over several
+    /// lines only when they don't fit. Some expressions, like long labels or text literals, can't
+    /// be broken and may still exceed it.
+    pub line_width: usize,
+    /// The number of spaces by which function bodies and arguments are indented.
+    pub indent: usize,
+    /// Whether to use the ASCII forms of symbols, like `PrintOptions::ascii`.
+    pub ascii: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            line_width: 80,
+            indent: 2,
+            ascii: false,
+        }
+    }
+}
+
+impl Expr {
+    /// Prints the expression over several lines, so that it fits in the configured width.
+    ///
+    /// Formatting is idempotent: the output parses back to the same expression, so formatting it
+    /// again gives the same text. Record fields are always printed in sorted order, since the
+    /// syntax tree doesn't keep track of the original one. Comments are not preserved.
+    pub fn format(&self, opts: FormatOptions) -> String {
+        let mut f = Formatter {
+            opts,
+            out: String::new(),
+        };
+        f.expr(PhasedExpr(self, PrintPhase::Base, f.print_opts()));
+        f.out
+    }
+}
+
+struct Formatter {
+    opts: FormatOptions,
+    out: String,
+}
+
+impl Formatter {
+    fn print_opts(&self) -> PrintOptions {
+        PrintOptions {
+            ascii: self.opts.ascii,
+            ..PrintOptions::default()
+        }
+    }
+
+    /// The column the next character will be written at.
+    fn column(&self) -> usize {
+        let line_start = self.out.rfind('\n').map_or(0, |i| i + 1);
+        self.out[line_start..].chars().count()
+    }
+
+    fn write(&mut self, s: &str) {
+        self.out.push_str(s)
+    }
+
+    fn newline(&mut self, column: usize) {
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(column));
+    }
+
+    /// Whether `s` can be written at the current position without exceeding the width.
+    fn fits(&self, s: &str) -> bool {
+        let mut lines = s.split('\n');
+        let first = lines.next().unwrap_or("");
+        self.column() + first.chars().count() <= self.opts.line_width
+            && lines.all(|l| l.chars().count() <= self.opts.line_width)
+    }
+
+    fn expr(&mut self, e: PhasedExpr<'_>) {
+        ensure_sufficient_stack(|| {
+            let flat = e.to_string();
+            if self.fits(&flat) {
+                return self.write(&flat);
+            }
+            let PhasedExpr(e, phase, popts) = e;
+            let needs_paren = e.kind().needs_paren(phase);
+            if needs_paren {
+                self.write("(");
+            }
+            if !self.broken(e, popts) {
+                let flat = PhasedExpr(e, PrintPhase::Base, popts).to_string();
+                self.write(&flat);
+            }
+            if needs_paren {
+                self.write(")");
+            }
+        })
+    }
+
+    /// Writes `e` over several lines, aligned to the current column. Returns `false` without
+    /// writing anything if `e` has no multi-line layout.
+    fn broken(&mut self, e: &Expr, popts: PrintOptions) -> bool {
+        use ExprKind::*;
+        let start = self.column();
+        let body_indent = start + self.opts.indent;
+        match e.kind().annotate_with_phases(popts) {
+            Let(..) => {
+                let mut e = e;
+                while let Let(l, t, v, body) = e.kind() {
+                    self.write(&format!("let {}", quote_label(l)));
+                    if let Some(t) = t {
+                        self.write(" : ");
+                        self.expr(PhasedExpr(t, PrintPhase::Base, popts));
+                    }
+                    self.write(" =");
+                    self.value(PhasedExpr(v, PrintPhase::Base, popts));
+                    self.newline(start);
+                    e = body;
+                }
+                self.write("in ");
+                self.expr(PhasedExpr(e, PrintPhase::Base, popts));
+            }
+            Lam(l, t, body) => {
+                self.write(&format!(
+                    "{}({} : ",
+                    popts.lambda(),
+                    quote_label(&l)
+                ));
+                self.expr(t);
+                self.write(&format!(") {}", popts.arrow()));
+                self.newline(body_indent);
+                self.expr(body);
+            }
+            Pi(l, t, body) => {
+                if String::from(&l) == "_" {
+                    self.expr(t);
+                } else {
+                    self.write(&format!(
+                        "{}({} : ",
+                        popts.forall(),
+                        quote_label(&l)
+                    ));
+                    self.expr(t);
+                    self.write(")");
+                }
+                self.write(&format!(" {}", popts.arrow()));
+                self.newline(start);
+                self.expr(body);
+            }
+            Op(OpKind::BoolIf(c, t, e)) => {
+                self.write("if ");
+                self.expr(c);
+                self.newline(start);
+                self.write("then ");
+                self.expr(t);
+                self.newline(start);
+                self.write("else ");
+                self.expr(e);
+            }
+            Op(OpKind::App(..)) => {
+                let mut args = Vec::new();
+                let mut head = e;
+                while let Op(OpKind::App(f, a)) = head.kind() {
+                    args.push(a);
+                    head = f;
+                }
+                self.expr(PhasedExpr(head, PrintPhase::App, popts));
+                for a in args.into_iter().rev() {
+                    self.newline(body_indent);
+                    self.expr(PhasedExpr(a, PrintPhase::Import, popts));
+                }
+            }
+            Op(OpKind::BinOp(op, ..)) => {
+                let mut operands = Vec::new();
+                collect_operands(op, e, &mut operands);
+                let symbol = if popts.ascii {
+                    op.ascii_symbol().to_string()
+                } else {
+                    op.to_string()
+                };
+                for (i, x) in operands.into_iter().enumerate() {
+                    if i > 0 {
+                        self.newline(start);
+                        self.write(&format!("{} ", symbol));
+                    }
+                    self.expr(PhasedExpr(x, PrintPhase::BinOp(op), popts));
+                }
+            }
+            Annot(x, t) => {
+                self.expr(x);
+                self.newline(start);
+                self.write(": ");
+                self.expr(t);
+            }
+            RecordLit(kvs) if !kvs.is_empty() => {
+                self.entries("{ ", ", ", "}", kvs, |f, (k, v)| {
+                    f.write(&format!("{} =", quote_label(&k)));
+                    f.value(v);
+                })
+            }
+            RecordType(kts) if !kts.is_empty() => {
+                self.entries("{ ", ", ", "}", kts, |f, (k, t)| {
+                    f.write(&format!("{} : ", quote_label(&k)));
+                    f.expr(t);
+                })
+            }
+            UnionType(kts) if !kts.is_empty() => {
+                self.entries("< ", "| ", ">", kts, |f, (k, t)| {
+                    f.write(&quote_label(&k));
+                    if let Some(t) = t {
+                        f.write(": ");
+                        f.expr(t);
+                    }
+                })
+            }
+            NEListLit(xs) => self.entries("[ ", ", ", "]", xs, Self::expr),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Writes the value of a `let` or record field after its `=`, on the next line if it doesn't
+    /// fit on the current one.
+    fn value(&mut self, v: PhasedExpr<'_>) {
+        let flat = v.to_string();
+        if self.fits(&format!(" {}", flat)) {
+            self.write(" ");
+            self.write(&flat);
+        } else {
+            let column = self.entry_column() + self.opts.indent;
+            self.newline(column);
+            self.expr(v);
+        }
+    }
+
+    /// The column of the first non-space character of the current line.
+    fn entry_column(&self) -> usize {
+        let line_start = self.out.rfind('\n').map_or(0, |i| i + 1);
+        self.out[line_start..]
+            .chars()
+            .take_while(|c| *c == ' ')
+            .count()
+    }
+
+    /// Writes a bracketed sequence with one entry per line, e.g. `{ a = 1\n, b = 2\n}`.
+    fn entries<T>(
+        &mut self,
+        open: &str,
+        sep: &str,
+        close: &str,
+        entries: impl IntoIterator<Item = T>,
+        mut entry: impl FnMut(&mut Self, T),
+    ) {
+        let start = self.column();
+        for (i, x) in entries.into_iter().enumerate() {
+            if i == 0 {
+                self.write(open);
+            } else {
+                self.newline(start);
+                self.write(sep);
+            }
+            entry(self, x);
+        }
+        self.newline(start);
+        self.write(close);
+    }
+}
+
+/// Flattens a chain of applications of the same operator.
+fn collect_operands<'a>(op: BinOp, e: &'a Expr, out: &mut Vec<&'a Expr>) {
+    match e.kind() {
+        ExprKind::Op(OpKind::BinOp(op2, a, b)) if *op2 == op => {
+            collect_operands(op, a, out);
+            collect_operands(op, b, out);
+        }
+        _ => out.push(e),
+    }
+}
+
+#[test]
+fn test_format_layout() {
+    use crate::syntax::parse_expr;
+
+    let format = |s: &str, line_width| {
+        let opts = FormatOptions {
+            line_width,
+            ..FormatOptions::default()
+        };
+        parse_expr(s).unwrap().format(opts)
+    };
+    assert_eq!(format("let a = 1 in a", 80), "let a = 1 in a");
+    assert_eq!(
+        format("let a = { x = 1, y = [ 2, 3 ] } let b = a.x in b + a.x", 20),
+        "\
+let a =
+  { x = 1
+  , y = [2, 3]
+  }
+let b = a.x
+in b + a.x"
+    );
+    assert_eq!(
+        format(r"\(x : Bool) -> if x then f 1 2 else g 3 4", 16),
+        "\
+λ(x : Bool) →
+  if x
+  then f 1 2
+  else g 3 4"
+    );
+    assert_eq!(
+        format("f (some_function argument) { a = 1 } : Natural", 24),
+        "\
+f
+  (some_function
+     argument)
+  { a = 1 }
+: Natural"
+    );
+}
+
+#[test]
+fn test_format_idempotent() {
+    use crate::syntax::parse_expr;
+
+    let exprs = [
+        "let a = 1 let b : Natural = a in a + b",
+        r"\(x : { a : Bool, b : List Natural }) -> if x.a then x.b # [1, 2, 3] else [] : List Natural",
+        "{ a = < A : Natural | B >.A 1, b = merge { A = \\(n : Natural) -> n, B = 0 } x, c = \"text\" }",
+        "forall (a : Type) -> (a -> a) -> a -> { fst : a, snd : List a }",
+        "f (g x y) (let z = 1 in z) (\\(x : Bool) -> x) ([] : List Bool) (Some 1) : T",
+        "(a || b && c) == (d != e) ++ \"x\" ++ \"y\"",
+        "''\n  multi\n  line ${x} text\n''",
+        "./foo.dhall ? env:BAR as Text ? missing",
+    ];
+    for s in &exprs {
+        let e = parse_expr(s).unwrap();
+        for &line_width in &[0, 10, 20, 40, 80] {
+            for &ascii in &[false, true] {
+                let opts = FormatOptions {
+                    line_width,
+                    ascii,
+                    ..FormatOptions::default()
+                };
+                let formatted = e.format(opts);
+                let reparsed = parse_expr(&formatted).unwrap_or_else(|err| {
+                    panic!("{}\ndoesn't parse:\n{}", formatted, err)
+                });
+                assert_eq!(reparsed, e, "{}", formatted);
+                assert_eq!(reparsed.format(opts), formatted);
+            }
+        }
+    }
+}
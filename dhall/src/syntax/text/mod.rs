@@ -1,2 +1,4 @@
+pub mod format;
 pub mod parser;
 pub mod printer;
+mod share;
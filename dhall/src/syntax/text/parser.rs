@@ -1,7 +1,9 @@
 use itertools::Itertools;
 use pest::prec_climber as pcl;
 use pest::prec_climber::PrecClimber;
-use std::collections::{BTreeMap, BTreeSet};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::iter::once;
 use std::rc::Rc;
 
@@ -11,9 +13,9 @@ use crate::operations::OpKind::*;
 use crate::syntax::ExprKind::*;
 use crate::syntax::NumKind::*;
 use crate::syntax::{
-    Double, Expr, FilePath, FilePrefix, Hash, ImportMode, ImportTarget,
-    Integer, InterpolatedText, InterpolatedTextContents, Label, NaiveDouble,
-    Natural, Scheme, Span, UnspannedExpr, URL, V,
+    Authority, Double, Expr, FilePath, FilePrefix, Hash, ImportMode,
+    ImportTarget, Integer, InterpolatedText, Label, NaiveDouble, Natural,
+    Scheme, Span, UnspannedExpr, URL, V,
 };
 
 // This file consumes the parse tree generated by pest and turns it into
@@ -22,8 +24,23 @@ use crate::syntax::{
 // are here and hopefully you can figure out how they work.
 
 type ParsedText = InterpolatedText<Expr>;
-type ParsedTextContents = InterpolatedTextContents<Expr>;
-type ParseInput<'input> = pest_consume::Node<'input, Rule, Rc<str>>;
+type ParseInput<'input> = pest_consume::Node<'input, Rule, Rc<Source>>;
+
+/// The input being parsed, shared by all the nodes of the parse tree.
+#[derive(Debug)]
+struct Source {
+    text: Rc<str>,
+    /// The file or URL the text was read from, if any.
+    origin: Option<Rc<str>>,
+    /// The labels seen so far, so that each distinct label is only allocated once.
+    labels: RefCell<HashSet<Label>>,
+}
+
+/// A piece of a text literal. Plain text borrows from the input where possible.
+enum TextChunk<'input> {
+    Text(Cow<'input, str>),
+    Expr(Expr),
+}
 
 pub type ParseError = pest::error::Error<Rule>;
 pub type ParseResult<T> = Result<T, ParseError>;
@@ -36,7 +53,43 @@ enum Selector {
 }
 
 fn input_to_span(input: ParseInput) -> Span {
-    Span::make(input.user_data().clone(), input.as_pair().as_span())
+    let source = input.user_data();
+    Span::make(
+        source.text.clone(),
+        source.origin.clone(),
+        input.as_pair().as_span(),
+    )
+}
+
+/// The label with the given name, shared with the previous occurrences of the same name.
+fn intern_label(input: &ParseInput, name: &str) -> Label {
+    let mut labels = input.user_data().labels.borrow_mut();
+    match labels.get(name) {
+        Some(label) => label.clone(),
+        None => {
+            let label = Label::from(name);
+            labels.insert(label.clone());
+            label
+        }
+    }
+}
+
+/// Joins consecutive plain text chunks, allocating once per run of plain text.
+fn collect_text<'input>(
+    chunks: impl Iterator<Item = TextChunk<'input>>,
+) -> ParsedText {
+    let mut head = String::new();
+    let mut tail: Vec<(Expr, String)> = Vec::new();
+    for chunk in chunks {
+        match chunk {
+            TextChunk::Text(s) => match tail.last_mut() {
+                Some((_, text)) => text.push_str(&s),
+                None => head.push_str(&s),
+            },
+            TextChunk::Expr(e) => tail.push((e, String::new())),
+        }
+    }
+    InterpolatedText::from((head, tail))
 }
 fn spanned(input: ParseInput, x: UnspannedExpr) -> Expr {
     Expr::new(x, input_to_span(input))
@@ -98,6 +151,7 @@ fn insert_recordlit_entry(map: &mut BTreeMap<Label, Expr>, l: Label, e: Expr) {
             let dummy = Expr::new(Num(Bool(false)), Span::Artificial);
             let other = entry.insert(dummy);
             let span = Span::DuplicateRecordFieldsSugar(
+                entry.key().clone(),
                 Box::new(other.span()),
                 Box::new(e.span()),
             );
@@ -109,6 +163,31 @@ fn insert_recordlit_entry(map: &mut BTreeMap<Label, Expr>, l: Label, e: Expr) {
     }
 }
 
+/// Parse the children of `input` as a list of labelled entries, failing if a label appears twice.
+/// The error points to the second occurrence and mentions the position of the first one.
+fn parse_unique_entries<T>(
+    input: ParseInput,
+    kind: &str,
+    context: &str,
+    parse_entry: impl Fn(ParseInput) -> ParseResult<(Label, T)>,
+) -> ParseResult<BTreeMap<Label, T>> {
+    let mut map = BTreeMap::new();
+    let mut positions = HashMap::new();
+    for node in input.children() {
+        let (line, col) = node.as_span().start_pos().line_col();
+        let (l, x) = parse_entry(node.clone())?;
+        if let Some((first_line, first_col)) = positions.get(&l) {
+            return Err(node.error(format!(
+                "Duplicate {} `{}` in {}, first defined at {}:{}",
+                kind, l, context, first_line, first_col
+            )));
+        }
+        positions.insert(l.clone(), (line, col));
+        map.insert(l, x);
+    }
+    Ok(map)
+}
+
 lazy_static::lazy_static! {
     static ref PRECCLIMBER: PrecClimber<Rule> = {
         use Rule::*;
@@ -155,53 +234,45 @@ impl DhallParser {
 
     #[alias(label)]
     fn simple_label(input: ParseInput) -> ParseResult<Label> {
-        Ok(Label::from(input.as_str()))
+        Ok(intern_label(&input, input.as_str()))
     }
     #[alias(label)]
     fn quoted_label(input: ParseInput) -> ParseResult<Label> {
-        Ok(Label::from(input.as_str()))
+        Ok(intern_label(&input, input.as_str()))
     }
 
     #[alias(label)]
     fn any_label_or_some(input: ParseInput) -> ParseResult<Label> {
-        Ok(match_nodes!(input.into_children();
+        Ok(match_nodes!(input.children();
             [label(l)] => l,
-            [Some_(_)] => Label::from("Some"),
+            [Some_(_)] => intern_label(&input, "Some"),
         ))
     }
 
     fn double_quote_literal(input: ParseInput) -> ParseResult<ParsedText> {
         Ok(match_nodes!(input.into_children();
-            [double_quote_chunk(chunks)..] => {
-                chunks.collect()
-            }
+            [double_quote_chunk(chunks)..] => collect_text(chunks),
         ))
     }
 
-    fn double_quote_chunk(
-        input: ParseInput,
-    ) -> ParseResult<ParsedTextContents> {
+    fn double_quote_chunk(input: ParseInput) -> ParseResult<TextChunk> {
         Ok(match_nodes!(input.into_children();
-            [expression(e)] => {
-                InterpolatedTextContents::Expr(e)
-            },
-            [double_quote_char(s)] => {
-                InterpolatedTextContents::Text(s)
-            },
+            [expression(e)] => TextChunk::Expr(e),
+            [double_quote_char(s)] => TextChunk::Text(s),
         ))
     }
     #[alias(double_quote_char)]
-    fn double_quote_escaped(input: ParseInput) -> ParseResult<String> {
+    fn double_quote_escaped(input: ParseInput) -> ParseResult<Cow<str>> {
         Ok(match input.as_str() {
-            "\"" => "\"".to_owned(),
-            "$" => "$".to_owned(),
-            "\\" => "\\".to_owned(),
-            "/" => "/".to_owned(),
-            "b" => "\u{0008}".to_owned(),
-            "f" => "\u{000C}".to_owned(),
-            "n" => "\n".to_owned(),
-            "r" => "\r".to_owned(),
-            "t" => "\t".to_owned(),
+            "\"" => "\"".into(),
+            "$" => "$".into(),
+            "\\" => "\\".into(),
+            "/" => "/".into(),
+            "b" => "\u{0008}".into(),
+            "f" => "\u{000C}".into(),
+            "n" => "\n".into(),
+            "r" => "\r".into(),
+            "t" => "\t".into(),
             // "uXXXX" or "u{XXXXX}"
             s => {
                 use std::convert::TryInto;
@@ -261,12 +332,12 @@ impl DhallParser {
                     _ => {}
                 }
                 let c: char = i.try_into().unwrap();
-                std::iter::once(c).collect()
+                Cow::Owned(c.to_string())
             }
         })
     }
-    fn double_quote_char(input: ParseInput) -> ParseResult<String> {
-        Ok(input.as_str().to_owned())
+    fn double_quote_char(input: ParseInput) -> ParseResult<Cow<str>> {
+        Ok(input.as_str().into())
     }
 
     fn single_quote_literal(input: ParseInput) -> ParseResult<ParsedText> {
@@ -278,7 +349,7 @@ impl DhallParser {
                 let mut lines: Vec<ParsedText> = lines
                     .into_iter()
                     .rev()
-                    .map(|l| l.into_iter().rev().collect::<ParsedText>())
+                    .map(|l| collect_text(l.into_iter().rev()))
                     .collect();
 
                 trim_indent(&mut lines);
@@ -304,12 +375,11 @@ impl DhallParser {
     // Returns a vec of lines in reversed order, where each line is also in reversed order.
     fn single_quote_continue(
         input: ParseInput,
-    ) -> ParseResult<Vec<Vec<ParsedTextContents>>> {
+    ) -> ParseResult<Vec<Vec<TextChunk>>> {
         Ok(match_nodes!(input.into_children();
             [expression(e), single_quote_continue(lines)] => {
-                let c = InterpolatedTextContents::Expr(e);
                 let mut lines = lines;
-                lines.last_mut().unwrap().push(c);
+                lines.last_mut().unwrap().push(TextChunk::Expr(e));
                 lines
             },
             [single_quote_char(c), single_quote_continue(lines)] => {
@@ -317,9 +387,7 @@ impl DhallParser {
                 if c == "\n" || c == "\r\n" {
                     lines.push(vec![]);
                 } else {
-                    // TODO: don't allocate for every char
-                    let c = InterpolatedTextContents::Text(c.to_owned());
-                    lines.last_mut().unwrap().push(c);
+                    lines.last_mut().unwrap().push(TextChunk::Text(c.into()));
                 }
                 lines
             },
@@ -380,7 +448,7 @@ impl DhallParser {
         let s = input.as_str().trim();
         if s.starts_with("0x") {
             let without_prefix = s.trim_start_matches("0x");
-            u64::from_str_radix(without_prefix, 16)
+            num_traits::Num::from_str_radix(without_prefix, 16)
                 .map_err(|e| input.error(format!("{}", e)))
         } else {
             s.parse().map_err(|e| input.error(format!("{}", e)))
@@ -393,7 +461,7 @@ impl DhallParser {
         if rest.starts_with("0x") {
             let without_prefix =
                 sign.to_owned() + rest.trim_start_matches("0x");
-            i64::from_str_radix(&without_prefix, 16)
+            num_traits::Num::from_str_radix(&without_prefix, 16)
                 .map_err(|e| input.error(format!("{}", e)))
         } else {
             s.parse().map_err(|e| input.error(format!("{}", e)))
@@ -409,8 +477,15 @@ impl DhallParser {
     }
 
     fn variable(input: ParseInput) -> ParseResult<V> {
-        Ok(match_nodes!(input.into_children();
-            [label(l), natural_literal(idx)] => V(l, idx as usize),
+        Ok(match_nodes!(input.children();
+            [label(l), natural_literal(idx)] => {
+                match num_traits::ToPrimitive::to_usize(&idx) {
+                    Some(idx) => V(l, idx),
+                    None => {
+                        return Err(input.error("Variable index is too large"))
+                    }
+                }
+            },
             [label(l)] => V(l, 0),
         ))
     }
@@ -421,30 +496,7 @@ impl DhallParser {
     }
     #[alias(path_component)]
     fn quoted_path_component(input: ParseInput) -> ParseResult<String> {
-        #[rustfmt::skip]
-        const RESERVED: &percent_encoding::AsciiSet =
-            &percent_encoding::CONTROLS
-            .add(b'=').add(b':').add(b'/').add(b'?')
-            .add(b'#').add(b'[').add(b']').add(b'@')
-            .add(b'!').add(b'$').add(b'&').add(b'\'')
-            .add(b'(').add(b')').add(b'*').add(b'+')
-            .add(b',').add(b';');
-        Ok(input
-            .as_str()
-            .chars()
-            .map(|c| {
-                // Percent-encode ascii chars
-                if c.is_ascii() {
-                    percent_encoding::utf8_percent_encode(
-                        &c.to_string(),
-                        RESERVED,
-                    )
-                    .to_string()
-                } else {
-                    c.to_string()
-                }
-            })
-            .collect())
+        Ok(input.as_str().to_string())
     }
     fn path(input: ParseInput) -> ParseResult<FilePath> {
         Ok(match_nodes!(input.into_children();
@@ -526,7 +578,30 @@ impl DhallParser {
         ))
     }
 
-    fn authority(input: ParseInput) -> ParseResult<String> {
+    fn authority(input: ParseInput) -> ParseResult<Authority> {
+        Ok(match_nodes!(input.into_children();
+            [host(host)] => Authority { userinfo: None, host, port: None },
+            [host(host), port(port)] => {
+                Authority { userinfo: None, host, port: Some(port) }
+            },
+            [userinfo(userinfo), host(host)] => {
+                Authority { userinfo: Some(userinfo), host, port: None }
+            },
+            [userinfo(userinfo), host(host), port(port)] => {
+                Authority { userinfo: Some(userinfo), host, port: Some(port) }
+            },
+        ))
+    }
+
+    fn userinfo(input: ParseInput) -> ParseResult<String> {
+        Ok(input.as_str().to_owned())
+    }
+
+    fn host(input: ParseInput) -> ParseResult<String> {
+        Ok(input.as_str().to_owned())
+    }
+
+    fn port(input: ParseInput) -> ParseResult<String> {
         Ok(input.as_str().to_owned())
     }
 
@@ -864,20 +939,10 @@ impl DhallParser {
     }
 
     fn labels(input: ParseInput) -> ParseResult<BTreeSet<Label>> {
-        Ok(match_nodes!(input.children();
-            [label(ls)..] => {
-                let mut set = BTreeSet::default();
-                for l in ls {
-                    if set.contains(&l) {
-                        return Err(
-                            input.error(format!("Duplicate field in projection"))
-                        )
-                    }
-                    set.insert(l);
-                }
-                set
-            },
-        ))
+        let map = parse_unique_entries(input, "field", "projection", |node| {
+            Ok((Self::label(node)?, ()))
+        })?;
+        Ok(map.into_iter().map(|(l, ())| l).collect())
     }
 
     #[alias(expression, shortcut = true)]
@@ -910,26 +975,12 @@ impl DhallParser {
     fn non_empty_record_type(
         input: ParseInput,
     ) -> ParseResult<BTreeMap<Label, Expr>> {
-        Ok(match_nodes!(input.children();
-            [record_type_entry(entries)..] => {
-                let mut map = BTreeMap::default();
-                for (l, t) in entries {
-                    use std::collections::btree_map::Entry;
-                    match map.entry(l) {
-                        Entry::Occupied(_) => {
-                            return Err(input.error(
-                                "Duplicate field in record type"
-                                    .to_string(),
-                            ));
-                        }
-                        Entry::Vacant(e) => {
-                            e.insert(t);
-                        }
-                    }
-                }
-                map
-            },
-        ))
+        parse_unique_entries(
+            input,
+            "field",
+            "record type",
+            Self::record_type_entry,
+        )
     }
 
     fn record_type_entry(input: ParseInput) -> ParseResult<(Label, Expr)> {
@@ -975,26 +1026,13 @@ impl DhallParser {
     }
 
     fn union_type(input: ParseInput) -> ParseResult<UnspannedExpr> {
-        Ok(match_nodes!(input.children();
-            [union_type_entry(entries)..] => {
-                let mut map = BTreeMap::default();
-                for (l, t) in entries {
-                    use std::collections::btree_map::Entry;
-                    match map.entry(l) {
-                        Entry::Occupied(_) => {
-                            return Err(input.error(
-                                "Duplicate variant in union type"
-                                    .to_string(),
-                            ));
-                        }
-                        Entry::Vacant(e) => {
-                            e.insert(t);
-                        }
-                    }
-                }
-                UnionType(map)
-            },
-        ))
+        let map = parse_unique_entries(
+            input,
+            "variant",
+            "union type",
+            Self::union_type_entry,
+        )?;
+        Ok(UnionType(map))
     }
 
     fn union_type_entry(
@@ -1024,16 +1062,594 @@ impl DhallParser {
     }
 }
 
+/// How deeply expressions can be nested by default.
+const DEFAULT_MAX_NESTING_DEPTH: usize = 1000;
+
+/// The stack the parser needs for each level of nesting, with room to spare: it is recursive, and
+/// its frames are large in debug builds, where a level takes up to about 40 KiB.
+const STACK_PER_NESTING_LEVEL: usize = 64 * 1024;
+
+/// Rejects inputs that nest expressions more than `max_depth` levels deep, before they reach the
+/// parser, and returns how deeply they are nested. This only looks at the structure of the input;
+/// everything else is left to the parser, including reporting unbalanced brackets.
+///
+/// Besides brackets and text interpolations, this counts the expressions that have no closing
+/// bracket but that the parser still nests: the right-hand sides of arrows, the branches of an
+/// `if`, the bindings and body of a `let`, and what follows a `:` or an `=`. Those end at the end
+/// of the enclosing bracket, at a `,` or a `|`, or at the keyword that ends them, e.g. at the next
+/// `let` for the value of a binding.
+fn check_nesting_depth(
+    input_str: &str,
+    max_depth: usize,
+) -> ParseResult<usize> {
+    #[derive(PartialEq)]
+    enum Nested {
+        /// A bracket, closed by the given character.
+        Bracket(u8),
+        Interpolation,
+        DoubleQuoted,
+        SingleQuoted,
+        /// The right-hand side of an arrow.
+        Arrow,
+        /// The branches of an `if`.
+        If,
+        /// The bindings and body of a `let`.
+        Let,
+        /// What follows a `:` or an `=`.
+        Value,
+    }
+    use Nested::*;
+    let is_open_expr = |n: &Nested| matches!(n, Arrow | If | Let | Value);
+    // Closes the expressions that the innermost `kind` of the current bracket contains, keeping
+    // it open. Returns whether there was one.
+    let close_to = |stack: &mut Vec<Nested>, kind: Nested| {
+        let open = stack.iter().rev().take_while(|n| is_open_expr(n));
+        match open.clone().position(|n| *n == kind) {
+            Some(pos) => {
+                stack.truncate(stack.len() - pos);
+                true
+            }
+            None => false,
+        }
+    };
+    // Closes all the expressions of the current bracket.
+    let close_all = |stack: &mut Vec<Nested>| {
+        while stack.last().is_some_and(is_open_expr) {
+            stack.pop();
+        }
+    };
+
+    let bytes = input_str.as_bytes();
+    let at = |i: usize| bytes.get(i).copied();
+    let is_label_char =
+        |b: u8| b.is_ascii_alphanumeric() || b"-/_".contains(&b);
+    let mut stack: Vec<Nested> = Vec::new();
+    // Text literals are on the stack, but don't nest.
+    let mut text_literals = 0;
+    let mut deepest = 0;
+    // Whether an expression can start here, as opposed to continuing the current one. A `let`
+    // there starts a nested `let`; elsewhere, it starts the next binding of the current one.
+    let mut expr_start = true;
+    // Whether the next `=` is that of a `with` clause, which doesn't nest.
+    let mut in_with = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let token_start = i;
+        let depth_before = stack.len();
+        let mut starts_expr = false;
+        match (stack.last(), bytes[i]) {
+            (Some(DoubleQuoted), b'\\') => i += 1,
+            (Some(DoubleQuoted), b'"') => {
+                stack.pop();
+                text_literals -= 1;
+            }
+            (Some(SingleQuoted), b'\'') if at(i + 1) == Some(b'\'') => {
+                if at(i + 2) == Some(b'\'') {
+                    i += 2;
+                } else if at(i + 2) == Some(b'$') {
+                    i += 3;
+                } else {
+                    stack.pop();
+                    text_literals -= 1;
+                    i += 1;
+                }
+            }
+            (Some(DoubleQuoted), b'$') | (Some(SingleQuoted), b'$')
+                if at(i + 1) == Some(b'{') =>
+            {
+                stack.push(Interpolation);
+                starts_expr = true;
+                i += 1;
+            }
+            (Some(DoubleQuoted), _) | (Some(SingleQuoted), _) => {}
+            (_, b'-') if at(i + 1) == Some(b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            (_, b'{') if at(i + 1) == Some(b'-') => {
+                let mut comment_depth = 0;
+                while i < bytes.len() {
+                    match (bytes[i], at(i + 1)) {
+                        (b'{', Some(b'-')) => comment_depth += 1,
+                        (b'-', Some(b'}')) => comment_depth -= 1,
+                        _ => {
+                            i += 1;
+                            continue;
+                        }
+                    }
+                    i += 2;
+                    if comment_depth == 0 {
+                        break;
+                    }
+                }
+                continue;
+            }
+            (_, b' ') | (_, b'\t') | (_, b'\n') | (_, b'\r') => {
+                i += 1;
+                continue;
+            }
+            (_, b'`') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'`' {
+                    i += 1;
+                }
+            }
+            (_, b'"') => {
+                stack.push(DoubleQuoted);
+                text_literals += 1;
+            }
+            (_, b'\'') if at(i + 1) == Some(b'\'') => {
+                stack.push(SingleQuoted);
+                text_literals += 1;
+                i += 1;
+            }
+            (_, b'(') | (_, b'[') | (_, b'{') | (_, b'<') => {
+                let closing = match bytes[i] {
+                    b'(' => b')',
+                    b'[' => b']',
+                    b'{' => b'}',
+                    _ => b'>',
+                };
+                stack.push(Bracket(closing));
+                starts_expr = true;
+            }
+            (_, b')') | (_, b']') | (_, b'}') | (_, b'>') => {
+                close_all(&mut stack);
+                match stack.last() {
+                    Some(Bracket(closing)) if *closing == bytes[i] => {
+                        stack.pop();
+                    }
+                    Some(Interpolation) if bytes[i] == b'}' => {
+                        stack.pop();
+                    }
+                    _ => {}
+                }
+            }
+            (_, b',') => {
+                close_all(&mut stack);
+                starts_expr = true;
+            }
+            (_, b'|') if at(i + 1) == Some(b'|') => i += 1,
+            (_, b'|') => {
+                close_all(&mut stack);
+                starts_expr = true;
+            }
+            (_, b'-') if at(i + 1) == Some(b'>') => {
+                stack.push(Arrow);
+                starts_expr = true;
+                i += 1;
+            }
+            _ if bytes[i..].starts_with("→".as_bytes()) => {
+                stack.push(Arrow);
+                starts_expr = true;
+                i += '→'.len_utf8() - 1;
+            }
+            // An annotation is followed by whitespace, unlike the `:` of `env:`, `https:` or `::`.
+            (_, b':')
+                if at(i + 1).is_some_and(|b| b" \t\n\r".contains(&b))
+                    || bytes[i + 1..].starts_with(b"{-") =>
+            {
+                stack.push(Value);
+                starts_expr = true;
+            }
+            (_, b'=') | (_, b'!') if at(i + 1) == Some(b'=') => {
+                while at(i + 1) == Some(b'=') {
+                    i += 1;
+                }
+            }
+            (_, b'=') if in_with => in_with = false,
+            (_, b'=') => {
+                stack.push(Value);
+                starts_expr = true;
+            }
+            (_, b) if b.is_ascii_alphabetic() || b == b'_' => {
+                let start = i;
+                while at(i + 1).is_some_and(is_label_char) {
+                    i += 1;
+                }
+                // Path components and fields can't be keywords.
+                let in_path = start > 0 && b"./".contains(&bytes[start - 1]);
+                match &input_str[start..=i] {
+                    _ if in_path => {}
+                    "if" => {
+                        stack.push(If);
+                        starts_expr = true;
+                    }
+                    "then" | "else" => {
+                        close_to(&mut stack, If);
+                        starts_expr = true;
+                    }
+                    "let" => {
+                        if expr_start || !close_to(&mut stack, Let) {
+                            stack.push(Let);
+                        }
+                        starts_expr = true;
+                    }
+                    "in" => {
+                        close_to(&mut stack, Let);
+                        starts_expr = true;
+                    }
+                    "with" => in_with = true,
+                    _ => {}
+                }
+            }
+            (_, b) if b.is_ascii_digit() => {
+                while at(i + 1)
+                    .is_some_and(|b| b.is_ascii_alphanumeric() || b == b'.')
+                {
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        expr_start = starts_expr;
+        let depth = stack.len() - text_literals;
+        if stack.len() > depth_before && depth > max_depth {
+            let pos = pest::Position::new(input_str, token_start).unwrap();
+            return Err(ParseError::new_from_pos(
+                pest::error::ErrorVariant::CustomError {
+                    message: format!(
+                        "nesting too deep (more than {} levels)",
+                        max_depth
+                    ),
+                },
+                pos,
+            ));
+        }
+        deepest = std::cmp::max(deepest, depth);
+        i += 1;
+    }
+    Ok(deepest)
+}
+
+/// Limits on the size of parsed expressions, to protect services that parse untrusted input, see
+/// `parse_expr_with_limits`. By default, only the nesting depth is limited.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// The maximum size of a text literal in the source, in bytes. This includes the quotes and
+    /// any interpolated expressions.
+    pub max_text_literal_len: Option<usize>,
+    /// The maximum number of elements of a list literal.
+    pub max_list_len: Option<usize>,
+    /// The maximum number of nodes of the parse tree. It grows linearly with the size of the
+    /// resulting expression, and is typically 3 to 10 times its number of nodes.
+    pub max_nodes: Option<usize>,
+    /// How deeply expressions can be nested, 1000 levels by default. This counts brackets and
+    /// text interpolations, but also e.g. the bodies of lambdas and the branches of `if`s. The
+    /// parser is recursive, so it needs more stack the deeper the input is nested.
+    pub max_nesting_depth: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_text_literal_len: None,
+            max_list_len: None,
+            max_nodes: None,
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+        }
+    }
+}
+
+/// Rejects parse trees that exceed `limits`, before they get turned into an expression.
+fn check_limits(
+    pairs: &pest::iterators::Pairs<Rule>,
+    limits: ParseLimits,
+) -> ParseResult<()> {
+    if limits.max_text_literal_len.is_none()
+        && limits.max_list_len.is_none()
+        && limits.max_nodes.is_none()
+    {
+        return Ok(());
+    }
+    let error = |pair: &pest::iterators::Pair<Rule>, message| {
+        Err(ParseError::new_from_span(
+            pest::error::ErrorVariant::CustomError { message },
+            pair.as_span(),
+        ))
+    };
+    for (i, pair) in pairs.clone().flatten().enumerate() {
+        if let Some(max) = limits.max_nodes {
+            if i >= max {
+                return error(
+                    &pair,
+                    format!("expression has more than {} syntax nodes", max),
+                );
+            }
+        }
+        match pair.as_rule() {
+            Rule::double_quote_literal | Rule::single_quote_literal => {
+                let len = pair.as_str().len();
+                match limits.max_text_literal_len {
+                    Some(max) if len > max => {
+                        return error(
+                            &pair,
+                            format!(
+                                "text literal is {} bytes long, more than the \
+                                 limit of {}",
+                                len, max
+                            ),
+                        )
+                    }
+                    _ => {}
+                }
+            }
+            Rule::non_empty_list_literal => match limits.max_list_len {
+                Some(max) if pair.clone().into_inner().count() > max => {
+                    return error(
+                        &pair,
+                        format!("list literal has more than {} elements", max),
+                    )
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Parses a path of nested record fields separated by dots, like `services.web.port`. Fields
+/// whose name isn't a simple label are quoted with backticks, like ``services.`web.v2`.port``.
+pub fn parse_field_path(input_str: &str) -> ParseResult<Vec<Label>> {
+    let error = |i, message: &str| {
+        Err(ParseError::new_from_pos(
+            pest::error::ErrorVariant::CustomError {
+                message: message.to_string(),
+            },
+            pest::Position::new(input_str, i).unwrap(),
+        ))
+    };
+    let mut labels = Vec::new();
+    let mut start = 0;
+    loop {
+        let rest = &input_str[start..];
+        let (label, end) = if let Some(quoted) = rest.strip_prefix('`') {
+            let len = match quoted.find('`') {
+                Some(len) => len,
+                None => return error(start, "unterminated quoted field name"),
+            };
+            let label = &quoted[..len];
+            let invalid = label.find(|c| !(' '..='~').contains(&c));
+            if let Some(i) = invalid {
+                return error(
+                    start + 1 + i,
+                    "invalid character in a field name",
+                );
+            }
+            (label, start + len + 2)
+        } else {
+            let label = &rest[..rest.find('.').unwrap_or(rest.len())];
+            if label.is_empty() {
+                return error(start, "expected a field name");
+            }
+            let invalid = label.char_indices().find(|&(i, c)| {
+                let first = c.is_ascii_alphabetic() || c == '_';
+                let next = c.is_ascii_digit() || c == '-' || c == '/';
+                !(first || (i > 0 && next))
+            });
+            if let Some((i, _)) = invalid {
+                return error(
+                    start + i,
+                    "invalid character in a field name, quote it with `backticks`",
+                );
+            }
+            (label, start + label.len())
+        };
+        labels.push(Label::from(label));
+        match input_str[end..].chars().next() {
+            None => return Ok(labels),
+            Some('.') => start = end + 1,
+            Some(_) => return error(end, "expected `.` after a field name"),
+        }
+    }
+}
+
 pub fn parse_expr(input_str: &str) -> ParseResult<Expr> {
-    let rc_input_str = input_str.to_string().into();
-    let inputs = DhallParser::parse_with_userdata(
-        Rule::final_expression,
-        input_str,
-        rc_input_str,
-    )?;
-    Ok(match_nodes!(<DhallParser>; inputs;
-        [expression(e)] => e,
-    ))
+    parse_expr_with_limits(input_str, ParseLimits::default())
+}
+
+/// Like `parse_expr`, but fails if the expression exceeds `limits`. This is checked on the parse
+/// tree, before the expression gets built.
+pub fn parse_expr_with_limits(
+    input_str: &str,
+    limits: ParseLimits,
+) -> ParseResult<Expr> {
+    parse_expr_from(input_str, limits, None)
+}
+
+/// Like `parse_expr_with_limits`, for text read from `origin`, a file path or URL. The spans of
+/// the expression remember it.
+pub(crate) fn parse_expr_from(
+    input_str: &str,
+    limits: ParseLimits,
+    origin: Option<&str>,
+) -> ParseResult<Expr> {
+    let depth = check_nesting_depth(input_str, limits.max_nesting_depth)?;
+    let stack = (depth + 1) * STACK_PER_NESTING_LEVEL;
+    stacker::maybe_grow(stack, stack, || {
+        let source = Rc::new(Source {
+            text: input_str.into(),
+            origin: origin.map(Rc::from),
+            labels: RefCell::new(HashSet::new()),
+        });
+        let inputs = DhallParser::parse_with_userdata(
+            Rule::final_expression,
+            input_str,
+            source,
+        )?;
+        check_limits(inputs.as_pairs(), limits)?;
+        Ok(match_nodes!(<DhallParser>; inputs;
+            [expression(e)] => e,
+        ))
+    })
+}
+
+#[test]
+fn test_nesting_depth() {
+    let nested = |n| "(".repeat(n) + "1" + &")".repeat(n);
+    let is_too_deep = |s: &str| match parse_expr(s) {
+        Err(e) => e.to_string().contains("nesting too deep"),
+        Ok(_) => false,
+    };
+    assert!(parse_expr(&nested(5)).is_ok());
+    assert!(is_too_deep(&nested(DEFAULT_MAX_NESTING_DEPTH + 1)));
+    assert!(is_too_deep(&"[".repeat(100_000)));
+    let shallow = ParseLimits {
+        max_nesting_depth: 4,
+        ..ParseLimits::default()
+    };
+    assert!(parse_expr_with_limits(&nested(4), shallow).is_ok());
+    assert!(parse_expr_with_limits(&nested(5), shallow).is_err());
+    // Interpolations count, but brackets in text and comments don't.
+    let interpolated = "\"${".repeat(200) + "1" + &"}\"".repeat(200);
+    assert!(is_too_deep(&("[".repeat(801) + &interpolated)));
+    let text = format!("\"{}\" -- {}\n", "(".repeat(200), "[".repeat(200));
+    assert!(parse_expr(&text).is_ok());
+    let text = format!("''\n{}'' {{- {} -}}", "{".repeat(200), "(".repeat(200));
+    assert!(parse_expr(&text).is_ok());
+
+    // Expressions that nest without brackets count too, and get enough stack up to the limit.
+    // How each link of the chain starts, and ends after the innermost expression, with how many
+    // levels it counts for.
+    let chains = [
+        ("\\(x : Bool) -> ", "", 1),
+        ("λ(x : Bool) → ", "", 1),
+        ("forall (x : Type) -> ", "", 1),
+        ("Bool -> ", "", 1),
+        ("< A : Bool | B > -> ", "", 1),
+        ("if True then 1 else ", "", 1),
+        ("if True then ", " else 2", 1),
+        ("let x = 1 in ", "", 1),
+        ("1 : ", "", 1),
+        ("assert : ", "", 1),
+        ("let x = ", " in x", 2),
+    ];
+    for &(open, close, levels) in chains.iter() {
+        let chain = |n| open.repeat(n) + "x" + &close.repeat(n);
+        let ok_depth = DEFAULT_MAX_NESTING_DEPTH / levels - 10;
+        assert!(parse_expr(&chain(ok_depth)).is_ok(), "{}", open);
+        assert!(is_too_deep(&chain(20_000)), "{}", open);
+    }
+    // Chains that don't nest aren't limited.
+    let flat = [
+        ("", "let x = 1 ", "in x"),
+        ("", "let x : Natural = 1 ", "in x"),
+        ("", "1 + ", "1"),
+        ("", "f ", "1"),
+        ("", "[ 1 ] # ", "[ 1 ]"),
+        ("x", " with a = 1", ""),
+        ("[ ", "1 : Natural, ", "1 ]"),
+    ];
+    for &(start, link, end) in flat.iter() {
+        let chain = start.to_owned() + &link.repeat(2_000) + end;
+        assert!(parse_expr(&chain).is_ok(), "{}", link);
+    }
+}
+
+#[test]
+fn test_parse_limits() {
+    let fails_with =
+        |s: &str, limits, msg: &str| match parse_expr_with_limits(s, limits) {
+            Err(e) => e.to_string().contains(msg),
+            Ok(_) => false,
+        };
+    let text = ParseLimits {
+        max_text_literal_len: Some(10),
+        ..ParseLimits::default()
+    };
+    assert!(parse_expr_with_limits(r#"["12345678"]"#, text).is_ok());
+    assert!(fails_with(r#""123456789""#, text, "11 bytes long"));
+    assert!(fails_with("''\n1234567''", text, "12 bytes long"));
+    let list = ParseLimits {
+        max_list_len: Some(3),
+        ..ParseLimits::default()
+    };
+    assert!(parse_expr_with_limits("[[1, 2, 3], [4, 5, 6]]", list).is_ok());
+    assert!(fails_with(
+        "{ x = [1, 2, 3, 4] }",
+        list,
+        "more than 3 elements"
+    ));
+    let nodes = ParseLimits {
+        max_nodes: Some(100),
+        ..ParseLimits::default()
+    };
+    assert!(parse_expr_with_limits("{ x = 1 + 2 }", nodes).is_ok());
+    let long = vec!["1"; 100].join(" + ");
+    assert!(fails_with(&long, nodes, "more than 100 syntax nodes"));
+}
+
+#[test]
+fn test_field_path() {
+    let path = |s: &str| match parse_field_path(s) {
+        Ok(labels) => Ok(labels.iter().map(String::from).collect::<Vec<_>>()),
+        Err(e) => Err(e.to_string()),
+    };
+    assert_eq!(path("a"), Ok(vec!["a".to_string()]));
+    assert_eq!(
+        path("services.`web.v2`.port-1"),
+        Ok(vec!["services".into(), "web.v2".into(), "port-1".into()])
+    );
+    assert_eq!(path("``.if"), Ok(vec!["".into(), "if".into()]));
+    let fails_at = |s: &str, pos: &str, msg: &str| match path(s) {
+        Err(e) => assert!(e.contains(pos) && e.contains(msg), "{}", e),
+        Ok(labels) => panic!("parsed {:?}", labels),
+    };
+    fails_at("", "1:1", "expected a field name");
+    fails_at("a..b", "1:3", "expected a field name");
+    fails_at("a.", "1:3", "expected a field name");
+    fails_at("a.b c", "1:4", "quote it with `backticks`");
+    fails_at("a.1b", "1:3", "invalid character");
+    fails_at("a.`b", "1:3", "unterminated quoted field name");
+    fails_at("`a`b", "1:4", "expected `.` after a field name");
+}
+
+#[test]
+fn test_record_sugar() {
+    let same = |sugared: &str, plain: &str| {
+        assert_eq!(parse_expr(sugared).unwrap(), parse_expr(plain).unwrap())
+    };
+    // Field puns.
+    same("{ x, y }", "{ x = x, y = y }");
+    same("{x}", "{ x = x }");
+    same("{ , x, }", "{ x = x }");
+    same("{ x, y = 1 }", "{ x = x, y = 1 }");
+    same("{\n  x, -- comment\n  `y z`\n}", "{ x = x, `y z` = `y z` }");
+    // Dotted fields, which merge with the other fields of the same name.
+    same("{ a.b = 1 }", "{ a = { b = 1 } }");
+    same("{ a . `b c`.d = 1 }", "{ a = { `b c` = { d = 1 } } }");
+    same("{ a.b = 1, a.c = 2 }", "{ a = { b = 1 } ∧ { c = 2 } }");
+    same(
+        "{ a.b = 1, a = { c = 2 } }",
+        "{ a = { b = 1 } ∧ { c = 2 } }",
+    );
+    // Puns can't be dotted, and variables can't have an index.
+    assert!(parse_expr("{ x.y }").is_err());
+    assert!(parse_expr("{ x@1 }").is_err());
+    assert!(parse_expr("{ if }").is_err());
 }
 
 #[test]
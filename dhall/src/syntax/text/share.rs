@@ -0,0 +1,169 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::syntax::{Expr, ExprKind, Label, Span, V};
+use crate::utils::ensure_sufficient_stack;
+
+/// Subexpressions smaller than this many nodes are never shared.
+const MIN_SHARED_SIZE: usize = 5;
+
+/// Factors out subexpressions that occur more than once into `let` bindings at the top of the
+/// expression. Normalization tends to duplicate let-bound values, and this makes the printed
+/// result readable again.
+///
+/// Only closed subexpressions are shared, and the largest ones are shared first. The new
+/// bindings are named `_1`, `_2`, etc., skipping the names already used in the expression.
+pub(crate) fn share_subexpressions(expr: &Expr) -> Expr {
+    let mut used = HashSet::new();
+    collect_names(expr, &mut used);
+    let mut names = names_in_order(&used);
+
+    let mut body = expr.clone();
+    // Outermost first. A binding may only refer to the ones before it.
+    let mut bindings: Vec<(Label, Expr)> = Vec::new();
+    while let Some(shared) = find_shared(&body, &bindings) {
+        let name = names.next().unwrap();
+        let var =
+            Expr::new(ExprKind::Var(V(name.clone(), 0)), Span::Artificial);
+        body = replace(&body, &shared, &var);
+        for (_, val) in &mut bindings {
+            *val = replace(val, &shared, &var);
+        }
+        // `shared` is closed, so it can go first.
+        bindings.insert(0, (name, shared));
+    }
+
+    // Number the bindings in the order they are printed.
+    let renaming: HashMap<Label, Label> = bindings
+        .iter()
+        .map(|(name, _)| name.clone())
+        .zip(names_in_order(&used))
+        .collect();
+    bindings.into_iter().rev().fold(
+        rename(&body, &renaming),
+        |body, (name, val)| {
+            body.add_let_binding(
+                renaming[&name].clone(),
+                rename(&val, &renaming),
+            )
+        },
+    )
+}
+
+/// The names `_1`, `_2`, etc. that are not in `used`.
+fn names_in_order(used: &HashSet<Label>) -> impl Iterator<Item = Label> + '_ {
+    (1..)
+        .map(|i| Label::from(format!("_{}", i)))
+        .filter(move |l| !used.contains(l))
+}
+
+/// Finds the largest closed subexpression that occurs at least twice. Ties are broken by order of
+/// appearance so that the output is deterministic.
+fn find_shared(body: &Expr, bindings: &[(Label, Expr)]) -> Option<Expr> {
+    let mut occurrences = Occurrences::default();
+    for (_, val) in bindings {
+        occurrences.visit(val);
+    }
+    occurrences.visit(body);
+
+    let mut best = None;
+    let mut best_size = MIN_SHARED_SIZE - 1;
+    for e in &occurrences.order {
+        let (count, size) = occurrences.counts[e];
+        if count >= 2 && size > best_size {
+            best = Some(*e);
+            best_size = size;
+        }
+    }
+    best.cloned()
+}
+
+#[derive(Default)]
+struct Occurrences<'a> {
+    /// Number of occurrences and size of each closed subexpression.
+    counts: HashMap<&'a Expr, (usize, usize)>,
+    /// Closed subexpressions in order of first appearance.
+    order: Vec<&'a Expr>,
+}
+
+impl<'a> Occurrences<'a> {
+    /// Records the closed subexpressions of `e`. Returns the size of `e` and its free variables.
+    fn visit(&mut self, e: &'a Expr) -> (usize, HashSet<V>) {
+        ensure_sufficient_stack(|| {
+            let mut size = 1;
+            let mut free = HashSet::new();
+            if let ExprKind::Var(v) = e.kind() {
+                free.insert(v.clone());
+            }
+            e.kind()
+                .traverse_ref_maybe_binder(|binder, child| {
+                    let (child_size, child_free) = self.visit(child);
+                    size += child_size;
+                    free.extend(child_free.into_iter().filter_map(
+                        |V(l, idx)| match binder {
+                            Some(b) if *b == l && idx == 0 => None,
+                            Some(b) if *b == l => Some(V(l, idx - 1)),
+                            _ => Some(V(l, idx)),
+                        },
+                    ));
+                    Ok::<_, ()>(())
+                })
+                .unwrap();
+
+            if free.is_empty() {
+                let order = &mut self.order;
+                let entry = self.counts.entry(e).or_insert_with(|| {
+                    order.push(e);
+                    (0, size)
+                });
+                entry.0 += 1;
+            }
+            (size, free)
+        })
+    }
+}
+
+/// Replaces every occurrence of the closed expression `target` in `e` with `with`.
+fn replace(e: &Expr, target: &Expr, with: &Expr) -> Expr {
+    ensure_sufficient_stack(|| {
+        if e == target {
+            with.clone()
+        } else {
+            let kind = e.kind().map_ref(|child| replace(child, target, with));
+            Expr::new(kind, e.span())
+        }
+    })
+}
+
+/// Renames the variables introduced by `share_subexpressions`. Their names are not used anywhere
+/// else, so there is no shadowing to worry about.
+fn rename(e: &Expr, renaming: &HashMap<Label, Label>) -> Expr {
+    ensure_sufficient_stack(|| match e.kind() {
+        ExprKind::Var(V(l, 0)) if renaming.contains_key(l) => {
+            Expr::new(ExprKind::Var(V(renaming[l].clone(), 0)), e.span())
+        }
+        kind => {
+            Expr::new(kind.map_ref(|child| rename(child, renaming)), e.span())
+        }
+    })
+}
+
+/// Collects the names of all variables and binders in `e`.
+fn collect_names(e: &Expr, names: &mut HashSet<Label>) {
+    ensure_sufficient_stack(|| {
+        match e.kind() {
+            ExprKind::Var(V(l, _))
+            | ExprKind::Lam(l, _, _)
+            | ExprKind::Pi(l, _, _)
+            | ExprKind::Let(l, _, _, _) => {
+                names.insert(l.clone());
+            }
+            _ => {}
+        }
+        e.kind()
+            .traverse_ref(|child| {
+                collect_names(child, names);
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+    })
+}
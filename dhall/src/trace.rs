@@ -0,0 +1,206 @@
+//! Records the reduction steps done during normalization, to find out why an expression normalizes
+//! to an unexpected value.
+//!
+//! Tracing is enabled on a context with [`Ctxt::start_trace`], and the steps done since then are
+//! retrieved with [`Ctxt::take_trace`]. The steps recorded are the applications of functions and
+//! builtins and the operators (`+`, `if`, `merge`, field selection, etc.) that reduced to something
+//! else. Builtins like `List/fold` apply their arguments without going through these steps, so
+//! only the steps inside the functions they apply are recorded.
+//!
+//! Normalization is lazy, so steps are recorded when the values are needed. The body of a
+//! function is evaluated again each time the function is converted back to an expression, so the
+//! steps inside it can be recorded several times. Operands are shown in normal form, and variables
+//! bound outside of a snippet are shown as `_`.
+//!
+//! # Example
+//!
+//! ```rust
+//! # fn main() -> Result<(), dhall::error::Error> {
+//! use dhall::trace::{StepKind, TraceOptions};
+//! use dhall::{Ctxt, Parsed};
+//!
+//! Ctxt::with_new(|cx| {
+//!     cx.start_trace(TraceOptions::default());
+//!     let parsed = Parsed::parse_str("(λ(x : Natural) → x * 2) (1 + 2)")?;
+//!     let typed = parsed.skip_resolve(cx)?.typecheck(cx)?;
+//!     assert_eq!(typed.normalize(cx)?.to_expr(cx).to_string(), "6");
+//!
+//!     let trace = cx.take_trace().unwrap();
+//!     let steps: Vec<String> =
+//!         trace.steps.iter().map(|step| step.to_string()).collect();
+//!     assert_eq!(
+//!         steps,
+//!         vec![
+//!             "operation: 1 + 2 ~> 3",
+//!             "operation: 3 * 2 ~> 6",
+//!             "application: (λ(x : Natural) → x * 2) 3 ~> 6",
+//!         ]
+//!     );
+//!     assert_eq!(trace.steps[2].kind, StepKind::Application);
+//!     Ok(())
+//! })
+//! # }
+//! ```
+use std::fmt;
+
+use crate::operations::OpKind;
+use crate::semantics::{Nir, NirKind, VarEnv};
+use crate::syntax::Expr;
+use crate::Ctxt;
+
+/// Which steps to record.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct TraceOptions {
+    /// Records only one step out of this many, to trace long evaluations. All the steps are
+    /// recorded by default.
+    pub sample_every: Option<usize>,
+    /// Stops recording after this many steps. The later steps are still counted.
+    pub max_steps: Option<usize>,
+}
+
+/// The kind of a reduction step.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StepKind {
+    /// A function was applied to its argument.
+    Application,
+    /// A builtin was applied to enough arguments to compute its result.
+    Builtin,
+    /// An operator, or another construct like `if` or `merge`, was computed.
+    Operation,
+}
+
+/// One reduction step.
+#[derive(Debug, Clone)]
+pub struct TraceStep {
+    /// The number of steps done before this one since the trace started, including those that
+    /// were not recorded.
+    pub index: usize,
+    pub kind: StepKind,
+    /// The expression that was reduced.
+    pub before: Expr,
+    /// What it reduced to, in normal form.
+    pub after: Expr,
+}
+
+/// The steps recorded since `Ctxt::start_trace`.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    /// The recorded steps, in the order they were completed: the steps that a step needed to
+    /// reduce its operands come before it.
+    pub steps: Vec<TraceStep>,
+    /// The number of steps done, including those that were not recorded.
+    pub total_steps: usize,
+}
+
+/// The steps recorded so far, whose snippets are only computed when the trace is taken.
+pub(crate) struct Tracer<'cx> {
+    options: TraceOptions,
+    total_steps: usize,
+    steps: Vec<(usize, StepKind, Nir<'cx>, Nir<'cx>)>,
+}
+
+impl fmt::Display for StepKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            StepKind::Application => "application",
+            StepKind::Builtin => "builtin",
+            StepKind::Operation => "operation",
+        })
+    }
+}
+
+/// Prints the step on one line. The precision, e.g. in `{:.40}`, limits the length of each
+/// snippet.
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let snippet = |expr: &Expr| {
+            let mut s = expr.to_string().replace('\n', " ");
+            if let Some(max_len) = f.precision() {
+                if let Some((i, _)) = s.char_indices().nth(max_len) {
+                    s.truncate(i);
+                    s.push('…');
+                }
+            }
+            s
+        };
+        write!(
+            f,
+            "{}: {} ~> {}",
+            self.kind,
+            snippet(&self.before),
+            snippet(&self.after)
+        )
+    }
+}
+
+impl<'cx> Tracer<'cx> {
+    pub(crate) fn new(options: TraceOptions) -> Self {
+        Tracer {
+            options,
+            total_steps: 0,
+            steps: Vec::new(),
+        }
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        kind: StepKind,
+        before: Nir<'cx>,
+        after: Nir<'cx>,
+    ) {
+        let index = self.total_steps;
+        self.total_steps += 1;
+        let sampled = match self.options.sample_every {
+            Some(n) => matches!(index.checked_rem(n), None | Some(0)),
+            None => true,
+        };
+        let full = matches!(
+            self.options.max_steps,
+            Some(max) if self.steps.len() >= max
+        );
+        if sampled && !full {
+            self.steps.push((index, kind, before, after));
+        }
+    }
+
+    /// Converts the recorded values to expressions. This must run after tracing stopped, since
+    /// it evaluates the bodies of functions again.
+    pub(crate) fn finish(self, cx: Ctxt<'cx>) -> Trace {
+        let to_expr =
+            |nir: &Nir<'cx>| nir.to_hir(VarEnv::lenient()).to_expr_noopts(cx);
+        Trace {
+            steps: self
+                .steps
+                .iter()
+                .map(|(index, kind, before, after)| TraceStep {
+                    index: *index,
+                    kind: *kind,
+                    before: to_expr(before),
+                    after: to_expr(after),
+                })
+                .collect(),
+            total_steps: self.total_steps,
+        }
+    }
+}
+
+/// The kind of step that computing `op` is, or `None` if it didn't reduce to anything else.
+pub(crate) fn step_kind<'cx>(
+    op: &OpKind<Nir<'cx>>,
+    result: &NirKind<'cx>,
+) -> Option<StepKind> {
+    let kind = match op {
+        OpKind::App(f, _) => match f.kind() {
+            NirKind::LamClosure { .. } => StepKind::Application,
+            NirKind::AppliedBuiltin(..) => StepKind::Builtin,
+            _ => StepKind::Operation,
+        },
+        _ => StepKind::Operation,
+    };
+    match result {
+        // Partial or stuck application of a builtin.
+        NirKind::AppliedBuiltin(..) if kind == StepKind::Builtin => None,
+        NirKind::Op(result_op) if result_op == op => None,
+        _ => Some(kind),
+    }
+}
@@ -1,17 +1,116 @@
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::error::Error;
 
 // Compute the sha256 hash of a bitstring.
+#[cfg(feature = "binary")]
 pub fn sha256_hash(data: &[u8]) -> Box<[u8]> {
     use sha2::Digest;
     sha2::Sha256::digest(data).as_slice().into()
 }
 
+/// Runs `f`, first growing the stack if it is close to overflowing.
+///
+/// Most passes over expressions are recursive, so they call this at each level to support
+/// arbitrarily deeply nested expressions.
+pub(crate) fn ensure_sufficient_stack<R>(f: impl FnOnce() -> R) -> R {
+    const RED_ZONE: usize = 100 * 1024;
+    const NEW_STACK_SIZE: usize = 1024 * 1024;
+    stacker::maybe_grow(RED_ZONE, NEW_STACK_SIZE, f)
+}
+
 pub fn read_binary_file(path: impl AsRef<Path>) -> Result<Box<[u8]>, Error> {
     let mut buffer = Vec::new();
     File::open(path)?.read_to_end(&mut buffer)?;
     Ok(buffer.into())
 }
+
+/// Writes `contents` to a new file next to `path`, then renames it to `path`, so that readers
+/// never see a partially written file.
+pub fn write_file_atomically(
+    path: &Path,
+    contents: &[u8],
+) -> std::io::Result<()> {
+    // Distinguishes the temporary files of concurrent writes from this process.
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let file_name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("not a file path: {}", path.display()),
+        )
+    })?;
+    let tmp_path = path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Returns the candidate closest to `name` by edit distance, if it is close enough to be a likely
+/// typo, e.g. `maxConnections` for `maxConection`. Used to suggest names in error messages.
+pub(crate) fn closest_name<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let max_distance = std::cmp::max(name.chars().count() / 3, 1);
+    candidates
+        .into_iter()
+        .filter(|c| *c != name)
+        .map(|c| {
+            // A difference in case only is the most likely typo.
+            let distance = if c.to_lowercase() == name.to_lowercase() {
+                0
+            } else {
+                edit_distance(name, c)
+            };
+            (distance, c)
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c)
+}
+
+/// The Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // `row[j]` is the distance between the prefix of `a` seen so far and `b[..j]`.
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diag + if ca == *cb { 0 } else { 1 };
+            diag = row[j + 1];
+            row[j + 1] =
+                std::cmp::min(substitution, std::cmp::min(diag, row[j]) + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[test]
+fn test_closest_name() {
+    let names = ["maxConnections", "minConnections", "timeout", "x"];
+    let closest = |name| closest_name(name, names.iter().copied());
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(closest("maxConection"), Some("maxConnections"));
+    assert_eq!(closest("MaxConnections"), Some("maxConnections"));
+    assert_eq!(closest("timout"), Some("timeout"));
+    assert_eq!(closest("y"), Some("x"));
+    assert_eq!(closest("retries"), None);
+    assert_eq!(closest("x"), None);
+}
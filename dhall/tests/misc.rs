@@ -1,7 +1,37 @@
-use dhall::error::Error;
+use dhall::error::{Error, ErrorKind};
 use dhall::semantics::*;
 use dhall::syntax::*;
 use dhall::*;
+use num_traits::ToPrimitive;
+
+/// Evaluates `src`, resolving its imports, and prints its normal form.
+fn eval(src: &str) -> Result<String, Error> {
+    eval_with(src, |_| {})
+}
+
+/// Like `eval`, in a context configured by `setup`. The source is parsed with the parse limits of
+/// the context.
+fn eval_with(
+    src: &str,
+    setup: impl for<'cx> FnOnce(Ctxt<'cx>),
+) -> Result<String, Error> {
+    Ctxt::with_new(|cx| {
+        setup(cx);
+        let nf = Parsed::parse_str_with_limits(src, cx.parse_limits())?
+            .resolve(cx)?
+            .typecheck(cx)?
+            .normalize(cx)?;
+        cx.evaluate(|| nf.to_expr(cx).to_string())
+    })
+}
+
+/// Typechecks `src`, resolving its imports.
+fn typecheck(src: &str) -> Result<(), Error> {
+    Ctxt::with_new(|cx| {
+        Parsed::parse_str(src)?.resolve(cx)?.typecheck(cx)?;
+        Ok(())
+    })
+}
 
 /// Test that showcases someone using the `dhall` crate directly for a simple operation. If
 /// possible try not to break this too much. See
@@ -9,14 +39,15 @@ use dhall::*;
 #[test]
 fn manual_function_application() {
     /// Apply a `Natural -> Natural` function to an argument.
+    #[allow(clippy::useless_conversion)]
     fn apply_natnat_fn<'cx>(f: &Nir<'cx>, n: u64) -> u64 {
         // Convert the number to the internal representation.
-        let n_nir = Nir::from_kind(NirKind::Num(NumKind::Natural(n)));
+        let n_nir = Nir::from_kind(NirKind::Num(NumKind::Natural(n.into())));
         // Apply `f` to `n`.
         let m_nir = f.app(n_nir);
         // Convert from the internal representation.
         match m_nir.kind() {
-            NirKind::Num(NumKind::Natural(m)) => *m,
+            NirKind::Num(NumKind::Natural(m)) => m.to_u64().unwrap(),
             _ => panic!("`f` was not `Natural -> Natural`"),
         }
     }
@@ -28,14 +59,14 @@ fn manual_function_application() {
         let f_ty = Parsed::parse_str(f_ty)?
             .skip_resolve(cx)?
             .typecheck(cx)?
-            .normalize(cx);
+            .normalize(cx)?;
 
         // Parse the function `f` itself, and also check its type.
         let f = "\\(x: Natural) -> x + 3";
         let f = Parsed::parse_str(f)?
             .skip_resolve(cx)?
             .typecheck_with(cx, &f_ty.to_hir())?
-            .normalize(cx);
+            .normalize(cx)?;
 
         // Do whatever we want with `f`.
         for i in 0..5 {
@@ -48,3 +79,1382 @@ fn manual_function_application() {
     // The crate uses essentially a global context, created here.
     Ctxt::with_new(run).unwrap();
 }
+
+/// Deeply nested expressions must not overflow the stack.
+#[test]
+fn deeply_nested_expression() {
+    let n = 20000;
+    assert_eq!(eval(&vec!["1"; n].join(" + ")).unwrap(), n.to_string());
+
+    // These take longer to parse, so the chains are shorter.
+    let n = 5000;
+    let lists = vec!["[ 1 ]"; n].join(" # ");
+    let input = format!("List/length Natural ({})", lists);
+    assert_eq!(eval(&input).unwrap(), n.to_string());
+    let n = 500;
+    let lets: String = (1..n)
+        .map(|i| format!("let x{} = x{} + 1 ", i, i - 1))
+        .collect();
+    let input = format!("let x0 = 0 {}in x{}", lets, n - 1);
+    assert_eq!(eval(&input).unwrap(), (n - 1).to_string());
+}
+
+/// Long `let` chains nest as deeply as they are long once parsed, and must not overflow the stack
+/// either.
+#[test]
+fn long_let_chain() {
+    let n = 20000;
+    let input = "let x = 1 ".repeat(n) + "in x";
+    Ctxt::with_new(|cx| {
+        let resolved = Parsed::parse_str(&input).unwrap().resolve(cx).unwrap();
+        let annotated = resolved.annotate(cx).unwrap();
+        assert!(annotated.to_string().ends_with("in x : Natural"));
+        let nf = resolved.typecheck(cx).unwrap().normalize(cx).unwrap();
+        assert_eq!(nf.to_expr(cx).to_string(), "1");
+    });
+}
+
+/// Bundling inlines imports transitively and keeps their hashes on request.
+#[test]
+#[cfg(feature = "binary")]
+fn bundle_inlines_imports() {
+    let dir = std::env::temp_dir()
+        .join(format!("dhall-bundle-test-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("sub/port.dhall"), "8080").unwrap();
+    std::fs::write(dir.join("sub/dep.dhall"), "{ port = ./port.dhall }")
+        .unwrap();
+    let hash = Ctxt::with_new(|cx| -> Result<String, Error> {
+        let e = Parsed::parse_file(&dir.join("sub/dep.dhall"))?
+            .resolve(cx)?
+            .typecheck(cx)?
+            .normalize(cx)?
+            .to_expr_alpha(cx);
+        Ok(hex::encode(e.sha256_hash()?))
+    })
+    .unwrap();
+    let main =
+        format!("let dep = ./sub/dep.dhall sha256:{} in dep.port + 1", hash);
+    std::fs::write(dir.join("main.dhall"), main).unwrap();
+
+    let bundled = bundle(&dir.join("main.dhall")).unwrap();
+    assert_eq!(bundled, "let dep = { port = 8080 } in dep.port + 1\n");
+    let bundled = bundle_with(
+        &dir.join("main.dhall"),
+        BundleOptions { keep_hashes: true },
+    )
+    .unwrap();
+    assert_eq!(
+        bundled,
+        format!(
+            "-- ./sub/dep.dhall sha256:{}\n\
+             let dep = {{ port = 8080 }} in dep.port + 1\n",
+            hash
+        )
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Hashed imports go through a user-provided cache.
+#[test]
+#[cfg(feature = "binary")]
+fn custom_import_cache() {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct MemCache(RefCell<HashMap<String, Vec<u8>>>);
+    impl Cache for MemCache {
+        fn get(&self, hash: &Hash) -> std::io::Result<Option<Vec<u8>>> {
+            Ok(self.0.borrow().get(&hash.to_string()).cloned())
+        }
+        fn put(&self, hash: &Hash, data: &[u8]) -> std::io::Result<()> {
+            self.0.borrow_mut().insert(hash.to_string(), data.to_vec());
+            Ok(())
+        }
+    }
+
+    let dir = std::env::temp_dir()
+        .join(format!("dhall-cache-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("dep.dhall"), "40 + 2").unwrap();
+    let hash = hex::encode(parse_expr("42").unwrap().sha256_hash().unwrap());
+    std::fs::write(
+        dir.join("main.dhall"),
+        format!("./dep.dhall sha256:{}", hash),
+    )
+    .unwrap();
+
+    let cache = Rc::new(MemCache::default());
+    let eval = |parsed: Parsed| {
+        Ctxt::with_new(|cx| -> Result<String, Error> {
+            let e = parsed
+                .resolve_with_cache(cx, Some(cache.clone()))?
+                .typecheck(cx)?
+                .normalize(cx)?
+                .to_expr(cx);
+            Ok(e.to_string())
+        })
+    };
+    let main = Parsed::parse_file(&dir.join("main.dhall")).unwrap();
+    assert_eq!(eval(main).unwrap(), "42");
+    assert_eq!(cache.0.borrow().len(), 1);
+    // Only the cache knows about this one.
+    let missing =
+        Parsed::parse_str(&format!("missing sha256:{}", hash)).unwrap();
+    assert_eq!(eval(missing).unwrap(), "42");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// The store is keyed by semantic hashes, and shares its format with the import cache.
+#[test]
+#[cfg(feature = "binary")]
+fn content_addressed_store() {
+    use dhall::store::Store;
+    use std::rc::Rc;
+
+    let dir = std::env::temp_dir()
+        .join(format!("dhall-store-test-{}", std::process::id()));
+    let store = Store::with_dir(&dir).unwrap();
+    let put = |s: &str| store.put(&parse_expr(s).unwrap()).unwrap();
+
+    let hash = put("λ(x : Natural) → [x, 1 + 1]");
+    assert_eq!(put("λ(y : Natural) → [y, 2]"), hash);
+    assert_ne!(put("λ(y : Natural) → [y, 3]"), hash);
+    let expected = parse_expr("λ(_ : Natural) → [_, 2]").unwrap();
+    assert_eq!(hash, Hash::SHA256(expected.sha256_hash().unwrap()));
+    assert_eq!(
+        store.get(&hash).unwrap().unwrap().to_string(),
+        "λ(_ : Natural) → [_, 2]"
+    );
+
+    // Another process can import the stored expression by its hash.
+    let import = Parsed::parse_str(&format!("missing {} 5", hash)).unwrap();
+    let result = Ctxt::with_new(|cx| -> Result<String, Error> {
+        let cache = Rc::new(FsCache::with_dir(&dir)?);
+        let e = import
+            .resolve_with_cache(cx, Some(cache))?
+            .typecheck(cx)?
+            .normalize(cx)?
+            .to_expr(cx);
+        Ok(e.to_string())
+    });
+    assert_eq!(result.unwrap(), "[5, 2]");
+
+    assert!(store.put(&parse_expr("1 + True").unwrap()).is_err());
+    assert!(store.put(&parse_expr("./file.dhall").unwrap()).is_err());
+    let unknown = Hash::SHA256(parse_expr("0").unwrap().sha256_hash().unwrap());
+    assert!(store.get(&unknown).unwrap().is_none());
+    // Corrupted entries are rejected.
+    let file = format!("1220{}", &hash.to_string()["sha256:".len()..]);
+    std::fs::write(dir.join(file), b"not cbor").unwrap();
+    assert!(store.get(&hash).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// All NaNs encode to the canonical NaN, and `-0.0` is kept unless asked otherwise.
+#[test]
+#[cfg(feature = "binary")]
+fn encode_special_doubles() {
+    use dhall::syntax::binary::{encode, encode_with, EncodeOptions};
+    let double = |x: f64| {
+        Expr::new(ExprKind::Num(NumKind::Double(x.into())), Span::Artificial)
+    };
+    let other_nan = f64::from_bits(f64::NAN.to_bits() | 0x8000_0000_0000_0001);
+    assert!(other_nan.is_nan());
+
+    assert_eq!(NaiveDouble::from(other_nan), NaiveDouble::from(f64::NAN));
+    assert_ne!(NaiveDouble::from(-0.0), NaiveDouble::from(0.0));
+    assert_eq!(
+        f64::from(NaiveDouble::from(other_nan).canonicalize()).to_bits(),
+        f64::NAN.to_bits()
+    );
+
+    assert_eq!(encode(&double(other_nan)).unwrap(), vec![0xf9, 0x7e, 0x00]);
+    assert_eq!(encode(&double(-0.0)).unwrap(), vec![0xf9, 0x80, 0x00]);
+    let opts = EncodeOptions {
+        normalize_negative_zero: true,
+    };
+    assert_eq!(
+        encode_with(&double(-0.0), opts).unwrap(),
+        vec![0xf9, 0x00, 0x00]
+    );
+    assert_eq!(
+        double(other_nan).sha256_hash().unwrap(),
+        double(f64::NAN).sha256_hash().unwrap()
+    );
+}
+
+/// Expressions encoded by older versions of the standard are decoded when possible.
+#[test]
+#[cfg(feature = "binary")]
+fn decode_old_encodings() {
+    use dhall::error::DecodeError;
+    use dhall::syntax::binary::{decode, encode};
+    let expr = parse_expr("λ(x : Natural) → x + 1").unwrap();
+    let with_tag = |tag: &str| {
+        // A CBOR array of two elements, starting with a text string.
+        let mut data = vec![0x82, 0x60 + tag.len() as u8];
+        data.extend(tag.as_bytes());
+        data.extend(encode(&expr).unwrap());
+        data
+    };
+    assert_eq!(decode(&with_tag("1.0")).unwrap(), expr);
+    assert!(matches!(
+        decode(&with_tag("0.9")),
+        Err(DecodeError::UnsupportedEncodingVersion(v)) if v == "version tag 0.9"
+    ));
+    assert!(matches!(
+        decode(&[0x81, 0x0c]),
+        Err(DecodeError::UnsupportedEncodingVersion(_))
+    ));
+
+    // `Optional/fold` was removed from the standard.
+    let mut data = vec![0x6d];
+    data.extend(b"Optional/fold");
+    let fold = decode(&data).unwrap();
+    let src =
+        format!("({}) Natural (Some 1) Natural (λ(x : Natural) → x) 0", fold);
+    assert_eq!(eval(&src).unwrap(), "1");
+}
+
+/// Evaluation stops when its cancellation token is cancelled, even from another thread.
+#[test]
+fn cancel_evaluation() {
+    let is_cancelled = |res: Result<String, Error>| match res {
+        Err(e) => matches!(e.kind(), ErrorKind::Cancelled),
+        Ok(_) => false,
+    };
+
+    let token = CancellationToken::new();
+    token.cancel();
+    let res = eval_with("./some/file.dhall", |cx| {
+        cx.set_cancellation_token(token.clone())
+    });
+    assert!(is_cancelled(res));
+
+    // Typechecks quickly, but takes exponential time to normalize.
+    let mut slow = String::from(
+        "let f = \\(g : Natural -> Natural) -> \\(n : Natural) -> g (g n) \
+         let g0 = \\(n : Natural) -> n + 1 ",
+    );
+    for i in 1..=30 {
+        slow.push_str(&format!("let g{} = f g{} ", i, i - 1));
+    }
+    slow.push_str("in g30 0");
+    let token = CancellationToken::new();
+    let canceller = {
+        let token = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            token.cancel();
+        })
+    };
+    let res = eval_with(&slow, |cx| cx.set_cancellation_token(token.clone()));
+    canceller.join().unwrap();
+    assert!(is_cancelled(res));
+}
+
+/// Evaluation stops once it has built more than the allowed amount of memory.
+#[test]
+fn eval_memory_limit() {
+    let run = |src: &str| {
+        eval_with(src, |cx| {
+            cx.set_eval_limits(EvalLimits {
+                max_memory: Some(1_000_000),
+            })
+        })
+    };
+
+    let res = run(
+        "Natural/fold 3 (List Natural) (\\(l : List Natural) -> l # l) [1]",
+    );
+    assert_eq!(res.unwrap(), "[1, 1, 1, 1, 1, 1, 1, 1]");
+
+    let res = run(
+        "Natural/fold 20 (List Natural) (\\(l : List Natural) -> l # l) [1]",
+    );
+    match res {
+        Err(e) => assert!(
+            matches!(e.kind(), ErrorKind::MemoryLimitExceeded(1_000_000)),
+            "{}",
+            e
+        ),
+        Ok(_) => panic!("the memory limit was not enforced"),
+    }
+
+    // The context can still be used after the limit was exceeded, and the values that were being
+    // evaluated are complete once evaluation is allowed to finish.
+    let res = Ctxt::with_new(|cx| -> Result<String, Error> {
+        cx.set_eval_limits(EvalLimits {
+            max_memory: Some(10_000),
+        });
+        let src = "let xs = Natural/fold 10 (List Natural) \
+                   (\\(l : List Natural) -> l # l) [1] \
+                   in List/length Natural xs";
+        let typed = Parsed::parse_str(src)?.skip_resolve(cx)?.typecheck(cx)?;
+        let err = typed.normalize(cx).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::MemoryLimitExceeded(10_000)));
+        cx.set_eval_limits(EvalLimits { max_memory: None });
+        Ok(typed.normalize(cx)?.to_expr(cx).to_string())
+    });
+    assert_eq!(res.unwrap(), "1024");
+}
+
+/// `Natural` overflow is reported where the overflowing operation is evaluated.
+#[test]
+#[cfg(not(feature = "bigint"))]
+fn natural_overflow() {
+    use dhall::error::EvalError;
+
+    let is_overflow = |err: Error, expected: &str| match err.kind() {
+        ErrorKind::Eval(EvalError::NaturalOverflow(op)) => op == expected,
+        _ => false,
+    };
+    Ctxt::with_new(|cx| {
+        let typed = |src: &str| -> Result<_, Error> {
+            Ok(Parsed::parse_str(src)?.skip_resolve(cx)?.typecheck(cx)?)
+        };
+
+        let err = typed("18446744073709551615 + 1")?
+            .normalize(cx)
+            .unwrap_err();
+        assert!(is_overflow(err, "18446744073709551615 + 1"));
+
+        // The elements of a list are only evaluated when they are read.
+        let list = typed("[1, 4294967296 * 4294967296]")?.normalize(cx)?;
+        let err = cx.evaluate(|| list.to_expr(cx)).unwrap_err();
+        assert!(is_overflow(err, "4294967296 * 4294967296"));
+
+        let err = typed("Natural/toInteger 9223372036854775808")?
+            .normalize(cx)
+            .unwrap_err();
+        assert!(is_overflow(err, "Natural/toInteger 9223372036854775808"));
+
+        let err = typed("assert : 18446744073709551615 + 1 === 0").unwrap_err();
+        assert!(is_overflow(err, "18446744073709551615 + 1"));
+
+        let max = typed("18446744073709551614 + 1")?.normalize(cx)?;
+        assert_eq!(max.to_expr(cx).to_string(), "18446744073709551615");
+        Ok::<_, Error>(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn canonicalize_expr() {
+    let canonical = |s: &str| {
+        let e = parse_expr(s).unwrap().canonicalize();
+        assert!(matches!(e.span(), Span::Artificial));
+        e.to_string()
+    };
+    assert_eq!(
+        canonical("{ b = ./a/./b/../c.dhall, a = ./../d.dhall }"),
+        "{ a = ../d.dhall, b = ./a/c.dhall }"
+    );
+    assert_eq!(
+        canonical("https://example.com/a/../b/./c.dhall using ./h/../h.dhall"),
+        "https://example.com/b/c.dhall using ./h.dhall"
+    );
+    assert_eq!(canonical("../../a/../b.dhall"), "../../b.dhall");
+    assert_eq!(canonical("λ(x : Bool) → x"), "λ(x : Bool) → x");
+}
+
+#[test]
+fn env_imports_and_headers() {
+    std::env::set_var("DHALL_TEST_ENV_CODE", "{ a = 1 + 1 }");
+    assert_eq!(eval("env:DHALL_TEST_ENV_CODE").unwrap(), "{ a = 2 }");
+    assert_eq!(
+        eval("env:DHALL_TEST_ENV_CODE as Text").unwrap(),
+        "\"{ a = 1 + 1 }\""
+    );
+
+    // The headers are resolved before anything is downloaded, so none of these reach the network.
+    assert_eq!(
+        eval("(https://example.invalid/x using env:DHALL_TEST_MISSING) ? 2")
+            .unwrap(),
+        "2"
+    );
+    std::env::set_var("DHALL_TEST_ENV_HEADERS", "[1, 2]");
+    let err =
+        eval("https://example.invalid/x using env:DHALL_TEST_ENV_HEADERS")
+            .unwrap_err()
+            .to_string();
+    assert!(err.contains("Invalid headers"), "{}", err);
+}
+
+#[test]
+fn missing_env_var_error() {
+    use dhall::error::ImportError;
+
+    let src = "{ a = env:DHALL_TEST_UNSET_VAR }";
+    let err = eval(src).unwrap_err();
+    match err.kind() {
+        ErrorKind::Resolve(ImportError::MissingEnvVar { name, span }) => {
+            assert_eq!(name, "DHALL_TEST_UNSET_VAR");
+            assert_eq!(
+                span.clone().map(|r| &src[r]),
+                Some("env:DHALL_TEST_UNSET_VAR")
+            );
+        }
+        _ => panic!("unexpected error: {}", err),
+    }
+}
+
+/// The parse limits of the context apply to imported code.
+#[test]
+fn imported_code_parse_limits() {
+    let limits = ParseLimits {
+        max_list_len: Some(2),
+        ..ParseLimits::default()
+    };
+    let eval = |s: &str| eval_with(s, |cx| cx.set_parse_limits(limits));
+    std::env::set_var("DHALL_TEST_LIMITS_CODE", "[1, 2, 3]");
+    assert_eq!(eval("[1, 2]").unwrap(), "[1, 2]");
+    assert!(eval("[1, 2, 3]").is_err());
+    let err = eval("env:DHALL_TEST_LIMITS_CODE").unwrap_err().to_string();
+    assert!(err.contains("more than 2 elements"), "{}", err);
+    // Imported as text, it isn't parsed.
+    assert_eq!(
+        eval("env:DHALL_TEST_LIMITS_CODE as Text").unwrap(),
+        "\"[1, 2, 3]\""
+    );
+}
+
+#[test]
+fn home_imports() {
+    let home = std::env::temp_dir()
+        .join(format!("dhall-home-test-{}", std::process::id()));
+    std::fs::create_dir_all(home.join("secrets")).unwrap();
+    std::fs::write(
+        home.join("secrets/token.dhall"),
+        "./prefix.dhall ++ \"42\"",
+    )
+    .unwrap();
+    std::fs::write(home.join("secrets/prefix.dhall"), "\"token-\"").unwrap();
+
+    let eval = |s: &str| eval_with(s, |cx| cx.set_home_dir(home.clone()));
+    assert_eq!(eval("~/secrets/token.dhall").unwrap(), "\"token-42\"");
+    assert_eq!(
+        eval("~/secrets/prefix.dhall as Text").unwrap(),
+        "\"\\\"token-\\\"\""
+    );
+    assert!(eval("~/missing.dhall").is_err());
+    std::fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn type_mismatch_diff() {
+    let type_error = |s: &str| typecheck(s).unwrap_err().to_string();
+    let err = type_error(
+        "{ host = \"localhost\", port = \"80\", tls = { on = True } } \
+         : { host : Text, port : Natural, tls : { on : Bool, cert : Text } }",
+    );
+    assert!(err.contains("field `port`: expected `Natural`, found `Text`"));
+    assert!(err.contains("missing field `tls.cert : Text`"));
+    assert_eq!(err.matches("= note").count(), 2, "{}", err);
+
+    let err = type_error(
+        "(λ(x : { a : Natural, b : < A | B : Bool > }) → x.a) \
+         { a = 1, b = < A | B : Natural >.A, c = 2 }",
+    );
+    assert!(err.contains("wrong type of function argument"));
+    assert!(err.contains("alternative `b.B`: expected `Bool`, found `Natural`"));
+    assert!(err.contains("unexpected field `c : Natural`"));
+
+    let err = type_error("[ 1 ] : Optional Natural");
+    assert!(err.contains("List Natural != Optional Natural"));
+}
+
+#[test]
+fn close_name_hints() {
+    let type_error = |s: &str| typecheck(s).unwrap_err().to_string();
+    let err = type_error("{ maxConnections = 10, timeout = 5 }.maxConection");
+    assert!(err.contains("did you mean `maxConnections`?"), "{}", err);
+    let err = type_error("let timeout = 5 in λ(retries : Natural) → timout");
+    assert!(err.contains("did you mean `timeout`?"), "{}", err);
+    let err = type_error("{ maxConnections = 10 }.retries");
+    assert!(!err.contains("did you mean"), "{}", err);
+}
+
+/// `assert` and `≡` are implemented throughout; this checks a Prelude-style test file end to end,
+/// since the standard's test suite isn't always available.
+#[test]
+fn assert_equivalence() {
+    let src = r#"
+        let not = λ(b : Bool) → b == False
+        let example0 = assert : not True ≡ False
+        let example1 = assert : not False === True
+        let double = λ(n : Natural) → n + n
+        let example2 = assert : double ≡ (λ(m : Natural) → m + m)
+        in  not
+    "#;
+    let expr = parse_expr(src).unwrap();
+    assert_eq!(parse_expr(&expr.to_string()).unwrap(), expr);
+    Ctxt::with_new(|cx| {
+        let typed = Parsed::parse_str(src)?.resolve(cx)?.typecheck(cx)?;
+        assert_eq!(
+            typed
+                .ty()
+                .as_nir()
+                .to_expr(cx, Default::default())
+                .to_string(),
+            "∀(b : Bool) → Bool"
+        );
+        let normalized = typed.normalize(cx)?.to_expr(cx).to_string();
+        assert_eq!(normalized, "λ(b : Bool) → b == False");
+        Ok::<_, Error>(())
+    })
+    .unwrap();
+
+    let err = typecheck("assert : 1 + 1 ≡ 3").unwrap_err().to_string();
+    assert!(err.contains("AssertMismatch"), "{}", err);
+    let err = typecheck("assert : Bool").unwrap_err().to_string();
+    assert!(err.contains("AssertMustTakeEquivalence"), "{}", err);
+}
+
+#[test]
+fn long_text_append_chain() {
+    let normalize = |s: &str| eval(s).unwrap();
+    assert_eq!(
+        normalize("λ(x : Text) → λ(y : Text) → x ++ y ++ \"\" ++ x"),
+        "λ(x : Text) → λ(y : Text) → x ++ y ++ x"
+    );
+    assert_eq!(
+        normalize("λ(x : Text) → x ++ \"a\" ++ x ++ \"b\" ++ \"c\""),
+        "λ(x : Text) → \"${ x }a${ x }bc\""
+    );
+    assert_eq!(
+        normalize("λ(x : Text) → \"\" ++ \"\" ++ x"),
+        "λ(x : Text) → x"
+    );
+    assert_eq!(
+        normalize("λ(x : Text) → Text/replace \"b\" x (\"ab\" ++ \"ba\")"),
+        "λ(x : Text) → \"a${ x }${ x }a\""
+    );
+    assert_eq!(
+        normalize("λ(x : Text) → Text/replace \"c\" x (\"ab\" ++ \"ba\")"),
+        "λ(x : Text) → \"abba\""
+    );
+    assert_eq!(
+        normalize("λ(x : Text) → Text/replace \"\" \"c\" x"),
+        "λ(x : Text) → x"
+    );
+
+    let n = 5_000;
+    let src = format!("λ(x : Text) → x{}", " ++ \"ab\" ++ x".repeat(n));
+    let expected =
+        format!("λ(x : Text) → \"${{ x }}{}\"", "ab${ x }".repeat(n));
+    assert_eq!(normalize(&src), expected);
+
+    // Templating by replacing placeholders in a long text built with `++`.
+    let src = format!(
+        "Text/replace \"{{name}}\" \"Dhall\" (\"\"{})",
+        " ++ \"Hello {name}! \"".repeat(n)
+    );
+    let expected = format!("\"{}\"", "Hello Dhall! ".repeat(n));
+    assert_eq!(normalize(&src), expected);
+}
+
+#[test]
+fn normalized_text_chunks() {
+    let normalize = |s: &str| eval(s).unwrap();
+    // Both used to leave a bare interpolation `"${ x }"`.
+    assert_eq!(
+        normalize("λ(x : Text) → Text/replace \"a\" \"\" \"a\" ++ x"),
+        "λ(x : Text) → x"
+    );
+    assert_eq!(
+        normalize("λ(x : Text) → Text/replace \"a\" x \"a\""),
+        "λ(x : Text) → x"
+    );
+
+    Ctxt::with_new(|cx| {
+        let src =
+            "\"a${\"b\"}${Natural/show 1}\" ++ Text/replace \"x\" \"\" \"x\"";
+        let nir = Parsed::parse_str(src)?
+            .resolve(cx)?
+            .typecheck(cx)?
+            .normalize(cx)?;
+        match nir.as_nir().kind() {
+            NirKind::TextLit(tlit) => {
+                assert_eq!(tlit.iter().count(), 1);
+                assert_eq!(tlit.as_text(), Some("ab1".to_owned()));
+            }
+            kind => panic!("expected a text literal, found {:?}", kind),
+        }
+        Ok::<_, Error>(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn normalization_trace() {
+    use dhall::trace::{StepKind, TraceOptions};
+
+    let trace = |s: &str, options: TraceOptions| {
+        Ctxt::with_new(|cx| {
+            let typed = Parsed::parse_str(s)?.resolve(cx)?.typecheck(cx)?;
+            cx.start_trace(options);
+            // Steps are recorded as the value is read.
+            typed.normalize(cx)?.to_expr(cx);
+            Ok::<_, Error>(cx.take_trace().unwrap())
+        })
+        .unwrap()
+    };
+    let lines = |trace: &dhall::trace::Trace| {
+        trace
+            .steps
+            .iter()
+            .map(|step| step.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // Variables bound outside of a snippet can't be named.
+    let src = "λ(x : Natural) → (λ(y : Natural) → y + x) 1 + 0";
+    let t = trace(src, TraceOptions::default());
+    assert_eq!(
+        lines(&t),
+        vec![
+            "application: (λ(y : Natural) → y + _) 1 ~> 1 + _",
+            "operation: 1 + _ + 0 ~> 1 + _",
+        ]
+    );
+
+    let src = "let f = λ(n : Natural) → Natural/even n \
+               in if f 3 then \"a\" ++ \"b\" else \"c\"";
+    let t = trace(src, TraceOptions::default());
+    assert_eq!(
+        lines(&t),
+        vec![
+            "builtin: Natural/even 3 ~> False",
+            "application: (λ(n : Natural) → Natural/even n) 3 ~> False",
+            // The unused branch was not evaluated, so `"a" ++ "b"` wasn't recorded.
+            "operation: if False then \"ab\" else \"c\" ~> \"c\"",
+        ]
+    );
+    assert_eq!(t.steps[0].kind, StepKind::Builtin);
+    assert_eq!(
+        format!("{:.12}", t.steps[1]),
+        "application: (λ(n : Natur… ~> False"
+    );
+
+    let src = "[1 + 1, 2 + 2, 3 + 3, 4 + 4, 5 + 5]";
+    let t = trace(src, TraceOptions::default());
+    assert_eq!(t.total_steps, 5);
+    let options = TraceOptions {
+        sample_every: Some(2),
+        max_steps: Some(2),
+    };
+    let t = trace(src, options);
+    assert_eq!(t.total_steps, 5);
+    assert_eq!(
+        lines(&t),
+        vec!["operation: 1 + 1 ~> 2", "operation: 3 + 3 ~> 6"]
+    );
+    assert_eq!(t.steps[1].index, 2);
+
+    // Nothing is recorded without a trace.
+    Ctxt::with_new(|cx| assert!(cx.take_trace().is_none()));
+}
+
+#[test]
+fn union_constructor_selection() {
+    let eval = |s: &str| {
+        Ctxt::with_new(|cx| {
+            let typed =
+                Parsed::parse_str(s)?.skip_resolve(cx)?.typecheck(cx)?;
+            let ty = typed.get_type()?.to_expr(cx);
+            Ok::<_, Error>(format!(
+                "{} : {}",
+                typed.normalize(cx)?.to_expr(cx),
+                ty
+            ))
+        })
+        .map_err(|e| e.to_string())
+    };
+    // Empty alternatives are values, the others are functions.
+    assert_eq!(
+        eval("< A | B : Natural >.A").unwrap(),
+        "< A | B: Natural >.A : < A | B: Natural >"
+    );
+    assert_eq!(
+        eval("< A | B : Natural >.B").unwrap(),
+        "< A | B: Natural >.B : ∀(B : Natural) → < A | B: Natural >"
+    );
+    assert_eq!(
+        eval("let U = < A | B : Natural > in [U.A, U.B 1]").unwrap(),
+        "[< A | B: Natural >.A, < A | B: Natural >.B 1] : List < A | B: Natural >"
+    );
+    assert_eq!(
+        eval("merge { A = 0, B = λ(n : Natural) → n } < A | B : Natural >.A")
+            .unwrap(),
+        "0 : Natural"
+    );
+    assert!(eval("let U = < A | B > in assert : U.A ≡ U.A").is_ok());
+    assert!(eval("let U = < A | B > in assert : U.A ≡ U.B").is_err());
+    assert!(eval("< A | B : Natural >.A 1").is_err());
+    assert!(eval("< A | B : Natural >.C").is_err());
+    // The printed values parse back.
+    let printed = eval("Some < A | B : Natural >.A").unwrap();
+    let value = printed.split(" : ").next().unwrap();
+    assert_eq!(parse_expr(value).unwrap().to_string(), value);
+}
+
+/// Resolution reports the files and environment variables it read, transitively.
+#[test]
+fn import_dependencies() {
+    use std::collections::BTreeSet;
+
+    let dir = std::env::temp_dir()
+        .join(format!("dhall-deps-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.dhall"), "let t = ./b.txt as Text in 1")
+        .unwrap();
+    std::fs::write(dir.join("b.txt"), "b").unwrap();
+    std::fs::write(
+        dir.join("main.dhall"),
+        "./a.dhall + (env:DHALL_DEPS_TEST_UNSET ? 2) + (./missing.dhall ? 3) \
+         + (./a.dhall ? ./unused.dhall) + (let l = ./c.dhall as Location in 4)",
+    )
+    .unwrap();
+
+    let deps = Ctxt::with_new(|cx| -> Result<_, Error> {
+        let main = Parsed::parse_file(&dir.join("main.dhall"))?;
+        let first = main.clone().resolve(cx)?.dependencies(cx);
+        // The imports are not fetched again, but their dependencies are still known.
+        let second = main.resolve(cx)?.dependencies(cx);
+        assert_eq!(first, second);
+        Ok(first)
+    })
+    .unwrap();
+    let env_vars: BTreeSet<String> = vec!["DHALL_DEPS_TEST_UNSET".to_owned()]
+        .into_iter()
+        .collect();
+    assert_eq!(deps.env_vars, env_vars);
+    let local_paths: BTreeSet<_> = vec!["a.dhall", "b.txt", "missing.dhall"]
+        .into_iter()
+        .map(|f| dir.join(f))
+        .collect();
+    assert_eq!(deps.local_paths, local_paths);
+
+    let deps = Ctxt::with_new(|cx| {
+        Parsed::parse_str("1 + 1")
+            .unwrap()
+            .resolve(cx)
+            .unwrap()
+            .dependencies(cx)
+    });
+    assert!(deps.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Remote imports can be required to carry an integrity hash. The checks happen before fetching
+/// anything.
+#[test]
+#[cfg(feature = "binary")]
+fn require_frozen_remote_imports() {
+    use dhall::error::ImportError;
+    use dhall::store::Store;
+    use std::rc::Rc;
+
+    let dir = std::env::temp_dir()
+        .join(format!("dhall-frozen-test-{}", std::process::id()));
+    let hash = Store::with_dir(&dir)
+        .unwrap()
+        .put(&parse_expr("42").unwrap())
+        .unwrap();
+    std::fs::write(dir.join("a.dhall"), "https://example.com/a.dhall").unwrap();
+    let eval = |src: &str| {
+        Ctxt::with_new(|cx| -> Result<String, Error> {
+            cx.set_require_frozen_remote_imports(true);
+            let cache = Rc::new(FsCache::with_dir(&dir)?);
+            let e = Parsed::parse_str(src)?
+                .resolve_with_cache(cx, Some(cache))?
+                .typecheck(cx)?
+                .normalize(cx)?
+                .to_expr(cx);
+            Ok(e.to_string())
+        })
+    };
+
+    let src = "{ a = https://example.com/b.dhall }";
+    let err = eval(src).unwrap_err();
+    match err.kind() {
+        ErrorKind::Resolve(ImportError::UnfrozenRemoteImport { url, span }) => {
+            assert_eq!(url.as_str(), "https://example.com/b.dhall");
+            assert_eq!(
+                span.clone().map(|r| &src[r]),
+                Some("https://example.com/b.dhall")
+            );
+        }
+        _ => panic!("unexpected error: {}", err),
+    }
+    assert_eq!(
+        err.to_string(),
+        "Remote import `https://example.com/b.dhall` is not protected by an \
+         integrity hash"
+    );
+    // Also in imported files.
+    let src = format!("{}", dir.join("a.dhall").display());
+    assert!(matches!(
+        eval(&src).unwrap_err().kind(),
+        ErrorKind::Resolve(ImportError::UnfrozenRemoteImport { .. })
+    ));
+
+    let src = format!("https://example.com/b.dhall {}", hash);
+    assert_eq!(eval(&src).unwrap(), "42");
+    assert_eq!(eval("https://example.com/b.dhall ? 1").unwrap(), "1");
+    let src = "https://example.com/b.dhall as Location";
+    assert_eq!(
+        eval(src).unwrap(),
+        "< Environment: Text | Local: Text | Missing | Remote: Text >.Remote \
+         \"https://example.com/b.dhall\""
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Serves `files` over HTTP on a local port, for `requests` requests, adding `extra_headers` to
+/// the responses. Other paths get a 404. Returns the base URL and receives the path and lowercase
+/// header names of each request.
+#[cfg(feature = "reqwest")]
+fn serve_http(
+    files: Vec<(&'static str, String)>,
+    extra_headers: &'static str,
+    requests: usize,
+) -> (String, std::sync::mpsc::Receiver<(String, Vec<String>)>) {
+    use std::io::{BufRead, BufReader, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().take(requests) {
+            let mut stream = stream.unwrap();
+            let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+            let request = lines.next().unwrap().unwrap();
+            let path = request.split(' ').nth(1).unwrap().to_owned();
+            let headers = lines
+                .map(Result::unwrap)
+                .take_while(|line| !line.is_empty())
+                .map(|line| line.split(':').next().unwrap().to_lowercase())
+                .collect();
+            let (status, body) = files
+                .iter()
+                .find(|(name, _)| path.split('?').next() == Some(name))
+                .map_or(("404 Not Found", "not found"), |(_, body)| {
+                    ("200 OK", body.as_str())
+                });
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                extra_headers,
+                body
+            )
+            .unwrap();
+            // The test may be done with the responses already.
+            let _ = sender.send((path, headers));
+        }
+    });
+    (url, receiver)
+}
+
+/// The relative imports of a remote file are sent with its headers, unless disabled.
+#[test]
+#[cfg(feature = "reqwest")]
+fn remote_import_header_forwarding() {
+    let sent = |policy: HeaderForwarding| {
+        let files = vec![
+            ("/dir/a.dhall", "./b.dhall + 1".to_owned()),
+            ("/dir/b.dhall", "1".to_owned()),
+        ];
+        let (url, requests) = serve_http(files, "", 2);
+        let src = format!(
+            "{}/dir/a.dhall using (toMap {{ X-Token = \"secret\" }})",
+            url
+        );
+        let (n, costs) = Ctxt::with_new(|cx| -> Result<_, Error> {
+            cx.set_header_forwarding(policy);
+            cx.profile_imports();
+            let e = Parsed::parse_str(&src)?
+                .resolve(cx)?
+                .typecheck(cx)?
+                .normalize(cx)?
+                .to_expr(cx);
+            let costs: Vec<(String, Vec<String>)> = cx
+                .take_import_costs()
+                .iter()
+                .map(|cost| {
+                    let url = cost.location.as_remote_url().unwrap();
+                    let names = cost.location.header_names().map(String::from);
+                    (url.path().to_owned(), names.collect())
+                })
+                .collect();
+            Ok((e.to_string(), costs))
+        })
+        .unwrap();
+        assert_eq!(n, "2");
+        let requests: Vec<_> = requests.iter().collect();
+        for (path, names) in &costs {
+            let (_, headers) =
+                requests.iter().find(|(p, _)| p == path).unwrap();
+            assert_eq!(
+                headers.contains(&"x-token".to_owned()),
+                names.contains(&"X-Token".to_owned())
+            );
+        }
+        costs
+    };
+
+    let token = vec!["X-Token".to_owned()];
+    assert_eq!(
+        sent(HeaderForwarding::SameOrigin),
+        vec![
+            ("/dir/b.dhall".to_owned(), token.clone()),
+            ("/dir/a.dhall".to_owned(), token.clone()),
+        ]
+    );
+    assert_eq!(
+        sent(HeaderForwarding::Never),
+        vec![
+            ("/dir/b.dhall".to_owned(), vec![]),
+            ("/dir/a.dhall".to_owned(), token),
+        ]
+    );
+}
+
+/// Remote URLs are requested and shown as they are written, without re-encoding.
+#[test]
+#[cfg(feature = "reqwest")]
+fn remote_import_url_round_trip() {
+    let location = |url: &str| {
+        format!(
+            "< Environment: Text | Local: Text | Missing | Remote: Text >.Remote \"{}\"",
+            url
+        )
+    };
+
+    // Written URLs are kept as is, even where the `url` crate would re-encode them.
+    let url = "https://example.com/a%20b/%7Ec?x='y'&z=%2f";
+    assert_eq!(
+        eval(&format!("{} as Location", url)).unwrap(),
+        location(url)
+    );
+
+    let files = vec![
+        (
+            "/a%20b/c.dhall",
+            "./\"d%20e\"/\"f g.dhall\" as Location".to_owned(),
+        ),
+        ("/a%20b/d%2520e/f%20g.dhall", "1".to_owned()),
+        ("/a%20b/h.dhall", "./\"d%20e\"/\"f g.dhall\" + 1".to_owned()),
+    ];
+    let (base, requests) = serve_http(files, "", 3);
+    assert_eq!(
+        eval(&format!("{}/a%20b/c.dhall?x='y'", base)).unwrap(),
+        location(&format!("{}/a%20b/d%2520e/f%20g.dhall", base))
+    );
+    assert_eq!(eval(&format!("{}/a%20b/h.dhall", base)).unwrap(), "2");
+    let paths: Vec<_> = requests.try_iter().map(|(path, _)| path).collect();
+    assert_eq!(
+        paths,
+        [
+            "/a%20b/c.dhall?x=%27y%27",
+            "/a%20b/h.dhall",
+            "/a%20b/d%2520e/f%20g.dhall"
+        ]
+    );
+}
+
+/// Remote files can't read local data, and can only import remote files from other origins that
+/// allow it, unless the check is disabled.
+#[test]
+#[cfg(feature = "reqwest")]
+fn remote_import_referential_sanity() {
+    let eval = |url: &str, check: bool| {
+        eval_with(url, |cx| cx.set_referential_sanity_check(check))
+    };
+    let is_insane = |res: Result<String, Error>| {
+        res.unwrap_err().to_string().contains("SanityCheck")
+    };
+
+    let other_origin = |cors: &'static str| {
+        let files = vec![("/b.dhall", "1".to_owned())];
+        serve_http(files, cors, 100).0
+    };
+    let no_cors = other_origin("");
+    let allow_all = other_origin("Access-Control-Allow-Origin: *\r\n");
+    let allow_other =
+        other_origin("Access-Control-Allow-Origin: http://example.com\r\n");
+    std::env::set_var("DHALL_SANITY_TEST_VAR", "1");
+    let files = vec![
+        ("/no_cors.dhall", format!("{}/b.dhall + 1", no_cors)),
+        ("/allow_all.dhall", format!("{}/b.dhall + 1", allow_all)),
+        ("/allow_other.dhall", format!("{}/b.dhall + 1", allow_other)),
+        ("/same_origin.dhall", "./c.dhall + 1".to_owned()),
+        ("/c.dhall", "1".to_owned()),
+        ("/env.dhall", "env:DHALL_SANITY_TEST_VAR + 1".to_owned()),
+        (
+            "/location.dhall",
+            "let l = env:HOME as Location in 1".to_owned(),
+        ),
+    ];
+    let (url, _) = serve_http(files, "", 100);
+
+    assert!(is_insane(eval(&format!("{}/no_cors.dhall", url), true)));
+    assert!(is_insane(eval(&format!("{}/allow_other.dhall", url), true)));
+    assert!(is_insane(eval(&format!("{}/env.dhall", url), true)));
+    assert_eq!(
+        eval(&format!("{}/allow_all.dhall", url), true).unwrap(),
+        "2"
+    );
+    assert_eq!(
+        eval(&format!("{}/same_origin.dhall", url), true).unwrap(),
+        "2"
+    );
+    assert_eq!(eval(&format!("{}/location.dhall", url), true).unwrap(), "1");
+
+    assert_eq!(eval(&format!("{}/no_cors.dhall", url), false).unwrap(), "2");
+    assert_eq!(eval(&format!("{}/env.dhall", url), false).unwrap(), "2");
+
+    // A failed download is an import error, which an alternative can recover from.
+    let missing = eval(&format!("{}/missing.dhall", url), true).unwrap_err();
+    assert!(missing.to_string().contains("404 Not Found"));
+    assert_eq!(
+        eval(&format!("{}/missing.dhall ? 1", url), true).unwrap(),
+        "1"
+    );
+    let unreachable = eval("http://127.0.0.1:1/a.dhall", true).unwrap_err();
+    assert!(unreachable.to_string().contains("Failed to download"));
+}
+
+#[test]
+fn repl_session() {
+    use dhall::repl::Session;
+
+    let dir = std::env::temp_dir()
+        .join(format!("dhall-repl-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("two.dhall"), "2").unwrap();
+    std::fs::write(
+        dir.join("session.dhall"),
+        "-- Relative to this file.\n\nlet two = ./two.dhall\n:type two\n",
+    )
+    .unwrap();
+
+    let mut session = Session::new();
+    assert_eq!(session.eval("1 + 1").unwrap(), "2");
+    assert_eq!(
+        session.eval("let x = 1 let y : Natural = x + 1").unwrap(),
+        "x : Natural\ny : Natural"
+    );
+    assert_eq!(session.eval("x + y").unwrap(), "3");
+    assert_eq!(session.eval(":type [ x, y ]").unwrap(), "List Natural");
+    // A full `let` expression doesn't bind anything.
+    assert_eq!(session.eval("let z = 5 in z").unwrap(), "5");
+    assert!(session.eval("z").is_err());
+    // Shadowing, and a comment at the end of the line.
+    assert_eq!(session.eval(":let x = \"a\" -- text").unwrap(), "x : Text");
+    assert_eq!(session.eval("x ++ \"b\"").unwrap(), "\"ab\"");
+
+    // A failing binding doesn't add the ones before it.
+    assert!(session.eval("let u = 1 let v = u + True").is_err());
+    assert!(session.eval("u").is_err());
+
+    let load = format!(":load {}", dir.join("session.dhall").display());
+    assert_eq!(session.eval(&load).unwrap(), "two : Natural\nNatural");
+    assert_eq!(session.eval("two * y").unwrap(), "4");
+    let names: Vec<_> = session
+        .bindings()
+        .map(|(label, _)| label.to_string())
+        .collect();
+    assert_eq!(names, ["x", "y", "x", "two"]);
+
+    let err = session.eval(":frobnicate").unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::UnknownReplCommand(_)));
+    assert!(session.eval(":type").is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn notebook_definitions() {
+    use dhall::notebook::Notebook;
+
+    let mut notebook = Notebook::new();
+    let eval = |notebook: &mut Notebook, src: &str| {
+        notebook.eval(src).map(|e| e.to_string())
+    };
+    assert_eq!(notebook.define("a", "1").unwrap(), Vec::<Label>::new());
+    notebook.define("b", "a + 1").unwrap();
+    notebook.define("c", "λ(a : Natural) → a + b").unwrap();
+    notebook.define("d", "\"unrelated\"").unwrap();
+    assert_eq!(eval(&mut notebook, "c 10").unwrap(), "12");
+    assert_eq!(
+        notebook.type_of("c").unwrap().to_string(),
+        "∀(a : Natural) → Natural"
+    );
+
+    // Redefining invalidates the dependents, transitively, but not the bound `a` in `c`.
+    let stale = notebook.define("a", "10").unwrap();
+    assert_eq!(stale, vec![Label::from("b"), Label::from("c")]);
+    assert!(notebook.is_stale("c") && !notebook.is_stale("d"));
+    assert_eq!(eval(&mut notebook, "c 0").unwrap(), "11");
+    assert!(!notebook.is_stale("b") && !notebook.is_stale("c"));
+
+    // Failing definitions change nothing.
+    assert!(notebook.define("a", "True + 1").is_err());
+    let err = notebook.define("a", "b").unwrap_err();
+    assert!(
+        matches!(err.kind(), ErrorKind::CyclicDefinition(name) if name == "a")
+    );
+    assert!(notebook.define("e", "e").is_err());
+    assert_eq!(eval(&mut notebook, "b").unwrap(), "11");
+
+    // Dependents of a removed definition fail until it is defined again.
+    assert_eq!(
+        notebook.remove("a"),
+        vec![Label::from("b"), Label::from("c")]
+    );
+    assert!(eval(&mut notebook, "b").is_err());
+    notebook.define("a", "2").unwrap();
+    assert_eq!(eval(&mut notebook, "b").unwrap(), "3");
+    let names: Vec<_> = notebook.names().map(|l| l.to_string()).collect();
+    assert_eq!(names, ["a", "b", "c", "d"]);
+}
+
+/// The `show` builtins must give exactly the same text as the other implementations, since it ends
+/// up in hashes and generated files.
+#[test]
+fn show_builtins_match_standard() {
+    let show = |src: &str| {
+        Ctxt::with_new(|cx| {
+            let e = Parsed::parse_str(src)
+                .unwrap()
+                .skip_resolve(cx)
+                .unwrap()
+                .typecheck(cx)
+                .unwrap()
+                .normalize(cx)
+                .unwrap()
+                .to_expr(cx);
+            match e.kind() {
+                ExprKind::TextLit(text) if text.tail().is_empty() => {
+                    text.head().to_owned()
+                }
+                _ => panic!("`{}` is not a text literal", e),
+            }
+        })
+    };
+
+    assert_eq!(show(r#"Text/show "abc""#), r#""abc""#);
+    assert_eq!(
+        show(r#"Text/show "\"\$\\\b\f\n\r\t\${x}""#),
+        r#""\"\u0024\\\b\f\n\r\t\u0024{x}""#
+    );
+    assert_eq!(
+        show(r#"Text/show "\u0000\u0007\u001F ""#),
+        r#""\u0000\u0007\u001f ""#
+    );
+    // Only the characters below U+0020 are escaped, not the other invisible ones.
+    assert_eq!(
+        show(r#"Text/show "\u007F\u0085\u200B\u{FEFF}é€😀""#),
+        "\"\u{7F}\u{85}\u{200B}\u{FEFF}é€😀\""
+    );
+
+    assert_eq!(show("Natural/show 0"), "0");
+    assert_eq!(
+        show("Natural/show 18446744073709551615"),
+        "18446744073709551615"
+    );
+    assert_eq!(show("Integer/show +0"), "+0");
+    assert_eq!(show("Integer/show +42"), "+42");
+    assert_eq!(show("Integer/show -42"), "-42");
+
+    for (double, shown) in &[
+        ("1.0", "1.0"),
+        ("-0.0", "-0.0"),
+        ("0.1", "0.1"),
+        ("0.01", "1.0e-2"),
+        ("2.5e-3", "2.5e-3"),
+        ("1234567.0", "1234567.0"),
+        ("12345678.0", "1.2345678e7"),
+        ("1e100", "1.0e100"),
+        ("-1.5e-10", "-1.5e-10"),
+        ("0.30000000000000004", "0.30000000000000004"),
+        ("1.7976931348623157e308", "1.7976931348623157e308"),
+        ("5e-324", "5.0e-324"),
+        ("NaN", "NaN"),
+        ("Infinity", "Infinity"),
+        ("-Infinity", "-Infinity"),
+    ] {
+        assert_eq!(show(&format!("Double/show {}", double)), *shown);
+    }
+}
+
+#[test]
+fn build_fold_normal_forms() {
+    let normalize = |src: &str| eval(src).unwrap();
+    assert_eq!(
+        normalize("List/build Bool (List/fold Bool [ True, False ])"),
+        "[True, False]"
+    );
+    assert_eq!(normalize("Natural/build (Natural/fold 3)"), "3");
+    // The standard has no rule that fuses `List/build` with `List/fold`, so that normal forms
+    // and semantic hashes agree with other implementations.
+    assert_eq!(
+        normalize(
+            "λ(x : List Natural) → List/build Natural (List/fold Natural x)"
+        ),
+        "λ(x : List Natural) → \
+         List/fold Natural x (List Natural) (λ(a : Natural) → λ(`as` : List Natural) → [a] # `as`) \
+         ([] : List Natural)"
+    );
+    assert_eq!(
+        normalize("λ(n : Natural) → Natural/build (Natural/fold n)"),
+        "λ(n : Natural) → Natural/fold n Natural (λ(x : Natural) → x + 1) 0"
+    );
+}
+
+/// Appending to a list shares its elements instead of copying them.
+#[test]
+fn long_list_appends() {
+    let src = "let l = Natural/fold 15 (List Natural) (λ(l : List Natural) → l # l) [ 0, 1 ] \
+               let r = List/reverse Natural ([ 2 ] # l) \
+               in { length = List/length Natural l, head = List/head Natural r, last = List/last Natural r }";
+    assert_eq!(
+        eval(src).unwrap(),
+        "{ head = Some 1, last = Some 2, length = 65536 }"
+    );
+}
+
+#[test]
+fn expr_shift_and_subst() {
+    let parse = |s: &str| parse_expr(s).unwrap();
+    let x = |n| V("x".into(), n);
+
+    let e = parse("λ(x : Natural) → x + x@1 + y");
+    assert_eq!(
+        e.shift(1, &x(0)).unwrap().to_string(),
+        "λ(x : Natural) → x + x@2 + y"
+    );
+    assert_eq!(
+        parse("x@1 + x@0").shift(-1, &x(1)).unwrap().to_string(),
+        "x + x"
+    );
+    assert!(parse("x@1 + x@0").shift(-1, &x(0)).is_none());
+
+    // Beta-reducing `λ(x : Natural) → e` applied to `y`: the `y` put under the `y` binder must be
+    // shifted, and the outer `x@1` becomes `x` once the binder is gone.
+    let e = parse("λ(y : Natural) → x + y + x@1");
+    assert_eq!(
+        e.subst_shift(&x(0), &parse("y")).to_string(),
+        "λ(y : Natural) → y@1 + y + x"
+    );
+    // Under a binder of the same name, the variable is `x@1`.
+    let e = parse("let x = x in x + x@1");
+    assert_eq!(
+        e.subst_shift(&x(0), &parse("1")).to_string(),
+        "let x = 1 in x + 1"
+    );
+    // Without the variable, nothing changes.
+    let e = parse("λ(y : Natural) → y + x@1");
+    assert_eq!(e.subst_shift(&x(2), &parse("y")), e);
+}
+
+#[test]
+fn errors_as_json() {
+    let dir = std::env::temp_dir()
+        .join(format!("dhall-json-errors-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let check = |name: &str, src: &str| -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, src).unwrap();
+        Ctxt::with_new(|cx| {
+            Parsed::parse_file(&path)?.resolve(cx)?.typecheck(cx)?;
+            Ok::<_, Error>(())
+        })
+        .unwrap_err()
+        .to_json()
+    };
+    let file = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+    let json = check("unbound.dhall", "let timeout = 5\nin  timout + 1");
+    assert_eq!(
+        json,
+        format!(
+            "{{\"code\":\"type\",\"message\":\"unbound variable `timout`\",\
+             \"file\":{:?},\"span\":{{\"start\":20,\"end\":26,\"line\":2,\
+             \"column\":5,\"label\":\"not found in this scope\",\
+             \"severity\":\"error\"}},\"related\":[],\"notes\":[{{\
+             \"severity\":\"help\",\"message\":\"did you mean `timeout`?\"}}]}}",
+            file("unbound.dhall")
+        )
+    );
+
+    let json = check("merge.dhall", "merge { A = 1 } (< A : Bool >.A True)");
+    assert!(json.contains("\"related\":[{\"start\":6,"), "{}", json);
+
+    let json = check("syntax.dhall", "{ a = 1,, }");
+    assert!(json.starts_with("{\"code\":\"parse\""), "{}", json);
+    assert!(
+        json.contains(&format!("\"file\":{:?}", file("syntax.dhall"))),
+        "{}",
+        json
+    );
+    assert!(json.contains("\"line\":1,\"column\":9"), "{}", json);
+
+    let err = ErrorKind::UnknownReplCommand(":quit \"now\"\n".into());
+    assert_eq!(
+        Error::new(err).to_json(),
+        "{\"code\":\"unknown-repl-command\",\
+         \"message\":\"Unknown command: `:quit \\\"now\\\"\\n`\",\
+         \"file\":null,\"span\":null,\"related\":[],\"notes\":[]}"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn translated_errors() {
+    use dhall::error::{English, MessageCatalog};
+    use std::borrow::Cow;
+
+    struct French;
+    impl MessageCatalog for French {
+        fn template(&self, id: &str) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(match id {
+                "TypeError" => "Erreur de type : {error}",
+                "UnboundVariable" => "variable `{name}` inconnue",
+                "NotInScope" => "absente de la portée",
+                "DidYouMean" => "vouliez-vous dire `{name}` ?",
+                "MissingEnvVar" => "Variable d'environnement `{name}` absente",
+                _ => return None,
+            }))
+        }
+    }
+    let err = |s: &str| typecheck(s).unwrap_err();
+
+    let e = err("let timeout = 5 in timout");
+    assert_eq!(e.render(&English), e.to_string());
+    let fr = e.render(&French);
+    assert!(fr.starts_with("Erreur de type : "), "{}", fr);
+    assert!(fr.contains("variable `timout` inconnue"), "{}", fr);
+    assert!(fr.contains("absente de la portée"), "{}", fr);
+    assert!(fr.contains("vouliez-vous dire `timeout` ?"), "{}", fr);
+
+    // Messages the catalog lacks are in English.
+    let fr = err("1 + True").render(&French);
+    assert!(fr.contains("BinOpTypeMismatch"), "{}", fr);
+
+    // Errors shown inside others are translated too.
+    let path = std::env::temp_dir().join(format!(
+        "dhall-translated-errors-test-{}.dhall",
+        std::process::id()
+    ));
+    std::fs::write(&path, "timout").unwrap();
+    let fr = err(&path.to_string_lossy()).render(&French);
+    std::fs::remove_file(&path).unwrap();
+    assert!(fr.contains("variable `timout` inconnue"), "{}", fr);
+}
@@ -119,7 +119,7 @@ impl TestFile {
     }
     /// Parse, resolve, tck and normalize the target file
     pub fn normalize<'cx>(&self, cx: Ctxt<'cx>) -> Result<Normalized<'cx>> {
-        Ok(self.typecheck(cx)?.normalize(cx))
+        Ok(self.typecheck(cx)?.normalize(cx)?)
     }
 
     /// If UPDATE_TEST_FILES is `true`, we overwrite the output files with our own output.
@@ -0,0 +1,233 @@
+use dhall::operations::OpKind;
+use dhall::syntax::{Expr, ExprKind, InterpolatedTextContents, Label, NumKind};
+use dhall::{Ctxt, Parsed};
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::Error;
+
+pub fn dhall(input: TokenStream) -> TokenStream {
+    TokenStream::from(match dhall_inner(input) {
+        Ok(tokens) => tokens,
+        Err(err) => err.to_compile_error(),
+    })
+}
+
+fn dhall_inner(input: TokenStream) -> Result<TokenStream2, Error> {
+    // Dhall code that Rust can't tokenize can be given as a string literal.
+    let (source, span) = match syn::parse::<syn::LitStr>(input.clone()) {
+        Ok(lit) => (lit.value(), lit.span()),
+        Err(_) => (input.to_string(), Span::call_site()),
+    };
+    let parsed = Parsed::parse_str(&source).map_err(|e| Error::new(span, e))?;
+    let expr = parsed.to_expr();
+    Ctxt::with_new(|cx| {
+        parsed.skip_resolve(cx)?.typecheck(cx)?;
+        Ok(())
+    })
+    .map_err(|e: dhall::error::Error| Error::new(span, e))?;
+    Ok(quote_expr(&expr))
+}
+
+fn ident(name: String) -> Ident {
+    Ident::new(&name, Span::call_site())
+}
+
+fn quote_label(l: &Label) -> TokenStream2 {
+    let s: &str = l.as_ref();
+    quote!(::serde_dhall::ast::Label::from(#s))
+}
+
+fn quote_opt(e: &Option<Expr>) -> TokenStream2 {
+    match e {
+        Some(e) => {
+            let e = quote_expr(e);
+            quote!(::std::option::Option::Some(#e))
+        }
+        None => quote!(::std::option::Option::None),
+    }
+}
+
+fn quote_map<'a, T: 'a>(
+    kvs: impl IntoIterator<Item = (&'a Label, &'a T)>,
+    quote_value: impl Fn(&T) -> TokenStream2,
+) -> TokenStream2 {
+    let kvs = kvs.into_iter().map(|(k, v)| {
+        let k = quote_label(k);
+        let v = quote_value(v);
+        quote!((#k, #v))
+    });
+    quote!(::std::vec![#(#kvs),*].into_iter().collect())
+}
+
+fn quote_num(n: &NumKind) -> TokenStream2 {
+    match n {
+        NumKind::Bool(b) => quote!(::serde_dhall::ast::NumKind::Bool(#b)),
+        NumKind::Natural(n) => {
+            let n = n.to_string();
+            let n = match n.parse::<u64>() {
+                Ok(n) => quote!(::std::convert::From::from(#n)),
+                Err(_) => quote!(#n.parse().unwrap()),
+            };
+            quote!(::serde_dhall::ast::NumKind::Natural(#n))
+        }
+        NumKind::Integer(n) => {
+            let n = n.to_string();
+            let n = match n.parse::<i64>() {
+                Ok(n) => quote!(::std::convert::From::from(#n)),
+                Err(_) => quote!(#n.parse().unwrap()),
+            };
+            quote!(::serde_dhall::ast::NumKind::Integer(#n))
+        }
+        NumKind::Double(x) => {
+            let bits = f64::from(*x).to_bits();
+            quote!(::serde_dhall::ast::NumKind::Double(
+                ::std::convert::From::from(f64::from_bits(#bits))
+            ))
+        }
+    }
+}
+
+fn quote_op(op: &OpKind<Expr>) -> TokenStream2 {
+    use OpKind::*;
+    let kind = match op {
+        App(f, a) => {
+            let (f, a) = (quote_expr(f), quote_expr(a));
+            quote!(App(#f, #a))
+        }
+        BinOp(o, x, y) => {
+            let o = ident(format!("{:?}", o));
+            let (x, y) = (quote_expr(x), quote_expr(y));
+            quote!(BinOp(::serde_dhall::ast::BinOp::#o, #x, #y))
+        }
+        BoolIf(b, t, f) => {
+            let (b, t, f) = (quote_expr(b), quote_expr(t), quote_expr(f));
+            quote!(BoolIf(#b, #t, #f))
+        }
+        Merge(x, y, t) => {
+            let (x, y, t) = (quote_expr(x), quote_expr(y), quote_opt(t));
+            quote!(Merge(#x, #y, #t))
+        }
+        ToMap(x, t) => {
+            let (x, t) = (quote_expr(x), quote_opt(t));
+            quote!(ToMap(#x, #t))
+        }
+        Field(e, l) => {
+            let (e, l) = (quote_expr(e), quote_label(l));
+            quote!(Field(#e, #l))
+        }
+        Projection(e, ls) => {
+            let e = quote_expr(e);
+            let ls = ls.iter().map(quote_label);
+            quote!(Projection(#e, ::std::vec![#(#ls),*].into_iter().collect()))
+        }
+        ProjectionByExpr(e, x) => {
+            let (e, x) = (quote_expr(e), quote_expr(x));
+            quote!(ProjectionByExpr(#e, #x))
+        }
+        Completion(e, x) => {
+            let (e, x) = (quote_expr(e), quote_expr(x));
+            quote!(Completion(#e, #x))
+        }
+        With(e, ls, x) => {
+            let (e, x) = (quote_expr(e), quote_expr(x));
+            let ls = ls.iter().map(quote_label);
+            quote!(With(#e, ::std::vec![#(#ls),*], #x))
+        }
+    };
+    quote!(::serde_dhall::ast::OpKind::#kind)
+}
+
+/// Generates the code that builds `expr`.
+fn quote_expr(expr: &Expr) -> TokenStream2 {
+    use ExprKind::*;
+    let kind = match expr.kind() {
+        Const(c) => {
+            let c = ident(format!("{:?}", c));
+            quote!(Const(::serde_dhall::ast::Const::#c))
+        }
+        Num(n) => {
+            let n = quote_num(n);
+            quote!(Num(#n))
+        }
+        Builtin(b) => {
+            let b = ident(format!("{:?}", b));
+            quote!(Builtin(::serde_dhall::ast::Builtin::#b))
+        }
+        TextLit(t) => {
+            let chunks = t.iter().map(|chunk| match chunk {
+                InterpolatedTextContents::Text(s) => quote!(
+                    ::serde_dhall::ast::InterpolatedTextContents::Text(
+                        ::std::string::String::from(#s)
+                    )
+                ),
+                InterpolatedTextContents::Expr(e) => {
+                    let e = quote_expr(e);
+                    quote!(::serde_dhall::ast::InterpolatedTextContents::Expr(
+                        #e
+                    ))
+                }
+            });
+            quote!(TextLit(::std::vec![#(#chunks),*].into_iter().collect()))
+        }
+        SomeLit(e) => {
+            let e = quote_expr(e);
+            quote!(SomeLit(#e))
+        }
+        EmptyListLit(t) => {
+            let t = quote_expr(t);
+            quote!(EmptyListLit(#t))
+        }
+        NEListLit(xs) => {
+            let xs = xs.iter().map(quote_expr);
+            quote!(NEListLit(::std::vec![#(#xs),*]))
+        }
+        RecordType(kts) => {
+            let kts = quote_map(kts, quote_expr);
+            quote!(RecordType(#kts))
+        }
+        RecordLit(kvs) => {
+            let kvs = quote_map(kvs, quote_expr);
+            quote!(RecordLit(#kvs))
+        }
+        UnionType(kts) => {
+            let kts = quote_map(kts, quote_opt);
+            quote!(UnionType(#kts))
+        }
+        Var(v) => {
+            let (l, i) = (quote_label(&v.0), v.1);
+            quote!(Var(::serde_dhall::ast::V(#l, #i)))
+        }
+        Lam(l, t, b) => {
+            let (l, t, b) = (quote_label(l), quote_expr(t), quote_expr(b));
+            quote!(Lam(#l, #t, #b))
+        }
+        Pi(l, t, b) => {
+            let (l, t, b) = (quote_label(l), quote_expr(t), quote_expr(b));
+            quote!(Pi(#l, #t, #b))
+        }
+        Let(l, t, v, b) => {
+            let (l, t) = (quote_label(l), quote_opt(t));
+            let (v, b) = (quote_expr(v), quote_expr(b));
+            quote!(Let(#l, #t, #v, #b))
+        }
+        Op(op) => {
+            let op = quote_op(op);
+            quote!(Op(#op))
+        }
+        Annot(x, t) => {
+            let (x, t) = (quote_expr(x), quote_expr(t));
+            quote!(Annot(#x, #t))
+        }
+        Assert(t) => {
+            let t = quote_expr(t);
+            quote!(Assert(#t))
+        }
+        // Typechecking fails on imports since they weren't resolved.
+        Import(_) => unreachable!("imports are not allowed in `dhall!`"),
+    };
+    quote!(::serde_dhall::ast::Expr::new(
+        ::serde_dhall::ast::ExprKind::#kind,
+        ::serde_dhall::ast::Span::Artificial,
+    ))
+}
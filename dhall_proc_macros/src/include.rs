@@ -0,0 +1,71 @@
+use std::path::{Path, PathBuf};
+
+use dhall::syntax::binary;
+use dhall::{Ctxt, Parsed};
+use proc_macro::TokenStream;
+use proc_macro2::{Literal, TokenStream as TokenStream2};
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{Error, LitStr, Token, Type};
+
+struct Input {
+    path: LitStr,
+    ty: Type,
+}
+
+impl Parse for Input {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let ty = input.parse()?;
+        // Allow a trailing comma.
+        let _ = input.parse::<Option<Token![,]>>()?;
+        Ok(Input { path, ty })
+    }
+}
+
+pub fn include_dhall(input: TokenStream) -> TokenStream {
+    TokenStream::from(match include_dhall_inner(input) {
+        Ok(tokens) => tokens,
+        Err(err) => err.to_compile_error(),
+    })
+}
+
+fn include_dhall_inner(input: TokenStream) -> Result<TokenStream2, Error> {
+    let Input { path, ty } = syn::parse(input)?;
+    let span = path.span();
+    let root = std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    let file = root.join(path.value());
+
+    let (data, imported) = Ctxt::with_new(|cx| {
+        cx.profile_imports();
+        let typed = Parsed::parse_file(&file)?.resolve(cx)?.typecheck(cx)?;
+        let expr = typed.normalize(cx)?.to_expr(cx);
+        let imported: Vec<PathBuf> = cx
+            .take_import_costs()
+            .iter()
+            .filter_map(|cost| cost.location.as_local_path())
+            .map(Path::to_owned)
+            .collect();
+        Ok((binary::encode(&expr)?, imported))
+    })
+    .map_err(|e: dhall::error::Error| Error::new(span, e))?;
+
+    // Rebuild when the file or the local files it imports change.
+    let tracked = std::iter::once(&file).chain(&imported).map(|p| {
+        let p = p.to_string_lossy();
+        quote!(
+            const _: &[u8] = ::std::include_bytes!(#p);
+        )
+    });
+    let data = Literal::byte_string(&data);
+    Ok(quote!({
+        #(#tracked)*
+        ::serde_dhall::from_binary(#data)
+            .imports(false)
+            .static_type_annotation()
+            .parse::<#ty>()
+    }))
+}
@@ -6,6 +6,8 @@
 //! [dhall-rust]: https://github.com/Nadrieril/dhall-rust
 
 mod derive;
+mod dhall_expr;
+mod include;
 
 use proc_macro::TokenStream;
 
@@ -13,3 +15,60 @@ use proc_macro::TokenStream;
 pub fn derive_static_type(input: TokenStream) -> TokenStream {
     derive::derive_static_type(input)
 }
+
+/// Parses and typechecks a Dhall expression at compile time, and expands to the code that builds
+/// its syntax tree, a [`serde_dhall::ast::Expr`].
+///
+/// The expression can be written directly, or as a string literal for the Dhall syntax that Rust
+/// can't tokenize, e.g. `//`, `\(x : T) -> e`, `''` strings, `+1` or `List/length`. It must be
+/// closed and not contain imports.
+///
+/// # Example
+///
+/// ```ignore
+/// use serde_dhall::dhall;
+///
+/// let config = dhall!({ port = 80, host = "x" });
+/// assert_eq!(config.to_string(), "{ host = \"x\", port = 80 }");
+///
+/// let double = dhall!("\\(x : Natural) -> x * 2");
+/// ```
+///
+/// [`serde_dhall::ast::Expr`]: https://docs.rs/serde_dhall/latest/serde_dhall/ast/struct.Expr.html
+#[proc_macro]
+pub fn dhall(input: TokenStream) -> TokenStream {
+    dhall_expr::dhall(input)
+}
+
+/// Loads a Dhall file at compile time and embeds its normalized value in the program, to be
+/// deserialized into the given type. Syntax errors, missing imports and type errors in the file
+/// are compile errors.
+///
+/// The path is relative to the root of the crate, i.e. the directory of its `Cargo.toml`. The
+/// program is rebuilt when the file or the local files it imports change.
+///
+/// This expands to an expression of type `serde_dhall::Result<T>`. The value is stored in the
+/// compact binary format, so that getting it at runtime doesn't involve parsing, resolving
+/// imports or evaluating anything. Its type is checked against the [`StaticType`] of `T` at
+/// runtime, since Rust types are not known to macros; a unit test that loads the configuration
+/// catches mismatches.
+///
+/// # Example
+///
+/// ```ignore
+/// use serde::Deserialize;
+/// use serde_dhall::{include_dhall, StaticType};
+///
+/// #[derive(Deserialize, StaticType)]
+/// struct Config {
+///     port: u64,
+/// }
+///
+/// let config = include_dhall!("config.dhall", Config)?;
+/// ```
+///
+/// [`StaticType`]: https://docs.rs/serde_dhall/latest/serde_dhall/trait.StaticType.html
+#[proc_macro]
+pub fn include_dhall(input: TokenStream) -> TokenStream {
+    include::include_dhall(input)
+}
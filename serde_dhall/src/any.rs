@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Index;
+
+use serde::de::{
+    self, Deserialize, DeserializeSeed, Deserializer, VariantAccess, Visitor,
+};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+
+/// Any value, to poke at Dhall data without defining Rust types for it, like
+/// `serde_json::Value`.
+///
+/// Unlike [`SimpleValue`](crate::SimpleValue), optional values are flattened: `Some x` becomes
+/// the value of `x`, and `None` becomes [`Any::Null`]. Indexing with a field name or a list index
+/// never panics; it returns `Any::Null` when the field or element is missing, so that lookups can
+/// be chained.
+///
+/// `Any` implements `Deserialize` and `Serialize`, so it can also be read from or written to other
+/// formats. Numbers must fit in 64 bits.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde_dhall::Any;
+///
+/// let any: Any = serde_dhall::from_str(
+///     "{ servers = [{ host = \"localhost\", port = Some 8080 }] }",
+/// )
+/// .parse()?;
+///
+/// assert_eq!(any["servers"][0]["port"].as_u64(), Some(8080));
+/// assert_eq!(any["servers"][0]["host"].as_str(), Some("localhost"));
+/// assert!(any["servers"][1]["port"].is_null());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Any {
+    /// `None`, and the result of indexing with a missing field or index.
+    Null,
+    /// `True` or `False`
+    Bool(bool),
+    /// A `Natural`, e.g. `1`
+    Natural(u64),
+    /// An `Integer`, e.g. `+1`
+    Integer(i64),
+    /// A `Double`, e.g. `1.0`
+    Double(f64),
+    /// A `Text`
+    Text(String),
+    /// A list of values
+    List(Vec<Any>),
+    /// A record, or a map with text keys
+    Record(BTreeMap<String, Any>),
+    /// An alternative of a union, with its payload if it has one.
+    Union(String, Option<Box<Any>>),
+}
+
+static NULL: Any = Any::Null;
+
+impl Any {
+    /// Returns the field `name` of a record, or `None` if this is not a record or the field is
+    /// missing.
+    pub fn get(&self, name: &str) -> Option<&Any> {
+        match self {
+            Any::Record(m) => m.get(name),
+            _ => None,
+        }
+    }
+
+    /// Whether this is [`Any::Null`].
+    pub fn is_null(&self) -> bool {
+        matches!(self, Any::Null)
+    }
+
+    /// Returns a `Bool`.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Any::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Returns a `Natural`, or a non-negative `Integer`.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Any::Natural(n) => Some(*n),
+            Any::Integer(n) if *n >= 0 => Some(*n as u64),
+            _ => None,
+        }
+    }
+
+    /// Returns an `Integer`, or a `Natural` that fits.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Any::Integer(n) => Some(*n),
+            Any::Natural(n) if *n <= i64::MAX as u64 => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    /// Returns a `Double`, or a `Natural` or `Integer` converted to one.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Any::Double(x) => Some(*x),
+            Any::Natural(n) => Some(*n as f64),
+            Any::Integer(n) => Some(*n as f64),
+            _ => None,
+        }
+    }
+
+    /// Returns a `Text`.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Any::Text(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements of a list.
+    pub fn as_list(&self) -> Option<&[Any]> {
+        match self {
+            Any::List(xs) => Some(xs),
+            _ => None,
+        }
+    }
+
+    /// Returns the fields of a record.
+    pub fn as_record(&self) -> Option<&BTreeMap<String, Any>> {
+        match self {
+            Any::Record(m) => Some(m),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the field of a record, or [`Any::Null`] if this is not a record or the field is
+/// missing.
+impl Index<&str> for Any {
+    type Output = Any;
+
+    fn index(&self, name: &str) -> &Any {
+        self.get(name).unwrap_or(&NULL)
+    }
+}
+
+/// Returns the element of a list, or [`Any::Null`] if this is not a list or the index is out of
+/// bounds.
+impl Index<usize> for Any {
+    type Output = Any;
+
+    fn index(&self, i: usize) -> &Any {
+        match self {
+            Any::List(xs) => xs.get(i).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+struct AnyVisitor {
+    /// Whether a unit is accepted as `Null`. It isn't for the payload of union alternatives,
+    /// since alternatives without a payload give a unit.
+    accept_unit: bool,
+}
+
+impl<'de> Visitor<'de> for AnyVisitor {
+    type Value = Any;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any value")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Any, E> {
+        Ok(Any::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Any, E> {
+        Ok(Any::Integer(value))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Any, E> {
+        Ok(Any::Natural(value))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Any, E> {
+        Ok(Any::Double(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Any, E> {
+        Ok(Any::Text(value.to_owned()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Any, E> {
+        Ok(Any::Text(value))
+    }
+
+    fn visit_none<E>(self) -> Result<Any, E> {
+        Ok(Any::Null)
+    }
+
+    fn visit_unit<E>(self) -> Result<Any, E>
+    where
+        E: de::Error,
+    {
+        if self.accept_unit {
+            Ok(Any::Null)
+        } else {
+            Err(E::invalid_type(de::Unexpected::Unit, &self))
+        }
+    }
+
+    fn visit_some<D>(self, val: D) -> Result<Any, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Any::deserialize(val)
+    }
+
+    fn visit_enum<V>(self, visitor: V) -> Result<Any, V::Error>
+    where
+        V: de::EnumAccess<'de>,
+    {
+        // Serde doesn't tell what kind of variant this is. Alternatives of Dhall unions without a
+        // payload give a unit, which the seed rejects.
+        let (name, variant): (String, _) = visitor.variant()?;
+        let val = variant
+            .newtype_variant_seed(AnyVisitor { accept_unit: false })
+            .ok();
+        Ok(Any::Union(name, val.map(Box::new)))
+    }
+
+    fn visit_seq<V>(self, mut visitor: V) -> Result<Any, V::Error>
+    where
+        V: de::SeqAccess<'de>,
+    {
+        let mut vec = Vec::new();
+        while let Some(elem) = visitor.next_element()? {
+            vec.push(elem);
+        }
+        Ok(Any::List(vec))
+    }
+
+    fn visit_map<V>(self, mut visitor: V) -> Result<Any, V::Error>
+    where
+        V: de::MapAccess<'de>,
+    {
+        let mut record = BTreeMap::new();
+        while let Some((key, value)) = visitor.next_entry()? {
+            record.insert(key, value);
+        }
+        Ok(Any::Record(record))
+    }
+}
+
+impl<'de> Deserialize<'de> for Any {
+    fn deserialize<D>(deserializer: D) -> Result<Any, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(AnyVisitor { accept_unit: true })
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for AnyVisitor {
+    type Value = Any;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Any, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl Serialize for Any {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Any::Null => serializer.serialize_none(),
+            Any::Bool(x) => serializer.serialize_bool(*x),
+            Any::Natural(x) => serializer.serialize_u64(*x),
+            Any::Integer(x) => serializer.serialize_i64(*x),
+            Any::Double(x) => serializer.serialize_f64(*x),
+            Any::Text(x) => serializer.serialize_str(x),
+            Any::List(xs) => {
+                let mut seq = serializer.serialize_seq(Some(xs.len()))?;
+                for x in xs {
+                    seq.serialize_element(x)?;
+                }
+                seq.end()
+            }
+            Any::Record(m) => {
+                let mut map = serializer.serialize_map(Some(m.len()))?;
+                for (k, v) in m {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            }
+            // Variant names must be `'static`, see the `Serialize` impl of `SimpleValue`.
+            Any::Union(name, None) => {
+                let name: Box<str> = name.clone().into();
+                serializer.serialize_unit_variant("Any", 0, Box::leak(name))
+            }
+            Any::Union(name, Some(x)) => {
+                let name: Box<str> = name.clone().into();
+                serializer.serialize_newtype_variant(
+                    "Any",
+                    0,
+                    Box::leak(name),
+                    x,
+                )
+            }
+        }
+    }
+}
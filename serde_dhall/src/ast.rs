@@ -0,0 +1,80 @@
+//! The syntax tree of Dhall expressions.
+//!
+//! This gives access to Dhall code as it was written, before imports are resolved and before any
+//! typechecking or evaluation. This is useful for tools that analyze configuration files, e.g. to
+//! list the files or environment variables they depend on.
+//!
+//! # Stability
+//!
+//! The types here come from the internal `dhall` crate, whose API changes with every release.
+//! Their re-exports here are part of the API of `serde_dhall` and follow its versioning, so tools
+//! should name them through this module rather than depend on `dhall` directly. This covers
+//! everything an [`Expr`] is made of, so that a syntax tree can be inspected and built without
+//! `dhall`.
+//!
+//! # Example
+//!
+//! ```
+//! # fn main() -> serde_dhall::Result<()> {
+//! use serde_dhall::ast::{ExprKind, Import, ImportTarget};
+//!
+//! let expr = serde_dhall::ast::parse_str("env:HOME as Text")?;
+//! match expr.as_ref() {
+//!     ExprKind::Import(Import {
+//!         location: ImportTarget::Env(var),
+//!         ..
+//!     }) => assert_eq!(var, "HOME"),
+//!     _ => unreachable!(),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::Path;
+
+use dhall::Parsed;
+
+use crate::{Error, ErrorKind, Result};
+
+pub use dhall::builtins::Builtin;
+pub use dhall::operations::{BinOp, OpKind};
+pub use dhall::syntax::{
+    Authority, Const, Double, Expr, ExprKind, FilePath, FilePrefix, Hash,
+    Import, ImportMode, ImportTarget, Integer, InterpolatedText,
+    InterpolatedTextContents, Label, NaiveDouble, Natural, NumKind, ParsedSpan,
+    Scheme, Span, UnspannedExpr, URL, V,
+};
+
+/// Parse a string of Dhall code into its syntax tree, without resolving imports.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> serde_dhall::Result<()> {
+/// let expr = serde_dhall::ast::parse_str("./foo.dhall ? 1")?;
+/// assert_eq!(expr.to_string(), "./foo.dhall ? 1");
+/// # Ok(())
+/// # }
+/// ```
+pub fn parse_str(s: &str) -> Result<Expr> {
+    let parsed = Parsed::parse_str(s).map_err(ErrorKind::Dhall)?;
+    Ok(parsed.to_expr())
+}
+
+/// Parse a file containing Dhall code into its syntax tree, without resolving imports.
+///
+/// # Example
+///
+/// ```no_run
+/// # fn main() -> serde_dhall::Result<()> {
+/// let expr = serde_dhall::ast::parse_file("config.dhall")?;
+/// println!("{}", expr);
+/// # Ok(())
+/// # }
+/// ```
+pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Expr> {
+    let parsed = Parsed::parse_file(path.as_ref())
+        .map_err(ErrorKind::Dhall)
+        .map_err(Error)?;
+    Ok(parsed.to_expr())
+}
@@ -0,0 +1,61 @@
+use crate::{Result, ToDhall, Value};
+
+/// Values made available as variables to a Dhall expression, e.g. to evaluate a user-supplied
+/// snippet against an existing configuration.
+///
+/// Each binding has a type, like any [`Value`], so the expression is typechecked against them.
+/// They are added with [`Deserializer::with_bindings()`], or used by
+/// [`Value::normalize_in_context()`].
+///
+/// [`Deserializer::with_bindings()`]: crate::Deserializer::with_bindings()
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde_dhall::{Bindings, Value};
+///
+/// let config: Value =
+///     serde_dhall::from_str("{ host = \"localhost\", port = 80 }").parse()?;
+/// let ctx = Bindings::new().bind("config", &config)?.bind("offset", &1000u64)?;
+///
+/// let port = Value::normalize_in_context("config.port + offset", &ctx)?;
+/// assert_eq!(port.as_natural()?, 1080);
+///
+/// // The snippet must be well-typed in the context.
+/// assert!(Value::normalize_in_context("config.port ++ \"x\"", &ctx).is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Bindings {
+    vars: Vec<(String, Value)>,
+}
+
+impl Bindings {
+    /// An empty set of bindings.
+    pub fn new() -> Self {
+        Bindings::default()
+    }
+
+    /// Binds `name` to `value`, converted with [`ToDhall`]. A later binding of the same name
+    /// replaces the earlier one.
+    pub fn bind<T>(mut self, name: &str, value: &T) -> Result<Self>
+    where
+        T: ToDhall + ?Sized,
+    {
+        let value = value.to_dhall(None)?;
+        self.vars.retain(|(n, _)| n != name);
+        self.vars.push((name.to_owned(), value));
+        Ok(self)
+    }
+
+    /// Returns the value bound to `name`.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.vars.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.vars.iter().map(|(n, v)| (n.as_str(), v))
+    }
+}
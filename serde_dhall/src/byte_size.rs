@@ -0,0 +1,72 @@
+//! Reads and writes numbers of bytes as Dhall `Text`, like `"512MiB"` or `"1.5 GB"`.
+//!
+//! Use it on a `u64` field with `#[serde(with = "serde_dhall::byte_size")]`. A size is a number
+//! followed by one of the units `B`, `kB`, `MB`, `GB`, `TB`, `PB` (powers of 1000) or `KiB`,
+//! `MiB`, `GiB`, `TiB`, `PiB` (powers of 1024), in any case. Without a unit, the number is a
+//! number of bytes, and plain `Natural`s are accepted too.
+//!
+//! # Example
+//!
+//! ```rust
+//! # fn main() -> serde_dhall::Result<()> {
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, PartialEq, Deserialize, Serialize)]
+//! struct Config {
+//!     #[serde(with = "serde_dhall::byte_size")]
+//!     max_upload: u64,
+//! }
+//!
+//! let config: Config = serde_dhall::from_str(r#"{ max_upload = "512MiB" }"#).parse()?;
+//! assert_eq!(config.max_upload, 512 * 1024 * 1024);
+//! assert_eq!(serde_dhall::serialize(&config).to_string()?, r#"{ max_upload = "512MiB" }"#);
+//!
+//! let config: Config = serde_dhall::from_str("{ max_upload = 1000 }").parse()?;
+//! assert_eq!(config.max_upload, 1000);
+//!
+//! let err = serde_dhall::from_str(r#"{ max_upload = "0.5B" }"#)
+//!     .parse::<Config>()
+//!     .unwrap_err();
+//! assert_eq!(
+//!     err.to_string(),
+//!     "max_upload: invalid byte size `0.5B`: not a whole number of bytes"
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+
+use serde::{Deserializer, Serializer};
+
+use crate::units::{format_byte_size, parse_byte_size};
+
+/// Deserializes a number of bytes from its text form, or from a number.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct Visitor;
+    impl serde::de::Visitor<'_> for Visitor {
+        type Value = u64;
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a byte size like `512MiB`, or a number of bytes")
+        }
+        fn visit_u64<E: serde::de::Error>(self, n: u64) -> Result<u64, E> {
+            Ok(n)
+        }
+        fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<u64, E> {
+            parse_byte_size(s).map_err(E::custom)
+        }
+    }
+    deserializer.deserialize_any(Visitor)
+}
+
+/// Serializes a number of bytes to its text form, with the unit that gives the smallest whole
+/// number, e.g. `512MiB` or `1500B`.
+pub fn serialize<S>(bytes: &u64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&format_byte_size(*bytes))
+}
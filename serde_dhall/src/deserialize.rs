@@ -1,16 +1,16 @@
-use std::borrow::Cow;
-use std::collections::BTreeMap;
 use std::fmt;
 
 use serde::de::value::{
-    MapAccessDeserializer, MapDeserializer, SeqDeserializer,
+    BorrowedStrDeserializer, MapAccessDeserializer, MapDeserializer,
+    SeqDeserializer,
 };
 use serde::de::VariantAccess as _;
 
 use dhall::syntax::NumKind;
+use num_traits::ToPrimitive;
 
 use crate::value::SimpleValue;
-use crate::{Error, ErrorKind, Value};
+use crate::{Error, ErrorKind, RecordMap, Value};
 
 pub trait Sealed {}
 
@@ -43,6 +43,15 @@ pub trait Sealed {}
 pub trait FromDhall: Sealed + Sized {
     #[doc(hidden)]
     fn from_dhall(v: &Value) -> crate::Result<Self>;
+
+    /// See [`Deserializer::deny_unknown_fields()`](crate::Deserializer::deny_unknown_fields()).
+    #[doc(hidden)]
+    fn from_dhall_with(
+        v: &Value,
+        _deny_unknown_fields: bool,
+    ) -> crate::Result<Self> {
+        Self::from_dhall(v)
+    }
 }
 
 impl<T> Sealed for T where T: serde::de::DeserializeOwned {}
@@ -53,8 +62,8 @@ impl<T> Sealed for T where T: serde::de::DeserializeOwned {}
 ///
 /// ```rust
 /// # fn main() -> serde_dhall::Result<()> {
-/// use std::collections::BTreeMap;
 /// use serde::Deserialize;
+/// use serde_dhall::RecordMap;
 ///
 /// // We use serde's derive feature
 /// #[derive(Deserialize)]
@@ -64,14 +73,14 @@ impl<T> Sealed for T where T: serde::de::DeserializeOwned {}
 /// }
 ///
 /// // Some Dhall data
-/// let mut data = BTreeMap::new();
+/// let mut data = RecordMap::new();
 /// data.insert(
 ///     "x".to_string(),
-///     serde_dhall::SimpleValue::Num(serde_dhall::NumKind::Natural(1))
+///     serde_dhall::SimpleValue::Num(serde_dhall::NumKind::Natural(1u64.into()))
 /// );
 /// data.insert(
 ///     "y".to_string(),
-///     serde_dhall::SimpleValue::Num(serde_dhall::NumKind::Natural(2))
+///     serde_dhall::SimpleValue::Num(serde_dhall::NumKind::Natural(2u64.into()))
 /// );
 /// let data = serde_dhall::SimpleValue::Record(data);
 ///
@@ -84,11 +93,34 @@ impl<T> Sealed for T where T: serde::de::DeserializeOwned {}
 /// # }
 /// ```
 ///
+/// To avoid copying strings, a `&SimpleValue` can also be used directly as a serde
+/// `Deserializer`. Types that borrow from it, like `&str` or `Cow<str>`, can then be deserialized
+/// without allocating. This also works with a `DeserializeSeed`.
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde::Deserialize;
+/// use serde_dhall::SimpleValue;
+///
+/// #[derive(Deserialize)]
+/// struct Config<'a> {
+///     name: &'a str,
+///     tags: Vec<&'a str>,
+/// }
+///
+/// let value: SimpleValue =
+///     serde_dhall::from_str(r#"{ name = "foo", tags = ["a", "b"] }"#).parse()?;
+/// let config = Config::deserialize(&value)?;
+/// assert_eq!(config.name, "foo");
+/// assert_eq!(config.tags, vec!["a", "b"]);
+/// # Ok(())
+/// # }
+/// ```
 pub fn from_simple_value<T>(v: SimpleValue) -> crate::Result<T>
 where
     T: serde::de::DeserializeOwned,
 {
-    T::deserialize(Deserializer(Cow::Owned(v)))
+    T::deserialize(&v)
 }
 
 impl<T> FromDhall for T
@@ -96,53 +128,192 @@ where
     T: serde::de::DeserializeOwned,
 {
     fn from_dhall(v: &Value) -> crate::Result<Self> {
-        let sval = v.to_simple_value().ok_or_else(|| {
-            Error(ErrorKind::Deserialize(format!(
-                "this cannot be deserialized into the serde data model: {}",
-                v
-            )))
-        })?;
-        from_simple_value(sval)
+        from_simple_value(v.to_simple_value()?)
     }
-}
 
-struct Deserializer<'a>(Cow<'a, SimpleValue>);
+    fn from_dhall_with(
+        v: &Value,
+        deny_unknown_fields: bool,
+    ) -> crate::Result<Self> {
+        let v = v.to_simple_value()?;
+        let mut de = ValueDeserializer::new(&v);
+        de.deny_unknown_fields = deny_unknown_fields;
+        T::deserialize(de)
+    }
+}
 
-impl<'de: 'a, 'a> serde::de::IntoDeserializer<'de, Error> for Deserializer<'a> {
-    type Deserializer = Deserializer<'a>;
+impl<'de> serde::de::IntoDeserializer<'de, Error> for &'de SimpleValue {
+    type Deserializer = Self;
     fn into_deserializer(self) -> Self::Deserializer {
         self
     }
 }
 
-impl<'de: 'a, 'a> serde::Deserializer<'de> for Deserializer<'a> {
+/// Deserializing from a borrowed `SimpleValue` allows borrowing text from it.
+impl<'de> serde::Deserializer<'de> for &'de SimpleValue {
     type Error = Error;
 
     fn deserialize_any<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        ValueDeserializer::new(self).deserialize_any(visitor)
+    }
+
+    fn deserialize_tuple<V>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        ValueDeserializer::new(self).deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        ValueDeserializer::new(self).deserialize_unit(visitor)
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        ValueDeserializer::new(self).deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        ValueDeserializer::new(self).deserialize_map(visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option newtype_struct seq
+        tuple_struct struct enum identifier ignored_any
+    }
+}
+
+/// The location of a value inside the value being deserialized, e.g. `servers[2].port`.
+#[derive(Debug, Clone, Copy)]
+enum Path<'a> {
+    Root,
+    Field(&'a Path<'a>, &'a str),
+    Index(&'a Path<'a>, usize),
+}
+
+impl fmt::Display for Path<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Path::Root => Ok(()),
+            Path::Field(Path::Root, name) => write!(f, "{}", name),
+            Path::Field(parent, name) => write!(f, "{}.{}", parent, name),
+            Path::Index(parent, i) => write!(f, "{}[{}]", parent, i),
+        }
+    }
+}
+
+/// Deserializes a value while keeping track of where it is, so that errors can point to the
+/// offending field.
+struct ValueDeserializer<'de, 'a> {
+    value: &'de SimpleValue,
+    path: Path<'a>,
+    /// Whether a struct fails to deserialize from a record that has fields it doesn't declare.
+    deny_unknown_fields: bool,
+}
+
+impl<'de> ValueDeserializer<'de, '_> {
+    fn new(value: &'de SimpleValue) -> Self {
+        ValueDeserializer {
+            value,
+            path: Path::Root,
+            deny_unknown_fields: false,
+        }
+    }
+
+    /// Adds the current path to an error that doesn't have one yet. The innermost value knows the
+    /// full path, so errors that already have one are left alone.
+    fn locate<T>(&self, res: crate::Result<T>) -> crate::Result<T> {
+        match res {
+            Err(Error(ErrorKind::Deserialize(msg)))
+                if !matches!(self.path, Path::Root) =>
+            {
+                Err(Error(ErrorKind::DeserializeAt {
+                    path: self.path.to_string(),
+                    msg,
+                }))
+            }
+            res => res,
+        }
+    }
+
+    fn child<'b>(
+        &self,
+        value: &'de SimpleValue,
+        path: Path<'b>,
+    ) -> ValueDeserializer<'de, 'b> {
+        ValueDeserializer {
+            value,
+            path,
+            deny_unknown_fields: self.deny_unknown_fields,
+        }
+    }
+
+    fn any<V>(&self, visitor: V) -> crate::Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
         use NumKind::*;
         use SimpleValue::*;
 
-        let val = |x| Deserializer(Cow::Borrowed(x));
-        match self.0.as_ref() {
+        match self.value {
             Num(Bool(x)) => visitor.visit_bool(*x),
-            Num(Natural(x)) => visitor.visit_u64(*x),
-            Num(Integer(x)) => visitor.visit_i64(*x),
+            Num(Natural(x)) => match (x.to_u64(), x.to_u128()) {
+                (Some(x), _) => visitor.visit_u64(x),
+                (None, Some(x)) => visitor.visit_u128(x),
+                (None, None) => Err(Error(ErrorKind::Deserialize(format!(
+                    "natural number too large: {}",
+                    x
+                )))),
+            },
+            Num(Integer(x)) => match (x.to_i64(), x.to_i128()) {
+                (Some(x), _) => visitor.visit_i64(x),
+                (None, Some(x)) => visitor.visit_i128(x),
+                (None, None) => Err(Error(ErrorKind::Deserialize(format!(
+                    "integer too large: {}",
+                    x
+                )))),
+            },
             Num(Double(x)) => visitor.visit_f64((*x).into()),
-            Text(x) => visitor.visit_str(x),
-            List(xs) => {
-                visitor.visit_seq(SeqDeserializer::new(xs.iter().map(val)))
-            }
-            Optional(None) => visitor.visit_none(),
-            Optional(Some(x)) => visitor.visit_some(val(x)),
-            Record(m) => visitor.visit_map(MapDeserializer::new(
-                m.iter().map(|(k, v)| (k.as_str(), val(v))),
+            Text(x) => visitor.visit_borrowed_str(x),
+            List(xs) => visitor.visit_seq(SeqDeserializer::new(
+                xs.iter()
+                    .enumerate()
+                    .map(|(i, x)| self.child(x, Path::Index(&self.path, i))),
             )),
+            Optional(None) => visitor.visit_none(),
+            Optional(Some(x)) => visitor.visit_some(self.child(x, self.path)),
+            Record(m) => visitor.visit_map(MapDeserializer::new(m.iter().map(
+                |(k, v)| {
+                    (
+                        BorrowedKey(k.as_str()),
+                        self.child(v, Path::Field(&self.path, k)),
+                    )
+                },
+            ))),
             Union(field_name, Some(x)) => visitor.visit_enum(
                 MapAccessDeserializer::new(MapDeserializer::new(
-                    Some((field_name.as_str(), val(x))).into_iter(),
+                    Some((field_name.as_str(), self.child(x, self.path)))
+                        .into_iter(),
                 )),
             ),
             Union(field_name, None) => visitor.visit_enum(
@@ -153,6 +324,127 @@ impl<'de: 'a, 'a> serde::Deserializer<'de> for Deserializer<'a> {
         }
     }
 
+    fn tuple<V>(&self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        match self.value {
+            // Blindly takes keys in sorted order.
+            SimpleValue::Record(m) => visitor.visit_seq(SeqDeserializer::new(
+                m.iter()
+                    .map(|(k, v)| self.child(v, Path::Field(&self.path, k))),
+            )),
+            _ => self.any(visitor),
+        }
+    }
+
+    fn unit<V>(&self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        match self.value {
+            SimpleValue::Record(m) if m.is_empty() => visitor.visit_unit(),
+            // See `UnitRepresentation::UnionAlternative`.
+            SimpleValue::Union(_, None) => visitor.visit_unit(),
+            _ => self.any(visitor),
+        }
+    }
+
+    fn strukt<V>(
+        &self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        if let (true, SimpleValue::Record(m)) =
+            (self.deny_unknown_fields, self.value)
+        {
+            if let Some(k) = m.keys().find(|k| !fields.contains(&k.as_str())) {
+                let err = serde::de::Error::unknown_field(k, fields);
+                return self
+                    .child(self.value, Path::Field(&self.path, k))
+                    .locate(Err(err));
+            }
+        }
+        self.any(visitor)
+    }
+
+    fn map<V>(&self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        // Maps with non-text keys are association lists.
+        let entry = |x: &'de SimpleValue| match x {
+            SimpleValue::Record(m) if m.len() == 2 => {
+                Some((m.get("mapKey")?, m.get("mapValue")?))
+            }
+            _ => None,
+        };
+        match self.value {
+            SimpleValue::List(xs) => {
+                match xs.iter().map(entry).collect::<Option<Vec<_>>>() {
+                    Some(entries) => {
+                        let paths: Vec<_> = (0..entries.len())
+                            .map(|i| Path::Index(&self.path, i))
+                            .collect();
+                        visitor.visit_map(MapDeserializer::new(
+                            entries.into_iter().zip(&paths).map(
+                                |((k, v), path)| {
+                                    (
+                                        self.child(
+                                            k,
+                                            Path::Field(path, "mapKey"),
+                                        ),
+                                        self.child(
+                                            v,
+                                            Path::Field(path, "mapValue"),
+                                        ),
+                                    )
+                                },
+                            ),
+                        ))
+                    }
+                    None => self.any(visitor),
+                }
+            }
+            _ => self.any(visitor),
+        }
+    }
+}
+
+/// A record key that is deserialized without copying it. `BorrowedStrDeserializer` itself only
+/// implements `IntoDeserializer` in recent versions of serde.
+struct BorrowedKey<'de>(&'de str);
+
+impl<'de> serde::de::IntoDeserializer<'de, Error> for BorrowedKey<'de> {
+    type Deserializer = BorrowedStrDeserializer<'de, Error>;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        BorrowedStrDeserializer::new(self.0)
+    }
+}
+
+impl<'de> serde::de::IntoDeserializer<'de, Error>
+    for ValueDeserializer<'de, '_>
+{
+    type Deserializer = Self;
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+impl<'de> serde::Deserializer<'de> for ValueDeserializer<'de, '_> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.locate(self.any(visitor))
+    }
+
     fn deserialize_tuple<V>(
         self,
         _: usize,
@@ -161,29 +453,50 @@ impl<'de: 'a, 'a> serde::Deserializer<'de> for Deserializer<'a> {
     where
         V: serde::de::Visitor<'de>,
     {
-        let val = |x| Deserializer(Cow::Borrowed(x));
-        match self.0.as_ref() {
-            // Blindly takes keys in sorted order.
-            SimpleValue::Record(m) => visitor
-                .visit_seq(SeqDeserializer::new(m.iter().map(|(_, v)| val(v)))),
-            _ => self.deserialize_any(visitor),
-        }
+        self.locate(self.tuple(visitor))
     }
 
     fn deserialize_unit<V>(self, visitor: V) -> crate::Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
-        match self.0.as_ref() {
-            SimpleValue::Record(m) if m.is_empty() => visitor.visit_unit(),
-            _ => self.deserialize_any(visitor),
-        }
+        self.locate(self.unit(visitor))
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.locate(self.map(visitor))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.locate(self.strukt(fields, visitor))
     }
 
     serde::forward_to_deserialize_any! {
         bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf option unit_struct newtype_struct seq
-        tuple_struct map struct enum identifier ignored_any
+        bytes byte_buf option newtype_struct seq
+        tuple_struct enum identifier ignored_any
     }
 }
 
@@ -200,12 +513,15 @@ impl<'de> serde::de::Visitor<'de> for SimpleValueVisitor {
         Ok(SimpleValue::Num(NumKind::Bool(value)))
     }
 
+    // The conversion is only needed with the `bigint` feature.
+    #[allow(clippy::useless_conversion)]
     fn visit_i64<E>(self, value: i64) -> Result<SimpleValue, E> {
-        Ok(SimpleValue::Num(NumKind::Integer(value)))
+        Ok(SimpleValue::Num(NumKind::Integer(value.into())))
     }
 
+    #[allow(clippy::useless_conversion)]
     fn visit_u64<E>(self, value: u64) -> Result<SimpleValue, E> {
-        Ok(SimpleValue::Num(NumKind::Natural(value)))
+        Ok(SimpleValue::Num(NumKind::Natural(value.into())))
     }
 
     fn visit_f64<E>(self, value: f64) -> Result<SimpleValue, E> {
@@ -260,7 +576,7 @@ impl<'de> serde::de::Visitor<'de> for SimpleValueVisitor {
     where
         V: serde::de::MapAccess<'de>,
     {
-        let mut record = BTreeMap::default();
+        let mut record = RecordMap::default();
         while let Some((key, value)) = visitor.next_entry()? {
             record.insert(key, value);
         }
@@ -0,0 +1,74 @@
+//! Reads and writes [`Duration`]s as Dhall `Text`, like `"30s"` or `"1h30m"`, since Dhall has no
+//! type for durations.
+//!
+//! Use it on a field with `#[serde(with = "serde_dhall::duration")]`. A duration is a sequence of
+//! numbers, each followed by a unit: `d`, `h`, `m` (or `min`), `s`, `ms`, `us` (or `µs`) or
+//! `ns`. Numbers can have a fractional part, as in `1.5s`.
+//!
+//! # Example
+//!
+//! ```rust
+//! # fn main() -> serde_dhall::Result<()> {
+//! use std::time::Duration;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, PartialEq, Deserialize, Serialize)]
+//! struct Config {
+//!     #[serde(with = "serde_dhall::duration")]
+//!     timeout: Duration,
+//! }
+//!
+//! let config: Config = serde_dhall::from_str(r#"{ timeout = "1m30s" }"#).parse()?;
+//! assert_eq!(config.timeout, Duration::from_secs(90));
+//! assert_eq!(serde_dhall::serialize(&config).to_string()?, r#"{ timeout = "1m30s" }"#);
+//!
+//! let err = serde_dhall::from_str(r#"{ timeout = "90 seconds" }"#)
+//!     .parse::<Config>()
+//!     .unwrap_err();
+//! assert_eq!(
+//!     err.to_string(),
+//!     "timeout: invalid duration `90 seconds`: unknown unit `seconds`, expected one of `d`, \
+//!      `h`, `m`, `s`, `ms`, `us`, `ns`"
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserializer, Serializer};
+
+use crate::units::{format_duration, parse_duration};
+
+/// Deserializes a duration from its text form.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct Visitor;
+    impl serde::de::Visitor<'_> for Visitor {
+        type Value = Duration;
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a duration like `30s` or `1h30m`")
+        }
+        fn visit_str<E: serde::de::Error>(
+            self,
+            s: &str,
+        ) -> Result<Duration, E> {
+            parse_duration(s).map_err(E::custom)
+        }
+    }
+    deserializer.deserialize_str(Visitor)
+}
+
+/// Serializes a duration to its text form, e.g. `1h30m` or `500ms`.
+pub fn serialize<S>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&format_duration(*duration))
+}
@@ -0,0 +1,188 @@
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::{from_simple_value, Error, ErrorKind, Result, SimpleValue};
+
+type Constructor<'a, T> =
+    Box<dyn Fn(Option<SimpleValue>) -> Result<Box<T>> + 'a>;
+
+/// Deserializes Dhall unions into boxed trait objects, with one Rust type per alternative. This
+/// is the common plugin configuration pattern, where each alternative configures a different
+/// implementation of a trait.
+///
+/// The alternatives are listed in a `Deserialize` implementation for `Box<dyn Trait>`, so that
+/// the trait objects can be deserialized anywhere, e.g. in a `Vec` or in a struct field. Besides
+/// Dhall unions, this accepts serde's externally tagged representation of enums, which formats
+/// like JSON or YAML use: the name of an alternative without payload as a string, e.g.
+/// `"Stdout"`, or a map with a single key from the name of the alternative to its payload, e.g.
+/// `{"Http": {"port": 80}}`.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde::{Deserialize, Deserializer};
+/// use serde_dhall::DynUnion;
+///
+/// trait Output {
+///     fn describe(&self) -> String;
+/// }
+///
+/// #[derive(Deserialize)]
+/// struct Http {
+///     port: u64,
+/// }
+/// impl Output for Http {
+///     fn describe(&self) -> String {
+///         format!("http on port {}", self.port)
+///     }
+/// }
+///
+/// struct Stdout;
+/// impl Output for Stdout {
+///     fn describe(&self) -> String {
+///         "stdout".to_string()
+///     }
+/// }
+///
+/// impl<'de> Deserialize<'de> for Box<dyn Output> {
+///     fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+///         DynUnion::<dyn Output>::new()
+///             .alternative("Http", |http: Http| Box::new(http))
+///             .alternative_without_payload("Stdout", || Box::new(Stdout))
+///             .deserialize(d)
+///     }
+/// }
+///
+/// #[derive(Deserialize)]
+/// struct Config {
+///     outputs: Vec<Box<dyn Output>>,
+/// }
+///
+/// let config: Config = serde_dhall::from_str(
+///     "let Output = < Http : { port : Natural } | Stdout >
+///      in { outputs = [ Output.Http { port = 80 }, Output.Stdout ] }",
+/// )
+/// .parse()?;
+/// let outputs: Vec<String> = config.outputs.iter().map(|o| o.describe()).collect();
+/// assert_eq!(outputs, vec!["http on port 80", "stdout"]);
+/// # Ok(())
+/// # }
+/// ```
+pub struct DynUnion<'a, T: ?Sized> {
+    alternatives: Vec<(&'a str, Constructor<'a, T>)>,
+}
+
+impl<'a, T: ?Sized> DynUnion<'a, T> {
+    /// A union without alternatives. Add them with [`alternative()`] and
+    /// [`alternative_without_payload()`].
+    ///
+    /// [`alternative()`]: DynUnion::alternative()
+    /// [`alternative_without_payload()`]: DynUnion::alternative_without_payload()
+    pub fn new() -> Self {
+        DynUnion {
+            alternatives: Vec::new(),
+        }
+    }
+
+    /// Adds the alternative `name`, whose payload is deserialized as a `U` and then boxed by
+    /// `make`.
+    pub fn alternative<U: DeserializeOwned>(
+        mut self,
+        name: &'a str,
+        make: impl Fn(U) -> Box<T> + 'a,
+    ) -> Self {
+        let constructor = move |payload: Option<SimpleValue>| match payload {
+            Some(payload) => Ok(make(from_simple_value(payload)?)),
+            None => Err(Error(ErrorKind::Deserialize(format!(
+                "expected a payload for alternative `{}`",
+                name
+            )))),
+        };
+        self.alternatives.push((name, Box::new(constructor)));
+        self
+    }
+
+    /// Adds the alternative `name`, which has no payload, built by `make`.
+    pub fn alternative_without_payload(
+        mut self,
+        name: &'a str,
+        make: impl Fn() -> Box<T> + 'a,
+    ) -> Self {
+        let constructor = move |payload: Option<SimpleValue>| match payload {
+            None => Ok(make()),
+            Some(_) => Err(Error(ErrorKind::Deserialize(format!(
+                "unexpected payload for alternative `{}`",
+                name
+            )))),
+        };
+        self.alternatives.push((name, Box::new(constructor)));
+        self
+    }
+
+    /// Deserializes a union value into the trait object of its alternative. This is meant to be
+    /// called from `Deserialize::deserialize`.
+    pub fn deserialize<'de, D>(
+        &self,
+        deserializer: D,
+    ) -> std::result::Result<Box<T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = SimpleValue::deserialize(deserializer)?;
+        self.from_simple_value(value)
+            .map_err(serde::de::Error::custom)
+    }
+
+    /// Converts a union value, or an externally tagged enum, into the trait object of its
+    /// alternative.
+    pub fn from_simple_value(&self, value: SimpleValue) -> Result<Box<T>> {
+        let err = |msg| Err(Error(ErrorKind::Deserialize(msg)));
+        let (name, payload) = match value {
+            SimpleValue::Union(name, payload) => (name, payload.map(|x| *x)),
+            SimpleValue::Text(name) => (name, None),
+            SimpleValue::Record(fields) if fields.len() == 1 => {
+                let (name, payload) = fields.into_iter().next().unwrap();
+                (name, Some(payload))
+            }
+            value => {
+                return err(format!(
+                    "expected a union value, found `{}`",
+                    value
+                ))
+            }
+        };
+        match self.alternatives.iter().find(|(k, _)| *k == name) {
+            Some((_, constructor)) => constructor(payload),
+            None => {
+                let mut expected: Vec<String> = self
+                    .alternatives
+                    .iter()
+                    .map(|(k, _)| format!("`{}`", k))
+                    .collect();
+                expected.sort();
+                err(format!(
+                    "unknown alternative `{}`, expected one of {}",
+                    name,
+                    expected.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> Default for DynUnion<'_, T> {
+    fn default() -> Self {
+        DynUnion::new()
+    }
+}
+
+impl<T: ?Sized> std::fmt::Debug for DynUnion<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let alternatives: Vec<&str> =
+            self.alternatives.iter().map(|(k, _)| *k).collect();
+        f.debug_struct("DynUnion")
+            .field("alternatives", &alternatives)
+            .finish()
+    }
+}
@@ -11,6 +11,11 @@ pub struct Error(pub(crate) ErrorKind);
 pub(crate) enum ErrorKind {
     Dhall(DhallError),
     Deserialize(String),
+    /// A deserialization error that occurred inside the value, at the given path.
+    DeserializeAt {
+        path: String,
+        msg: String,
+    },
     Serialize(String),
 }
 
@@ -25,6 +30,9 @@ impl std::fmt::Display for Error {
         match &self.0 {
             ErrorKind::Dhall(err) => write!(f, "{}", err),
             ErrorKind::Deserialize(err) => write!(f, "{}", err),
+            ErrorKind::DeserializeAt { path, msg } => {
+                write!(f, "{}: {}", path, msg)
+            }
             ErrorKind::Serialize(err) => write!(f, "{}", err),
         }
     }
@@ -0,0 +1,107 @@
+use dhall::semantics::{Nir, NirKind};
+use dhall::syntax::Expr;
+use dhall::Ctxt;
+
+use crate::{
+    Error, ErrorKind, FromDhall, NonSimpleAlternatives, Result, SimpleType,
+    ToDhall, Value,
+};
+
+/// A Dhall function from a simple type to a simple type, that can be called from Rust.
+///
+/// This can be obtained with [`Deserializer::parse_functions()`], e.g. to let a configuration
+/// define named hooks.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// let hooks = serde_dhall::from_str(
+///     r#"{
+///         double = λ(n : Natural) → n * 2,
+///         greet = λ(name : Text) → "Hello, ${name}!",
+///     }"#,
+/// )
+/// .parse_functions()?;
+///
+/// assert_eq!(hooks["double"].call::<_, u64>(&21u64)?, 42);
+/// assert_eq!(hooks["greet"].call::<_, String>(&"world")?, "Hello, world!");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Deserializer::parse_functions()`]: crate::Deserializer::parse_functions()
+#[derive(Debug, Clone)]
+pub struct DhallFunction {
+    /// The normalized function. It has no free variables.
+    expr: Expr,
+    input: SimpleType,
+    output: SimpleType,
+}
+
+impl DhallFunction {
+    pub(crate) fn from_nir_and_ty<'cx>(
+        cx: Ctxt<'cx>,
+        x: &Nir<'cx>,
+        ty: &Nir<'cx>,
+    ) -> Result<Self> {
+        let types = match ty.kind() {
+            NirKind::PiClosure { annot, closure, .. } => {
+                SimpleType::from_nir(cx, annot, NonSimpleAlternatives::Fail)
+                    .ok()
+                    .and_then(|input| {
+                        // Types can't depend on values, so the output type can't mention the
+                        // argument if its type is simple.
+                        let output = closure.remove_binder()?;
+                        let output = SimpleType::from_nir(
+                            cx,
+                            &output,
+                            NonSimpleAlternatives::Fail,
+                        )
+                        .ok()?;
+                        Some((input, output))
+                    })
+            }
+            _ => None,
+        };
+        match types {
+            Some((input, output)) => Ok(DhallFunction {
+                expr: x.to_expr(cx, Default::default()),
+                input,
+                output,
+            }),
+            None => {
+                let ty = ty.to_expr(cx, Default::default());
+                Err(Error(ErrorKind::Deserialize(format!(
+                    "expected a function from a simple type to a simple type, \
+                     found a value of type `{}`",
+                    ty
+                ))))
+            }
+        }
+    }
+
+    /// The type of the argument of the function.
+    pub fn input_type(&self) -> &SimpleType {
+        &self.input
+    }
+
+    /// The type of the result of the function.
+    pub fn output_type(&self) -> &SimpleType {
+        &self.output
+    }
+
+    /// Applies the function to `arg` and deserializes the result.
+    ///
+    /// Fails if `arg` doesn't have the input type of the function.
+    pub fn call<A, R>(&self, arg: &A) -> Result<R>
+    where
+        A: ToDhall,
+        R: FromDhall,
+    {
+        let arg = arg.to_dhall(Some(&self.input))?;
+        let app = self.expr.clone().apply_to(arg.to_expr());
+        let val = Value::eval(app)?;
+        R::from_dhall(&val)
+    }
+}
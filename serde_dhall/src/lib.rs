@@ -113,6 +113,9 @@
 //! Beware that in order to serialize empty options, empty lists or enums correctly, you will need
 //! to provide a type annotation!
 //!
+//! To write a Dhall file instead, formatted for humans to read, use [`to_file()`] or
+//! [`Serializer::to_file()`].
+//!
 //! # Replacing `serde_json` or `serde_yaml`
 //!
 //! If you used to consume JSON or YAML, you only need to replace [`serde_json::from_str`] or
@@ -258,22 +261,58 @@ mod test_readme {
     doc_comment::doctest!("../../README.md");
 }
 
+mod any;
+pub mod ast;
+mod bindings;
+pub mod byte_size;
 mod deserialize;
+pub mod duration;
+mod dyn_union;
 mod error;
+mod function;
 mod options;
+mod render;
+mod report;
+mod schema;
 mod serialize;
+mod session;
+mod snapshot;
 mod static_type;
+mod union_match;
+mod units;
+mod validate;
 /// Dhall values
 mod value;
 
 #[doc(hidden)]
 pub use dhall_proc_macros::StaticType;
+pub use dhall_proc_macros::{dhall, include_dhall};
 
+pub use any::Any;
+pub use bindings::Bindings;
 pub use deserialize::{from_simple_value, FromDhall};
+pub use dhall::profile::{CountingAllocator, ImportCost, PhaseCost};
+pub use dhall::semantics::{Cache, FsCache};
+pub use dhall::syntax::{ParseLimits, PrintOptions};
+pub use dhall::{EvalLimits, HeaderForwarding};
+pub use dyn_union::DynUnion;
 pub(crate) use error::ErrorKind;
 pub use error::{Error, Result};
-pub use options::de::{from_binary_file, from_file, from_str, Deserializer};
-pub use options::ser::{serialize, Serializer};
-pub use serialize::ToDhall;
+pub use function::DhallFunction;
+pub use options::de::{
+    from_binary, from_binary_file, from_file, from_str, Deserializer,
+};
+pub use options::rename::RenameRule;
+pub use options::ser::{serialize, to_file, Serializer};
+pub use render::render;
+pub use report::Report;
+pub use schema::Schema;
+pub use serialize::{NumericPolicy, ToDhall, UnitRepresentation};
+pub use session::Session;
 pub use static_type::StaticType;
-pub use value::{NumKind, SimpleType, SimpleValue, Value};
+pub use union_match::UnionMatch;
+pub use validate::{ValidationErrors, Violation};
+pub use value::{
+    NonSimpleAlternatives, NumKind, Provenance, RecordMap, SimpleType,
+    SimpleValue, Value,
+};
@@ -1,17 +1,35 @@
-use std::collections::HashMap;
-use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
 
-use dhall::{Ctxt, Parsed};
+use dhall::analysis::field_origins;
+use dhall::semantics::NirKind;
+use dhall::semantics::{Cache, ImportLocation};
+use dhall::syntax::{
+    Expr, ExprKind, FilePath, FilePrefix, Import, ImportMode, ImportTarget,
+    ParseLimits,
+};
+use dhall::{Ctxt, EvalLimits, HeaderForwarding, Parsed, Resolved, Typed};
 
 use crate::options::{HasAnnot, ManualAnnot, NoAnnot, StaticAnnot, TypeAnnot};
-use crate::SimpleType;
-use crate::{Error, ErrorKind, FromDhall, Result, Value};
+use crate::snapshot;
+use crate::value::Coercions;
+use crate::{
+    Bindings, DhallFunction, Error, ErrorKind, FromDhall, Result, Value,
+};
+use crate::{
+    NonSimpleAlternatives, NumericPolicy, Provenance, RenameRule, Report,
+    Session, SimpleType,
+};
 
 #[derive(Debug, Clone)]
 enum Source<'a> {
     Str(&'a str),
     File(PathBuf),
     BinaryFile(PathBuf),
+    Binary(&'a [u8]),
+    /// The contents of a file, imported `as Text`.
+    TextFile(PathBuf),
     // Url(&'a str),
 }
 
@@ -60,8 +78,23 @@ pub struct Deserializer<'a, A> {
     annot: A,
     allow_imports: bool,
     builtins: HashMap<dhall::syntax::Label, dhall::syntax::Expr>,
+    union_naming: Option<RenameRule>,
+    numeric_policy: NumericPolicy,
+    maps_as_records: bool,
+    deny_unknown_fields: bool,
+    non_simple_alternatives: NonSimpleAlternatives,
+    /// Parsed with the source, so that an invalid path is reported like a parse error.
+    path: Option<String>,
+    /// `None` means the default on-disk cache.
+    import_cache: Option<Rc<dyn Cache>>,
+    track_provenance: bool,
+    parse_limits: ParseLimits,
+    eval_limits: EvalLimits,
+    require_frozen_remote_imports: bool,
+    header_forwarding: HeaderForwarding,
+    referential_sanity_check: bool,
+    snapshot: Option<PathBuf>,
     // allow_remote_imports: bool,
-    // use_cache: bool,
 }
 
 impl<'a> Deserializer<'a, NoAnnot> {
@@ -71,8 +104,21 @@ impl<'a> Deserializer<'a, NoAnnot> {
             annot: NoAnnot,
             allow_imports: true,
             builtins: HashMap::new(),
+            union_naming: None,
+            numeric_policy: NumericPolicy::default(),
+            maps_as_records: false,
+            deny_unknown_fields: false,
+            non_simple_alternatives: NonSimpleAlternatives::default(),
+            path: None,
+            import_cache: None,
+            track_provenance: false,
+            parse_limits: ParseLimits::default(),
+            eval_limits: EvalLimits::default(),
+            require_frozen_remote_imports: false,
+            header_forwarding: HeaderForwarding::default(),
+            referential_sanity_check: true,
+            snapshot: None,
             // allow_remote_imports: true,
-            // use_cache: true,
         }
     }
     fn from_str(s: &'a str) -> Self {
@@ -84,10 +130,36 @@ impl<'a> Deserializer<'a, NoAnnot> {
     fn from_binary_file<P: AsRef<Path>>(path: P) -> Self {
         Self::default_with_source(Source::BinaryFile(path.as_ref().to_owned()))
     }
+    fn from_binary(data: &'a [u8]) -> Self {
+        Self::default_with_source(Source::Binary(data))
+    }
     // fn from_url(url: &'a str) -> Self {
     //     Self::default_with_source(Source::Url(url))
     // }
 
+    /// Reads the contents of a file that isn't Dhall code, like the `./file as Text` Dhall
+    /// expression does. The result is a Dhall `Text`, so it deserializes into a `String`.
+    ///
+    /// This goes through Dhall's import system, so the file is subject to the same rules as other
+    /// imports, and [`imports(false)`] makes it fail. A relative path is relative to the current
+    /// directory.
+    ///
+    /// [`imports(false)`]: Deserializer::imports()
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Deserializer;
+    ///
+    /// let readme = Deserializer::import_as_text("README.md").parse::<String>()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn import_as_text<P: AsRef<Path>>(path: P) -> Self {
+        Self::default_with_source(Source::TextFile(path.as_ref().to_owned()))
+    }
+
     /// Ensures that the parsed value matches the provided type.
     ///
     /// In many cases the Dhall type that corresponds to a Rust type can be inferred automatically.
@@ -135,6 +207,20 @@ impl<'a> Deserializer<'a, NoAnnot> {
             source: self.source,
             allow_imports: self.allow_imports,
             builtins: self.builtins,
+            union_naming: self.union_naming,
+            numeric_policy: self.numeric_policy,
+            maps_as_records: self.maps_as_records,
+            deny_unknown_fields: self.deny_unknown_fields,
+            non_simple_alternatives: self.non_simple_alternatives,
+            path: self.path,
+            import_cache: self.import_cache,
+            track_provenance: self.track_provenance,
+            parse_limits: self.parse_limits,
+            eval_limits: self.eval_limits,
+            require_frozen_remote_imports: self.require_frozen_remote_imports,
+            header_forwarding: self.header_forwarding,
+            referential_sanity_check: self.referential_sanity_check,
+            snapshot: self.snapshot,
         }
     }
 
@@ -143,6 +229,10 @@ impl<'a> Deserializer<'a, NoAnnot> {
     /// `T` must implement the [`StaticType`] trait. If it doesn't, you can use
     /// [`type_annotation()`] to provide a type manually.
     ///
+    /// Maps are given the type of the lists built by `toMap`, but a record is also accepted where
+    /// a map with text keys is expected, e.g. `{ a = 1 }` for a `HashMap<String, u64>`. This
+    /// applies to manual annotations too.
+    ///
     /// # Example
     ///
     /// ```
@@ -186,6 +276,20 @@ impl<'a> Deserializer<'a, NoAnnot> {
             source: self.source,
             allow_imports: self.allow_imports,
             builtins: self.builtins,
+            union_naming: self.union_naming,
+            numeric_policy: self.numeric_policy,
+            maps_as_records: self.maps_as_records,
+            deny_unknown_fields: self.deny_unknown_fields,
+            non_simple_alternatives: self.non_simple_alternatives,
+            path: self.path,
+            import_cache: self.import_cache,
+            track_provenance: self.track_provenance,
+            parse_limits: self.parse_limits,
+            eval_limits: self.eval_limits,
+            require_frozen_remote_imports: self.require_frozen_remote_imports,
+            header_forwarding: self.header_forwarding,
+            referential_sanity_check: self.referential_sanity_check,
+            snapshot: self.snapshot,
         }
     }
 }
@@ -219,6 +323,209 @@ impl<'a, A> Deserializer<'a, A> {
         }
     }
 
+    /// Sets where imports protected by a hash are cached.
+    ///
+    /// By default, they are cached in the standard Dhall cache directory. Providing another
+    /// [`Cache`] makes it possible to share resolved imports between machines, e.g. through a
+    /// key-value database.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use std::cell::RefCell;
+    /// use std::collections::HashMap;
+    /// use serde_dhall::ast::Hash;
+    /// use serde_dhall::Cache;
+    ///
+    /// #[derive(Default)]
+    /// struct MemCache(RefCell<HashMap<String, Vec<u8>>>);
+    ///
+    /// impl Cache for MemCache {
+    ///     fn get(&self, hash: &Hash) -> std::io::Result<Option<Vec<u8>>> {
+    ///         Ok(self.0.borrow().get(&hash.to_string()).cloned())
+    ///     }
+    ///     fn put(&self, hash: &Hash, data: &[u8]) -> std::io::Result<()> {
+    ///         self.0.borrow_mut().insert(hash.to_string(), data.to_vec());
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let data = serde_dhall::from_str("1 + 1")
+    ///     .import_cache(MemCache::default())
+    ///     .parse::<u64>()?;
+    /// assert_eq!(data, 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn import_cache(self, cache: impl Cache + 'static) -> Self {
+        Deserializer {
+            import_cache: Some(Rc::new(cache)),
+            ..self
+        }
+    }
+
+    /// Sets limits on the size of the parsed code, including imported files, to protect against
+    /// untrusted input that would take too much memory. Exceeding them is a parse error.
+    ///
+    /// By default, only the nesting depth is limited, see [`ParseLimits`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::ParseLimits;
+    ///
+    /// let limits = ParseLimits {
+    ///     max_list_len: Some(3),
+    ///     ..ParseLimits::default()
+    /// };
+    /// let data = serde_dhall::from_str("[1, 2, 3]")
+    ///     .parse_limits(limits)
+    ///     .parse::<Vec<u64>>()?;
+    /// assert_eq!(data, vec![1, 2, 3]);
+    /// assert!(serde_dhall::from_str("[1, 2, 3, 4]")
+    ///     .parse_limits(limits)
+    ///     .parse::<Vec<u64>>()
+    ///     .is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn parse_limits(self, limits: ParseLimits) -> Self {
+        Deserializer {
+            parse_limits: limits,
+            ..self
+        }
+    }
+
+    /// Sets limits on the resources used to typecheck and evaluate the code, including imported
+    /// files, to protect against untrusted input that is small but builds huge values, like
+    /// repeated uses of `List/replicate`. Exceeding them is an error.
+    ///
+    /// By default, there are no limits. See [`EvalLimits`] for how memory is counted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::EvalLimits;
+    ///
+    /// let limits = EvalLimits {
+    ///     max_memory: Some(1_000_000),
+    /// };
+    /// let data = serde_dhall::from_str("[1, 2, 3] # [4]")
+    ///     .eval_limits(limits)
+    ///     .parse::<Vec<u64>>()?;
+    /// assert_eq!(data, vec![1, 2, 3, 4]);
+    ///
+    /// let bomb = r#"Natural/fold 30 Text (\(t : Text) -> t ++ t) "a""#;
+    /// let err = serde_dhall::from_str(bomb)
+    ///     .eval_limits(limits)
+    ///     .parse::<String>()
+    ///     .unwrap_err();
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "Evaluation exceeded the memory limit of 1000000 bytes"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn eval_limits(self, limits: EvalLimits) -> Self {
+        Deserializer {
+            eval_limits: limits,
+            ..self
+        }
+    }
+
+    /// Makes remote imports fail unless they are protected by an integrity hash (`sha256:...`),
+    /// to enforce that only pinned code gets loaded. The imports of a file that is itself
+    /// protected by a hash don't need one, since the hash covers them.
+    ///
+    /// By default, remote imports don't need a hash.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let data = "https://example.com/config.dhall";
+    /// let err = serde_dhall::from_str(data)
+    ///     .require_frozen_remote_imports(true)
+    ///     .parse::<u64>()
+    ///     .unwrap_err();
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "Remote import `https://example.com/config.dhall` is not protected by an integrity hash"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn require_frozen_remote_imports(self, require: bool) -> Self {
+        Deserializer {
+            require_frozen_remote_imports: require,
+            ..self
+        }
+    }
+
+    /// Sets which HTTP headers the relative imports of remote files are sent with. By default,
+    /// they get the headers of the file that imports them, as the Dhall standard specifies;
+    /// [`HeaderForwarding::Never`] disables that. The headers sent for each import are listed in
+    /// the [`Report`](crate::Report) returned by
+    /// [`parse_with_report()`](Deserializer::parse_with_report()).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::HeaderForwarding;
+    ///
+    /// let data = r#"
+    ///     https://example.com/config/package.dhall
+    ///         using (toMap { Authorization = "token secret" })
+    /// "#;
+    /// // `package.dhall` gets the token, but not the files it imports.
+    /// let (port, report) = serde_dhall::from_str(data)
+    ///     .header_forwarding(HeaderForwarding::Never)
+    ///     .parse_with_report::<u64>()?;
+    /// for import in &report.imports {
+    ///     let headers: Vec<&str> = import.location.header_names().collect();
+    ///     println!("{:?}: {:?}", import.location.as_remote_url(), headers);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn header_forwarding(self, policy: HeaderForwarding) -> Self {
+        Deserializer {
+            header_forwarding: policy,
+            ..self
+        }
+    }
+
+    /// Enables or disables the referential sanity check of the Dhall standard, which is enabled by
+    /// default. It prevents remote files from reading local files or environment variables, e.g.
+    /// to send secrets elsewhere, and from importing remote files of another origin unless those
+    /// allow it with the `Access-Control-Allow-Origin` header. Only disable it if the remote files
+    /// are trusted.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// // This file reads `env:HOST`.
+    /// let data = "https://config.example.com/server.dhall";
+    /// assert!(serde_dhall::from_str(data).parse::<String>().is_err());
+    /// let host = serde_dhall::from_str(data)
+    ///     .referential_sanity_check(false)
+    ///     .parse::<String>()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn referential_sanity_check(self, check: bool) -> Self {
+        Deserializer {
+            referential_sanity_check: check,
+            ..self
+        }
+    }
+
     // /// TODO
     // pub fn remote_imports(&mut self, imports: bool) -> &mut Self {
     //     self.allow_remote_imports = imports;
@@ -315,40 +622,551 @@ impl<'a, A> Deserializer<'a, A> {
         self
     }
 
-    fn _parse<T>(&self) -> dhall::error::Result<Result<Value>>
+    /// Makes the given values available to the parsed dhall code as variables. Like for
+    /// [`with_builtin_types()`], they are not accessible to imported files.
+    ///
+    /// See [`Bindings`] for an example, and [`Value::normalize_in_context()`] for a shortcut.
+    ///
+    /// [`with_builtin_types()`]: Deserializer::with_builtin_types()
+    pub fn with_bindings(mut self, bindings: &Bindings) -> Self {
+        self.builtins.extend(bindings.iter().map(|(name, value)| {
+            (dhall::syntax::Label::from_str(name), value.to_expr())
+        }));
+        self
+    }
+
+    /// Sets the naming convention followed by the alternatives of Dhall unions.
+    ///
+    /// Union alternatives are converted from this convention to `PascalCase` before being
+    /// deserialized into Rust enums. The type annotation, if any, is converted the other way.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde::Deserialize;
+    /// use serde_dhall::RenameRule;
+    ///
+    /// #[derive(Debug, PartialEq, Deserialize)]
+    /// enum Mode {
+    ///     ReadOnly,
+    ///     ReadWrite,
+    /// }
+    ///
+    /// let mode = serde_dhall::from_str("< READ_ONLY | READ_WRITE >.READ_WRITE")
+    ///     .union_naming(RenameRule::ScreamingSnakeCase)
+    ///     .parse::<Mode>()?;
+    /// assert_eq!(mode, Mode::ReadWrite);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn union_naming(self, rule: RenameRule) -> Self {
+        Deserializer {
+            union_naming: Some(rule),
+            ..self
+        }
+    }
+
+    /// Sets how strictly the `Natural` and `Integer` types in the type annotation must be
+    /// followed.
+    ///
+    /// By default they must match exactly. With any other [`NumericPolicy`], a `Natural` is also
+    /// accepted where the annotation expects an `Integer`, and a non-negative `Integer` where it
+    /// expects a `Natural`.
+    pub fn numeric_policy(self, policy: NumericPolicy) -> Self {
+        Deserializer {
+            numeric_policy: policy,
+            ..self
+        }
+    }
+
+    /// Sets whether a map built by `toMap`, i.e. a `List { mapKey : Text, mapValue : T }`, is
+    /// accepted where the type annotation expects a record. The keys of the map must then be
+    /// exactly the fields of the record, and all the fields must have type `T`.
+    ///
+    /// By default it isn't. The other way around, a record is always accepted where the annotation
+    /// expects a map with text keys. Together, these let a configuration switch between the two
+    /// layouts without updating the Rust types that read it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde::Deserialize;
+    /// use serde_dhall::StaticType;
+    ///
+    /// #[derive(Debug, PartialEq, Deserialize, StaticType)]
+    /// struct Ports {
+    ///     http: u64,
+    ///     https: u64,
+    /// }
+    ///
+    /// let data = "toMap { http = 80, https = 443 }";
+    /// assert!(serde_dhall::from_str(data)
+    ///     .static_type_annotation()
+    ///     .parse::<Ports>()
+    ///     .is_err());
+    ///
+    /// let ports = serde_dhall::from_str(data)
+    ///     .static_type_annotation()
+    ///     .maps_as_records(true)
+    ///     .parse::<Ports>()?;
+    /// assert_eq!(ports, Ports { http: 80, https: 443 });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn maps_as_records(self, allow: bool) -> Self {
+        Deserializer {
+            maps_as_records: allow,
+            ..self
+        }
+    }
+
+    /// Makes deserializing a struct fail if the Dhall record has fields that the struct doesn't
+    /// declare. The error points to the first such field, e.g. to catch a typo like `prot = 80`.
+    ///
+    /// By default these fields are ignored, unless the struct has `#[serde(deny_unknown_fields)]`.
+    /// A type annotation also rejects them, but this works for structs that don't implement
+    /// [`StaticType`]. Structs that use `#[serde(flatten)]` are not checked.
+    ///
+    /// [`StaticType`]: crate::StaticType
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Server {
+    ///     host: String,
+    ///     port: Option<u64>,
+    /// }
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Config {
+    ///     server: Server,
+    /// }
+    ///
+    /// let data = r#"{ server = { host = "localhost", prot = 80 } }"#;
+    /// let config = serde_dhall::from_str(data).parse::<Config>()?;
+    /// assert_eq!(config.server.port, None);
+    ///
+    /// let err = serde_dhall::from_str(data)
+    ///     .deny_unknown_fields()
+    ///     .parse::<Config>()
+    ///     .err()
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "server.prot: unknown field `prot`, expected `host` or `port`"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn deny_unknown_fields(self) -> Self {
+        Deserializer {
+            deny_unknown_fields: true,
+            ..self
+        }
+    }
+
+    /// Sets what to do with the alternatives of unions whose payload is not simple, e.g. a type or
+    /// a function.
+    ///
+    /// By default the whole value then fails to convert, even if it doesn't use these
+    /// alternatives. With the other [`NonSimpleAlternatives`] policies, these alternatives are
+    /// removed from the union types, or kept without their payload, so that the rest of the value
+    /// can still be read.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde::Deserialize;
+    /// use serde_dhall::NonSimpleAlternatives;
+    ///
+    /// #[derive(Debug, PartialEq, Deserialize)]
+    /// enum Check {
+    ///     Exists,
+    ///     Custom,
+    /// }
+    ///
+    /// let data = "let Check = < Exists | Custom : Text -> Bool >
+    ///             in [Check.Exists, Check.Custom (λ(x : Text) → True)]";
+    /// assert!(serde_dhall::from_str(data).parse::<Vec<Check>>().is_err());
+    ///
+    /// let checks = serde_dhall::from_str(data)
+    ///     .non_simple_alternatives(NonSimpleAlternatives::Stub)
+    ///     .parse::<Vec<Check>>()?;
+    /// assert_eq!(checks, vec![Check::Exists, Check::Custom]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn non_simple_alternatives(
+        self,
+        policy: NonSimpleAlternatives,
+    ) -> Self {
+        Deserializer {
+            non_simple_alternatives: policy,
+            ..self
+        }
+    }
+
+    /// Only deserializes the value found at the given path, given as a sequence of field names
+    /// separated by dots. Field names that contain dots or other special characters are quoted
+    /// with backticks, as in Dhall: ``services.`web.v2`.port``. An empty or invalid path is a
+    /// parse error.
+    ///
+    /// The whole expression is still typechecked, but only the selected value is normalized and
+    /// deserialized. A type annotation applies to the selected value.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let data = "{ services = { web = { port = 8080 }, db = { port = 5432 } } }";
+    /// let port = serde_dhall::from_str(data)
+    ///     .path("services.web.port")
+    ///     .parse::<u64>()?;
+    /// assert_eq!(port, 8080);
+    ///
+    /// assert!(serde_dhall::from_str(data)
+    ///     .path("services.cache.port")
+    ///     .parse::<u64>()
+    ///     .is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn path(self, path: &str) -> Self {
+        Deserializer {
+            path: Some(path.to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets whether to record where each record field is set, including in imported files and
+    /// through `let` bindings and `⫽` or `∧` merges. This can then be queried with
+    /// [`Value::field_provenance()`], e.g. to answer "which file sets this value?" in a
+    /// configuration split over several files.
+    ///
+    /// By default, provenance is not tracked, since it keeps the source of all imported files in
+    /// memory.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let value: Value = serde_dhall::from_str("{ a = 1 } // { b = 2 }")
+    ///     .track_provenance(true)
+    ///     .parse()?;
+    /// assert_eq!(value.field_provenance("b").unwrap().line_col, Some((1, 20)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn track_provenance(self, track: bool) -> Self {
+        Deserializer {
+            track_provenance: track,
+            ..self
+        }
+    }
+
+    /// Keeps a snapshot of the value in the file at `path`, so that it can be loaded again without
+    /// evaluating anything as long as the configuration didn't change. The snapshot is written
+    /// after evaluating the source, and used instead of evaluating it if the source, the options
+    /// and the contents of the local files and environment variables it imports are unchanged.
+    /// Remote imports are assumed not to change; delete the snapshot to fetch them again.
+    ///
+    /// Failing to read or write the snapshot is not an error: the value is just evaluated. A
+    /// snapshot is always evaluated in a new context, even with
+    /// [`parse_in()`](Deserializer::parse_in()).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use std::collections::HashMap;
+    ///
+    /// // Slow the first time, instant afterwards until `config.dhall` or its imports change.
+    /// let config = serde_dhall::from_file("config.dhall")
+    ///     .snapshot("config.snapshot")
+    ///     .parse::<HashMap<String, u64>>()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn snapshot<P: AsRef<Path>>(self, path: P) -> Self {
+        Deserializer {
+            snapshot: Some(path.as_ref().to_owned()),
+            ..self
+        }
+    }
+
+    /// Describes everything that the value depends on, except for the imports.
+    fn snapshot_fingerprint<T>(&self) -> Vec<u8>
     where
         A: TypeAnnot,
         T: HasAnnot<A>,
     {
+        let source = match &self.source {
+            Source::Str(s) => Some(s.as_bytes().to_vec()),
+            Source::Binary(data) => Some(data.to_vec()),
+            Source::File(p) | Source::BinaryFile(p) | Source::TextFile(p) => {
+                std::fs::read(p).ok()
+            }
+        };
+        let annot = T::get_annot(self.annot).map(|ty| ty.to_string());
+        let builtins: BTreeMap<_, _> = self
+            .builtins
+            .iter()
+            .map(|(name, expr)| (String::from(name), expr.to_string()))
+            .collect();
+        let mut fingerprint = format!(
+            "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+            self.source,
+            annot,
+            self.allow_imports,
+            self.require_frozen_remote_imports,
+            self.header_forwarding,
+            self.referential_sanity_check,
+            builtins,
+            self.union_naming,
+            self.numeric_policy,
+            self.maps_as_records,
+            self.non_simple_alternatives,
+            self.path,
+            self.track_provenance,
+        )
+        .into_bytes();
+        fingerprint.extend(source.unwrap_or_default());
+        fingerprint
+    }
+
+    /// Loads the value from its snapshot if it is up to date, or evaluates it and writes the
+    /// snapshot.
+    fn parse_with_snapshot<T>(
+        &self,
+        path: &Path,
+        report: &mut Report,
+    ) -> dhall::error::Result<Result<Value>>
+    where
+        A: TypeAnnot,
+        T: HasAnnot<A>,
+    {
+        let fingerprint = self.snapshot_fingerprint::<T>();
+        if let Some(val) = snapshot::load(path, &fingerprint) {
+            return Ok(Ok(val));
+        }
+        // Evaluate in a new context so that all the imports get recorded.
         Ctxt::with_new(|cx| {
-            let parsed = match &self.source {
-                Source::Str(s) => Parsed::parse_str(s)?,
-                Source::File(p) => Parsed::parse_file(p.as_ref())?,
-                Source::BinaryFile(p) => Parsed::parse_binary_file(p.as_ref())?,
-            };
-
-            let parsed_with_builtins =
-                self.builtins.iter().fold(parsed, |acc, (name, subst)| {
-                    acc.add_let_binding(name.clone(), subst.clone())
-                });
-
-            let resolved = if self.allow_imports {
-                parsed_with_builtins.resolve(cx)?
-            } else {
-                parsed_with_builtins.skip_resolve(cx)?
-            };
-            let typed = match &T::get_annot(self.annot) {
-                None => resolved.typecheck(cx)?,
-                Some(ty) => resolved.typecheck_with(cx, &ty.to_hir())?,
-            };
-            Ok(Value::from_nir_and_ty(
-                cx,
-                typed.normalize(cx).as_nir(),
-                typed.ty().as_nir(),
-            ))
+            cx.profile_imports();
+            let val = self.evaluate::<T>(cx, report)?;
+            let imports = cx.take_import_costs();
+            if let Ok(val) = &val {
+                let dependencies = imports
+                    .iter()
+                    .filter_map(|cost| snapshot::dependency(cx, &cost.location))
+                    .collect();
+                snapshot::save(path, &fingerprint, dependencies, val);
+            }
+            report.imports.extend(imports);
+            Ok(val)
         })
     }
 
+    /// Parses the source and resolves its imports, as configured.
+    fn resolve<'cx>(
+        &self,
+        cx: Ctxt<'cx>,
+    ) -> dhall::error::Result<Resolved<'cx>> {
+        let parsed = self.parse_source(cx)?;
+        self.resolve_parsed(cx, parsed)
+    }
+
+    /// Parses the source, as configured.
+    fn parse_source(&self, cx: Ctxt<'_>) -> dhall::error::Result<Parsed> {
+        cx.set_parse_limits(self.parse_limits);
+        cx.set_eval_limits(self.eval_limits);
+        cx.set_require_frozen_remote_imports(
+            self.require_frozen_remote_imports,
+        );
+        cx.set_header_forwarding(self.header_forwarding);
+        cx.set_referential_sanity_check(self.referential_sanity_check);
+        let parsed = match &self.source {
+            Source::Str(s) => {
+                Parsed::parse_str_with_limits(s, self.parse_limits)?
+            }
+            Source::File(p) => {
+                Parsed::parse_file_with_limits(p.as_ref(), self.parse_limits)?
+            }
+            Source::BinaryFile(p) => Parsed::parse_binary_file(p.as_ref())?,
+            Source::Binary(data) => Parsed::parse_binary(data)?,
+            Source::TextFile(p) => {
+                Parsed::parse_str(&text_import(p).to_string())?
+            }
+        };
+
+        let parsed = match &self.path {
+            Some(path) => dhall::syntax::parse_field_path(path)?
+                .into_iter()
+                .fold(parsed, |acc, label| acc.select_field(label)),
+            None => parsed,
+        };
+
+        Ok(self.builtins.iter().fold(parsed, |acc, (name, subst)| {
+            acc.add_let_binding(name.clone(), subst.clone())
+        }))
+    }
+
+    /// Resolves the imports of the parsed source, as configured.
+    fn resolve_parsed<'cx>(
+        &self,
+        cx: Ctxt<'cx>,
+        parsed: Parsed,
+    ) -> dhall::error::Result<Resolved<'cx>> {
+        Ok(if !self.allow_imports {
+            parsed.skip_resolve(cx)?
+        } else if let Some(cache) = &self.import_cache {
+            parsed.resolve_with_cache(cx, Some(cache.clone()))?
+        } else {
+            parsed.resolve(cx)?
+        })
+    }
+
+    fn _parse<'cx, T>(
+        &self,
+        cx: Ctxt<'cx>,
+        report: &mut Report,
+    ) -> dhall::error::Result<Result<Value>>
+    where
+        A: TypeAnnot,
+        T: HasAnnot<A>,
+    {
+        match &self.snapshot {
+            Some(path) => self.parse_with_snapshot::<T>(path, report),
+            None => self.evaluate::<T>(cx, report),
+        }
+    }
+
+    /// Evaluates the source into a value, as configured.
+    fn evaluate<'cx, T>(
+        &self,
+        cx: Ctxt<'cx>,
+        report: &mut Report,
+    ) -> dhall::error::Result<Result<Value>>
+    where
+        A: TypeAnnot,
+        T: HasAnnot<A>,
+    {
+        if self.track_provenance {
+            cx.keep_import_sources();
+        }
+        let parsed = report.parse.measure(|| self.parse_source(cx))?;
+        let resolved =
+            report.resolve.measure(|| self.resolve_parsed(cx, parsed))?;
+        let provenance = if self.track_provenance {
+            Some(self.provenance(cx, &resolved))
+        } else {
+            None
+        };
+        let mut val = self.eval::<T>(cx, resolved, report)?;
+        if let (Some(provenance), Ok(val)) = (provenance, &mut val) {
+            val.set_provenance(provenance);
+        }
+        Ok(val)
+    }
+
+    /// Where each field of the value was set, keyed by dotted path.
+    fn provenance<'cx>(
+        &self,
+        cx: Ctxt<'cx>,
+        resolved: &Resolved<'cx>,
+    ) -> BTreeMap<String, Provenance> {
+        let root = match &self.source {
+            Source::File(p) | Source::BinaryFile(p) => {
+                ImportLocation::local_dhall_code(p.clone())
+            }
+            Source::Str(_) | Source::Binary(_) | Source::TextFile(_) => {
+                ImportLocation::dhall_code_of_unknown_origin()
+            }
+        };
+        field_origins(cx, resolved, &root)
+            .into_iter()
+            .map(|(path, origin)| {
+                let path: Vec<String> = path.iter().map(String::from).collect();
+                let source = match (
+                    origin.location.as_local_path(),
+                    origin.location.as_remote_url(),
+                ) {
+                    (Some(path), _) => Some(path.display().to_string()),
+                    (_, Some(url)) => Some(url.to_string()),
+                    (None, None) => None,
+                };
+                let provenance = Provenance {
+                    source,
+                    line_col: origin.line_col,
+                };
+                (path.join("."), provenance)
+            })
+            .collect()
+    }
+
+    /// Typechecks and normalizes the resolved source, as configured.
+    fn eval<'cx, T>(
+        &self,
+        cx: Ctxt<'cx>,
+        resolved: Resolved<'cx>,
+        report: &mut Report,
+    ) -> dhall::error::Result<Result<Value>>
+    where
+        A: TypeAnnot,
+        T: HasAnnot<A>,
+    {
+        let annot =
+            T::get_annot(self.annot).map(|ty| match self.union_naming {
+                Some(rule) => {
+                    ty.rename_union_alternatives(&|k| rule.rename_variant(k))
+                }
+                None => ty,
+            });
+        let to_value = |typed: &Typed<'cx>| -> dhall::error::Result<_> {
+            let val = typed.normalize(cx)?;
+            cx.evaluate(|| {
+                Value::from_nir_and_ty(
+                    cx,
+                    val.as_nir(),
+                    typed.ty().as_nir(),
+                    self.non_simple_alternatives,
+                )
+            })
+        };
+        let typed = match &annot {
+            None => report.typecheck.measure(|| resolved.typecheck(cx))?,
+            Some(ty) => {
+                // Check the type after converting records to maps and, depending on the options,
+                // maps to records and the numbers. If that fails, typechecking against the
+                // annotation gives the appropriate error.
+                let coercions = Coercions {
+                    numbers: self.numeric_policy != NumericPolicy::Exact,
+                    maps_as_records: self.maps_as_records,
+                };
+                let typed =
+                    report.typecheck.measure(|| resolved.typecheck(cx))?;
+                let val = report.normalize.measure(|| to_value(&typed))?;
+                if let Some(val) =
+                    val.ok().and_then(|v| v.coerce(ty, coercions))
+                {
+                    return Ok(Ok(val));
+                }
+                report
+                    .typecheck
+                    .measure(|| resolved.typecheck_with(cx, &ty.to_hir()))?
+            }
+        };
+        report.normalize.measure(|| to_value(&typed))
+    }
+
     /// Parses the chosen dhall value with the options provided.
     ///
     /// If you enabled static annotations, `T` is required to implement [`StaticType`].
@@ -366,15 +1184,136 @@ impl<'a, A> Deserializer<'a, A> {
     ///
     /// [`StaticType`]: crate::StaticType
     pub fn parse<T>(&self) -> Result<T>
+    where
+        A: TypeAnnot,
+        T: FromDhall + HasAnnot<A>,
+    {
+        Session::with(|session| self.parse_in(session))
+    }
+
+    /// Like [`parse()`](Deserializer::parse()), but in a [`Session`] that is reused across many
+    /// expressions, so that their common imports are only loaded once.
+    pub fn parse_in<T>(&self, session: Session<'_>) -> Result<T>
+    where
+        A: TypeAnnot,
+        T: FromDhall + HasAnnot<A>,
+    {
+        self.parse_with(session.0, &mut Report::default())
+    }
+
+    /// Like [`parse()`](Deserializer::parse()), but also reports the time and memory taken by
+    /// each phase and each import, to find out why a configuration is slow to load. Memory is only
+    /// measured if [`CountingAllocator`] is the global allocator.
+    ///
+    /// [`CountingAllocator`]: crate::CountingAllocator
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let (data, report) = serde_dhall::from_str("List/length Natural [1, 2, 3]")
+    ///     .parse_with_report::<u64>()?;
+    /// assert_eq!(data, 3);
+    /// println!("typechecking took {:?}", report.typecheck.time);
+    /// assert!(report.imports.is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn parse_with_report<T>(&self) -> Result<(T, Report)>
+    where
+        A: TypeAnnot,
+        T: FromDhall + HasAnnot<A>,
+    {
+        Ctxt::with_new(|cx| {
+            cx.profile_imports();
+            let mut report = Report::default();
+            let val = self.parse_with(cx, &mut report)?;
+            report.imports.extend(cx.take_import_costs());
+            Ok((val, report))
+        })
+    }
+
+    fn parse_with<T>(&self, cx: Ctxt<'_>, report: &mut Report) -> Result<T>
     where
         A: TypeAnnot,
         T: FromDhall + HasAnnot<A>,
     {
         let val = self
-            ._parse::<T>()
+            ._parse::<T>(cx, report)
             .map_err(ErrorKind::Dhall)
             .map_err(Error)??;
-        T::from_dhall(&val)
+        let val = match self.union_naming {
+            Some(rule) => {
+                val.rename_union_alternatives(&|k| rule.recover_variant(k))
+            }
+            None => val,
+        };
+        T::from_dhall_with(&val, self.deny_unknown_fields)
+    }
+
+    /// Parses a record of functions, e.g. hooks defined in a configuration file. Each function
+    /// must take and return [simple types][`SimpleType`]; they can then be called from Rust.
+    ///
+    /// The type annotation, union naming and numeric policy options are not used.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let hooks = serde_dhall::from_str("{ port = λ(tls : Bool) → if tls then 443 else 80 }")
+    ///     .parse_functions()?;
+    ///
+    /// let port = &hooks["port"];
+    /// assert_eq!(port.output_type(), &serde_dhall::SimpleType::Natural);
+    /// assert_eq!(port.call::<_, u64>(&true)?, 443);
+    /// assert_eq!(port.call::<_, u64>(&false)?, 80);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn parse_functions(&self) -> Result<HashMap<String, DhallFunction>> {
+        Ctxt::with_new(|cx| {
+            let typed = self.resolve(cx)?.typecheck(cx)?;
+            let val = typed.normalize(cx)?;
+            let (fields, tys) =
+                match (val.as_nir().kind(), typed.ty().as_nir().kind()) {
+                    (NirKind::RecordLit(fields), NirKind::RecordType(tys)) => {
+                        (fields, tys)
+                    }
+                    _ => {
+                        let ty =
+                            typed.ty().as_nir().to_expr(cx, Default::default());
+                        return Ok(Err(Error(ErrorKind::Deserialize(
+                            format!(
+                                "expected a record of functions, found a \
+                                 value of type `{}`",
+                                ty
+                            ),
+                        ))));
+                    }
+                };
+            Ok(fields
+                .iter()
+                .map(|(name, f)| {
+                    let name = String::from(name);
+                    match DhallFunction::from_nir_and_ty(
+                        cx,
+                        f,
+                        &tys[name.as_str()],
+                    ) {
+                        Ok(f) => Ok((name, f)),
+                        Err(Error(ErrorKind::Deserialize(msg))) => {
+                            Err(Error(ErrorKind::DeserializeAt {
+                                path: name,
+                                msg,
+                            }))
+                        }
+                        Err(e) => Err(e),
+                    }
+                })
+                .collect())
+        })
+        .map_err(ErrorKind::Dhall)
+        .map_err(Error)?
     }
 }
 
@@ -415,6 +1354,28 @@ pub fn from_str(s: &str) -> Deserializer<'_, NoAnnot> {
     Deserializer::from_str(s)
 }
 
+/// The `path as Text` Dhall expression.
+fn text_import(path: &Path) -> ExprKind<Expr> {
+    let prefix = if path.has_root() {
+        FilePrefix::Absolute
+    } else {
+        FilePrefix::Here
+    };
+    let file_path = path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(s.to_string_lossy().into_owned()),
+            Component::ParentDir => Some("..".to_string()),
+            _ => None,
+        })
+        .collect();
+    ExprKind::Import(Import {
+        mode: ImportMode::RawText,
+        location: ImportTarget::Local(prefix, FilePath { file_path }),
+        hash: None,
+    })
+}
+
 /// Deserialize a value from a Dhall file.
 ///
 /// This returns a [`Deserializer`] object. Call the [`parse()`] method to get the deserialized
@@ -481,6 +1442,26 @@ pub fn from_binary_file<'a, P: AsRef<Path>>(
     Deserializer::from_binary_file(path)
 }
 
+/// Deserialize a value from CBOR-encoded Dhall binary data, like [`from_binary_file()`]. This is
+/// what [`include_dhall!`](crate::include_dhall) uses to embed a configuration in the program.
+///
+/// Imports will be resolved relative to the current directory.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// // The encoding of `[1, 2]`.
+/// let data = [0x84, 0x04, 0xf6, 0x82, 0x0f, 0x01, 0x82, 0x0f, 0x02];
+/// let list: Vec<u64> = serde_dhall::from_binary(&data).parse()?;
+/// assert_eq!(list, vec![1, 2]);
+/// # Ok(())
+/// # }
+/// ```
+pub fn from_binary(data: &[u8]) -> Deserializer<'_, NoAnnot> {
+    Deserializer::from_binary(data)
+}
+
 // pub fn from_url(url: &str) -> Deserializer<'_, NoAnnot> {
 //     Deserializer::from_url(url)
 // }
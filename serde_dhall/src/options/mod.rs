@@ -1,6 +1,7 @@
 use crate::{SimpleType, StaticType};
 
 pub(crate) mod de;
+pub(crate) mod rename;
 pub(crate) mod ser;
 
 #[derive(Debug, Clone, Copy)]
@@ -0,0 +1,122 @@
+/// A naming convention for the alternatives of Dhall unions.
+///
+/// Rust enum variants are conventionally written in `PascalCase`, but Dhall code often uses other
+/// conventions. When a `RenameRule` is given to [`Deserializer::union_naming()`] or
+/// [`Serializer::union_naming()`], the alternatives of Dhall unions are assumed to follow the
+/// given convention, and are converted from and to `PascalCase` variant names.
+///
+/// Words are split the same way serde's `rename_all` attribute does: every uppercase letter starts
+/// a new word.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde::Deserialize;
+/// use serde_dhall::{RenameRule, StaticType};
+///
+/// #[derive(Debug, PartialEq, Deserialize, StaticType)]
+/// enum Level {
+///     VeryLow,
+///     High,
+/// }
+///
+/// let level = serde_dhall::from_str("< very-low | high >.very-low")
+///     .static_type_annotation()
+///     .union_naming(RenameRule::KebabCase)
+///     .parse::<Level>()?;
+/// assert_eq!(level, Level::VeryLow);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Deserializer::union_naming()`]: crate::Deserializer::union_naming()
+/// [`Serializer::union_naming()`]: crate::Serializer::union_naming()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameRule {
+    /// `camelCase`
+    CamelCase,
+    /// `snake_case`
+    SnakeCase,
+    /// `SCREAMING_SNAKE_CASE`
+    ScreamingSnakeCase,
+    /// `kebab-case`
+    KebabCase,
+    /// `SCREAMING-KEBAB-CASE`
+    ScreamingKebabCase,
+}
+
+impl RenameRule {
+    /// Converts a `PascalCase` Rust variant name to this convention.
+    pub(crate) fn rename_variant(self, variant: &str) -> String {
+        use RenameRule::*;
+        match self {
+            CamelCase => {
+                let mut chars = variant.chars();
+                match chars.next() {
+                    Some(c) => c.to_lowercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            }
+            SnakeCase | KebabCase => {
+                let sep = if self == SnakeCase { '_' } else { '-' };
+                let mut out = String::new();
+                for (i, c) in variant.char_indices() {
+                    if c.is_uppercase() && i > 0 {
+                        out.push(sep);
+                    }
+                    out.extend(c.to_lowercase());
+                }
+                out
+            }
+            ScreamingSnakeCase => {
+                SnakeCase.rename_variant(variant).to_uppercase()
+            }
+            ScreamingKebabCase => {
+                KebabCase.rename_variant(variant).to_uppercase()
+            }
+        }
+    }
+
+    /// Converts a name following this convention to a `PascalCase` Rust variant name.
+    pub(crate) fn recover_variant(self, name: &str) -> String {
+        use RenameRule::*;
+        let sep = match self {
+            CamelCase => {
+                let mut chars = name.chars();
+                return match chars.next() {
+                    Some(c) => c.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                };
+            }
+            SnakeCase | ScreamingSnakeCase => '_',
+            KebabCase | ScreamingKebabCase => '-',
+        };
+        name.split(sep)
+            .flat_map(|word| {
+                let mut chars = word.chars();
+                let first =
+                    chars.next().into_iter().flat_map(char::to_uppercase);
+                first.chain(chars.flat_map(char::to_lowercase))
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_rename_rule() {
+    use RenameRule::*;
+    let cases = [
+        (CamelCase, "veryLow"),
+        (SnakeCase, "very_low"),
+        (ScreamingSnakeCase, "VERY_LOW"),
+        (KebabCase, "very-low"),
+        (ScreamingKebabCase, "VERY-LOW"),
+    ];
+    for &(rule, name) in &cases {
+        assert_eq!(rule.rename_variant("VeryLow"), name);
+        assert_eq!(rule.recover_variant(name), "VeryLow");
+    }
+    assert_eq!(KebabCase.rename_variant("A"), "a");
+    assert_eq!(KebabCase.recover_variant(""), "");
+}
@@ -1,5 +1,12 @@
+use std::path::Path;
+
+use dhall::syntax::{Expr, FormatOptions};
+use dhall::utils::write_file_atomically;
+
 use crate::options::{HasAnnot, ManualAnnot, NoAnnot, StaticAnnot, TypeAnnot};
-use crate::{Result, SimpleType, ToDhall};
+use crate::serialize::SerializeOptions;
+use crate::{ErrorKind, NumericPolicy, UnitRepresentation};
+use crate::{RenameRule, Result, Schema, SimpleType, ToDhall};
 
 /// Controls how a Dhall value is written.
 ///
@@ -45,6 +52,11 @@ use crate::{Result, SimpleType, ToDhall};
 pub struct Serializer<'a, T, A> {
     data: &'a T,
     annot: A,
+    union_naming: Option<RenameRule>,
+    header: Option<String>,
+    /// The schema whose defaults to leave out, and the Dhall expression that refers to it.
+    omit_defaults: Option<(Schema, String)>,
+    opts: SerializeOptions,
 }
 
 impl<'a, T> Serializer<'a, T, NoAnnot> {
@@ -89,6 +101,10 @@ impl<'a, T> Serializer<'a, T, NoAnnot> {
         Serializer {
             annot: ManualAnnot(ty),
             data: self.data,
+            union_naming: self.union_naming,
+            header: self.header,
+            omit_defaults: self.omit_defaults,
+            opts: self.opts,
         }
     }
 
@@ -127,6 +143,168 @@ impl<'a, T> Serializer<'a, T, NoAnnot> {
         Serializer {
             annot: StaticAnnot,
             data: self.data,
+            union_naming: self.union_naming,
+            header: self.header,
+            omit_defaults: self.omit_defaults,
+            opts: self.opts,
+        }
+    }
+}
+
+impl<'a, T, A> Serializer<'a, T, A> {
+    /// Sets the naming convention to use for the alternatives of Dhall unions.
+    ///
+    /// Rust enum variants are converted from `PascalCase` to this convention.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde::Serialize;
+    /// use serde_dhall::{RenameRule, StaticType};
+    ///
+    /// #[derive(Serialize, StaticType)]
+    /// enum Mode {
+    ///     ReadOnly,
+    ///     ReadWrite,
+    /// }
+    ///
+    /// let string = serde_dhall::serialize(&Mode::ReadOnly)
+    ///     .static_type_annotation()
+    ///     .union_naming(RenameRule::SnakeCase)
+    ///     .to_string()?;
+    /// assert_eq!(string, "< read_only | read_write >.read_only");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn union_naming(self, rule: RenameRule) -> Self {
+        Serializer {
+            union_naming: Some(rule),
+            ..self
+        }
+    }
+
+    /// Sets whether to accept maps whose keys are not strings.
+    ///
+    /// Dhall record fields can only be text labels. When this is enabled, maps with other keys,
+    /// e.g. numbers or enums, are serialized as association lists of type
+    /// `List { mapKey : K, mapValue : V }` instead, like the ones produced by `toMap`. Maps with
+    /// string keys are still serialized as records. By default, non-string keys are an error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use std::collections::BTreeMap;
+    ///
+    /// let mut ports = BTreeMap::new();
+    /// ports.insert(80u64, "http".to_string());
+    /// ports.insert(443u64, "https".to_string());
+    ///
+    /// assert!(serde_dhall::serialize(&ports).to_string().is_err());
+    /// let string = serde_dhall::serialize(&ports)
+    ///     .non_text_map_keys(true)
+    ///     .to_string()?;
+    /// assert_eq!(
+    ///     string,
+    ///     "[{ mapKey = 80, mapValue = \"http\" }, { mapKey = 443, mapValue = \"https\" }]"
+    /// );
+    ///
+    /// // They can be read back.
+    /// let read: BTreeMap<u64, String> = serde_dhall::from_str(&string).parse()?;
+    /// assert_eq!(read, ports);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn non_text_map_keys(self, allow: bool) -> Self {
+        Serializer {
+            opts: SerializeOptions {
+                non_text_map_keys: allow,
+                ..self.opts
+            },
+            ..self
+        }
+    }
+
+    /// Sets how to represent the unit type `()` and unit structs.
+    ///
+    /// By default they become the empty record `{=}`. See [`UnitRepresentation`] for the
+    /// alternatives.
+    pub fn unit_representation(self, unit: UnitRepresentation) -> Self {
+        Serializer {
+            opts: SerializeOptions { unit, ..self.opts },
+            ..self
+        }
+    }
+
+    /// Sets how Rust integers map to the `Natural` and `Integer` types.
+    ///
+    /// By default unsigned integers become `Natural`s and signed ones `Integer`s. See
+    /// [`NumericPolicy`] for the alternatives. With a type annotation, numbers are converted to
+    /// the type it expects when they fit.
+    pub fn numeric_policy(self, numbers: NumericPolicy) -> Self {
+        Serializer {
+            opts: SerializeOptions {
+                numbers,
+                ..self.opts
+            },
+            ..self
+        }
+    }
+
+    /// Sets a comment to write at the start of the file with [`to_file()`], e.g. to say that the
+    /// file is generated. Each line of `text` becomes a `--` comment line.
+    ///
+    /// [`to_file()`]: Serializer::to_file()
+    pub fn header_comment(self, text: impl Into<String>) -> Self {
+        Serializer {
+            header: Some(text.into()),
+            ..self
+        }
+    }
+
+    /// Writes the value, which must be a record of the type of `schema`, as a completion
+    /// `reference::{ ... }` that only has the fields whose value differs from their default.
+    /// `reference` is the Dhall expression the output uses for the schema, e.g. an import like
+    /// `./Config.dhall` or a variable bound elsewhere.
+    ///
+    /// Unless another type annotation is set, the type of the schema is used as annotation.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde::Serialize;
+    /// use serde_dhall::{from_str, Schema};
+    ///
+    /// #[derive(Serialize)]
+    /// struct Config {
+    ///     host: String,
+    ///     port: u64,
+    ///     tags: Vec<String>,
+    /// }
+    ///
+    /// let schema = Schema::new(
+    ///     from_str("{ host : Text, port : Natural, tags : List Text }").parse()?,
+    ///     from_str("{ port = 80, tags = [] : List Text }").parse()?,
+    /// )?;
+    ///
+    /// let config = Config {
+    ///     host: "example.com".to_string(),
+    ///     port: 80,
+    ///     tags: vec![],
+    /// };
+    /// let string = serde_dhall::serialize(&config)
+    ///     .omit_defaults(&schema, "./Config.dhall")
+    ///     .to_string()?;
+    /// assert_eq!(string, r#"(./Config.dhall)::{ host = "example.com" }"#);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn omit_defaults(self, schema: &Schema, reference: &str) -> Self {
+        Serializer {
+            omit_defaults: Some((schema.clone(), reference.to_owned())),
+            ..self
         }
     }
 }
@@ -140,7 +318,9 @@ where
     /// If you enabled static annotations, `T` is required to implement [`StaticType`].
     ///
     /// Note that if you do not provide a type annotation, some values may not be convertible to
-    /// Dhall, like empty lists or enums.
+    /// Dhall, like empty lists or enums. The type of an empty list or of `None` is inferred from
+    /// the other elements when they are in a list; otherwise the error names the field that needs
+    /// an annotation.
     ///
     ///
     /// # Example
@@ -160,8 +340,83 @@ where
     where
         T: ToDhall + HasAnnot<A>,
     {
-        let val = self.data.to_dhall(T::get_annot(self.annot).as_ref())?;
-        Ok(val.to_string())
+        Ok(self.to_expr()?.to_string())
+    }
+
+    /// Writes the value to the file at `path`, formatted over several lines and preceded by the
+    /// [`header_comment()`], if any.
+    ///
+    /// The file is replaced atomically: the text is first written to a temporary file in the
+    /// same directory, which is then renamed to `path`. Readers of `path` never see a partially
+    /// written file, and it is left untouched if the value can't be serialized.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde::Serialize;
+    /// use serde_dhall::StaticType;
+    ///
+    /// #[derive(Serialize, StaticType)]
+    /// struct Config {
+    ///     hosts: Vec<String>,
+    /// }
+    ///
+    /// let config = Config { hosts: vec![] };
+    /// serde_dhall::serialize(&config)
+    ///     .static_type_annotation()
+    ///     .header_comment("Generated by build.rs, do not edit.")
+    ///     .to_file("config.dhall")?;
+    /// // config.dhall now contains:
+    /// // -- Generated by build.rs, do not edit.
+    /// //
+    /// // { hosts = [] : List Text }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`header_comment()`]: Serializer::header_comment()
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()>
+    where
+        T: ToDhall + HasAnnot<A>,
+    {
+        let mut text = String::new();
+        if let Some(header) = &self.header {
+            for line in header.lines() {
+                text.push_str("--");
+                if !line.is_empty() {
+                    text.push(' ');
+                    text.push_str(line);
+                }
+                text.push('\n');
+            }
+            text.push('\n');
+        }
+        let expr = self.to_expr()?;
+        text.push_str(&expr.format(FormatOptions::default()));
+        text.push('\n');
+        write_file_atomically(path.as_ref(), text.as_bytes())
+            .map_err(|e| ErrorKind::Dhall(e.into()))?;
+        Ok(())
+    }
+
+    fn to_expr(&self) -> Result<Expr>
+    where
+        T: ToDhall + HasAnnot<A>,
+    {
+        let schema_ty = self.omit_defaults.as_ref().map(|(s, _)| s.ty());
+        let annot = T::get_annot(self.annot).or_else(|| schema_ty.cloned());
+        let val = self.data.to_dhall_with(annot.as_ref(), &self.opts)?;
+        let val = match self.union_naming {
+            Some(rule) => {
+                val.rename_union_alternatives(&|k| rule.rename_variant(k))
+            }
+            None => val,
+        };
+        match &self.omit_defaults {
+            Some((schema, reference)) => schema.completion(reference, &val),
+            None => Ok(val.to_expr()),
+        }
     }
 }
 
@@ -226,5 +481,37 @@ where
     Serializer {
         data,
         annot: NoAnnot,
+        union_naming: None,
+        header: None,
+        omit_defaults: None,
+        opts: SerializeOptions::default(),
+    }
+}
+
+/// Writes a value to a Dhall file, with the type `ty` if given.
+///
+/// This is a shorthand for [`serialize()`] followed by [`Serializer::to_file()`], which also
+/// allows setting a header comment and the other serialization options.
+///
+/// # Example
+///
+/// ```no_run
+/// # fn main() -> serde_dhall::Result<()> {
+/// use std::collections::BTreeMap;
+///
+/// let mut ports = BTreeMap::new();
+/// ports.insert("http".to_string(), 80u64);
+/// serde_dhall::to_file("ports.dhall", &ports, None)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn to_file<P, T>(path: P, data: &T, ty: Option<&SimpleType>) -> Result<()>
+where
+    P: AsRef<Path>,
+    T: ToDhall,
+{
+    match ty {
+        Some(ty) => serialize(data).type_annotation(ty).to_file(path),
+        None => serialize(data).to_file(path),
     }
 }
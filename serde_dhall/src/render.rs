@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use dhall::{Ctxt, Parsed};
+
+use crate::{
+    Error, ErrorKind, FromDhall, NonSimpleAlternatives, Result, SimpleType,
+    ToDhall, Value,
+};
+
+/// Render a Dhall template file with the provided bindings.
+///
+/// The file must contain a Dhall function that returns `Text`, e.g.
+/// `\(args : { name : Text }) -> "Hello ${args.name}!"`. The bindings are serialized to Dhall,
+/// passed as argument to that function, and the resulting text is returned. This covers the
+/// common use of Dhall as a templating language in a single call.
+///
+/// Imports in the template will be resolved relative to the provided file's path.
+///
+/// Note that as with [`serialize()`], some values (like empty lists or enums) cannot be converted
+/// to Dhall without a type annotation, and will therefore fail to render.
+///
+/// # Example
+///
+/// ```no_run
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde::Serialize;
+///
+/// #[derive(Serialize)]
+/// struct Args {
+///     name: String,
+/// }
+///
+/// // `greeting.dhall` contains `\(args : { name : Text }) -> "Hello ${args.name}!"`
+/// let args = Args { name: "world".to_string() };
+/// let text = serde_dhall::render("greeting.dhall", &args)?;
+/// assert_eq!(text, "Hello world!");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`serialize()`]: crate::serialize()
+pub fn render<P, T>(template_path: P, bindings: &T) -> Result<String>
+where
+    P: AsRef<Path>,
+    T: ToDhall + ?Sized,
+{
+    let arg = bindings.to_dhall(None)?.to_expr();
+    let val = Ctxt::with_new(|cx| {
+        let typed = Parsed::parse_file(template_path.as_ref())?
+            .apply_to(arg)
+            .resolve(cx)?
+            .typecheck_with(cx, &SimpleType::Text.to_hir())?;
+        let val = typed.normalize(cx)?;
+        cx.evaluate(|| {
+            Value::from_nir_and_ty(
+                cx,
+                val.as_nir(),
+                typed.ty().as_nir(),
+                NonSimpleAlternatives::Fail,
+            )
+        })
+    })
+    .map_err(ErrorKind::Dhall)
+    .map_err(Error)??;
+    String::from_dhall(&val)
+}
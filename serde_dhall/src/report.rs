@@ -0,0 +1,20 @@
+use dhall::profile::{ImportCost, PhaseCost};
+
+/// The time and memory taken to load a value, returned by
+/// [`parse_with_report()`](crate::Deserializer::parse_with_report()).
+///
+/// The imports are loaded while resolving, so their costs are part of `resolve`.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Parsing the source.
+    pub parse: PhaseCost,
+    /// Fetching, typechecking and normalizing the imports.
+    pub resolve: PhaseCost,
+    /// Typechecking the source, and checking it against the type annotation if any.
+    pub typecheck: PhaseCost,
+    /// Normalizing the source and converting it to a Rust value.
+    pub normalize: PhaseCost,
+    /// The cost of each import, in the order they finished loading. The cost of an import includes
+    /// the costs of the imports it contains.
+    pub imports: Vec<ImportCost>,
+}
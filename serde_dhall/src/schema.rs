@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use dhall::operations::OpKind;
+use dhall::syntax::{Expr, ExprKind, Span};
+use dhall::Parsed;
+
+use crate::{
+    from_file, Error, ErrorKind, RecordMap, Result, SimpleType, SimpleValue,
+    Value,
+};
+
+/// A Dhall schema: a record type with default values for some of its fields, written in Dhall as
+/// `{ Type = T, default = d }` and used with the `::` operator.
+///
+/// With [`Serializer::omit_defaults()`], values of the record type are written as
+/// `Schema::{ ... }`, with only the fields that differ from the defaults.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde_dhall::{from_str, Schema};
+///
+/// let schema = Schema::new(
+///     from_str("{ host : Text, port : Natural }").parse()?,
+///     from_str("{ port = 80 }").parse()?,
+/// )?;
+/// assert!(schema.default_fields().contains_key("port"));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Serializer::omit_defaults()`]: crate::Serializer::omit_defaults()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schema {
+    ty: SimpleType,
+    default: RecordMap,
+}
+
+impl Schema {
+    /// Makes a schema from its record type and the record of default values. Fails if either is
+    /// not a record.
+    pub fn new(ty: SimpleType, default: SimpleValue) -> Result<Schema> {
+        let err = |msg: String| Err(Error(ErrorKind::Serialize(msg)));
+        match (&ty, default) {
+            (SimpleType::Record(_), SimpleValue::Record(default)) => {
+                Ok(Schema { ty, default })
+            }
+            (SimpleType::Record(_), default) => err(format!(
+                "the default of a schema must be a record, found `{}`",
+                default
+            )),
+            (ty, _) => err(format!(
+                "the type of a schema must be a record type, found `{}`",
+                ty
+            )),
+        }
+    }
+
+    /// Reads a schema from a Dhall file that evaluates to a record `{ Type = T, default = d }`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// // `Config.dhall` contains
+    /// // `{ Type = { host : Text, port : Natural }, default.port = 80 }`
+    /// let schema = serde_dhall::Schema::from_file("Config.dhall")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Schema> {
+        let path = path.as_ref();
+        Schema::new(
+            from_file(path).path("Type").parse()?,
+            from_file(path).path("default").parse()?,
+        )
+    }
+
+    /// The record type of the values.
+    pub fn ty(&self) -> &SimpleType {
+        &self.ty
+    }
+
+    /// The fields that have a default value.
+    pub fn default_fields(&self) -> &RecordMap {
+        &self.default
+    }
+
+    /// Writes `val` as `reference::{ ... }`, leaving out the fields that have their default value.
+    pub(crate) fn completion(
+        &self,
+        reference: &str,
+        val: &Value,
+    ) -> Result<Expr> {
+        let val = val.to_simple_value()?;
+        // Check that the value has the type of the schema.
+        val.to_expr(Some(&self.ty))?;
+        let (kvs, kts) = match (val, &self.ty) {
+            (SimpleValue::Record(kvs), SimpleType::Record(kts)) => (kvs, kts),
+            _ => unreachable!("the schema type is a record type"),
+        };
+        let mut overrides = RecordMap::new();
+        let mut override_tys = HashMap::new();
+        for (k, v) in kvs {
+            if self.default.get(&k) != Some(&v) {
+                override_tys.insert(k.clone(), kts[&k].clone());
+                overrides.insert(k, v);
+            }
+        }
+        let overrides = SimpleValue::Record(overrides)
+            .to_expr(Some(&SimpleType::Record(override_tys)))?;
+        let reference = Parsed::parse_str(reference)
+            .map_err(ErrorKind::Dhall)?
+            .to_expr();
+        Ok(Expr::new(
+            ExprKind::Op(OpKind::Completion(reference, overrides)),
+            Span::Artificial,
+        ))
+    }
+}
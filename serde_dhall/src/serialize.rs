@@ -1,10 +1,11 @@
 use serde::ser;
-use std::collections::BTreeMap;
 
-use dhall::syntax::NumKind;
+use dhall::syntax::{Integer, Natural, NumKind};
+use num_traits::ToPrimitive;
+use std::convert::TryFrom;
 
 use crate::value::SimpleValue;
-use crate::{Error, ErrorKind, Result, SimpleType, Value};
+use crate::{Error, ErrorKind, RecordMap, Result, SimpleType, Value};
 use SimpleValue::*;
 
 pub trait Sealed {}
@@ -39,7 +40,128 @@ pub trait Sealed {}
 /// [serde]: https://serde.rs
 pub trait ToDhall: Sealed {
     #[doc(hidden)]
-    fn to_dhall(&self, ty: Option<&SimpleType>) -> Result<Value>;
+    fn to_dhall(&self, ty: Option<&SimpleType>) -> Result<Value> {
+        self.to_dhall_with(ty, &SerializeOptions::default())
+    }
+    #[doc(hidden)]
+    fn to_dhall_with(
+        &self,
+        ty: Option<&SimpleType>,
+        opts: &SerializeOptions,
+    ) -> Result<Value>;
+}
+
+/// Controls how Rust values are converted to Dhall. Set through [`crate::Serializer`].
+#[doc(hidden)]
+#[derive(Debug, Default, Clone)]
+pub struct SerializeOptions {
+    /// Whether maps with non-`Text` keys become association lists instead of an error.
+    pub non_text_map_keys: bool,
+    /// How to represent `()`.
+    pub unit: UnitRepresentation,
+    /// How to map Rust integers to Dhall numbers.
+    pub numbers: NumericPolicy,
+}
+
+/// How to represent the unit type `()` and unit structs in Dhall.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde_dhall::{from_str, serialize, SimpleType, UnitRepresentation};
+///
+/// let ty: SimpleType = from_str("Optional < Nothing >").parse()?;
+/// let string = serialize(&Some(()))
+///     .type_annotation(&ty)
+///     .unit_representation(UnitRepresentation::UnionAlternative(
+///         "Nothing".to_string(),
+///     ))
+///     .to_string()?;
+/// assert_eq!(string, "Some < Nothing >.Nothing");
+///
+/// // It can be read back.
+/// let data: Option<()> = from_str(&string).type_annotation(&ty).parse()?;
+/// assert_eq!(data, Some(()));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnitRepresentation {
+    /// The empty record `{=}`, of type `{}`. This is the default, and matches the type given by
+    /// [`StaticType`](crate::StaticType).
+    EmptyRecord,
+    /// The given alternative of a union, without a payload, e.g. `< Unit >.Unit`. The type of the
+    /// union must be provided with a type annotation.
+    UnionAlternative(String),
+}
+
+// `#[default]` on enum variants needs a more recent compiler than we support.
+#[allow(clippy::derivable_impls)]
+impl Default for UnitRepresentation {
+    fn default() -> Self {
+        UnitRepresentation::EmptyRecord
+    }
+}
+
+/// How Rust integer types map to the Dhall `Natural` and `Integer` types.
+///
+/// With a policy other than `Exact`, a `Natural` is also accepted where a type annotation expects
+/// an `Integer`, and a non-negative `Integer` where it expects a `Natural`, both when serializing
+/// and when deserializing. This lets e.g. a struct with `i64` fields be read with
+/// [`static_type_annotation()`] from a file that uses `Natural` literals.
+///
+/// [`static_type_annotation()`]: crate::Deserializer::static_type_annotation()
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde::{Deserialize, Serialize};
+/// use serde_dhall::{from_str, serialize, NumericPolicy, StaticType};
+///
+/// #[derive(Debug, PartialEq, Deserialize, Serialize, StaticType)]
+/// struct Offset {
+///     x: i64,
+///     y: i64,
+/// }
+///
+/// let data = "{ x = 1, y = 2 }";
+/// assert!(from_str(data)
+///     .static_type_annotation()
+///     .parse::<Offset>()
+///     .is_err());
+/// let offset = from_str(data)
+///     .static_type_annotation()
+///     .numeric_policy(NumericPolicy::NaturalWhenPossible)
+///     .parse::<Offset>()?;
+/// assert_eq!(offset, Offset { x: 1, y: 2 });
+///
+/// let string = serialize(&Offset { x: 1, y: -2 })
+///     .numeric_policy(NumericPolicy::NaturalWhenPossible)
+///     .to_string()?;
+/// assert_eq!(string, "{ x = 1, y = -2 }");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumericPolicy {
+    /// Unsigned integers are `Natural`s and signed integers are `Integer`s, and type annotations
+    /// must match exactly. This is the default, and matches the types given by
+    /// [`StaticType`](crate::StaticType).
+    Exact,
+    /// All integers are serialized as `Integer`s.
+    IntegerForAll,
+    /// Non-negative integers are serialized as `Natural`s, and only negative ones as `Integer`s.
+    NaturalWhenPossible,
+}
+
+// `#[default]` on enum variants needs a more recent compiler than we support.
+#[allow(clippy::derivable_impls)]
+impl Default for NumericPolicy {
+    fn default() -> Self {
+        NumericPolicy::Exact
+    }
 }
 
 impl<T> Sealed for T where T: ser::Serialize {}
@@ -48,25 +170,35 @@ impl<T> ToDhall for T
 where
     T: ser::Serialize,
 {
-    fn to_dhall(&self, ty: Option<&SimpleType>) -> Result<Value> {
-        let sval: SimpleValue = self.serialize(Serializer)?;
+    fn to_dhall_with(
+        &self,
+        ty: Option<&SimpleType>,
+        opts: &SerializeOptions,
+    ) -> Result<Value> {
+        let sval: SimpleValue = self.serialize(Serializer(opts))?;
+        let sval = match ty {
+            Some(ty) if opts.numbers != NumericPolicy::Exact => {
+                sval.coerce_numbers(ty)
+            }
+            _ => sval,
+        };
         sval.into_value(ty)
     }
 }
 
-#[derive(Default, Clone, Copy)]
-struct Serializer;
+#[derive(Clone, Copy)]
+struct Serializer<'o>(&'o SerializeOptions);
 
-impl ser::Serializer for Serializer {
+impl<'o> ser::Serializer for Serializer<'o> {
     type Ok = SimpleValue;
     type Error = Error;
 
-    type SerializeSeq = SeqSerializer;
-    type SerializeTuple = TupleSerializer;
+    type SerializeSeq = SeqSerializer<'o>;
+    type SerializeTuple = TupleSerializer<'o>;
     type SerializeTupleStruct = ser::Impossible<Self::Ok, Self::Error>;
     type SerializeTupleVariant = ser::Impossible<Self::Ok, Self::Error>;
-    type SerializeMap = MapSerializer;
-    type SerializeStruct = StructSerializer;
+    type SerializeMap = MapSerializer<'o>;
+    type SerializeStruct = StructSerializer<'o>;
     type SerializeStructVariant = ser::Impossible<Self::Ok, Self::Error>;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
@@ -83,7 +215,19 @@ impl ser::Serializer for Serializer {
         self.serialize_i64(i64::from(v))
     }
     fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
-        Ok(Num(NumKind::Integer(v)))
+        self.serialize_i128(i128::from(v))
+    }
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok> {
+        if self.0.numbers == NumericPolicy::NaturalWhenPossible && v >= 0 {
+            return self.serialize_u128(v as u128);
+        }
+        match Integer::try_from(v) {
+            Ok(v) => Ok(Num(NumKind::Integer(v))),
+            Err(_) => {
+                Err(ErrorKind::Serialize(format!("integer too large: {}", v))
+                    .into())
+            }
+        }
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
@@ -96,7 +240,27 @@ impl ser::Serializer for Serializer {
         self.serialize_u64(u64::from(v))
     }
     fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
-        Ok(Num(NumKind::Natural(v)))
+        self.serialize_u128(u128::from(v))
+    }
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok> {
+        if self.0.numbers == NumericPolicy::IntegerForAll {
+            return match Integer::try_from(v) {
+                Ok(v) => Ok(Num(NumKind::Integer(v))),
+                Err(_) => Err(ErrorKind::Serialize(format!(
+                    "integer too large: {}",
+                    v
+                ))
+                .into()),
+            };
+        }
+        match Natural::try_from(v) {
+            Ok(v) => Ok(Num(NumKind::Natural(v))),
+            Err(_) => Err(ErrorKind::Serialize(format!(
+                "natural number too large: {}",
+                v
+            ))
+            .into()),
+        }
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
@@ -131,7 +295,12 @@ impl ser::Serializer for Serializer {
     }
 
     fn serialize_unit(self) -> Result<Self::Ok> {
-        Ok(Record(Default::default()))
+        Ok(match &self.0.unit {
+            UnitRepresentation::EmptyRecord => Record(Default::default()),
+            UnitRepresentation::UnionAlternative(variant) => {
+                Union(variant.clone(), None)
+            }
+        })
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
@@ -155,7 +324,7 @@ impl ser::Serializer for Serializer {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStruct> {
-        Ok(StructSerializer::default())
+        Ok(StructSerializer(self.0, RecordMap::default()))
     }
 
     fn serialize_unit_variant(
@@ -205,7 +374,7 @@ impl ser::Serializer for Serializer {
     }
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
-        Ok(TupleSerializer::default())
+        Ok(TupleSerializer(self.0, Vec::new()))
     }
     fn serialize_tuple_struct(
         self,
@@ -219,18 +388,22 @@ impl ser::Serializer for Serializer {
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        Ok(SeqSerializer::default())
+        Ok(SeqSerializer(self.0, Vec::new()))
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        Ok(MapSerializer::default())
+        Ok(MapSerializer {
+            opts: self.0,
+            entries: Vec::new(),
+            key: None,
+            val: None,
+        })
     }
 }
 
-#[derive(Default)]
-struct SeqSerializer(Vec<SimpleValue>);
+struct SeqSerializer<'o>(&'o SerializeOptions, Vec<SimpleValue>);
 
-impl ser::SerializeSeq for SeqSerializer {
+impl ser::SerializeSeq for SeqSerializer<'_> {
     type Ok = SimpleValue;
     type Error = Error;
 
@@ -238,19 +411,18 @@ impl ser::SerializeSeq for SeqSerializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        self.0.push(value.serialize(Serializer)?);
+        self.1.push(value.serialize(Serializer(self.0))?);
         Ok(())
     }
 
     fn end(self) -> Result<Self::Ok> {
-        Ok(List(self.0))
+        Ok(List(self.1))
     }
 }
 
-#[derive(Default)]
-struct TupleSerializer(Vec<SimpleValue>);
+struct TupleSerializer<'o>(&'o SerializeOptions, Vec<SimpleValue>);
 
-impl ser::SerializeTuple for TupleSerializer {
+impl ser::SerializeTuple for TupleSerializer<'_> {
     type Ok = SimpleValue;
     type Error = Error;
 
@@ -258,13 +430,13 @@ impl ser::SerializeTuple for TupleSerializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        self.0.push(value.serialize(Serializer)?);
+        self.1.push(value.serialize(Serializer(self.0))?);
         Ok(())
     }
 
     fn end(self) -> Result<Self::Ok> {
         Ok(Record(
-            self.0
+            self.1
                 .into_iter()
                 .enumerate()
                 .map(|(i, x)| (format!("_{}", i + 1), x))
@@ -273,14 +445,14 @@ impl ser::SerializeTuple for TupleSerializer {
     }
 }
 
-#[derive(Default)]
-struct MapSerializer {
-    map: BTreeMap<String, SimpleValue>,
-    key: Option<String>,
+struct MapSerializer<'o> {
+    opts: &'o SerializeOptions,
+    entries: Vec<(SimpleValue, SimpleValue)>,
+    key: Option<SimpleValue>,
     val: Option<SimpleValue>,
 }
 
-impl ser::SerializeMap for MapSerializer {
+impl ser::SerializeMap for MapSerializer<'_> {
     type Ok = SimpleValue;
     type Error = Error;
 
@@ -288,12 +460,15 @@ impl ser::SerializeMap for MapSerializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        let key = match key.serialize(Serializer)? {
-            Text(key) => key,
-            _ => return Err(<Error as ser::Error>::custom("not a string")),
+        let key = match key.serialize(Serializer(self.opts))? {
+            Text(key) => Text(key),
+            _ if !self.opts.non_text_map_keys => {
+                return Err(<Error as ser::Error>::custom("not a string"))
+            }
+            key => key,
         };
         if let Some(val) = self.val.take() {
-            self.map.insert(key, val);
+            self.entries.push((key, val));
         } else {
             self.key = Some(key);
         }
@@ -304,9 +479,9 @@ impl ser::SerializeMap for MapSerializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        let val: SimpleValue = val.serialize(Serializer)?;
+        let val: SimpleValue = val.serialize(Serializer(self.opts))?;
         if let Some(key) = self.key.take() {
-            self.map.insert(key, val);
+            self.entries.push((key, val));
         } else {
             self.val = Some(val);
         }
@@ -314,14 +489,37 @@ impl ser::SerializeMap for MapSerializer {
     }
 
     fn end(self) -> Result<Self::Ok> {
-        Ok(Record(self.map))
+        if self.entries.iter().all(|(k, _)| matches!(k, Text(_))) {
+            Ok(Record(
+                self.entries
+                    .into_iter()
+                    .map(|(k, v)| match k {
+                        Text(k) => (k, v),
+                        _ => unreachable!(),
+                    })
+                    .collect(),
+            ))
+        } else {
+            // Dhall record fields can only be labels, so we use the same representation as
+            // `toMap`.
+            Ok(List(
+                self.entries
+                    .into_iter()
+                    .map(|(k, v)| {
+                        let mut entry = RecordMap::new();
+                        entry.insert("mapKey".to_owned(), k);
+                        entry.insert("mapValue".to_owned(), v);
+                        Record(entry)
+                    })
+                    .collect(),
+            ))
+        }
     }
 }
 
-#[derive(Default)]
-struct StructSerializer(BTreeMap<String, SimpleValue>);
+struct StructSerializer<'o>(&'o SerializeOptions, RecordMap);
 
-impl ser::SerializeStruct for StructSerializer {
+impl ser::SerializeStruct for StructSerializer<'_> {
     type Ok = SimpleValue;
     type Error = Error;
 
@@ -329,13 +527,13 @@ impl ser::SerializeStruct for StructSerializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        let val: SimpleValue = val.serialize(Serializer)?;
-        self.0.insert(key.into(), val);
+        let val: SimpleValue = val.serialize(Serializer(self.0))?;
+        self.1.insert(key.into(), val);
         Ok(())
     }
 
     fn end(self) -> Result<Self::Ok> {
-        Ok(Record(self.0))
+        Ok(Record(self.1))
     }
 }
 
@@ -353,8 +551,22 @@ impl serde::ser::Serialize for SimpleValue {
 
         match self {
             Num(Bool(x)) => serializer.serialize_bool(*x),
-            Num(Natural(x)) => serializer.serialize_u64(*x),
-            Num(Integer(x)) => serializer.serialize_i64(*x),
+            Num(Natural(x)) => match (x.to_u64(), x.to_u128()) {
+                (Some(x), _) => serializer.serialize_u64(x),
+                (None, Some(x)) => serializer.serialize_u128(x),
+                (None, None) => Err(<S::Error as ser::Error>::custom(format!(
+                    "natural number too large: {}",
+                    x
+                ))),
+            },
+            Num(Integer(x)) => match (x.to_i64(), x.to_i128()) {
+                (Some(x), _) => serializer.serialize_i64(x),
+                (None, Some(x)) => serializer.serialize_i128(x),
+                (None, None) => Err(<S::Error as ser::Error>::custom(format!(
+                    "integer too large: {}",
+                    x
+                ))),
+            },
             Num(Double(x)) => serializer.serialize_f64((*x).into()),
             Text(x) => serializer.serialize_str(x),
             List(xs) => {
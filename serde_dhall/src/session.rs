@@ -0,0 +1,42 @@
+use dhall::Ctxt;
+
+/// A context in which many Dhall expressions can be loaded, e.g. by a server that evaluates lots
+/// of small configurations. Each import is then only fetched and typechecked once per session,
+/// instead of once per expression.
+///
+/// A session only exists inside the closure given to [`Session::with()`]; everything it stores is
+/// freed when that returns. Imports are not fetched again during a session, so changes to
+/// imported files are only seen by later sessions.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde_dhall::Session;
+///
+/// let inputs = ["1 + 1", "2 * 3", "List/length Natural [1, 2, 3]"];
+/// let results = Session::with(|session| {
+///     inputs
+///         .iter()
+///         .map(|input| serde_dhall::from_str(input).parse_in::<u64>(session))
+///         .collect::<serde_dhall::Result<Vec<u64>>>()
+/// })?;
+/// assert_eq!(results, vec![2, 6, 3]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy)]
+pub struct Session<'cx>(pub(crate) Ctxt<'cx>);
+
+impl Session<'_> {
+    /// Runs `f` in a new session.
+    pub fn with<T>(f: impl for<'cx> FnOnce(Session<'cx>) -> T) -> T {
+        Ctxt::with_new(|cx| f(Session(cx)))
+    }
+}
+
+impl std::fmt::Debug for Session<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Session")
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use dhall::builtins::Builtin;
+use dhall::operations::OpKind;
+use dhall::semantics::ImportLocation;
+use dhall::syntax::{binary, Expr, ExprKind, Label, NumKind, Span};
+use dhall::utils::{sha256_hash, write_file_atomically};
+use dhall::Ctxt;
+use num_traits::ToPrimitive;
+
+use crate::value::ValueKind;
+use crate::{Error, ErrorKind, Provenance, Result, SimpleType, SimpleValue};
+use crate::{RecordMap, Value};
+
+/// Starts every snapshot, with the version of the format.
+const MAGIC: &[u8] = b"DHSNAP1\n";
+
+impl Value {
+    /// Encodes this value to a compact binary snapshot, that [`Value::from_snapshot()`] loads
+    /// without parsing, typechecking or normalizing anything. This makes it possible to load a
+    /// large configuration instantly at startup, as long as it hasn't changed; see
+    /// [`Deserializer::snapshot()`] to check that automatically.
+    ///
+    /// The snapshot contains the value, its type and the provenance of its fields if it was
+    /// tracked. It is a Dhall expression in the standard binary encoding, preceded by a header.
+    ///
+    /// [`Deserializer::snapshot()`]: crate::Deserializer::snapshot()
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let value: Value = serde_dhall::from_str("{ port = 8000 + 80, hosts = [] : List Text }")
+    ///     .parse()?;
+    /// let snapshot = value.to_snapshot()?;
+    /// assert_eq!(Value::from_snapshot(&snapshot)?, value);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_snapshot(&self) -> Result<Vec<u8>> {
+        encode(self, None)
+    }
+
+    /// Loads a snapshot made by [`Value::to_snapshot()`]. The snapshot is trusted to contain a
+    /// well-typed value: it is only checked to have the expected shape.
+    pub fn from_snapshot(data: &[u8]) -> Result<Value> {
+        decode(data).map(|(value, _)| value)
+    }
+}
+
+/// What a snapshot written by `Deserializer::snapshot` was computed from.
+struct Inputs {
+    /// The hash of the options, the source and the current contents of the dependencies.
+    hash: String,
+    /// The imports that were loaded, see `dependency`.
+    dependencies: Vec<String>,
+}
+
+fn invalid_snapshot(reason: &str) -> Error {
+    Error(ErrorKind::Deserialize(format!(
+        "invalid snapshot: {}",
+        reason
+    )))
+}
+
+fn mkexpr(kind: ExprKind<Expr>) -> Expr {
+    Expr::new(kind, Span::Artificial)
+}
+
+fn text(s: impl Into<String>) -> Expr {
+    mkexpr(ExprKind::TextLit(s.into().into()))
+}
+
+// The conversion is only needed with the `bigint` feature.
+#[allow(clippy::useless_conversion)]
+fn natural(n: usize) -> Expr {
+    mkexpr(ExprKind::Num(NumKind::Natural((n as u64).into())))
+}
+
+fn encode(value: &Value, inputs: Option<&Inputs>) -> Result<Vec<u8>> {
+    let mut fields = BTreeMap::new();
+    match &value.kind {
+        ValueKind::Val(_, ty) => {
+            fields.insert("value".into(), value.to_expr());
+            if let Some(ty) = ty {
+                fields.insert("type".into(), ty.to_expr());
+            }
+        }
+        ValueKind::Ty(ty) => {
+            fields.insert("type_value".into(), ty.to_expr());
+        }
+    }
+    if !value.provenance.is_empty() {
+        let origins = value
+            .provenance
+            .iter()
+            .map(|(path, provenance)| {
+                let mut origin = BTreeMap::new();
+                if let Some(source) = &provenance.source {
+                    origin.insert("source".into(), text(source.as_str()));
+                }
+                if let Some((line, column)) = provenance.line_col {
+                    origin.insert("line".into(), natural(line));
+                    origin.insert("column".into(), natural(column));
+                }
+                (path.as_str().into(), mkexpr(ExprKind::RecordLit(origin)))
+            })
+            .collect();
+        fields
+            .insert("provenance".into(), mkexpr(ExprKind::RecordLit(origins)));
+    }
+    if let Some(inputs) = inputs {
+        let dependencies = if inputs.dependencies.is_empty() {
+            ExprKind::EmptyListLit(mkexpr(ExprKind::Op(OpKind::App(
+                mkexpr(ExprKind::Builtin(Builtin::List)),
+                mkexpr(ExprKind::Builtin(Builtin::Text)),
+            ))))
+        } else {
+            ExprKind::NEListLit(
+                inputs
+                    .dependencies
+                    .iter()
+                    .map(|d| text(d.as_str()))
+                    .collect(),
+            )
+        };
+        let mut kvs = BTreeMap::new();
+        kvs.insert("hash".into(), text(inputs.hash.as_str()));
+        kvs.insert("dependencies".into(), mkexpr(dependencies));
+        fields.insert("inputs".into(), mkexpr(ExprKind::RecordLit(kvs)));
+    }
+
+    let expr = mkexpr(ExprKind::RecordLit(fields));
+    let encoded =
+        binary::encode(&expr).map_err(|e| Error(ErrorKind::Dhall(e.into())))?;
+    let mut data = MAGIC.to_vec();
+    data.extend(encoded);
+    Ok(data)
+}
+
+fn decode(data: &[u8]) -> Result<(Value, Option<Inputs>)> {
+    if !data.starts_with(MAGIC) {
+        return Err(invalid_snapshot("wrong header"));
+    }
+    let expr = binary::decode(&data[MAGIC.len()..])
+        .map_err(|e| Error(ErrorKind::Dhall(e.into())))?;
+    let mut fields = match expr.kind() {
+        ExprKind::RecordLit(fields) => fields.clone(),
+        _ => return Err(invalid_snapshot("not a record")),
+    };
+    let mut take = |name: &str| fields.remove(&Label::from(name));
+
+    let ty = match take("type") {
+        Some(ty) => Some(
+            type_from_expr(&ty).ok_or_else(|| invalid_snapshot("bad type"))?,
+        ),
+        None => None,
+    };
+    let kind = match (take("value"), take("type_value")) {
+        (Some(val), None) => ValueKind::Val(
+            value_from_expr(&val, ty.as_ref())
+                .ok_or_else(|| invalid_snapshot("bad value"))?,
+            ty,
+        ),
+        (None, Some(ty)) => ValueKind::Ty(
+            type_from_expr(&ty).ok_or_else(|| invalid_snapshot("bad type"))?,
+        ),
+        _ => return Err(invalid_snapshot("no value")),
+    };
+    let provenance = match take("provenance") {
+        Some(origins) => provenance_from_expr(&origins)
+            .ok_or_else(|| invalid_snapshot("bad provenance"))?,
+        None => BTreeMap::new(),
+    };
+    let inputs = match take("inputs") {
+        Some(inputs) => Some(
+            inputs_from_expr(&inputs)
+                .ok_or_else(|| invalid_snapshot("bad inputs"))?,
+        ),
+        None => None,
+    };
+    Ok((Value { kind, provenance }, inputs))
+}
+
+fn as_text(expr: &Expr) -> Option<String> {
+    match expr.kind() {
+        ExprKind::TextLit(t) if t.tail().is_empty() => {
+            Some(t.head().to_owned())
+        }
+        _ => None,
+    }
+}
+
+fn as_usize(expr: &Expr) -> Option<usize> {
+    match expr.kind() {
+        ExprKind::Num(NumKind::Natural(n)) => n.to_usize(),
+        _ => None,
+    }
+}
+
+fn provenance_from_expr(expr: &Expr) -> Option<BTreeMap<String, Provenance>> {
+    match expr.kind() {
+        ExprKind::RecordLit(origins) => origins
+            .iter()
+            .map(|(path, origin)| {
+                let origin = match origin.kind() {
+                    ExprKind::RecordLit(origin) => origin,
+                    _ => return None,
+                };
+                let get = |name: &str| origin.get(&Label::from(name));
+                let source = match get("source") {
+                    Some(source) => Some(as_text(source)?),
+                    None => None,
+                };
+                let line_col = match (get("line"), get("column")) {
+                    (Some(line), Some(column)) => {
+                        Some((as_usize(line)?, as_usize(column)?))
+                    }
+                    _ => None,
+                };
+                Some((path.into(), Provenance { source, line_col }))
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+fn inputs_from_expr(expr: &Expr) -> Option<Inputs> {
+    let kvs = match expr.kind() {
+        ExprKind::RecordLit(kvs) => kvs,
+        _ => return None,
+    };
+    let hash = as_text(kvs.get(&Label::from("hash"))?)?;
+    let dependencies = match kvs.get(&Label::from("dependencies"))?.kind() {
+        ExprKind::EmptyListLit(_) => Vec::new(),
+        ExprKind::NEListLit(deps) => {
+            deps.iter().map(as_text).collect::<Option<_>>()?
+        }
+        _ => return None,
+    };
+    Some(Inputs { hash, dependencies })
+}
+
+/// The inverse of `SimpleType::to_expr`.
+fn type_from_expr(expr: &Expr) -> Option<SimpleType> {
+    use SimpleType as T;
+    Some(match expr.kind() {
+        ExprKind::Builtin(Builtin::Bool) => T::Bool,
+        ExprKind::Builtin(Builtin::Natural) => T::Natural,
+        ExprKind::Builtin(Builtin::Integer) => T::Integer,
+        ExprKind::Builtin(Builtin::Double) => T::Double,
+        ExprKind::Builtin(Builtin::Text) => T::Text,
+        ExprKind::Op(OpKind::App(f, t)) => match f.kind() {
+            ExprKind::Builtin(Builtin::Optional) => {
+                T::Optional(Box::new(type_from_expr(t)?))
+            }
+            ExprKind::Builtin(Builtin::List) => {
+                T::List(Box::new(type_from_expr(t)?))
+            }
+            _ => return None,
+        },
+        ExprKind::RecordType(kts) => T::Record(
+            kts.iter()
+                .map(|(k, t)| Some((k.into(), type_from_expr(t)?)))
+                .collect::<Option<HashMap<_, _>>>()?,
+        ),
+        ExprKind::UnionType(kts) => T::Union(
+            kts.iter()
+                .map(|(k, t)| {
+                    let t = match t {
+                        Some(t) => Some(type_from_expr(t)?),
+                        None => None,
+                    };
+                    Some((k.into(), t))
+                })
+                .collect::<Option<HashMap<_, _>>>()?,
+        ),
+        _ => return None,
+    })
+}
+
+/// The inverse of `SimpleValue::to_expr`.
+fn value_from_expr(
+    expr: &Expr,
+    ty: Option<&SimpleType>,
+) -> Option<SimpleValue> {
+    use SimpleType as T;
+    use SimpleValue as V;
+    Some(match (expr.kind(), ty) {
+        (ExprKind::Num(num), _) => {
+            match (num, ty) {
+                (NumKind::Bool(_), Some(T::Bool))
+                | (NumKind::Natural(_), Some(T::Natural))
+                | (NumKind::Integer(_), Some(T::Integer))
+                | (NumKind::Double(_), Some(T::Double))
+                | (_, None) => {}
+                _ => return None,
+            }
+            V::Num(num.clone())
+        }
+        (ExprKind::TextLit(_), Some(T::Text))
+        | (ExprKind::TextLit(_), None) => V::Text(as_text(expr)?),
+        (ExprKind::SomeLit(v), Some(T::Optional(t))) => {
+            V::Optional(Some(Box::new(value_from_expr(v, Some(t))?)))
+        }
+        (ExprKind::SomeLit(v), None) => {
+            V::Optional(Some(Box::new(value_from_expr(v, None)?)))
+        }
+        (ExprKind::EmptyListLit(_), Some(T::List(_))) => V::List(Vec::new()),
+        (ExprKind::NEListLit(xs), Some(T::List(t))) => V::List(
+            xs.iter()
+                .map(|x| value_from_expr(x, Some(t)))
+                .collect::<Option<_>>()?,
+        ),
+        (ExprKind::NEListLit(xs), None) => V::List(
+            xs.iter()
+                .map(|x| value_from_expr(x, None))
+                .collect::<Option<_>>()?,
+        ),
+        (ExprKind::RecordLit(kvs), Some(T::Record(kts))) => V::Record(
+            kvs.iter()
+                .map(|(k, v)| {
+                    let t = kts.get(&String::from(k))?;
+                    Some((k.into(), value_from_expr(v, Some(t))?))
+                })
+                .collect::<Option<RecordMap>>()?,
+        ),
+        (ExprKind::RecordLit(kvs), None) => V::Record(
+            kvs.iter()
+                .map(|(k, v)| Some((k.into(), value_from_expr(v, None)?)))
+                .collect::<Option<RecordMap>>()?,
+        ),
+        (ExprKind::Op(OpKind::Field(_, variant)), Some(T::Union(kts))) => {
+            match kts.get(&String::from(variant))? {
+                None => V::Union(variant.into(), None),
+                Some(_) => return None,
+            }
+        }
+        (ExprKind::Op(OpKind::App(f, v)), Some(T::Union(kts))) => {
+            match f.kind() {
+                ExprKind::Op(OpKind::Field(_, variant)) => {
+                    let t = kts.get(&String::from(variant))?.as_ref()?;
+                    V::Union(
+                        variant.into(),
+                        Some(Box::new(value_from_expr(v, Some(t))?)),
+                    )
+                }
+                _ => return None,
+            }
+        }
+        (ExprKind::Op(OpKind::App(f, _)), Some(T::Optional(_))) => {
+            match f.kind() {
+                ExprKind::Builtin(Builtin::OptionalNone) => V::Optional(None),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    })
+}
+
+/// How a loaded import is recorded in a snapshot, so that its contents can be checked later.
+/// `None` for imports whose contents can't change.
+pub(crate) fn dependency(
+    cx: Ctxt<'_>,
+    location: &ImportLocation,
+) -> Option<String> {
+    if let Some(path) = location.as_local_path() {
+        let path = match (path.strip_prefix("~"), cx.home_dir()) {
+            (Ok(rest), Some(home)) => home.join(rest),
+            _ => path.to_owned(),
+        };
+        Some(format!("local:{}", path.display()))
+    } else if let Some(url) = location.as_remote_url() {
+        Some(format!("remote:{}", url))
+    } else {
+        location.as_env_var().map(|name| format!("env:{}", name))
+    }
+}
+
+/// The hash of `fingerprint`, which describes the options and the source, and of the current
+/// contents of the dependencies. Remote imports are not fetched again.
+fn inputs_hash(fingerprint: &[u8], dependencies: &[String]) -> String {
+    let mut data = Vec::new();
+    let mut add = |bytes: Option<&[u8]>| match bytes {
+        Some(bytes) => {
+            data.extend((bytes.len() as u64).to_le_bytes().iter());
+            data.extend(bytes);
+        }
+        None => data.extend(u64::MAX.to_le_bytes().iter()),
+    };
+    add(Some(fingerprint));
+    for dep in dependencies {
+        add(Some(dep.as_bytes()));
+        if let Some(path) = dep.strip_prefix("local:") {
+            add(std::fs::read(path).ok().as_deref());
+        } else if let Some(name) = dep.strip_prefix("env:") {
+            let value = std::env::var_os(name);
+            let value = value.as_ref().map(|v| v.to_string_lossy());
+            add(value.as_ref().map(|v| v.as_bytes()));
+        }
+    }
+    sha256_hash(&data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Loads the snapshot at `path` if it was written with the same `fingerprint` and its
+/// dependencies didn't change since.
+pub(crate) fn load(path: &Path, fingerprint: &[u8]) -> Option<Value> {
+    let data = std::fs::read(path).ok()?;
+    let (value, inputs) = decode(&data).ok()?;
+    let inputs = inputs?;
+    if inputs_hash(fingerprint, &inputs.dependencies) == inputs.hash {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Writes a snapshot of `value` to `path`, atomically so that concurrent loads never see a
+/// truncated snapshot. Failing to write it is not an error: the value will just be evaluated
+/// again next time.
+pub(crate) fn save(
+    path: &Path,
+    fingerprint: &[u8],
+    dependencies: Vec<String>,
+    value: &Value,
+) {
+    let inputs = Inputs {
+        hash: inputs_hash(fingerprint, &dependencies),
+        dependencies,
+    };
+    if let Ok(data) = encode(value, Some(&inputs)) {
+        let _ = write_file_atomically(path, &data);
+    }
+}
@@ -6,9 +6,10 @@ use crate::SimpleType;
 ///
 /// This trait can be automatically derived, and this is the recommended way of implementing it.
 ///
-/// Some Rust types cannot implement this trait, because there isn't a single Dhall type that
-/// corresponds to them. For example, `HashMap<String, u64>` could correspond to multiple different
-/// Dhall types, e.g. `{ foo: Natural, bar: Natural }` and `{ baz: Natural }`.
+/// Maps like `HashMap<String, u64>` are represented by the type of the lists built by `toMap`,
+/// here `List { mapKey : Text, mapValue : Natural }`, since a record type would fix the keys.
+/// Records are still accepted where such a map is expected, see
+/// [`Deserializer::static_type_annotation`](crate::Deserializer::static_type_annotation).
 ///
 /// See also [the table of type correspondances].
 ///
@@ -76,9 +77,11 @@ macro_rules! derive_builtin {
 }
 
 derive_builtin!(bool, Bool);
+derive_builtin!(u128, Natural);
 derive_builtin!(usize, Natural);
 derive_builtin!(u64, Natural);
 derive_builtin!(u32, Natural);
+derive_builtin!(i128, Integer);
 derive_builtin!(isize, Integer);
 derive_builtin!(i64, Integer);
 derive_builtin!(i32, Integer);
@@ -206,3 +209,50 @@ where
         T::static_type()
     }
 }
+
+/// The type of the lists built by `toMap`, i.e. `Prelude.Map.Type K V`.
+fn map_type<K, V>() -> SimpleType
+where
+    K: StaticType,
+    V: StaticType,
+{
+    SimpleType::List(Box::new(SimpleType::Record(
+        vec![
+            ("mapKey".to_owned(), K::static_type()),
+            ("mapValue".to_owned(), V::static_type()),
+        ]
+        .into_iter()
+        .collect(),
+    )))
+}
+
+impl<K, V, S> StaticType for std::collections::HashMap<K, V, S>
+where
+    K: StaticType,
+    V: StaticType,
+{
+    fn static_type() -> SimpleType {
+        map_type::<K, V>()
+    }
+}
+
+impl<K, V> StaticType for std::collections::BTreeMap<K, V>
+where
+    K: StaticType,
+    V: StaticType,
+{
+    fn static_type() -> SimpleType {
+        map_type::<K, V>()
+    }
+}
+
+#[cfg(feature = "preserve_order")]
+impl<K, V, S> StaticType for indexmap::IndexMap<K, V, S>
+where
+    K: StaticType,
+    V: StaticType,
+{
+    fn static_type() -> SimpleType {
+        map_type::<K, V>()
+    }
+}
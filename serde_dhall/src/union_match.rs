@@ -0,0 +1,163 @@
+use std::collections::HashSet;
+
+use crate::{Error, ErrorKind, Result, SimpleType, Value};
+
+/// Handles the alternatives of a union [`Value`] with Rust closures, like a Dhall `merge`
+/// expression. This avoids deserializing the union into a mirrored Rust enum.
+///
+/// This is obtained with [`Value::match_union()`]. Only the handler for the alternative of the
+/// value is called. Like `merge`, [`finish()`](UnionMatch::finish()) fails unless there is
+/// exactly one handler for each alternative of the union type.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde_dhall::Value;
+///
+/// let value: Value =
+///     serde_dhall::from_str("< Tcp : Natural | Unix : Text | Stdio >.Tcp 8080")
+///         .parse()?;
+///
+/// let description = value
+///     .match_union::<serde_dhall::Result<String>>()
+///     .case("Tcp", |port| Ok(format!("port {}", port.as_natural()?)))
+///     .case("Unix", |path| Ok(format!("socket at {}", path.as_text()?)))
+///     .case_without_payload("Stdio", || Ok("stdio".to_string()))
+///     .finish()??;
+/// assert_eq!(description, "port 8080");
+///
+/// let missing_handler = value
+///     .match_union()
+///     .case("Tcp", |_| ())
+///     .case_without_payload("Stdio", || ())
+///     .finish();
+/// assert_eq!(
+///     missing_handler.unwrap_err().to_string(),
+///     "missing handler for alternative `Unix`"
+/// );
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct UnionMatch<'a, R> {
+    value: &'a Value,
+    result: Option<R>,
+    handled: HashSet<String>,
+    /// The first problem found, reported by `finish`.
+    error: Option<String>,
+}
+
+impl<'a, R> UnionMatch<'a, R> {
+    pub(crate) fn new(value: &'a Value) -> Self {
+        let error = match value.as_union() {
+            Some(_) => None,
+            None => Some(format!("expected a union value, found `{}`", value)),
+        };
+        UnionMatch {
+            value,
+            result: None,
+            handled: HashSet::new(),
+            error,
+        }
+    }
+
+    /// Handles the alternative `name`, which must have a payload. `f` is called with the payload
+    /// if the value is this alternative.
+    pub fn case(mut self, name: &str, f: impl FnOnce(Value) -> R) -> Self {
+        if let Some(payload) = self.check(name, true) {
+            self.result = Some(f(payload.unwrap()));
+        }
+        self
+    }
+
+    /// Handles the alternative `name`, which must not have a payload. `f` is called if the value
+    /// is this alternative.
+    pub fn case_without_payload(
+        mut self,
+        name: &str,
+        f: impl FnOnce() -> R,
+    ) -> Self {
+        if self.check(name, false).is_some() {
+            self.result = Some(f());
+        }
+        self
+    }
+
+    /// Returns the result of the handler that was called. Fails if the value is not a union, or
+    /// if the handlers don't match the alternatives of its type.
+    pub fn finish(self) -> Result<R> {
+        let err = |msg| Err(Error(ErrorKind::Deserialize(msg)));
+        if let Some(msg) = self.error {
+            return err(msg);
+        }
+        let (name, _, ty) = self.value.as_union().unwrap();
+        if let Some(SimpleType::Union(kts)) = ty {
+            let mut missing: Vec<&String> =
+                kts.keys().filter(|k| !self.handled.contains(*k)).collect();
+            missing.sort();
+            if let Some(k) = missing.first() {
+                return err(format!("missing handler for alternative `{}`", k));
+            }
+        }
+        match self.result {
+            Some(result) => Ok(result),
+            None => err(format!("missing handler for alternative `{}`", name)),
+        }
+    }
+
+    /// Records a handler for `name`. Returns the payload if the handler must be called.
+    fn check(
+        &mut self,
+        name: &str,
+        with_payload: bool,
+    ) -> Option<Option<Value>> {
+        if self.error.is_some() {
+            return None;
+        }
+        let (alternative, payload, ty) = self.value.as_union().unwrap();
+        let has_payload = match ty {
+            Some(SimpleType::Union(kts)) => match kts.get(name) {
+                Some(payload_ty) => Some(payload_ty.is_some()),
+                None => {
+                    self.error = Some(format!(
+                        "no alternative `{}` in the union type `{}`",
+                        name,
+                        ty.unwrap()
+                    ));
+                    return None;
+                }
+            },
+            _ if alternative == name => Some(payload.is_some()),
+            _ => None,
+        };
+        if !self.handled.insert(name.to_owned()) {
+            self.error =
+                Some(format!("duplicate handler for alternative `{}`", name));
+            return None;
+        }
+        match has_payload {
+            Some(true) if !with_payload => {
+                self.error = Some(format!(
+                    "alternative `{}` has a payload, handle it with `case`",
+                    name
+                ));
+                return None;
+            }
+            Some(false) if with_payload => {
+                self.error = Some(format!(
+                    "alternative `{}` has no payload, handle it with \
+                     `case_without_payload`",
+                    name
+                ));
+                return None;
+            }
+            _ => {}
+        }
+        if alternative == name {
+            Some(payload)
+        } else {
+            None
+        }
+    }
+}
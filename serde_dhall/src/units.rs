@@ -0,0 +1,194 @@
+//! Parsing and printing of the text forms of durations and byte sizes, for the [`duration`] and
+//! [`byte_size`] adapters.
+//!
+//! [`duration`]: crate::duration
+//! [`byte_size`]: crate::byte_size
+
+use std::convert::TryFrom;
+use std::time::Duration;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+const DURATION_UNITS: &[(&str, u128)] = &[
+    ("d", 86_400 * NANOS_PER_SEC),
+    ("h", 3_600 * NANOS_PER_SEC),
+    ("m", 60 * NANOS_PER_SEC),
+    ("s", NANOS_PER_SEC),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("ns", 1),
+];
+
+const BYTE_UNITS: &[(&str, u128)] = &[
+    ("B", 1),
+    ("kB", 1_000),
+    ("KiB", 1 << 10),
+    ("MB", 1_000_000),
+    ("MiB", 1 << 20),
+    ("GB", 1_000_000_000),
+    ("GiB", 1 << 30),
+    ("TB", 1_000_000_000_000),
+    ("TiB", 1 << 40),
+    ("PB", 1_000_000_000_000_000),
+    ("PiB", 1 << 50),
+];
+
+/// Parses a sequence of numbers with units, like `1h30m` or `1.5s`.
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, String> {
+    let err = |msg: String| Err(format!("invalid duration `{}`: {}", s, msg));
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return err("expected a number followed by a unit, e.g. `30s`".into());
+    }
+    let mut nanos: u128 = 0;
+    while !rest.is_empty() {
+        let (num, unit, after) = split_number(rest);
+        if num.is_empty() {
+            return err(format!("expected a number, found `{}`", rest));
+        }
+        rest = after.trim_start();
+        let unit_nanos = match unit {
+            "min" => 60 * NANOS_PER_SEC,
+            "µs" => 1_000,
+            _ => match DURATION_UNITS.iter().find(|(u, _)| *u == unit) {
+                Some((_, n)) => *n,
+                None if unit.is_empty() => {
+                    return err(format!("missing unit after `{}`", num))
+                }
+                None => {
+                    return err(format!(
+                        "unknown unit `{}`, expected one of {}",
+                        unit,
+                        unit_list(DURATION_UNITS)
+                    ))
+                }
+            },
+        };
+        // Fractions of nanoseconds are dropped.
+        let (n, _) = match scale(num, unit_nanos) {
+            Some(n) => n,
+            None => return err(format!("invalid number `{}`", num)),
+        };
+        nanos = match nanos.checked_add(n) {
+            Some(nanos) => nanos,
+            None => return err("too large".into()),
+        };
+    }
+    match u64::try_from(nanos / NANOS_PER_SEC) {
+        Ok(secs) => Ok(Duration::new(secs, (nanos % NANOS_PER_SEC) as u32)),
+        Err(_) => err("too large".into()),
+    }
+}
+
+/// Prints a duration in the form read by `parse_duration`, like `1h30m`.
+pub(crate) fn format_duration(d: Duration) -> String {
+    let mut nanos = d.as_nanos();
+    if nanos == 0 {
+        return "0s".to_owned();
+    }
+    let mut out = String::new();
+    for (unit, unit_nanos) in DURATION_UNITS {
+        if nanos >= *unit_nanos {
+            out.push_str(&format!("{}{}", nanos / unit_nanos, unit));
+            nanos %= unit_nanos;
+        }
+    }
+    out
+}
+
+/// Parses a number of bytes with an optional unit, like `512MiB` or `1.5 GB`.
+pub(crate) fn parse_byte_size(s: &str) -> Result<u64, String> {
+    let err = |msg: String| Err(format!("invalid byte size `{}`: {}", s, msg));
+    let (num, unit, rest) = split_number(s.trim());
+    if num.is_empty() {
+        return err("expected a number, e.g. `512MiB`".into());
+    }
+    let unit = unit.to_owned() + rest.trim_start();
+    let unit_bytes = if unit.is_empty() {
+        1
+    } else {
+        match BYTE_UNITS
+            .iter()
+            .find(|(u, _)| u.eq_ignore_ascii_case(&unit))
+        {
+            Some((_, n)) => *n,
+            None => {
+                return err(format!(
+                    "unknown unit `{}`, expected one of {}",
+                    unit,
+                    unit_list(BYTE_UNITS)
+                ))
+            }
+        }
+    };
+    match scale(num, unit_bytes) {
+        None => err(format!("invalid number `{}`", num)),
+        Some((_, false)) => err("not a whole number of bytes".into()),
+        Some((n, true)) => {
+            u64::try_from(n).or_else(|_| err("too large".into()))
+        }
+    }
+}
+
+/// Prints a number of bytes with the unit that gives the smallest whole number, like `512MiB`.
+pub(crate) fn format_byte_size(bytes: u64) -> String {
+    let bytes = u128::from(bytes);
+    let (unit, unit_bytes) = BYTE_UNITS
+        .iter()
+        .filter(|(_, n)| bytes % n == 0)
+        .max_by_key(|(_, n)| *n)
+        .filter(|_| bytes != 0)
+        .unwrap_or(&BYTE_UNITS[0]);
+    format!("{}{}", bytes / unit_bytes, unit)
+}
+
+/// Splits the leading number, the unit after it, and the rest.
+fn split_number(s: &str) -> (&str, &str, &str) {
+    let num_end = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (num, rest) = s.split_at(num_end);
+    let rest = rest.trim_start();
+    let unit_end = rest
+        .find(|c: char| c.is_ascii_digit() || c == '.' || c.is_whitespace())
+        .unwrap_or(rest.len());
+    let (unit, rest) = rest.split_at(unit_end);
+    (num, unit, rest)
+}
+
+/// Multiplies a decimal number like `1.5` by `unit`. Returns the integral part of the result, and
+/// whether it is exact.
+fn scale(num: &str, unit: u128) -> Option<(u128, bool)> {
+    let (int, frac) = match num.find('.') {
+        Some(i) => (&num[..i], &num[i + 1..]),
+        None => (num, ""),
+    };
+    if int.is_empty() && frac.is_empty() {
+        return None;
+    }
+    let parse = |digits: &str| -> Option<u128> {
+        if digits.is_empty() {
+            Some(0)
+        } else if digits.bytes().all(|b| b.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    };
+    let int = parse(int)?;
+    // Digits beyond these don't change the result, unless they make it inexact.
+    let (frac, dropped) = frac.split_at(frac.len().min(18));
+    let dropped_zero = dropped.bytes().all(|b| b == b'0');
+    if !dropped.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let denom = 10u128.pow(frac.len() as u32);
+    let frac = parse(frac)?.checked_mul(unit)?;
+    let n = int.checked_mul(unit)?.checked_add(frac / denom)?;
+    Some((n, frac % denom == 0 && dropped_zero))
+}
+
+fn unit_list(units: &[(&str, u128)]) -> String {
+    let units: Vec<_> = units.iter().map(|(u, _)| format!("`{}`", u)).collect();
+    units.join(", ")
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::{Error, ErrorKind, NumKind, SimpleType, SimpleValue};
+
+/// The ways a [`SimpleValue`] doesn't match a [`SimpleType`], as returned by
+/// [`SimpleType::validate()`].
+///
+/// All the violations are reported, not just the first one. This prints one violation per line.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde_dhall::{SimpleType, SimpleValue};
+///
+/// let schema: SimpleType =
+///     serde_dhall::from_str("{ name : Text, ports : List Natural }").parse()?;
+/// let data: SimpleValue =
+///     serde_dhall::from_str("{ ports = [+80, -1], user = \"root\" }").parse()?;
+///
+/// let errors = schema.validate(&data).unwrap_err();
+/// assert_eq!(
+///     errors.to_string(),
+///     "name: missing field of type `Text`\n\
+///      ports[1]: expected `Natural`, found the negative Integer -1\n\
+///      user: unexpected field"
+/// );
+/// assert_eq!(errors.violations()[1].path, "ports[1]");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationErrors(Vec<Violation>);
+
+/// A part of a value that doesn't match its type. See [`ValidationErrors`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// Where the violation is, e.g. `servers[0].port`. Empty for the whole value.
+    pub path: String,
+    /// What is wrong.
+    pub message: String,
+}
+
+impl ValidationErrors {
+    /// The violations, in the order in which they appear in the value.
+    pub fn violations(&self) -> &[Violation] {
+        &self.0
+    }
+}
+
+impl SimpleType {
+    /// Checks that `value` has this type, without going through Dhall typechecking. This is meant
+    /// for data obtained from other formats, e.g. a `SimpleValue` read from JSON, to be checked
+    /// against a schema written in Dhall.
+    ///
+    /// Since other formats make fewer distinctions than Dhall, the check is more lenient than
+    /// Dhall's:
+    /// - a `Natural` is accepted where an `Integer` is expected, and so is a non-negative
+    ///   `Integer` where a `Natural` is expected; both are accepted where a `Double` is expected;
+    /// - where an `Optional T` is expected, a value of type `T` is accepted as is, and a record
+    ///   field can be missing;
+    /// - a record is accepted where a map with text keys (`List { mapKey : Text, mapValue : T }`)
+    ///   is expected.
+    ///
+    /// See [`ValidationErrors`] for an example.
+    pub fn validate(
+        &self,
+        value: &SimpleValue,
+    ) -> Result<(), ValidationErrors> {
+        let mut violations = Vec::new();
+        check(self, value, "", &mut violations);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(violations))
+        }
+    }
+}
+
+fn check(
+    ty: &SimpleType,
+    val: &SimpleValue,
+    path: &str,
+    out: &mut Vec<Violation>,
+) {
+    use SimpleType as T;
+    use SimpleValue as V;
+    let field_path = |k: &str| {
+        if path.is_empty() {
+            k.to_owned()
+        } else {
+            format!("{}.{}", path, k)
+        }
+    };
+    let message = match (ty, val) {
+        (T::Bool, V::Num(NumKind::Bool(_)))
+        | (T::Natural, V::Num(NumKind::Natural(_)))
+        | (T::Integer, V::Num(NumKind::Natural(_)))
+        | (T::Integer, V::Num(NumKind::Integer(_)))
+        | (T::Double, V::Num(NumKind::Natural(_)))
+        | (T::Double, V::Num(NumKind::Integer(_)))
+        | (T::Double, V::Num(NumKind::Double(_)))
+        | (T::Text, V::Text(_))
+        | (T::Optional(_), V::Optional(None)) => return,
+        (T::Natural, V::Num(NumKind::Integer(n))) => {
+            if *n >= num_traits::Zero::zero() {
+                return;
+            }
+            format!("expected `Natural`, found the negative Integer {}", n)
+        }
+        (T::Optional(t), V::Optional(Some(v))) => {
+            return check(t, v, path, out)
+        }
+        (T::Optional(t), v) => return check(t, v, path, out),
+        (T::List(t), V::List(xs)) => {
+            for (i, x) in xs.iter().enumerate() {
+                check(t, x, &format!("{}[{}]", path, i), out);
+            }
+            return;
+        }
+        (T::Record(kts), V::Record(kvs)) => {
+            let names: BTreeSet<&String> =
+                kts.keys().chain(kvs.keys()).collect();
+            for k in names {
+                match (kts.get(k), kvs.get(k)) {
+                    (Some(t), Some(v)) => check(t, v, &field_path(k), out),
+                    (Some(T::Optional(_)), None) => {}
+                    (Some(t), None) => out.push(Violation {
+                        path: field_path(k),
+                        message: format!(
+                            "missing field of type `{}`",
+                            t.to_expr()
+                        ),
+                    }),
+                    (None, _) => out.push(Violation {
+                        path: field_path(k),
+                        message: "unexpected field".to_owned(),
+                    }),
+                }
+            }
+            return;
+        }
+        (T::List(_), V::Record(kvs)) if ty.text_map_value().is_some() => {
+            let t = ty.text_map_value().unwrap();
+            for (k, v) in kvs {
+                check(t, v, &field_path(k), out);
+            }
+            return;
+        }
+        (T::Union(alts), V::Union(name, payload)) => {
+            match (alts.get(name), payload) {
+                (Some(None), None) => return,
+                (Some(Some(t)), Some(v)) => return check(t, v, path, out),
+                (None, _) => format!(
+                    "no alternative `{}` in the union type `{}`",
+                    name,
+                    ty.to_expr()
+                ),
+                (Some(None), Some(_)) => {
+                    format!("alternative `{}` has no payload", name)
+                }
+                (Some(Some(_)), None) => {
+                    format!("alternative `{}` needs a payload", name)
+                }
+            }
+        }
+        _ => format!("expected `{}`, found {}", ty.to_expr(), describe(val)),
+    };
+    out.push(Violation {
+        path: path.to_owned(),
+        message,
+    })
+}
+
+/// Describes what kind of value this is, for error messages.
+fn describe(val: &SimpleValue) -> String {
+    match val {
+        SimpleValue::Num(NumKind::Bool(b)) => format!("the Bool {}", b),
+        SimpleValue::Num(NumKind::Natural(n)) => format!("the Natural {}", n),
+        SimpleValue::Num(NumKind::Integer(n)) => format!("the Integer {}", n),
+        SimpleValue::Num(NumKind::Double(x)) => format!("the Double {}", x),
+        SimpleValue::Text(_) => "a Text".to_owned(),
+        SimpleValue::Optional(_) => "an Optional".to_owned(),
+        SimpleValue::List(_) => "a List".to_owned(),
+        SimpleValue::Record(_) => "a record".to_owned(),
+        SimpleValue::Union(name, _) => {
+            format!("the union alternative `{}`", name)
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, v) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            v.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl From<ValidationErrors> for Error {
+    fn from(errors: ValidationErrors) -> Error {
+        ErrorKind::Deserialize(errors.to_string()).into()
+    }
+}
@@ -2,16 +2,20 @@ use std::collections::{BTreeMap, HashMap};
 use std::result::Result as StdResult;
 
 use dhall::builtins::Builtin;
-use dhall::operations::OpKind;
+use dhall::operations::{BinOp, OpKind};
 use dhall::semantics::{Hir, HirKind, Nir, NirKind};
 pub use dhall::syntax::NumKind;
-use dhall::syntax::{Expr, ExprKind, Span};
-use dhall::Ctxt;
+use dhall::syntax::{Expr, ExprKind, Label, PrintOptions, Span};
+use dhall::{Ctxt, Parsed};
+use num_traits::ToPrimitive;
 
-use crate::{Error, ErrorKind, FromDhall, Result, ToDhall};
+use crate::serialize::SerializeOptions;
+use crate::{
+    Bindings, Error, ErrorKind, FromDhall, Result, ToDhall, UnionMatch,
+};
 
 #[derive(Debug, Clone)]
-enum ValueKind {
+pub(crate) enum ValueKind {
     /// Invariant: the value must be printable with the given type.
     Val(SimpleValue, Option<SimpleType>),
     Ty(SimpleType),
@@ -19,9 +23,23 @@ enum ValueKind {
 
 #[doc(hidden)]
 /// An arbitrary Dhall value.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct Value {
-    kind: ValueKind,
+    pub(crate) kind: ValueKind,
+    /// Where each field was set, keyed by dotted path. Empty unless provenance was tracked.
+    pub(crate) provenance: BTreeMap<String, Provenance>,
+}
+
+/// Where the value of a record field was set, as returned by [`Value::field_provenance()`].
+///
+/// This is printed as `file:line:column`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// The file or URL that sets the field, or `None` for the text given to [`from_str()`].
+    pub source: Option<String>,
+    /// The line and column, both starting at 1, where the value starts in `source`. `None` if it
+    /// is not known, e.g. for desugared fields like those of `{ a.b = 1 }`.
+    pub line_col: Option<(usize, usize)>,
 }
 
 /// A value of the kind that can be decoded by `serde_dhall`, e.g. `{ x = True, y = [1, 2, 3] }`.
@@ -32,9 +50,8 @@ pub struct Value {
 ///
 /// ```rust
 /// # fn main() -> serde_dhall::Result<()> {
-/// use std::collections::BTreeMap;
 /// use serde::Deserialize;
-/// use serde_dhall::{from_simple_value, NumKind, SimpleValue};
+/// use serde_dhall::{from_simple_value, NumKind, RecordMap, SimpleValue};
 ///
 /// #[derive(Debug, PartialEq, Eq, Deserialize)]
 /// struct Foo {
@@ -48,7 +65,7 @@ pub struct Value {
 /// assert_eq!(
 ///     value,
 ///     SimpleValue::Record({
-///         let mut r = BTreeMap::new();
+///         let mut r = RecordMap::new();
 ///         r.insert(
 ///             "x".to_string(),
 ///             SimpleValue::Num(NumKind::Bool(true))
@@ -56,9 +73,9 @@ pub struct Value {
 ///         r.insert(
 ///             "y".to_string(),
 ///             SimpleValue::List(vec![
-///                 SimpleValue::Num(NumKind::Natural(1)),
-///                 SimpleValue::Num(NumKind::Natural(2)),
-///                 SimpleValue::Num(NumKind::Natural(3)),
+///                 SimpleValue::Num(NumKind::Natural(1u64.into())),
+///                 SimpleValue::Num(NumKind::Natural(2u64.into())),
+///                 SimpleValue::Num(NumKind::Natural(3u64.into())),
 ///             ])
 ///         );
 ///         r
@@ -80,15 +97,14 @@ pub struct Value {
 ///
 /// ```rust
 /// # fn main() -> serde_dhall::Result<()> {
-/// use std::collections::BTreeMap;
-/// use serde_dhall::{NumKind, SimpleValue};
+/// use serde_dhall::{NumKind, RecordMap, SimpleValue};
 ///
 /// let value: SimpleValue =
 ///     serde_dhall::from_str("{ x = 1, y = 2 }").parse()?;
 ///
-/// let mut map = BTreeMap::new();
-/// map.insert("x".to_string(), SimpleValue::Num(NumKind::Natural(1)));
-/// map.insert("y".to_string(), SimpleValue::Num(NumKind::Natural(2)));
+/// let mut map = RecordMap::new();
+/// map.insert("x".to_string(), SimpleValue::Num(NumKind::Natural(1u64.into())));
+/// map.insert("y".to_string(), SimpleValue::Num(NumKind::Natural(2u64.into())));
 /// assert_eq!(value, SimpleValue::Record(map));
 /// # Ok(())
 /// # }
@@ -107,12 +123,39 @@ pub enum SimpleValue {
     Optional(Option<Box<SimpleValue>>),
     /// A list of values - `[a, b, c, d, e]`
     List(Vec<SimpleValue>),
-    /// A record value - `{ k1 = v1, k2 = v2 }`
-    Record(BTreeMap<String, SimpleValue>),
+    /// A record value - `{ k1 = v1, k2 = v2 }`. See [`RecordMap`] for the order of the fields.
+    Record(RecordMap),
     /// A union value (both the name of the variant and the variant's value) - `Left e`
     Union(String, Option<Box<SimpleValue>>),
 }
 
+/// The fields of a [`SimpleValue::Record`].
+///
+/// Dhall records are unordered, and evaluation does not remember the order in which fields were
+/// written, so records read from Dhall code always have their fields sorted by name. The
+/// exception are the association lists read as records (e.g. the result of `toMap`), which keep
+/// the order of the list.
+///
+/// By default this is a `BTreeMap`, so fields are always iterated in sorted order, whatever the
+/// order in which they were inserted. With the `preserve_order` feature, this is an
+/// [`IndexMap`](https://docs.rs/indexmap/1/indexmap/map/struct.IndexMap.html) instead, which
+/// iterates in insertion order. Records built from Rust values then keep the order of the struct
+/// fields or of the map entries, e.g. when converting them to JSON.
+#[cfg(not(feature = "preserve_order"))]
+pub type RecordMap = std::collections::BTreeMap<String, SimpleValue>;
+/// The fields of a [`SimpleValue::Record`].
+///
+/// Dhall records are unordered, and evaluation does not remember the order in which fields were
+/// written, so records read from Dhall code always have their fields sorted by name. The
+/// exception are the association lists read as records (e.g. the result of `toMap`), which keep
+/// the order of the list.
+///
+/// With the `preserve_order` feature, this is an `IndexMap`, which iterates in insertion order.
+/// Records built from Rust values then keep the order of the struct fields or of the map entries,
+/// e.g. when converting them to JSON.
+#[cfg(feature = "preserve_order")]
+pub type RecordMap = indexmap::IndexMap<String, SimpleValue>;
+
 /// The type of a value that can be decoded by `serde_dhall`, e.g. `{ x: Bool, y: List Natural }`.
 ///
 /// A `SimpleType` is used when deserializing values to ensure they are of the expected type.
@@ -148,7 +191,7 @@ pub enum SimpleValue {
 /// `Prelude.Map.Type Text T`  | `HashMap<String, T>`, structs
 /// `T -> U`  | unsupported
 /// `Prelude.JSON.Type`  | unsupported
-/// `Prelude.Map.Type T U`  | unsupported
+/// `Prelude.Map.Type T U`  | `HashMap<T, U>`
 ///
 /// # Examples
 ///
@@ -207,47 +250,627 @@ pub enum SimpleType {
     Union(HashMap<String, Option<SimpleType>>),
 }
 
+/// What to do with the alternatives of unions whose payload is not simple, e.g. a type or a
+/// function, when converting a Dhall value. See
+/// [`Deserializer::non_simple_alternatives()`](crate::Deserializer::non_simple_alternatives()).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonSimpleAlternatives {
+    /// Fail to convert the whole value. This is the default.
+    Fail,
+    /// Remove these alternatives from the union types. Converting a value of one of these
+    /// alternatives still fails.
+    Skip,
+    /// Keep these alternatives, but without their payload: a value `< A : Type | B >.A Bool`
+    /// becomes `< A | B >.A`.
+    Stub,
+}
+
+// `#[default]` on enum variants needs a more recent compiler than we support.
+#[allow(clippy::derivable_impls)]
+impl Default for NonSimpleAlternatives {
+    fn default() -> Self {
+        NonSimpleAlternatives::Fail
+    }
+}
+
 impl Value {
     pub(crate) fn from_nir_and_ty<'cx>(
         cx: Ctxt<'cx>,
         x: &Nir<'cx>,
         ty: &Nir<'cx>,
+        alternatives: NonSimpleAlternatives,
     ) -> Result<Self> {
-        Ok(if let Ok(val) = SimpleValue::from_nir(x) {
-            // The type must be simple if the value is simple.
-            let ty = SimpleType::from_nir(ty).unwrap();
-            Value {
-                kind: ValueKind::Val(val, Some(ty)),
-            }
-        } else if let Ok(ty) = SimpleType::from_nir(x) {
-            Value {
-                kind: ValueKind::Ty(ty),
-            }
+        // Types are the values whose type is `Type`, `Kind` or `Sort`.
+        let kind = if let NirKind::Const(_) = ty.kind() {
+            ValueKind::Ty(
+                SimpleType::from_nir(cx, x, alternatives)
+                    .map_err(NotSimple::into_error)?,
+            )
         } else {
-            let expr = x.to_hir_noenv().to_expr(cx, Default::default());
-            return Err(Error(ErrorKind::Deserialize(format!(
-                "this is neither a simple type nor a simple value: {}",
-                expr
-            ))));
+            let val = SimpleValue::from_nir(cx, x, alternatives)
+                .map_err(NotSimple::into_error)?;
+            // The type can still fail to be simple, e.g. if an unused alternative of a union has
+            // a function as payload.
+            let ty = SimpleType::from_nir(cx, ty, alternatives)
+                .map_err(NotSimple::into_error)?;
+            ValueKind::Val(val, Some(ty))
+        };
+        Ok(Value {
+            kind,
+            provenance: BTreeMap::new(),
         })
     }
 
-    /// Converts a Value into a SimpleValue.
-    pub(crate) fn to_simple_value(&self) -> Option<SimpleValue> {
+    /// Converts this to a [`SimpleValue`]. Fails if this is a type.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::{NumKind, SimpleValue, Value};
+    ///
+    /// let value: Value = serde_dhall::from_str("1 + 1").parse()?;
+    /// assert_eq!(
+    ///     value.to_simple_value()?,
+    ///     SimpleValue::Num(NumKind::Natural(2u64.into()))
+    /// );
+    ///
+    /// let value: Value = serde_dhall::from_str("Natural").parse()?;
+    /// assert_eq!(
+    ///     value.to_simple_value().unwrap_err().to_string(),
+    ///     "this is a type, not a value: `Natural`"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_simple_value(&self) -> Result<SimpleValue> {
+        match &self.kind {
+            ValueKind::Val(val, _) => Ok(val.clone()),
+            ValueKind::Ty(_) => Err(Error(ErrorKind::Deserialize(format!(
+                "this is a type, not a value: `{}`",
+                self
+            )))),
+        }
+    }
+
+    /// Whether this is a type, like `Natural` or `{ x : Bool }`, rather than a value.
+    ///
+    /// Kinds like `Type`, and records of types, can't be read at all since they have no
+    /// [`SimpleType`] equivalent.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let schema: Value = serde_dhall::from_str("{ x : Bool }").parse()?;
+    /// assert!(schema.is_type());
+    /// let value: Value = serde_dhall::from_str("{ x = True }").parse()?;
+    /// assert!(!value.is_type());
+    ///
+    /// assert!(serde_dhall::from_str("Type").parse::<Value>().is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn is_type(&self) -> bool {
+        matches!(self.kind, ValueKind::Ty(_))
+    }
+
+    /// Converts this to a [`SimpleType`]. Fails if this is not a type.
+    pub fn to_simple_type(&self) -> Result<SimpleType> {
+        match &self.kind {
+            ValueKind::Ty(ty) => Ok(ty.clone()),
+            ValueKind::Val(..) => Err(Error(ErrorKind::Deserialize(format!(
+                "this is a value, not a type: `{}`",
+                self
+            )))),
+        }
+    }
+
+    /// Returns the boolean if this is a `Bool`.
+    ///
+    /// This and the other `as_*` methods read a single scalar without going through serde. The
+    /// error says what the value actually was.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let value: Value = serde_dhall::from_str("Natural/isZero 0").parse()?;
+    /// assert_eq!(value.as_bool()?, true);
+    ///
+    /// let value: Value = serde_dhall::from_str("[1, 2]").parse()?;
+    /// assert_eq!(
+    ///     value.as_bool().unwrap_err().to_string(),
+    ///     "expected a value of type `Bool`, found `[1, 2]` of type `List Natural`"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn as_bool(&self) -> Result<bool> {
+        match &self.kind {
+            ValueKind::Val(SimpleValue::Num(NumKind::Bool(b)), _) => Ok(*b),
+            _ => Err(self.unexpected("Bool")),
+        }
+    }
+
+    /// Returns the number if this is a `Natural`. Fails if it doesn't fit in a `u64`.
+    pub fn as_natural(&self) -> Result<u64> {
+        match &self.kind {
+            ValueKind::Val(SimpleValue::Num(NumKind::Natural(n)), _) => {
+                n.to_u64().ok_or_else(|| {
+                    Error(ErrorKind::Deserialize(format!(
+                        "natural number too large: {}",
+                        n
+                    )))
+                })
+            }
+            _ => Err(self.unexpected("Natural")),
+        }
+    }
+
+    /// Returns the number if this is an `Integer`. Fails if it doesn't fit in an `i64`.
+    pub fn as_integer(&self) -> Result<i64> {
         match &self.kind {
-            ValueKind::Val(val, _) => Some(val.clone()),
+            ValueKind::Val(SimpleValue::Num(NumKind::Integer(n)), _) => {
+                n.to_i64().ok_or_else(|| {
+                    Error(ErrorKind::Deserialize(format!(
+                        "integer too large: {}",
+                        n
+                    )))
+                })
+            }
+            _ => Err(self.unexpected("Integer")),
+        }
+    }
+
+    /// Returns the number if this is a `Double`.
+    pub fn as_double(&self) -> Result<f64> {
+        match &self.kind {
+            ValueKind::Val(SimpleValue::Num(NumKind::Double(x)), _) => {
+                Ok((*x).into())
+            }
+            _ => Err(self.unexpected("Double")),
+        }
+    }
+
+    /// Returns the text if this is a `Text`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let value: Value = serde_dhall::from_str(r#""Hello, ${"world"}!""#).parse()?;
+    /// assert_eq!(value.as_text()?, "Hello, world!");
+    ///
+    /// let value: Value = serde_dhall::from_str("Text").parse()?;
+    /// assert_eq!(
+    ///     value.as_text().unwrap_err().to_string(),
+    ///     "expected a value of type `Text`, found the type `Text`"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn as_text(&self) -> Result<&str> {
+        match &self.kind {
+            ValueKind::Val(SimpleValue::Text(s), _) => Ok(s),
+            _ => Err(self.unexpected("Text")),
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        let msg = match &self.kind {
+            ValueKind::Val(_, Some(ty)) => format!(
+                "expected a value of type `{}`, found `{}` of type `{}`",
+                expected, self, ty
+            ),
+            ValueKind::Val(_, None) => format!(
+                "expected a value of type `{}`, found `{}`",
+                expected, self
+            ),
+            ValueKind::Ty(_) => format!(
+                "expected a value of type `{}`, found the type `{}`",
+                expected, self
+            ),
+        };
+        Error(ErrorKind::Deserialize(msg))
+    }
+
+    /// Handles each alternative of a union value with a Rust closure, like a Dhall `merge`. See
+    /// [`UnionMatch`] for an example.
+    ///
+    /// [`UnionMatch`]: crate::UnionMatch
+    pub fn match_union<R>(&self) -> UnionMatch<'_, R> {
+        UnionMatch::new(self)
+    }
+
+    /// If this is a union value, returns the name of its alternative, its payload if any, and its
+    /// type if it is known.
+    pub(crate) fn as_union(
+        &self,
+    ) -> Option<(&str, Option<Value>, Option<&SimpleType>)> {
+        match &self.kind {
+            ValueKind::Val(SimpleValue::Union(name, payload), ty) => {
+                let payload_ty = match ty {
+                    Some(SimpleType::Union(kts)) => {
+                        kts.get(name).cloned().flatten()
+                    }
+                    _ => None,
+                };
+                let payload = payload.as_ref().map(|x| Value {
+                    kind: ValueKind::Val((**x).clone(), payload_ty),
+                    provenance: BTreeMap::new(),
+                });
+                Some((name, payload, ty.as_ref()))
+            }
             _ => None,
         }
     }
 
-    /// Converts a Value into a SimpleType.
-    pub(crate) fn to_simple_type(&self) -> Option<SimpleType> {
+    /// If this value is a list, returns its number of elements.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let value: Value = serde_dhall::from_str("[1, 2, 3]").parse()?;
+    /// assert_eq!(value.len(), Some(3));
+    ///
+    /// let value: Value = serde_dhall::from_str("True").parse()?;
+    /// assert_eq!(value.len(), None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn len(&self) -> Option<usize> {
         match &self.kind {
-            ValueKind::Ty(ty) => Some(ty.clone()),
+            ValueKind::Val(SimpleValue::List(xs), _) => Some(xs.len()),
             _ => None,
         }
     }
 
+    /// If this value is a list, returns whether it is empty.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let value: Value = serde_dhall::from_str("[] : List Bool").parse()?;
+    /// assert_eq!(value.is_empty(), Some(true));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn is_empty(&self) -> Option<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Returns a copy of this record with the field `name` set to `value`.
+    ///
+    /// If the field already exists, `value` must have the same type as the old one. The resulting
+    /// value can be printed back as Dhall source with `to_string()`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let config: Value =
+    ///     serde_dhall::from_str("{ name = \"foo\", version = 1 }").parse()?;
+    /// let config = config.with_field("version", &2u64)?;
+    /// assert_eq!(config.to_string(), "{ name = \"foo\", version = 2 }");
+    ///
+    /// assert!(config.with_field("version", &"two").is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_field<T: ToDhall>(
+        &self,
+        name: &str,
+        value: &T,
+    ) -> Result<Value> {
+        let (mut fields, mut field_tys) = self.to_record()?;
+        let value = value.to_dhall(field_tys.get(name))?;
+        let (value, ty) = match value.kind {
+            ValueKind::Val(val, Some(ty)) => (val, ty),
+            ValueKind::Val(val, None) => match val.typecheck()?.kind {
+                ValueKind::Val(val, Some(ty)) => (val, ty),
+                _ => unreachable!(),
+            },
+            ValueKind::Ty(_) => {
+                return Err(Error(ErrorKind::Serialize(format!(
+                    "cannot store a type in a record field: {}",
+                    value
+                ))))
+            }
+        };
+        fields.insert(name.to_owned(), value);
+        field_tys.insert(name.to_owned(), ty);
+        SimpleValue::Record(fields)
+            .into_value(Some(&SimpleType::Record(field_tys)))
+    }
+
+    /// Returns a copy of this record without the field `name`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let config: Value = serde_dhall::from_str("{ x = 1, y = True }").parse()?;
+    /// let config = config.without_field("y")?;
+    /// assert_eq!(config.to_string(), "{ x = 1 }");
+    ///
+    /// assert!(config.without_field("y").is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn without_field(&self, name: &str) -> Result<Value> {
+        let (mut fields, mut field_tys) = self.to_record()?;
+        #[cfg(feature = "preserve_order")]
+        let removed = fields.shift_remove(name);
+        #[cfg(not(feature = "preserve_order"))]
+        let removed = fields.remove(name);
+        if removed.is_none() {
+            return Err(Error(ErrorKind::Serialize(format!(
+                "no field `{}` in record: {}",
+                name, self
+            ))));
+        }
+        field_tys.remove(name);
+        SimpleValue::Record(fields)
+            .into_value(Some(&SimpleType::Record(field_tys)))
+    }
+
+    /// Merges two records with `⫽`: the fields of `other` override the fields of the same name in
+    /// `self`. This is useful to layer configuration files already loaded as `Value`s.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let defaults: Value =
+    ///     serde_dhall::from_str("{ host = \"localhost\", port = 80 }").parse()?;
+    /// let overrides: Value = serde_dhall::from_str("{ port = 8080 }").parse()?;
+    /// assert_eq!(
+    ///     defaults.merge_prefer(&overrides)?.to_string(),
+    ///     "{ host = \"localhost\", port = 8080 }"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn merge_prefer(&self, other: &Value) -> Result<Value> {
+        let mut merged = self.binop(BinOp::RightBiasedRecordMerge, other)?;
+        // The fields of `other` replace those of `self` entirely.
+        let replaced = other.record_fields();
+        merged.provenance = self
+            .provenance
+            .iter()
+            .filter(|(path, _)| !replaced.contains(&top_level_field(path)))
+            .chain(&other.provenance)
+            .map(|(path, p)| (path.clone(), p.clone()))
+            .collect();
+        Ok(merged)
+    }
+
+    /// Merges two records recursively with `∧`. Fields present in both must be records, which are
+    /// merged in turn; any other collision is a type error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let a: Value = serde_dhall::from_str("{ db = { host = \"db\" } }").parse()?;
+    /// let b: Value = serde_dhall::from_str("{ db = { port = 5432 } }").parse()?;
+    /// assert_eq!(
+    ///     a.merge_recursive(&b)?.to_string(),
+    ///     "{ db = { host = \"db\", port = 5432 } }"
+    /// );
+    /// assert!(a.merge_recursive(&a).is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn merge_recursive(&self, other: &Value) -> Result<Value> {
+        let mut merged = self.binop(BinOp::RecursiveRecordMerge, other)?;
+        merged.provenance = self.provenance.clone();
+        merged.provenance.extend(other.provenance.clone());
+        Ok(merged)
+    }
+
+    /// Merges two record types recursively with `⩓`, with the same rules as
+    /// [`merge_recursive()`](Value::merge_recursive()).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let a: Value = serde_dhall::from_str("{ x : Bool }").parse()?;
+    /// let b: Value = serde_dhall::from_str("{ y : Natural }").parse()?;
+    /// assert_eq!(a.merge_types(&b)?.to_string(), "{ x : Bool, y : Natural }");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn merge_types(&self, other: &Value) -> Result<Value> {
+        self.binop(BinOp::RecursiveRecordTypeMerge, other)
+    }
+
+    /// Selects the given fields of a record, like the Dhall projection `x.{ host, port }`. Fails
+    /// if one of the fields is missing.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let config: Value = serde_dhall::from_str(
+    ///     "{ host = \"example.com\", port = 443, admins = [\"root\"] }",
+    /// )
+    /// .parse()?;
+    /// assert_eq!(
+    ///     config.project(&["host", "port"])?.to_string(),
+    ///     "{ host = \"example.com\", port = 443 }"
+    /// );
+    /// assert!(config.project(&["hostname"]).is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn project(&self, fields: &[&str]) -> Result<Value> {
+        let labels = fields.iter().map(|f| Label::from(*f)).collect();
+        let mut projected = Value::eval(Expr::new(
+            ExprKind::Op(OpKind::Projection(self.to_expr(), labels)),
+            Span::Artificial,
+        ))?;
+        projected.provenance = self
+            .provenance
+            .iter()
+            .filter(|(path, _)| fields.contains(&top_level_field(path)))
+            .map(|(path, p)| (path.clone(), p.clone()))
+            .collect();
+        Ok(projected)
+    }
+
+    /// Returns where the field at `path`, given as field names separated by dots, was set. This
+    /// is only known for values parsed with [`Deserializer::track_provenance()`], and for the
+    /// records obtained from them with [`merge_prefer()`](Value::merge_prefer()),
+    /// [`merge_recursive()`](Value::merge_recursive()) and [`project()`](Value::project()).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::Value;
+    ///
+    /// let config: Value = serde_dhall::from_str(
+    ///     "let defaults = { host = \"localhost\", port = 80 }\n\
+    ///      in  defaults // { port = 8080 }",
+    /// )
+    /// .track_provenance(true)
+    /// .parse()?;
+    /// let port = config.field_provenance("port").unwrap();
+    /// assert_eq!(port.line_col, Some((2, 26)));
+    /// assert_eq!(port.to_string(), "<string>:2:26");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Deserializer::track_provenance()`]: crate::Deserializer::track_provenance()
+    pub fn field_provenance(&self, path: &str) -> Option<&Provenance> {
+        self.provenance.get(path)
+    }
+
+    pub(crate) fn set_provenance(
+        &mut self,
+        provenance: BTreeMap<String, Provenance>,
+    ) {
+        self.provenance = provenance;
+    }
+
+    /// The names of the fields, if this is a record.
+    fn record_fields(&self) -> Vec<&str> {
+        match &self.kind {
+            ValueKind::Val(SimpleValue::Record(kvs), _) => {
+                kvs.keys().map(String::as_str).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Evaluates the Dhall expression `expr`, whose free variables are given by `ctx`. This is
+    /// a shortcut for [`from_str(expr).with_bindings(ctx).parse()`](crate::Deserializer::with_bindings()).
+    ///
+    /// See [`Bindings`] for an example.
+    pub fn normalize_in_context(expr: &str, ctx: &Bindings) -> Result<Value> {
+        crate::from_str(expr).with_bindings(ctx).parse()
+    }
+
+    fn binop(&self, op: BinOp, other: &Value) -> Result<Value> {
+        Value::eval(Expr::new(
+            ExprKind::Op(OpKind::BinOp(op, self.to_expr(), other.to_expr())),
+            Span::Artificial,
+        ))
+    }
+
+    /// Typechecks and evaluates an expression without imports.
+    pub(crate) fn eval(expr: Expr) -> Result<Value> {
+        Ctxt::with_new(|cx| {
+            let typed = Parsed::from_expr_without_imports(expr)
+                .skip_resolve(cx)?
+                .typecheck(cx)?;
+            let val = typed.normalize(cx)?;
+            cx.evaluate(|| {
+                Value::from_nir_and_ty(
+                    cx,
+                    val.as_nir(),
+                    typed.ty().as_nir(),
+                    NonSimpleAlternatives::Fail,
+                )
+            })
+        })
+        .map_err(ErrorKind::Dhall)
+        .map_err(Error)?
+    }
+
+    fn to_record(&self) -> Result<(RecordMap, HashMap<String, SimpleType>)> {
+        match &self.kind {
+            ValueKind::Val(
+                SimpleValue::Record(fields),
+                Some(SimpleType::Record(field_tys)),
+            ) => Ok((fields.clone(), field_tys.clone())),
+            _ => Err(Error(ErrorKind::Serialize(format!(
+                "this is not a record value: {}",
+                self
+            )))),
+        }
+    }
+
+    /// Prints the value as Dhall code, with the given options.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::{PrintOptions, Value};
+    ///
+    /// let value: Value = serde_dhall::from_str(
+    ///     "let ports = [80, 443, 8080, 8443] in { web = ports, proxy = ports }",
+    /// )
+    /// .parse()?;
+    /// assert_eq!(
+    ///     value.to_string(),
+    ///     "{ proxy = [80, 443, 8080, 8443], web = [80, 443, 8080, 8443] }"
+    /// );
+    ///
+    /// let opts = PrintOptions {
+    ///     share_subexpressions: true,
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(
+    ///     value.to_string_with(opts),
+    ///     "let _1 = [80, 443, 8080, 8443] in { proxy = _1, web = _1 }"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_string_with(&self, opts: PrintOptions) -> String {
+        self.to_expr().display_with(opts).to_string()
+    }
+
     /// Converts a value back to the corresponding AST expression.
     pub(crate) fn to_expr(&self) -> Expr {
         match &self.kind {
@@ -255,13 +878,117 @@ impl Value {
             ValueKind::Ty(ty) => ty.to_expr(),
         }
     }
+
+    /// Gives this value the type `ty` if it only differs from its own type by records where `ty`
+    /// expects maps with text keys, or by the conversions enabled in `coercions`. Returns `None`
+    /// if the value doesn't have type `ty` even after that.
+    pub(crate) fn coerce(
+        self,
+        ty: &SimpleType,
+        coercions: Coercions,
+    ) -> Option<Self> {
+        match self.kind {
+            ValueKind::Val(val, Some(val_ty))
+                if val_ty.coercible_to(ty, coercions)
+                    && (!coercions.maps_as_records
+                        || val.has_fields_of(ty)) =>
+            {
+                let val = if coercions.numbers {
+                    val.coerce_numbers(ty)
+                } else {
+                    val
+                };
+                val.into_value(Some(ty)).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// Renames the alternatives of all the unions in this value and its type.
+    pub(crate) fn rename_union_alternatives(
+        self,
+        f: &impl Fn(&str) -> String,
+    ) -> Self {
+        let kind = match self.kind {
+            ValueKind::Val(val, ty) => ValueKind::Val(
+                val.rename_union_alternatives(f),
+                ty.map(|ty| ty.rename_union_alternatives(f)),
+            ),
+            ValueKind::Ty(ty) => ValueKind::Ty(ty.rename_union_alternatives(f)),
+        };
+        Value {
+            kind,
+            provenance: self.provenance,
+        }
+    }
+}
+
+/// The conversions that [`Value::coerce`] may do besides turning records into maps.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Coercions {
+    /// Replace `Natural`s with `Integer`s and vice-versa (see [`SimpleValue::coerce_numbers`]).
+    pub numbers: bool,
+    /// Turn maps with text keys into records.
+    pub maps_as_records: bool,
+}
+
+/// `a` for the path `a.b.c`.
+fn top_level_field(path: &str) -> &str {
+    path.split('.').next().unwrap()
 }
 
+/// Why a value or a type isn't simple.
 #[derive(Debug)]
-struct NotSimpleValue;
+pub(crate) struct NotSimple {
+    /// The path to the offending subterm, innermost first, e.g. `["[0]", "a"]` for `a[0]`.
+    path: Vec<String>,
+    /// The offending subterm, printed.
+    term: String,
+    reason: &'static str,
+}
+
+impl NotSimple {
+    fn new<'cx>(cx: Ctxt<'cx>, nir: &Nir<'cx>, reason: &'static str) -> Self {
+        NotSimple {
+            path: Vec::new(),
+            term: nir.to_expr(cx, Default::default()).to_string(),
+            reason,
+        }
+    }
+
+    fn in_field(mut self, name: &str) -> Self {
+        self.path.push(name.to_owned());
+        self
+    }
+
+    fn in_index(mut self, i: usize) -> Self {
+        self.path.push(format!("[{}]", i));
+        self
+    }
+
+    pub(crate) fn into_error(self) -> Error {
+        let mut path = String::new();
+        for segment in self.path.iter().rev() {
+            if !path.is_empty() && !segment.starts_with('[') {
+                path.push('.');
+            }
+            path.push_str(segment);
+        }
+        let msg = format!("{}: `{}`", self.reason, self.term);
+        Error(if path.is_empty() {
+            ErrorKind::Deserialize(msg)
+        } else {
+            ErrorKind::DeserializeAt { path, msg }
+        })
+    }
+}
 
 impl SimpleValue {
-    fn from_nir(nir: &Nir) -> StdResult<Self, NotSimpleValue> {
+    fn from_nir<'cx>(
+        cx: Ctxt<'cx>,
+        nir: &Nir<'cx>,
+        alternatives: NonSimpleAlternatives,
+    ) -> StdResult<Self, NotSimple> {
         Ok(match nir.kind() {
             NirKind::Num(lit) => SimpleValue::Num(lit.clone()),
             NirKind::TextLit(x) => SimpleValue::Text(
@@ -269,15 +996,16 @@ impl SimpleValue {
                     .expect("Normal form should ensure the text is a string"),
             ),
             NirKind::EmptyOptionalLit(_) => SimpleValue::Optional(None),
-            NirKind::NEOptionalLit(x) => {
-                SimpleValue::Optional(Some(Box::new(Self::from_nir(x)?)))
-            }
+            NirKind::NEOptionalLit(x) => SimpleValue::Optional(Some(Box::new(
+                Self::from_nir(cx, x, alternatives)?,
+            ))),
             NirKind::EmptyListLit(t) => {
                 // Detect and handle the special records that make assoc maps
                 if let NirKind::RecordType(kts) = t.kind() {
                     if kts.len() == 2
-                        && kts.contains_key("mapKey")
                         && kts.contains_key("mapValue")
+                        && kts.get("mapKey").map(|t| t.kind())
+                            == Some(&NirKind::BuiltinType(Builtin::Text))
                     {
                         return Ok(SimpleValue::Record(Default::default()));
                     }
@@ -287,11 +1015,16 @@ impl SimpleValue {
             NirKind::NEListLit(xs) => {
                 // Detect and handle the special records that make assoc maps
                 if let NirKind::RecordLit(kvs) = xs[0].kind() {
+                    // Only text keys can become record fields.
+                    let text_keys = matches!(
+                        kvs.get("mapKey").map(|k| k.kind()),
+                        Some(NirKind::TextLit(_))
+                    );
                     if kvs.len() == 2
-                        && kvs.contains_key("mapKey")
                         && kvs.contains_key("mapValue")
+                        && text_keys
                     {
-                        let convert_entry = |x: &Nir| match x.kind() {
+                        let convert_entry = |x: &Nir<'cx>| match x.kind() {
                             NirKind::RecordLit(kvs) => {
                                 let k = match kvs.get("mapKey").unwrap().kind()
                                 {
@@ -307,8 +1040,11 @@ impl SimpleValue {
                                     ),
                                 };
                                 let v = Self::from_nir(
+                                    cx,
                                     kvs.get("mapValue").unwrap(),
-                                )?;
+                                    alternatives,
+                                )
+                                .map_err(|e| e.in_field(&k))?;
                                 Ok((k, v))
                             }
                             _ => unreachable!("Internal type error"),
@@ -322,25 +1058,90 @@ impl SimpleValue {
                 }
                 SimpleValue::List(
                     xs.iter()
-                        .map(Self::from_nir)
+                        .enumerate()
+                        .map(|(i, x)| {
+                            Self::from_nir(cx, x, alternatives)
+                                .map_err(|e| e.in_index(i))
+                        })
                         .collect::<StdResult<_, _>>()?,
                 )
             }
-            NirKind::RecordLit(kvs) => SimpleValue::Record(
-                kvs.iter()
-                    .map(|(k, v)| Ok((k.to_string(), Self::from_nir(v)?)))
-                    .collect::<StdResult<_, _>>()?,
-            ),
-            NirKind::UnionLit(field, x, _) => SimpleValue::Union(
-                field.into(),
-                Some(Box::new(Self::from_nir(x)?)),
-            ),
+            NirKind::RecordLit(kvs) => {
+                let mut kvs = kvs
+                    .iter()
+                    .map(|(k, v)| {
+                        let v = Self::from_nir(cx, v, alternatives)
+                            .map_err(|e| e.in_field(&k.to_string()))?;
+                        Ok((k.to_string(), v))
+                    })
+                    .collect::<StdResult<Vec<_>, _>>()?;
+                // The order of the fields is lost during evaluation.
+                kvs.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+                SimpleValue::Record(kvs.into_iter().collect())
+            }
+            NirKind::UnionLit(field, x, _) => {
+                match Self::from_nir(cx, x, alternatives) {
+                    Ok(x) => {
+                        SimpleValue::Union(field.into(), Some(Box::new(x)))
+                    }
+                    Err(_) if alternatives == NonSimpleAlternatives::Stub => {
+                        SimpleValue::Union(field.into(), None)
+                    }
+                    Err(e) => return Err(e.in_field(&field.to_string())),
+                }
+            }
             NirKind::UnionConstructor(field, ty)
                 if ty.get(field).map(|f| f.is_some()) == Some(false) =>
             {
                 SimpleValue::Union(field.into(), None)
             }
-            _ => return Err(NotSimpleValue),
+            NirKind::LamClosure { .. } => {
+                return Err(NotSimple::new(
+                    cx,
+                    nir,
+                    "functions are not supported",
+                ))
+            }
+            NirKind::UnionConstructor(..) => {
+                return Err(NotSimple::new(
+                    cx,
+                    nir,
+                    "union constructors are not supported",
+                ))
+            }
+            NirKind::AppliedBuiltin(..) => {
+                return Err(NotSimple::new(
+                    cx,
+                    nir,
+                    "builtin functions are not supported",
+                ))
+            }
+            NirKind::Const(_) => {
+                return Err(NotSimple::new(
+                    cx,
+                    nir,
+                    "kinds and sorts are not supported",
+                ))
+            }
+            NirKind::BuiltinType(_)
+            | NirKind::OptionalType(_)
+            | NirKind::ListType(_)
+            | NirKind::RecordType(_)
+            | NirKind::UnionType(_)
+            | NirKind::PiClosure { .. } => {
+                return Err(NotSimple::new(
+                    cx,
+                    nir,
+                    "types are only supported as the whole value",
+                ))
+            }
+            _ => {
+                return Err(NotSimple::new(
+                    cx,
+                    nir,
+                    "this is not a simple value",
+                ))
+            }
         })
     }
 
@@ -404,6 +1205,32 @@ impl SimpleValue {
                     .map(|(k, v)| Ok((k.clone().into(), v.to_hir(None)?)))
                     .collect::<Result<_>>()?,
             ),
+            // Maps with non-text keys are association lists.
+            (V::Record(v), Some(T::List(t))) if v.is_empty() => {
+                ExprKind::EmptyListLit(hir(ExprKind::Op(OpKind::App(
+                    hir(ExprKind::Builtin(Builtin::List)),
+                    t.to_hir(),
+                ))))
+            }
+            (V::Record(v), Some(ty @ T::List(_))) => {
+                let entry_ty = ty.text_map_value().ok_or_else(type_error)?;
+                ExprKind::NEListLit(
+                    v.iter()
+                        .map(|(k, v)| {
+                            let entry = vec![
+                                (
+                                    "mapKey".into(),
+                                    hir(ExprKind::TextLit(k.clone().into())),
+                                ),
+                                ("mapValue".into(), v.to_hir(Some(entry_ty))?),
+                            ];
+                            Ok(hir(ExprKind::RecordLit(
+                                entry.into_iter().collect(),
+                            )))
+                        })
+                        .collect::<Result<_>>()?,
+                )
+            }
             (V::Record(v), Some(T::Record(t))) => ExprKind::RecordLit(
                 v.iter()
                     .map(|(k, v)| match t.get(k) {
@@ -441,26 +1268,347 @@ impl SimpleValue {
         Ok(hir(kind))
     }
     pub(crate) fn into_value(self, ty: Option<&SimpleType>) -> Result<Value> {
+        // Without an annotation, types like the one of `None` may still be found elsewhere in
+        // the value.
+        let ty = match ty {
+            Some(ty) => ty.clone(),
+            None => self
+                .infer_type("")?
+                .complete()
+                .map_err(|e| Error(ErrorKind::Serialize(e)))?,
+        };
         // Check that the value is printable with the given type.
-        self.to_hir(ty)?;
+        self.to_hir(Some(&ty))?;
         Ok(Value {
-            kind: ValueKind::Val(self, ty.cloned()),
+            kind: ValueKind::Val(self, Some(ty)),
+            provenance: BTreeMap::new(),
+        })
+    }
+
+    /// Infers as much of the type of this value as possible. `path` locates the value for error
+    /// messages.
+    fn infer_type(&self, path: &str) -> Result<PartialType> {
+        use NumKind::*;
+        use PartialType as T;
+        let at_path = |msg: String| {
+            if path.is_empty() {
+                msg
+            } else {
+                format!("field `{}`: {}", path, msg)
+            }
+        };
+        let hole =
+            |what: &str| T::Hole(at_path(format!("cannot infer {}", what)));
+        let err = |msg: String| Err(Error(ErrorKind::Serialize(at_path(msg))));
+        Ok(match self {
+            SimpleValue::Num(Bool(_)) => T::Scalar(SimpleType::Bool),
+            SimpleValue::Num(Natural(_)) => T::Scalar(SimpleType::Natural),
+            SimpleValue::Num(Integer(_)) => T::Scalar(SimpleType::Integer),
+            SimpleValue::Num(Double(_)) => T::Scalar(SimpleType::Double),
+            SimpleValue::Text(_) => T::Scalar(SimpleType::Text),
+            SimpleValue::Optional(None) => {
+                T::Optional(Box::new(hole("type of `None`")))
+            }
+            SimpleValue::Optional(Some(v)) => {
+                T::Optional(Box::new(v.infer_type(path)?))
+            }
+            SimpleValue::List(xs) => {
+                let mut elt_ty = hole("element type of empty list");
+                for (i, x) in xs.iter().enumerate() {
+                    let x_ty = x.infer_type(&format!("{}[{}]", path, i))?;
+                    elt_ty = match elt_ty.unify(x_ty) {
+                        Some(ty) => ty,
+                        None => {
+                            return err(
+                                "list elements have different types".to_owned()
+                            )
+                        }
+                    };
+                }
+                T::List(Box::new(elt_ty))
+            }
+            SimpleValue::Record(kvs) => T::Record(
+                kvs.iter()
+                    .map(|(k, v)| {
+                        let path = if path.is_empty() {
+                            k.clone()
+                        } else {
+                            format!("{}.{}", path, k)
+                        };
+                        Ok((k.clone(), v.infer_type(&path)?))
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            // The other alternatives can't be known.
+            SimpleValue::Union(k, x) => {
+                let mut kts = BTreeMap::new();
+                let x_ty = match x {
+                    Some(x) => Some(x.infer_type(path)?),
+                    None => None,
+                };
+                kts.insert(k.clone(), x_ty);
+                T::Union(
+                    at_path(format!(
+                        "cannot serialize value without a type annotation: {:?}",
+                        self
+                    )),
+                    kts,
+                )
+            }
         })
     }
 
+    /// Prints the value as Dhall code, with the type annotations it needs. The type is `ty` if
+    /// given, and is otherwise inferred from the value, which fails for the same values as
+    /// [`serialize()`](crate::serialize()) without a type annotation, e.g. unions and empty
+    /// lists.
+    ///
+    /// Unlike the `Display` implementation, this never guesses the type, so the result can be
+    /// read back with that type.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::{RecordMap, SimpleType, SimpleValue};
+    ///
+    /// let mut fields = RecordMap::new();
+    /// fields.insert("tags".to_string(), SimpleValue::List(vec![]));
+    /// fields.insert("user name".to_string(), SimpleValue::Text("\"me\"".to_string()));
+    /// let value = SimpleValue::Record(fields);
+    ///
+    /// assert!(value.to_dhall_string(None).is_err());
+    ///
+    /// let ty: SimpleType =
+    ///     serde_dhall::from_str("{ tags : List Text, `user name` : Text }").parse()?;
+    /// assert_eq!(
+    ///     value.to_dhall_string(Some(&ty))?,
+    ///     r#"{ tags = [] : List Text, `user name` = "\"me\"" }"#,
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_dhall_string(&self, ty: Option<&SimpleType>) -> Result<String> {
+        Ok(self.clone().into_value(ty)?.to_string())
+    }
+
+    /// Infers the type of a value that doesn't need a type annotation to be printed.
+    fn typecheck(self) -> Result<Value> {
+        Value::eval(self.to_expr(None)?)
+    }
+
     /// Converts back to the corresponding AST expression.
     pub(crate) fn to_expr(&self, ty: Option<&SimpleType>) -> Result<Expr> {
         Ctxt::with_new(|cx| {
             Ok(self.to_hir(ty)?.to_expr(cx, Default::default()))
         })
     }
+
+    fn rename_union_alternatives(self, f: &impl Fn(&str) -> String) -> Self {
+        use SimpleValue::*;
+        let rename =
+            |x: Box<SimpleValue>| Box::new(x.rename_union_alternatives(f));
+        match self {
+            Num(_) | Text(_) => self,
+            Optional(x) => Optional(x.map(rename)),
+            List(xs) => List(
+                xs.into_iter()
+                    .map(|x| x.rename_union_alternatives(f))
+                    .collect(),
+            ),
+            Record(kvs) => Record(
+                kvs.into_iter()
+                    .map(|(k, v)| (k, v.rename_union_alternatives(f)))
+                    .collect(),
+            ),
+            Union(k, x) => Union(f(&k), x.map(rename)),
+        }
+    }
+
+    /// Whether the records in this value have exactly the fields that `ty` expects. This only
+    /// matters for maps that were turned into records, since the types ensure it otherwise.
+    fn has_fields_of(&self, ty: &SimpleType) -> bool {
+        use SimpleType as T;
+        use SimpleValue as V;
+        match (self, ty) {
+            (V::Optional(Some(x)), T::Optional(t)) => x.has_fields_of(t),
+            (V::List(xs), T::List(t)) => xs.iter().all(|x| x.has_fields_of(t)),
+            (V::Record(kvs), T::Record(kts)) => {
+                kvs.len() == kts.len()
+                    && kvs.iter().all(|(k, v)| match kts.get(k) {
+                        Some(t) => v.has_fields_of(t),
+                        None => false,
+                    })
+            }
+            (V::Record(kvs), ty) => match ty.text_map_value() {
+                Some(t) => kvs.values().all(|v| v.has_fields_of(t)),
+                None => true,
+            },
+            (V::Union(k, Some(x)), T::Union(kts)) => match kts.get(k) {
+                Some(Some(t)) => x.has_fields_of(t),
+                _ => true,
+            },
+            _ => true,
+        }
+    }
+
+    /// Converts `Natural`s to `Integer`s and back where `ty` expects the other one, as long as
+    /// the number fits. Everything else is left alone; the result still needs to be checked
+    /// against `ty`.
+    // The numbers are not `Copy` with the `bigint` feature.
+    #[allow(clippy::clone_on_copy)]
+    pub(crate) fn coerce_numbers(self, ty: &SimpleType) -> Self {
+        use std::convert::TryFrom;
+        use SimpleType as T;
+        use SimpleValue as V;
+        match (self, ty) {
+            (V::Num(NumKind::Natural(n)), T::Integer) => {
+                match dhall::syntax::Integer::try_from(n.clone()) {
+                    Ok(n) => V::Num(NumKind::Integer(n)),
+                    Err(_) => V::Num(NumKind::Natural(n)),
+                }
+            }
+            (V::Num(NumKind::Integer(n)), T::Natural) => {
+                match dhall::syntax::Natural::try_from(n.clone()) {
+                    Ok(n) => V::Num(NumKind::Natural(n)),
+                    Err(_) => V::Num(NumKind::Integer(n)),
+                }
+            }
+            (V::Optional(Some(x)), T::Optional(t)) => {
+                V::Optional(Some(Box::new(x.coerce_numbers(t))))
+            }
+            (V::List(xs), T::List(t)) => {
+                V::List(xs.into_iter().map(|x| x.coerce_numbers(t)).collect())
+            }
+            (V::Record(kvs), T::Record(kts)) => V::Record(
+                kvs.into_iter()
+                    .map(|(k, v)| match kts.get(&k) {
+                        Some(t) => {
+                            let v = v.coerce_numbers(t);
+                            (k, v)
+                        }
+                        None => (k, v),
+                    })
+                    .collect(),
+            ),
+            (V::Record(kvs), ty) => match ty.text_map_value() {
+                Some(t) => V::Record(
+                    kvs.into_iter()
+                        .map(|(k, v)| (k, v.coerce_numbers(t)))
+                        .collect(),
+                ),
+                None => V::Record(kvs),
+            },
+            (V::Union(k, Some(x)), T::Union(kts)) => match kts.get(&k) {
+                Some(Some(t)) => {
+                    let x = x.coerce_numbers(t);
+                    V::Union(k, Some(Box::new(x)))
+                }
+                _ => V::Union(k, Some(x)),
+            },
+            (val, _) => val,
+        }
+    }
 }
 
-#[derive(Debug)]
-struct NotSimpleType;
+/// A type inferred from a value, where the parts that can't be known from the value are holes.
+enum PartialType {
+    /// Holds the error to report if nothing fills the hole.
+    Hole(String),
+    /// `Bool`, `Natural`, `Integer`, `Double` or `Text`.
+    Scalar(SimpleType),
+    Optional(Box<PartialType>),
+    List(Box<PartialType>),
+    Record(BTreeMap<String, PartialType>),
+    /// The alternatives found so far. Also holds the error to report if the whole type is
+    /// needed, since the other alternatives can't be known.
+    Union(String, BTreeMap<String, Option<PartialType>>),
+}
+
+impl PartialType {
+    /// Combines the information of two types. Returns `None` if they are incompatible.
+    fn unify(self, other: PartialType) -> Option<PartialType> {
+        use PartialType::*;
+        Some(match (self, other) {
+            (Hole(e), Hole(_)) => Hole(e),
+            (Hole(_), t) | (t, Hole(_)) => t,
+            (Scalar(t1), Scalar(t2)) if t1 == t2 => Scalar(t1),
+            (Optional(t1), Optional(t2)) => Optional(Box::new(t1.unify(*t2)?)),
+            (List(t1), List(t2)) => List(Box::new(t1.unify(*t2)?)),
+            (Record(mut kts1), Record(kts2)) => {
+                if kts1.len() != kts2.len() {
+                    return None;
+                }
+                for (k, t2) in kts2 {
+                    let t1 = kts1.remove(&k)?;
+                    kts1.insert(k, t1.unify(t2)?);
+                }
+                Record(kts1)
+            }
+            (Union(e, mut kts1), Union(_, kts2)) => {
+                for (k, t2) in kts2 {
+                    let t = match (kts1.remove(&k), t2) {
+                        (None, t2) => t2,
+                        (Some(None), None) => None,
+                        (Some(Some(t1)), Some(t2)) => Some(t1.unify(t2)?),
+                        _ => return None,
+                    };
+                    kts1.insert(k, t);
+                }
+                Union(e, kts1)
+            }
+            _ => return None,
+        })
+    }
+
+    /// Fails with the error of the first hole.
+    fn complete(self) -> StdResult<SimpleType, String> {
+        Ok(match self {
+            PartialType::Hole(e) | PartialType::Union(e, _) => return Err(e),
+            PartialType::Scalar(t) => t,
+            PartialType::Optional(t) => {
+                SimpleType::Optional(Box::new(t.complete()?))
+            }
+            PartialType::List(t) => SimpleType::List(Box::new(t.complete()?)),
+            PartialType::Record(kts) => SimpleType::Record(
+                kts.into_iter()
+                    .map(|(k, t)| Ok((k, t.complete()?)))
+                    .collect::<StdResult<_, String>>()?,
+            ),
+        })
+    }
+
+    /// Fills the holes with `{}`, and takes unions to only have the alternatives found.
+    fn complete_or_guess(self) -> SimpleType {
+        match self {
+            PartialType::Hole(_) => SimpleType::Record(HashMap::new()),
+            PartialType::Scalar(t) => t,
+            PartialType::Optional(t) => {
+                SimpleType::Optional(Box::new(t.complete_or_guess()))
+            }
+            PartialType::List(t) => {
+                SimpleType::List(Box::new(t.complete_or_guess()))
+            }
+            PartialType::Record(kts) => SimpleType::Record(
+                kts.into_iter()
+                    .map(|(k, t)| (k, t.complete_or_guess()))
+                    .collect(),
+            ),
+            PartialType::Union(_, kts) => SimpleType::Union(
+                kts.into_iter()
+                    .map(|(k, t)| (k, t.map(PartialType::complete_or_guess)))
+                    .collect(),
+            ),
+        }
+    }
+}
 
 impl SimpleType {
-    fn from_nir(nir: &Nir) -> StdResult<Self, NotSimpleType> {
+    pub(crate) fn from_nir<'cx>(
+        cx: Ctxt<'cx>,
+        nir: &Nir<'cx>,
+        alternatives: NonSimpleAlternatives,
+    ) -> StdResult<Self, NotSimple> {
         Ok(match nir.kind() {
             NirKind::BuiltinType(b) => match b {
                 Builtin::Bool => SimpleType::Bool,
@@ -470,28 +1618,68 @@ impl SimpleType {
                 Builtin::Text => SimpleType::Text,
                 _ => unreachable!(),
             },
-            NirKind::OptionalType(t) => {
-                SimpleType::Optional(Box::new(Self::from_nir(t)?))
-            }
+            NirKind::OptionalType(t) => SimpleType::Optional(Box::new(
+                Self::from_nir(cx, t, alternatives)?,
+            )),
             NirKind::ListType(t) => {
-                SimpleType::List(Box::new(Self::from_nir(t)?))
+                SimpleType::List(Box::new(Self::from_nir(cx, t, alternatives)?))
             }
             NirKind::RecordType(kts) => SimpleType::Record(
-                kts.iter()
-                    .map(|(k, v)| Ok((k.into(), Self::from_nir(v)?)))
-                    .collect::<StdResult<_, _>>()?,
-            ),
-            NirKind::UnionType(kts) => SimpleType::Union(
                 kts.iter()
                     .map(|(k, v)| {
-                        Ok((
-                            k.into(),
-                            v.as_ref().map(Self::from_nir).transpose()?,
-                        ))
+                        let v = Self::from_nir(cx, v, alternatives)
+                            .map_err(|e| e.in_field(&k.to_string()))?;
+                        Ok((k.into(), v))
                     })
                     .collect::<StdResult<_, _>>()?,
             ),
-            _ => return Err(NotSimpleType),
+            NirKind::UnionType(kts) => {
+                let mut alts = HashMap::new();
+                for (k, v) in kts {
+                    let v = v
+                        .as_ref()
+                        .map(|v| Self::from_nir(cx, v, alternatives))
+                        .transpose();
+                    let v = match (v, alternatives) {
+                        (Ok(v), _) => v,
+                        (Err(_), NonSimpleAlternatives::Skip) => continue,
+                        (Err(_), NonSimpleAlternatives::Stub) => None,
+                        (Err(e), NonSimpleAlternatives::Fail) => {
+                            return Err(e.in_field(&k.to_string()))
+                        }
+                    };
+                    alts.insert(k.into(), v);
+                }
+                SimpleType::Union(alts)
+            }
+            NirKind::Var(_) => {
+                return Err(NotSimple::new(
+                    cx,
+                    nir,
+                    "type variables are not supported",
+                ))
+            }
+            NirKind::PiClosure { .. } => {
+                return Err(NotSimple::new(
+                    cx,
+                    nir,
+                    "function types are not supported",
+                ))
+            }
+            NirKind::Const(_) => {
+                return Err(NotSimple::new(
+                    cx,
+                    nir,
+                    "kinds and sorts are not supported",
+                ))
+            }
+            _ => {
+                return Err(NotSimple::new(
+                    cx,
+                    nir,
+                    "this is not a simple type",
+                ))
+            }
         })
     }
 
@@ -530,6 +1718,152 @@ impl SimpleType {
     pub(crate) fn to_expr(&self) -> Expr {
         Ctxt::with_new(|cx| self.to_hir().to_expr(cx, Default::default()))
     }
+
+    /// Builds the value of this union type made of the alternative `name`, with `payload` as its
+    /// argument. `payload` must be `None` exactly when the alternative is empty. The value prints
+    /// as a constructor selection, e.g. `< A | B: Natural >.A` or `< A | B: Natural >.B 1`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::{NumKind, SimpleType, SimpleValue};
+    ///
+    /// let ty: SimpleType = serde_dhall::from_str("< A | B : Natural >").parse()?;
+    ///
+    /// let a = ty.union_value("A", None)?;
+    /// assert_eq!(a.to_string(), "< A | B: Natural >.A");
+    ///
+    /// let one = SimpleValue::Num(NumKind::Natural(1u64.into()));
+    /// let b = ty.union_value("B", Some(one.clone()))?;
+    /// assert_eq!(b.to_string(), "< A | B: Natural >.B 1");
+    ///
+    /// assert!(ty.union_value("A", Some(one)).is_err());
+    /// assert!(ty.union_value("C", None).is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn union_value(
+        &self,
+        name: &str,
+        payload: Option<SimpleValue>,
+    ) -> Result<Value> {
+        let err = |msg: String| Err(Error(ErrorKind::Serialize(msg)));
+        let alts = match self {
+            SimpleType::Union(alts) => alts,
+            _ => {
+                return err(format!(
+                    "expected a union type, found `{}`",
+                    self.to_expr()
+                ))
+            }
+        };
+        match (alts.get(name), &payload) {
+            (None, _) => {
+                return err(format!(
+                    "no alternative `{}` in the union type `{}`",
+                    name,
+                    self.to_expr()
+                ))
+            }
+            (Some(None), Some(_)) => {
+                return err(format!("alternative `{}` has no payload", name))
+            }
+            (Some(Some(_)), None) => {
+                return err(format!("alternative `{}` needs a payload", name))
+            }
+            _ => {}
+        }
+        SimpleValue::Union(name.to_owned(), payload.map(Box::new))
+            .into_value(Some(self))
+    }
+
+    pub(crate) fn rename_union_alternatives(
+        self,
+        f: &impl Fn(&str) -> String,
+    ) -> Self {
+        use SimpleType::*;
+        let rename =
+            |t: Box<SimpleType>| Box::new(t.rename_union_alternatives(f));
+        match self {
+            Bool | Natural | Integer | Double | Text => self,
+            Optional(t) => Optional(rename(t)),
+            List(t) => List(rename(t)),
+            Record(kts) => Record(
+                kts.into_iter()
+                    .map(|(k, t)| (k, t.rename_union_alternatives(f)))
+                    .collect(),
+            ),
+            Union(kts) => Union(
+                kts.into_iter()
+                    .map(|(k, t)| {
+                        (f(&k), t.map(|t| t.rename_union_alternatives(f)))
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Whether a value of this type can be used where `other` is expected: the two types must be
+    /// equal, up to records where `other` expects maps with text keys and the conversions enabled
+    /// in `coercions`. A map turned into a record must also have the right keys, which is checked
+    /// on the value by [`SimpleValue::has_fields_of`].
+    fn coercible_to(&self, other: &SimpleType, coercions: Coercions) -> bool {
+        use SimpleType::*;
+        match (self, other) {
+            (Natural, Integer) | (Integer, Natural) => coercions.numbers,
+            (Optional(t), Optional(u)) | (List(t), List(u)) => {
+                t.coercible_to(u, coercions)
+            }
+            (Record(kts), Record(kus)) => {
+                kts.len() == kus.len()
+                    && kts.iter().all(|(k, t)| match kus.get(k) {
+                        Some(u) => t.coercible_to(u, coercions),
+                        None => false,
+                    })
+            }
+            (Record(kts), u) => match u.text_map_value() {
+                Some(u) => kts.values().all(|t| t.coercible_to(u, coercions)),
+                None => false,
+            },
+            (t, Record(kus)) if coercions.maps_as_records => {
+                match t.text_map_value() {
+                    Some(t) => {
+                        kus.values().all(|u| t.coercible_to(u, coercions))
+                    }
+                    None => false,
+                }
+            }
+            (Union(kts), Union(kus)) => {
+                kts.len() == kus.len()
+                    && kts.iter().all(|(k, t)| match (t, kus.get(k)) {
+                        (None, Some(None)) => true,
+                        (Some(t), Some(Some(u))) => {
+                            t.coercible_to(u, coercions)
+                        }
+                        _ => false,
+                    })
+            }
+            (t, u) => t == u,
+        }
+    }
+
+    /// `Some(T)` if this is the type `List { mapKey : Text, mapValue : T }` of maps with text
+    /// keys.
+    pub(crate) fn text_map_value(&self) -> Option<&SimpleType> {
+        match self {
+            SimpleType::List(entry) => match &**entry {
+                SimpleType::Record(kts)
+                    if kts.len() == 2
+                        && kts.get("mapKey") == Some(&SimpleType::Text) =>
+                {
+                    kts.get("mapValue")
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 }
 
 impl crate::deserialize::Sealed for Value {}
@@ -543,20 +1877,27 @@ impl FromDhall for Value {
 }
 impl FromDhall for SimpleType {
     fn from_dhall(v: &Value) -> Result<Self> {
-        v.to_simple_type().ok_or_else(|| {
-            Error(ErrorKind::Deserialize(format!(
-                "this cannot be deserialized into a simple type: {}",
-                v
-            )))
-        })
+        v.to_simple_type()
     }
 }
 impl ToDhall for Value {
-    fn to_dhall(&self, _ty: Option<&SimpleType>) -> Result<Value> {
+    fn to_dhall_with(
+        &self,
+        _ty: Option<&SimpleType>,
+        _opts: &SerializeOptions,
+    ) -> Result<Value> {
         Ok(self.clone())
     }
 }
 
+/// Provenance is not compared.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+impl Eq for Value {}
+
 impl Eq for ValueKind {}
 impl PartialEq for ValueKind {
     fn eq(&self, other: &Self) -> bool {
@@ -577,6 +1918,40 @@ impl std::fmt::Display for Value {
     }
 }
 
+impl std::fmt::Display for Provenance {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter,
+    ) -> StdResult<(), std::fmt::Error> {
+        write!(f, "{}", self.source.as_deref().unwrap_or("<string>"))?;
+        if let Some((line, col)) = self.line_col {
+            write!(f, ":{}:{}", line, col)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the value as Dhall code. Where the value doesn't tell its full type, the type
+/// annotations are guessed: empty lists and `None` get the element type `{}`, and union types
+/// only have the alternatives used in the value. Use
+/// [`to_dhall_string()`](SimpleValue::to_dhall_string()) to give the type instead. Values that
+/// no Dhall expression has, like lists whose elements have different types, are printed with
+/// `Debug`.
+impl std::fmt::Display for SimpleValue {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter,
+    ) -> StdResult<(), std::fmt::Error> {
+        let expr = self
+            .infer_type("")
+            .and_then(|ty| self.to_expr(Some(&ty.complete_or_guess())));
+        match expr {
+            Ok(expr) => expr.fmt(f),
+            Err(_) => write!(f, "{:?}", self),
+        }
+    }
+}
+
 impl std::fmt::Display for SimpleType {
     fn fmt(
         &self,
@@ -600,6 +1975,7 @@ fn test_display_value() {
     let val = SimpleValue::List(vec![]);
     let val = Value {
         kind: ValueKind::Val(val, Some(ty)),
+        provenance: BTreeMap::new(),
     };
     assert_eq!(val.to_string(), "[] : List (Optional Natural)".to_string())
 }
@@ -58,6 +58,40 @@ mod serde {
         assert_serde("1.0", 1.0f32);
     }
 
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    fn natural_overflow() {
+        fn parse(s: &str) -> Result<Vec<u64>, String> {
+            from_str(s).parse().map_err(|e| e.to_string())
+        }
+        assert_eq!(
+            parse("[1, 18446744073709551615 + 1]"),
+            Err("Evaluation error: Natural overflow in `18446744073709551615 + 1`".to_string())
+        );
+        assert_eq!(
+            parse("[Natural/fold 2 Natural (\\(x : Natural) -> x * 0x100000000) 1]"),
+            Err("Evaluation error: Natural overflow in `4294967296 * 4294967296`".to_string())
+        );
+        assert_eq!(parse("[18446744073709551614 + 1]"), Ok(vec![u64::MAX]));
+    }
+
+    #[test]
+    #[cfg(feature = "bigint")]
+    fn big_numbers() {
+        let big_nat = u128::from(u64::MAX) + 1;
+        assert_serde("18446744073709551616", big_nat);
+        assert_de("18446744073709551615 + 1", big_nat);
+        assert_de("0xFFFFFFFFFFFFFFFF * 2", 2 * u128::from(u64::MAX));
+
+        let big_int = i128::from(i64::MIN) - 1;
+        assert_serde("-9223372036854775809", big_int);
+        assert_de("Integer/negate +9223372036854775809", big_int);
+        assert_de(
+            "Natural/toInteger 9223372036854775808",
+            i128::from(i64::MAX) + 1,
+        );
+    }
+
     #[test]
     fn text() {
         assert_serde(r#""foo""#, "foo".to_owned());
@@ -82,8 +116,49 @@ mod serde {
         assert_serde("None Text", None::<String>);
         assert_serde("Some 1", Some(1u64));
         assert_eq!(
-            serialize(&None::<u64>).to_string().map_err(|e| e.to_string()),
-            Err("cannot serialize value without a type annotation: Optional(None)".to_string())
+            serialize(&None::<u64>)
+                .to_string()
+                .map_err(|e| e.to_string()),
+            Err("cannot infer type of `None`".to_string())
+        );
+    }
+
+    #[test]
+    fn infer_annotation() {
+        #[derive(Debug, Clone, PartialEq, Serialize)]
+        struct Server {
+            port: Option<u64>,
+            tags: Vec<String>,
+        }
+        let ser = |x: &Vec<Server>| {
+            serialize(x).to_string().map_err(|e| e.to_string())
+        };
+        let server = |port, tags: &[&str]| Server {
+            port,
+            tags: tags.iter().map(|s| s.to_string()).collect(),
+        };
+
+        // Types missing in one element can be found in another one.
+        assert_eq!(
+            ser(&vec![server(None, &[]), server(Some(80), &["web"])]),
+            Ok("[{ port = None Natural, tags = [] : List Text }, \
+                { port = Some 80, tags = [\"web\"] }]"
+                .to_string())
+        );
+        assert_eq!(
+            ser(&vec![server(Some(80), &[]), server(Some(443), &[])]),
+            Err("field `[0].tags`: cannot infer element type of empty list"
+                .to_string())
+        );
+        assert_eq!(
+            ser(&vec![server(None, &["web"])]),
+            Err("field `[0].port`: cannot infer type of `None`".to_string())
+        );
+        assert_eq!(
+            serialize(&vec![Some(1u64), None, Some(2)])
+                .to_string()
+                .map_err(|e| e.to_string()),
+            Ok("[Some 1, None Natural, Some 2]".to_string())
         );
     }
 
@@ -124,6 +199,70 @@ mod serde {
         assert_serde::<Baz>("{ x = 1, y = -2 }", Baz { x: 1, y: -2 });
     }
 
+    #[test]
+    fn maps() {
+        type M<V> = collections::BTreeMap<String, V>;
+        let map = |kvs: &[(&str, u64)]| -> M<u64> {
+            kvs.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+        };
+
+        assert_serde(
+            "[] : List { mapKey : Text, mapValue : Natural }",
+            M::<u64>::new(),
+        );
+        assert_serde(
+            r#"[{ mapKey = "x", mapValue = 1 }, { mapKey = "y", mapValue = 2 }]"#,
+            map(&[("x", 1), ("y", 2)]),
+        );
+        assert_serde(
+            r#"Some [[{ mapKey = "x", mapValue = [Some 1, None Natural] }]]"#,
+            Some(vec![vec![("x".to_owned(), vec![Some(1u64), None])]
+                .into_iter()
+                .collect::<M<_>>()]),
+        );
+        assert_de(
+            "[{ mapKey = 1, mapValue = Some +1 }]",
+            vec![(1u64, Some(1i64))]
+                .into_iter()
+                .collect::<collections::BTreeMap<_, _>>(),
+        );
+
+        // Records and `toMap` are accepted where a map is expected, at any depth.
+        assert_de("toMap { x = 1, y = 2 }", map(&[("x", 1), ("y", 2)]));
+        assert_de("{ x = 1, y = 2 }", map(&[("x", 1), ("y", 2)]));
+        assert_de("{=}", M::<u64>::new());
+        assert_de(
+            "Some [{ a = 1 }, { a = 2 }]",
+            Some(vec![map(&[("a", 1)]), map(&[("a", 2)])]),
+        );
+        let mut nested = M::new();
+        nested.insert("a".to_owned(), map(&[("b", 1)]));
+        let mut outer = M::new();
+        outer.insert("x".to_owned(), nested);
+        assert_de("{ x = toMap { a = { b = 1 } } }", outer);
+        assert!(from_str("{ x = True }")
+            .static_type_annotation()
+            .parse::<M<u64>>()
+            .is_err());
+
+        #[derive(
+            Debug, Clone, PartialEq, Eq, Deserialize, Serialize, StaticType,
+        )]
+        struct Config {
+            servers: Option<Vec<collections::HashMap<String, Vec<u64>>>>,
+        }
+        let mut servers = collections::HashMap::new();
+        servers.insert("a".to_owned(), vec![80, 443]);
+        let config = Config {
+            servers: Some(vec![servers]),
+        };
+        assert_serde(
+            r#"{ servers = Some [[{ mapKey = "a", mapValue = [80, 443] }]] }"#,
+            config.clone(),
+        );
+        assert_de(r#"{ servers = Some [{ a = [80, 443] }] }"#, config);
+    }
+
     #[test]
     fn enums() {
         #[derive(
@@ -325,6 +464,882 @@ mod serde {
         );
     }
 
+    #[test]
+    fn test_import_as_text() {
+        let path = std::env::temp_dir().join("serde_dhall_test_as_text.txt");
+        std::fs::write(&path, "some \"text\" ${not interpolated}\n").unwrap();
+        assert_eq!(
+            serde_dhall::Deserializer::import_as_text(&path)
+                .parse::<String>()
+                .map_err(|e| e.to_string()),
+            Ok("some \"text\" ${not interpolated}\n".to_string())
+        );
+        assert!(serde_dhall::Deserializer::import_as_text(&path)
+            .imports(false)
+            .parse::<String>()
+            .is_err());
+        assert_eq!(
+            serde_dhall::from_str(&format!(
+                "{} as Text ++ \"!\"",
+                path.display()
+            ))
+            .parse::<String>()
+            .map_err(|e| e.to_string()),
+            Ok("some \"text\" ${not interpolated}\n!".to_string())
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_render() {
+        #[derive(Serialize)]
+        struct Args {
+            name: String,
+            count: u64,
+        }
+
+        let path = std::env::temp_dir().join("serde_dhall_test_render.dhall");
+        std::fs::write(
+            &path,
+            r#"\(args : { name : Text, count : Natural }) ->
+                "${args.name} has ${Natural/show args.count} items""#,
+        )
+        .unwrap();
+        let args = Args {
+            name: "foo".to_string(),
+            count: 3,
+        };
+        assert_eq!(
+            serde_dhall::render(&path, &args).map_err(|e| e.to_string()),
+            Ok("foo has 3 items".to_string())
+        );
+        assert!(serde_dhall::render(&path, &true).is_err());
+
+        std::fs::write(&path, r#"\(x : Bool) -> x"#).unwrap();
+        assert!(serde_dhall::render(&path, &true).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_to_file() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize, StaticType)]
+        struct Config {
+            hosts: Vec<String>,
+            backup: Option<String>,
+        }
+
+        let dir = std::env::temp_dir().join("serde_dhall_test_to_file");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.dhall");
+        let config = Config {
+            hosts: (1..=5).map(|i| format!("host-{}.example.com", i)).collect(),
+            backup: None,
+        };
+        serialize(&config)
+            .static_type_annotation()
+            .header_comment("Generated by a test.\n\nDo not edit.")
+            .to_file(&path)
+            .unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with(
+            "-- Generated by a test.\n--\n-- Do not edit.\n\n{ backup = None Text\n"
+        ));
+        assert!(text.ends_with("]\n}\n"));
+        let read: Config = serde_dhall::from_file(&path)
+            .static_type_annotation()
+            .parse()
+            .unwrap();
+        assert_eq!(read, config);
+
+        // A failed serialization leaves the file as it was.
+        let empty: Vec<u64> = Vec::new();
+        assert!(serde_dhall::to_file(&path, &empty, None).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+
+        let ty = serde_dhall::SimpleType::List(Box::new(
+            serde_dhall::SimpleType::Natural,
+        ));
+        serde_dhall::to_file(&path, &empty, Some(&ty)).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "[] : List Natural\n"
+        );
+        // Only the file itself is left in the directory.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_omit_defaults() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize, StaticType)]
+        struct Config {
+            host: String,
+            port: u64,
+            backup: Option<String>,
+        }
+
+        let dir = std::env::temp_dir().join("serde_dhall_test_omit_defaults");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("Config.dhall"),
+            "{ Type = { host : Text, port : Natural, backup : Optional Text }\n\
+             , default = { port = 80, backup = None Text }\n\
+             }",
+        )
+        .unwrap();
+        let schema =
+            serde_dhall::Schema::from_file(dir.join("Config.dhall")).unwrap();
+
+        let path = dir.join("config.dhall");
+        for (config, expected) in &[
+            (
+                Config {
+                    host: "a".to_string(),
+                    port: 80,
+                    backup: None,
+                },
+                r#"(./Config.dhall)::{ host = "a" }"#,
+            ),
+            (
+                Config {
+                    host: "b".to_string(),
+                    port: 8080,
+                    backup: Some("c".to_string()),
+                },
+                r#"(./Config.dhall)::{ backup = Some "c", host = "b", port = 8080 }"#,
+            ),
+        ] {
+            let string = serialize(config)
+                .omit_defaults(&schema, "./Config.dhall")
+                .to_string()
+                .unwrap();
+            assert_eq!(string, *expected);
+
+            // The output reads back to the same value.
+            serialize(config)
+                .static_type_annotation()
+                .omit_defaults(&schema, "./Config.dhall")
+                .to_file(&path)
+                .unwrap();
+            let read: Config = serde_dhall::from_file(&path)
+                .static_type_annotation()
+                .parse()
+                .unwrap();
+            assert_eq!(&read, config);
+        }
+
+        // Values of other types are rejected.
+        assert!(serialize(&1u64)
+            .omit_defaults(&schema, "./Config.dhall")
+            .to_string()
+            .is_err());
+        let ty = serde_dhall::SimpleType::Natural;
+        assert!(serde_dhall::Schema::new(
+            ty,
+            serde_dhall::from_str("{=}").parse().unwrap()
+        )
+        .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dyn_union() {
+        use serde::Deserializer;
+        use serde_dhall::DynUnion;
+
+        trait Shape {
+            fn area(&self) -> f64;
+        }
+        #[derive(Deserialize)]
+        struct Square {
+            side: f64,
+        }
+        impl Shape for Square {
+            fn area(&self) -> f64 {
+                self.side * self.side
+            }
+        }
+        struct Point;
+        impl Shape for Point {
+            fn area(&self) -> f64 {
+                0.0
+            }
+        }
+        impl<'de> Deserialize<'de> for Box<dyn Shape> {
+            fn deserialize<D: Deserializer<'de>>(
+                d: D,
+            ) -> Result<Self, D::Error> {
+                DynUnion::<dyn Shape>::new()
+                    .alternative("Square", |x: Square| Box::new(x))
+                    .alternative_without_payload("Point", || Box::new(Point))
+                    .deserialize(d)
+            }
+        }
+
+        let parse = |s: &str| {
+            from_str(s)
+                .parse::<collections::BTreeMap<String, Box<dyn Shape>>>()
+                .map(|shapes| {
+                    shapes
+                        .into_iter()
+                        .map(|(k, v)| (k, v.area()))
+                        .collect::<Vec<_>>()
+                })
+                .map_err(|e| e.to_string())
+        };
+        let shape = "< Square : { side : Double } | Point | Circle : Double >";
+        assert_eq!(
+            parse(&format!(
+                "let S = {} in {{ a = S.Square {{ side = 2.0 }}, b = S.Point }}",
+                shape
+            )),
+            Ok(vec![("a".to_string(), 4.0), ("b".to_string(), 0.0)])
+        );
+        assert_eq!(
+            parse(&format!("{{ c = ({}).Circle 1.0 }}", shape)),
+            Err("unknown alternative `Circle`, expected one of `Point`, \
+                 `Square`"
+                .to_string())
+        );
+        assert_eq!(
+            parse("{ a = < Square | Point >.Square }"),
+            Err("expected a payload for alternative `Square`".to_string())
+        );
+        assert_eq!(
+            parse("{ a = < Square | Point : Natural >.Point 1 }"),
+            Err("unexpected payload for alternative `Point`".to_string())
+        );
+        assert_eq!(
+            parse("{ a = 1 }"),
+            Err("expected a union value, found `1`".to_string())
+        );
+        // Externally tagged, like serde formats such as JSON represent enums.
+        assert_eq!(
+            parse(r#"{ a = { Square = { side = 3.0 } }, b = "Point" }"#),
+            Ok(vec![("a".to_string(), 9.0), ("b".to_string(), 0.0)])
+        );
+        assert_eq!(
+            parse("{ a = { Square = { side = 3.0 }, Point = {=} } }"),
+            Err("expected a union value, found `{ Point = {=}, Square = { \
+                 side = 3.0 } }`"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn test_duration_and_byte_size() {
+        use std::time::Duration;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Limits {
+            #[serde(with = "serde_dhall::duration")]
+            timeout: Duration,
+            #[serde(with = "serde_dhall::byte_size")]
+            memory: u64,
+        }
+        let parse = |timeout: &str, memory: &str| {
+            from_str(&format!(
+                "{{ timeout = {:?}, memory = {:?} }}",
+                timeout, memory
+            ))
+            .parse::<Limits>()
+            .map(|l| (l.timeout, l.memory))
+            .map_err(|e| e.to_string())
+        };
+        let ok =
+            |secs: f64, bytes: u64| Ok((Duration::from_secs_f64(secs), bytes));
+
+        assert_eq!(parse("30s", "512MiB"), ok(30.0, 512 << 20));
+        assert_eq!(parse("1h 30m", "1.5 GB"), ok(5400.0, 1_500_000_000));
+        assert_eq!(parse("1.5s", "2kib"), ok(1.5, 2048));
+        assert_eq!(parse("2d1min", "100"), ok(172_860.0, 100));
+        assert_eq!(parse("250ms", "1.5KiB"), ok(0.25, 1536));
+        assert_eq!(parse("3µs", "0B"), ok(0.000_003, 0));
+
+        assert_eq!(
+            parse("30", "1B"),
+            Err("timeout: invalid duration `30`: missing unit after `30`"
+                .to_string())
+        );
+        assert_eq!(
+            parse("s", "1B"),
+            Err(
+                "timeout: invalid duration `s`: expected a number, found `s`"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            parse("1.2.3s", "1B"),
+            Err("timeout: invalid duration `1.2.3s`: invalid number `1.2.3`"
+                .to_string())
+        );
+        assert_eq!(
+            parse("1s", "10 MB/s"),
+            Err("memory: invalid byte size `10 MB/s`: unknown unit `MB/s`, \
+                 expected one of `B`, `kB`, `KiB`, `MB`, `MiB`, `GB`, `GiB`, \
+                 `TB`, `TiB`, `PB`, `PiB`"
+                .to_string())
+        );
+        assert_eq!(
+            parse("1s", "100000PiB"),
+            Err("memory: invalid byte size `100000PiB`: too large".to_string())
+        );
+        assert_eq!(
+            from_str("{ timeout = 30, memory = 1 }")
+                .parse::<Limits>()
+                .unwrap_err()
+                .to_string(),
+            "timeout: invalid type: integer `30`, expected a duration like \
+             `30s` or `1h30m`"
+        );
+
+        // Values are written in the form they are read in.
+        for (timeout, memory) in &[
+            (Duration::from_secs(0), 0),
+            (Duration::from_millis(90_500), 1500),
+            (Duration::from_nanos(86_400_000_000_001), 3 << 40),
+            (Duration::from_secs(3600), 5_000_000),
+        ] {
+            let limits = Limits {
+                timeout: *timeout,
+                memory: *memory,
+            };
+            let string = serialize(&limits).to_string().unwrap();
+            let read: Limits = from_str(&string).parse().unwrap();
+            assert_eq!(read, limits, "{}", string);
+        }
+        assert_eq!(
+            serialize(&Limits {
+                timeout: Duration::from_millis(90_500),
+                memory: 3 << 40,
+            })
+            .to_string()
+            .unwrap(),
+            r#"{ memory = "3TiB", timeout = "1m30s500ms" }"#
+        );
+    }
+
+    #[test]
+    fn test_parse_ast() {
+        use serde_dhall::ast::{Expr, ExprKind, ImportTarget};
+
+        fn collect_imports(e: &Expr, out: &mut Vec<String>) {
+            if let ExprKind::Import(import) = e.as_ref() {
+                out.push(match &import.location {
+                    ImportTarget::Env(var) => format!("env:{}", var),
+                    _ => e.to_string(),
+                });
+            }
+            e.as_ref()
+                .traverse_ref(|e| {
+                    collect_imports(e, out);
+                    Ok::<_, ()>(())
+                })
+                .unwrap();
+        }
+
+        let expr = serde_dhall::ast::parse_str(
+            "let x = ./foo.dhall in { a = x, b = env:HOME as Text }",
+        )
+        .unwrap();
+        let mut imports = Vec::new();
+        collect_imports(&expr, &mut imports);
+        assert_eq!(imports, vec!["./foo.dhall", "env:HOME"]);
+
+        assert!(serde_dhall::ast::parse_str("let x =").is_err());
+    }
+
+    #[test]
+    fn test_build_ast() {
+        use serde_dhall::ast::{
+            BinOp, Builtin, Expr, ExprKind, NaiveDouble, NumKind, OpKind, Span,
+        };
+
+        let mk = |kind| Expr::new(kind, Span::Artificial);
+        let double = |x: f64| mk(ExprKind::Num(NumKind::Double(x.into())));
+        let expr = mk(ExprKind::Op(OpKind::BinOp(
+            BinOp::ListAppend,
+            mk(ExprKind::NEListLit(vec![double(1.5)])),
+            mk(ExprKind::EmptyListLit(mk(ExprKind::Op(OpKind::App(
+                mk(ExprKind::Builtin(Builtin::List)),
+                mk(ExprKind::Builtin(Builtin::Double)),
+            ))))),
+        )));
+        assert_eq!(expr.to_string(), "[1.5] # ([] : List Double)");
+
+        let expr = serde_dhall::ast::parse_str("{ x =\n  2.0 }").unwrap();
+        let field = match expr.as_ref() {
+            ExprKind::RecordLit(fields) => fields["x"].clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            field.as_ref(),
+            &ExprKind::Num(NumKind::Double(NaiveDouble::from(2.0)))
+        );
+        match field.span() {
+            Span::Parsed(span) => {
+                assert_eq!(span.byte_range(), 8..11);
+                assert_eq!(span.start_line_col(), (2, 3));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_value_update() {
+        let config: Value =
+            serde_dhall::from_str("{ version = 1, tags = [] : List Text }")
+                .parse()
+                .unwrap();
+        let config = config
+            .with_field("version", &2u64)
+            .unwrap()
+            .with_field("tags", &vec!["a"])
+            .unwrap()
+            .with_field("enabled", &true)
+            .unwrap();
+        assert_eq!(
+            config.to_string(),
+            "{ enabled = True, tags = [\"a\"], version = 2 }"
+        );
+
+        let config = config.without_field("tags").unwrap();
+        let reparsed: Value =
+            serde_dhall::from_str(&config.to_string()).parse().unwrap();
+        assert_eq!(reparsed, config);
+
+        // Untyped empty lists can't be added as new fields.
+        assert!(config.with_field("xs", &Vec::<u64>::new()).is_err());
+        // Only records can be updated.
+        let n: Value = serde_dhall::from_str("1").parse().unwrap();
+        assert!(n.with_field("x", &1u64).is_err());
+        assert!(n.without_field("x").is_err());
+    }
+
+    #[test]
+    fn test_non_text_map_keys() {
+        #[derive(
+            Debug,
+            Clone,
+            Copy,
+            PartialEq,
+            Eq,
+            PartialOrd,
+            Ord,
+            Serialize,
+            Deserialize,
+        )]
+        enum Level {
+            Low,
+            High,
+        }
+        type M = collections::BTreeMap<Level, u64>;
+        let ty =
+            from_str("List { mapKey : < Low | High >, mapValue : Natural }")
+                .parse()
+                .unwrap();
+        let ser = |m: &M| {
+            serialize(m)
+                .type_annotation(&ty)
+                .non_text_map_keys(true)
+                .to_string()
+                .map_err(|e| e.to_string())
+        };
+        let de =
+            |s: &str| from_str(s).type_annotation(&ty).parse::<M>().unwrap();
+
+        let mut m = M::new();
+        m.insert(Level::High, 2);
+        m.insert(Level::Low, 1);
+        let s = ser(&m).unwrap();
+        assert_eq!(
+            s,
+            "[{ mapKey = < High | Low >.Low, mapValue = 1 }, \
+             { mapKey = < High | Low >.High, mapValue = 2 }]"
+        );
+        assert_eq!(de(&s), m);
+
+        let empty = M::new();
+        let s = ser(&empty).unwrap();
+        assert_eq!(
+            s,
+            "[] : List { mapKey : < High | Low >, mapValue : Natural }"
+        );
+        assert_eq!(de(&s), empty);
+
+        // Text keys are unaffected.
+        let mut m = collections::BTreeMap::new();
+        m.insert("x".to_string(), 1u64);
+        assert_eq!(
+            serialize(&m).non_text_map_keys(true).to_string().unwrap(),
+            "{ x = 1 }"
+        );
+        // Without the option, other keys are an error.
+        assert!(serialize(&empty).type_annotation(&ty).to_string().is_ok());
+        let mut m = M::new();
+        m.insert(Level::Low, 1);
+        assert!(serialize(&m).type_annotation(&ty).to_string().is_err());
+    }
+
+    #[test]
+    fn test_unit_representation() {
+        use serde_dhall::UnitRepresentation;
+
+        assert_serde("Some {=}", Some(()));
+        assert_serde("None {}", None::<()>);
+
+        #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+        struct Marker;
+        #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+        struct Pair {
+            a: Marker,
+            b: Option<()>,
+        }
+        let ty = from_str("{ a : < Unit >, b : Optional < Unit > }")
+            .parse()
+            .unwrap();
+        let repr = UnitRepresentation::UnionAlternative("Unit".to_string());
+        for &(s, b) in &[
+            ("{ a = < Unit >.Unit, b = Some < Unit >.Unit }", Some(())),
+            ("{ a = < Unit >.Unit, b = None < Unit > }", None),
+        ] {
+            let x = Pair { a: Marker, b };
+            let ser = serialize(&x)
+                .type_annotation(&ty)
+                .unit_representation(repr.clone())
+                .to_string()
+                .map_err(|e| e.to_string());
+            assert_eq!(ser, Ok(s.to_string()));
+            let de = from_str(s).type_annotation(&ty).parse::<Pair>();
+            assert_eq!(de.map_err(|e| e.to_string()), Ok(x));
+        }
+    }
+
+    #[test]
+    fn test_numeric_policy() {
+        use serde_dhall::NumericPolicy;
+        #[derive(Debug, PartialEq, Serialize, Deserialize, StaticType)]
+        struct Numbers {
+            signed: i64,
+            unsigned: u64,
+        }
+        let x = Numbers {
+            signed: 1,
+            unsigned: 2,
+        };
+        let ser = |policy| {
+            serialize(&x)
+                .numeric_policy(policy)
+                .to_string()
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            ser(NumericPolicy::Exact),
+            Ok("{ signed = +1, unsigned = 2 }".to_string())
+        );
+        assert_eq!(
+            ser(NumericPolicy::IntegerForAll),
+            Ok("{ signed = +1, unsigned = +2 }".to_string())
+        );
+        assert_eq!(
+            ser(NumericPolicy::NaturalWhenPossible),
+            Ok("{ signed = 1, unsigned = 2 }".to_string())
+        );
+        // The annotation takes precedence.
+        for &policy in &[
+            NumericPolicy::IntegerForAll,
+            NumericPolicy::NaturalWhenPossible,
+        ] {
+            let ser = serialize(&x)
+                .static_type_annotation()
+                .numeric_policy(policy)
+                .to_string()
+                .map_err(|e| e.to_string());
+            assert_eq!(ser, Ok("{ signed = +1, unsigned = 2 }".to_string()));
+        }
+
+        let de = |s: &str, policy| {
+            from_str(s)
+                .static_type_annotation()
+                .numeric_policy(policy)
+                .parse::<Numbers>()
+                .map_err(|e| e.to_string())
+        };
+        for &s in &[
+            "{ signed = +1, unsigned = 2 }",
+            "{ signed = 1, unsigned = 2 }",
+            "{ signed = 1, unsigned = +2 }",
+        ] {
+            assert_eq!(
+                de(s, NumericPolicy::IntegerForAll),
+                Ok(Numbers {
+                    signed: 1,
+                    unsigned: 2
+                })
+            );
+        }
+        assert!(
+            de("{ signed = 1, unsigned = 2 }", NumericPolicy::Exact).is_err()
+        );
+        assert!(de(
+            "{ signed = 1, unsigned = -2 }",
+            NumericPolicy::IntegerForAll
+        )
+        .is_err());
+        assert!(de(
+            "{ signed = 1, unsigned = 2, other = 3 }",
+            NumericPolicy::IntegerForAll
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_maps_as_records() {
+        use collections::BTreeMap;
+        #[derive(Debug, PartialEq, Deserialize, StaticType)]
+        struct Ports {
+            http: u64,
+            https: u64,
+        }
+        #[derive(Debug, PartialEq, Deserialize, StaticType)]
+        struct Server {
+            name: String,
+            ports: Ports,
+        }
+        let server = || Server {
+            name: "web".to_owned(),
+            ports: Ports {
+                http: 80,
+                https: 443,
+            },
+        };
+        let de = |s: &str, allow| {
+            from_str(s)
+                .static_type_annotation()
+                .maps_as_records(allow)
+                .parse::<Server>()
+                .map_err(|e| e.to_string())
+        };
+        let map =
+            r#"{ name = "web", ports = toMap { http = 80, https = 443 } }"#;
+        assert!(de(map, false).is_err());
+        assert_eq!(de(map, true), Ok(server()));
+        // Records are still accepted.
+        let record = r#"{ name = "web", ports = { http = 80, https = 443 } }"#;
+        assert_eq!(de(record, true), Ok(server()));
+        // The keys must match the fields exactly.
+        assert!(de(r#"{ name = "web", ports = toMap { http = 80 } }"#, true)
+            .is_err());
+        assert!(de(
+            r#"{ name = "web", ports = toMap { http = 80, https = 443, ssh = 22 } }"#,
+            true
+        )
+        .is_err());
+        assert!(de(
+            r#"{ name = "web", ports = toMap { http = "80", https = "443" } }"#,
+            true
+        )
+        .is_err());
+        // Combined with the numeric policy.
+        assert_eq!(
+            from_str(
+                r#"{ name = "web", ports = toMap { http = +80, https = +443 } }"#
+            )
+            .static_type_annotation()
+            .maps_as_records(true)
+            .numeric_policy(serde_dhall::NumericPolicy::IntegerForAll)
+            .parse::<Server>()
+            .map_err(|e| e.to_string()),
+            Ok(server())
+        );
+
+        // The other way around is always accepted.
+        assert_de(
+            "{ http = 80, https = 443 }",
+            vec![("http".to_owned(), 80u64), ("https".to_owned(), 443)]
+                .into_iter()
+                .collect::<BTreeMap<_, _>>(),
+        );
+        // Without an annotation, both layouts are read either way.
+        assert_eq!(
+            from_str(map).parse::<Server>().map_err(|e| e.to_string()),
+            Ok(server())
+        );
+        assert_eq!(
+            from_str("{ http = 80 }")
+                .parse::<BTreeMap<String, u64>>()
+                .map_err(|e| e.to_string()),
+            Ok(Some(("http".to_owned(), 80)).into_iter().collect())
+        );
+    }
+
+    #[test]
+    fn test_deny_unknown_fields() {
+        use collections::HashMap;
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Server {
+            host: String,
+            port: Option<u64>,
+        }
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum Backend {
+            Single(Server),
+            Pool(Vec<Server>),
+        }
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Config {
+            backend: Backend,
+            env: HashMap<String, String>,
+        }
+        let de = |s: &str| {
+            from_str(s)
+                .deny_unknown_fields()
+                .parse::<Config>()
+                .map_err(|e| e.to_string())
+        };
+        let backend = |b: &str| {
+            format!(
+                "let B = < Single : {{ host : Text, port : Optional Natural }} \
+                         | Pool : List {{ host : Text, port : Optional Natural }} > \
+                 in {{ backend = {}, env = {{ HOME = \"/root\" }} }}",
+                b
+            )
+        };
+
+        assert_eq!(
+            de(&backend(r#"B.Single { host = "a", port = Some 80 }"#)),
+            Ok(Config {
+                backend: Backend::Single(Server {
+                    host: "a".to_owned(),
+                    port: Some(80)
+                }),
+                env: Some(("HOME".to_owned(), "/root".to_owned()))
+                    .into_iter()
+                    .collect(),
+            })
+        );
+        assert_eq!(
+            de(
+                r#"{ backend = < Single : { host : Text, prot : Natural } >.Single { host = "a", prot = 80 }, env = {=} }"#
+            ),
+            Err(
+                "backend.prot: unknown field `prot`, expected `host` or `port`"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            de(
+                r#"{ backend = < Pool : List { host : Text } >.Pool [{ host = "a" }], env = {=}, debug = True }"#
+            ),
+            Err("debug: unknown field `debug`, expected `backend` or `env`"
+                .to_owned())
+        );
+        assert_eq!(
+            de(r#"{ backend = < Pool : List { host : Text, prot : Natural } >.Pool [{ host = "a", prot = 1 }], env = {=} }"#),
+            Err("backend[0].prot: unknown field `prot`, expected `host` or `port`"
+                .to_owned())
+        );
+        // Ignored by default.
+        assert!(from_str(
+            r#"{ backend = < Pool : List { host : Text, prot : Natural } >.Pool [{ host = "a", prot = 1 }], env = {=}, debug = True }"#
+        )
+        .parse::<Config>()
+        .is_ok());
+    }
+
+    #[test]
+    fn test_error_path() {
+        #[derive(Debug, Deserialize)]
+        struct Server {
+            #[allow(dead_code)]
+            port: u8,
+        }
+        #[derive(Debug, Deserialize)]
+        struct Config {
+            #[allow(dead_code)]
+            servers: Vec<Server>,
+            #[allow(dead_code)]
+            ports: collections::HashMap<u64, Option<Server>>,
+        }
+        let parse =
+            |s: &str| from_str(s).parse::<Config>().map_err(|e| e.to_string());
+
+        let err = parse(
+            r#"{ servers = [{ port = 1 }, { port = 300 }], ports = [] : List { mapKey : Natural, mapValue : Optional { port : Natural } } }"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            "servers[1].port: invalid value: integer `300`, expected u8"
+        );
+
+        let err = parse(
+            r#"{ servers = [{ name = "a" }], ports = [] : List { mapKey : Natural, mapValue : Optional { port : Natural } } }"#,
+        )
+        .unwrap_err();
+        assert_eq!(err, "servers[0]: missing field `port`");
+
+        let err = parse(
+            r#"{ servers = [] : List { port : Natural }, ports = [{ mapKey = 1, mapValue = Some { port = -1 } }] }"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            "ports[0].mapValue.port: invalid value: integer `-1`, expected u8"
+        );
+
+        let err = from_str("{ port = True }")
+            .parse::<Server>()
+            .map_err(|e| e.to_string());
+        assert_eq!(
+            err.unwrap_err(),
+            "port: invalid type: boolean `true`, expected u8"
+        );
+        let err = from_str("{=}").parse::<Server>().map_err(|e| e.to_string());
+        assert_eq!(err.unwrap_err(), "missing field `port`");
+    }
+
+    #[test]
+    fn test_path() {
+        let data = "{ services = { `web.v2` = { port = 8080 }, db = { port = 5432 } } }";
+        let parse = |path: &str| {
+            from_str(data)
+                .path(path)
+                .parse::<u64>()
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(parse("services.`web.v2`.port"), Ok(8080));
+        assert_eq!(parse("services.db.port"), Ok(5432));
+        // The dot splits paths, so a field name with a dot must be quoted.
+        let err = parse("services.web.v2.port").unwrap_err();
+        assert!(err.contains("MissingRecordField"), "{}", err);
+        let err = parse("services.cache.port").unwrap_err();
+        assert!(err.contains("MissingRecordField"), "{}", err);
+        let err = parse("").unwrap_err();
+        assert!(err.contains("expected a field name"), "{}", err);
+        let err = parse("services..port").unwrap_err();
+        assert!(err.contains("expected a field name"), "{}", err);
+
+        // A type annotation applies to the selected value.
+        #[derive(Debug, PartialEq, Deserialize, StaticType)]
+        struct Service {
+            port: u64,
+        }
+        let service = from_str(data)
+            .path("services.db")
+            .static_type_annotation()
+            .parse::<Service>()
+            .map_err(|e| e.to_string());
+        assert_eq!(service, Ok(Service { port: 5432 }));
+        let port = from_str(data)
+            .path("services.db.port")
+            .type_annotation(&serde_dhall::SimpleType::Bool)
+            .parse::<serde_dhall::SimpleValue>()
+            .map_err(|e| e.to_string());
+        let err = port.unwrap_err();
+        assert!(err.contains("annot"), "{}", err);
+    }
+
     #[test]
     #[ignore] // Way too slow
     fn test_prelude() {
@@ -339,6 +1354,449 @@ mod serde {
         );
     }
 
+    #[test]
+    fn test_parse_functions() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Point {
+            x: u64,
+            y: u64,
+        }
+
+        let hooks = from_str(
+            r#"
+            let Point = { x : Natural, y : Natural }
+            in  { swap = λ(p : Point) → { x = p.y, y = p.x }
+                , norm1 = λ(p : Point) → p.x + p.y
+                }
+            "#,
+        )
+        .parse_functions()
+        .unwrap();
+        assert_eq!(hooks.len(), 2);
+        assert_eq!(
+            hooks["swap"]
+                .call::<_, Point>(&Point { x: 1, y: 2 })
+                .unwrap(),
+            Point { x: 2, y: 1 }
+        );
+        assert_eq!(
+            hooks["norm1"]
+                .call::<_, u64>(&Point { x: 1, y: 2 })
+                .unwrap(),
+            3
+        );
+        assert!(hooks["norm1"].call::<_, u64>(&true).is_err());
+
+        let err =
+            |s: &str| from_str(s).parse_functions().unwrap_err().to_string();
+        assert_eq!(
+            err("{ f = λ(n : Natural) → n, g = 1 }"),
+            "g: expected a function from a simple type to a simple type, \
+             found a value of type `Natural`"
+        );
+        assert_eq!(
+            err("{ id = λ(a : Type) → λ(x : a) → x }"),
+            "id: expected a function from a simple type to a simple type, \
+             found a value of type `∀(a : Type) → ∀(x : a) → a`"
+        );
+        assert_eq!(
+            err("λ(n : Natural) → n"),
+            "expected a record of functions, found a value of type \
+             `∀(n : Natural) → Natural`"
+        );
+    }
+
+    #[test]
+    fn test_field_provenance() {
+        let dir = std::env::temp_dir().join("serde_dhall_test_provenance");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("base.dhall"),
+            "{ host = \"localhost\"\n, db = { port = 5432, user = \"app\" }\n}",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.dhall"),
+            "let base = ./base.dhall\nin  (base // { host = \"example.com\" }) with db.port = 6543",
+        )
+        .unwrap();
+
+        let value: Value = serde_dhall::from_file(dir.join("main.dhall"))
+            .track_provenance(true)
+            .parse()
+            .unwrap();
+        let origin = |path: &str| {
+            let p = value.field_provenance(path).unwrap();
+            let file = std::path::Path::new(p.source.as_ref().unwrap());
+            (
+                file.file_name().unwrap().to_str().unwrap().to_owned(),
+                p.line_col.unwrap(),
+            )
+        };
+        assert_eq!(origin("host"), ("main.dhall".to_owned(), (2, 23)));
+        assert_eq!(origin("db.user"), ("base.dhall".to_owned(), (2, 30)));
+        assert_eq!(origin("db.port"), ("main.dhall".to_owned(), (2, 55)));
+        assert!(value.field_provenance("db.name").is_none());
+
+        // Merging values keeps track of where their fields come from.
+        let overrides: Value = from_str("{ host = \"other\" }")
+            .track_provenance(true)
+            .parse()
+            .unwrap();
+        let merged = value.merge_prefer(&overrides).unwrap();
+        assert_eq!(merged.field_provenance("host").unwrap().source, None);
+        assert_eq!(
+            merged.field_provenance("db.user"),
+            value.field_provenance("db.user")
+        );
+        let projected = merged.project(&["host"]).unwrap();
+        assert!(projected.field_provenance("db.user").is_none());
+
+        // Provenance is only tracked on demand.
+        let value: Value = serde_dhall::from_file(dir.join("main.dhall"))
+            .parse()
+            .unwrap();
+        assert!(value.field_provenance("host").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_session() {
+        use serde_dhall::Session;
+
+        let dir = std::env::temp_dir().join("serde_dhall_test_session");
+        std::fs::create_dir_all(&dir).unwrap();
+        let shared = dir.join("shared.dhall");
+        std::fs::write(&shared, "40").unwrap();
+        let input = format!("{} + 2", shared.display());
+
+        let (first, second) = Session::with(|session| {
+            let first = from_str(&input).parse_in::<u64>(session).unwrap();
+            // The import was loaded once for the whole session.
+            std::fs::write(&shared, "0").unwrap();
+            let second = from_str(&input).parse_in::<u64>(session).unwrap();
+            let err = from_str("[1, 2]")
+                .parse_limits(serde_dhall::ParseLimits {
+                    max_list_len: Some(1),
+                    ..Default::default()
+                })
+                .parse_in::<Vec<u64>>(session);
+            assert!(err.is_err());
+            (first, second)
+        });
+        assert_eq!((first, second), (42, 42));
+        assert_eq!(from_str(&input).parse::<u64>().unwrap(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_non_simple_alternatives() {
+        use serde_dhall::NonSimpleAlternatives::{self, *};
+
+        let parse = |s: &str, policy: NonSimpleAlternatives| {
+            from_str(s)
+                .non_simple_alternatives(policy)
+                .parse::<Value>()
+                .map(|v| v.to_string())
+                .map_err(|e| e.to_string())
+        };
+        let unused = "{ x = < A : Natural | B : Type >.A 1, y = 2 }";
+        assert_eq!(
+            parse(unused, Fail),
+            Err("x.B: kinds and sorts are not supported: `Type`".to_owned())
+        );
+        assert_eq!(
+            parse(unused, Skip),
+            Ok("{ x = < A: Natural >.A 1, y = 2 }".to_owned())
+        );
+        assert_eq!(
+            parse(unused, Stub),
+            Ok("{ x = < A: Natural | B >.A 1, y = 2 }".to_owned())
+        );
+
+        let used =
+            "< A : Natural | B : Natural -> Natural >.B (λ(x : Natural) → x)";
+        assert!(parse(used, Fail).is_err());
+        assert!(parse(used, Skip).is_err());
+        assert_eq!(parse(used, Stub), Ok("< A: Natural | B >.B".to_owned()));
+
+        // The policy also applies inside the payloads of other alternatives.
+        let nested = "< A : < C : Type | D > | B >.A < C : Type | D >.D";
+        assert_eq!(
+            parse(nested, Skip),
+            Ok("< A: < D > | B >.A < D >.D".to_owned())
+        );
+
+        // Types are converted the same way.
+        assert_eq!(
+            from_str("< A : Bool | B : Type >")
+                .non_simple_alternatives(Skip)
+                .parse::<serde_dhall::SimpleType>()
+                .map_err(|e| e.to_string()),
+            from_str("< A : Bool >").parse().map_err(|e| e.to_string())
+        );
+    }
+
+    #[test]
+    fn test_normalize_in_context() {
+        use serde_dhall::Bindings;
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Server {
+            host: String,
+            port: u64,
+        }
+
+        let config: Value = from_str(r#"{ host = "localhost", port = 80 }"#)
+            .parse()
+            .unwrap();
+        let port_type: Value = from_str("Natural").parse().unwrap();
+        let ctx = Bindings::new()
+            .bind("config", &config)
+            .unwrap()
+            .bind("Port", &port_type)
+            .unwrap()
+            .bind("tags", &vec!["a".to_owned()])
+            .unwrap();
+        assert_eq!(ctx.get("config"), Some(&config));
+        assert_eq!(ctx.get("missing"), None);
+
+        let overridden = Value::normalize_in_context(
+            "config // { port = 8080 : Port }",
+            &ctx,
+        )
+        .unwrap();
+        assert_eq!(
+            overridden.to_string(),
+            r#"{ host = "localhost", port = 8080 }"#
+        );
+        assert_eq!(
+            Value::normalize_in_context("List/length Text tags", &ctx)
+                .unwrap()
+                .as_natural()
+                .unwrap(),
+            1
+        );
+        assert!(Value::normalize_in_context("config.user", &ctx).is_err());
+        assert!(Value::normalize_in_context("unbound", &ctx).is_err());
+
+        let server = from_str("config // { host = \"example.com\" }")
+            .with_bindings(&ctx)
+            .parse::<Server>()
+            .unwrap();
+        assert_eq!(
+            server,
+            Server {
+                host: "example.com".to_owned(),
+                port: 80
+            }
+        );
+
+        // Rebinding a name replaces the previous value.
+        let ctx = ctx.bind("config", &1u64).unwrap();
+        assert_eq!(
+            Value::normalize_in_context("config + 1", &ctx)
+                .unwrap()
+                .as_natural()
+                .unwrap(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_dhall_macro() {
+        use serde_dhall::{ast, dhall};
+
+        let expr = dhall!({ port = 80, host = "x" });
+        assert_eq!(
+            expr,
+            ast::parse_str(r#"{ port = 80, host = "x" }"#).unwrap()
+        );
+        assert_eq!(expr.to_string(), r#"{ host = "x", port = 80 }"#);
+
+        let source = r#"
+            let Server = { Type = { port : Natural, tls : Optional Bool }, default = { tls = None Bool } }
+            let f = \(x : Natural) -> [x, x * 2] # ([] : List Natural)
+            in  { server = Server::{ port = 8080 }
+                , text = "${Natural/show 1}-''"
+                , int = -2
+                , double = 1.5e-3
+                , alt = (< A | B : Text >.B "b")
+                , m = merge { A = 0, B = \(t : Text) -> 1 } (< A | B : Text >.A)
+                , sel = { a = 1, b = 2 }.{ a }
+                , w = { a.b = 1 } with a.c = 2
+                , t = Type
+                , fn = f
+                } // { m2 = toMap { x = True } }
+        "#;
+        let expr = dhall!(
+            r#"
+            let Server = { Type = { port : Natural, tls : Optional Bool }, default = { tls = None Bool } }
+            let f = \(x : Natural) -> [x, x * 2] # ([] : List Natural)
+            in  { server = Server::{ port = 8080 }
+                , text = "${Natural/show 1}-''"
+                , int = -2
+                , double = 1.5e-3
+                , alt = (< A | B : Text >.B "b")
+                , m = merge { A = 0, B = \(t : Text) -> 1 } (< A | B : Text >.A)
+                , sel = { a = 1, b = 2 }.{ a }
+                , w = { a.b = 1 } with a.c = 2
+                , t = Type
+                , fn = f
+                } // { m2 = toMap { x = True } }
+        "#
+        );
+        assert_eq!(expr, ast::parse_str(source).unwrap());
+    }
+
+    #[test]
+    fn test_include_dhall() {
+        use serde_dhall::include_dhall;
+
+        #[derive(Debug, PartialEq, Deserialize, StaticType)]
+        struct Config {
+            host: String,
+            port: u64,
+            tags: Vec<String>,
+        }
+        #[derive(Debug, Deserialize, StaticType)]
+        struct Wrong {
+            #[allow(dead_code)]
+            port: String,
+        }
+
+        assert_eq!(
+            include_dhall!("tests/data/include.dhall", Config).unwrap(),
+            Config {
+                host: "localhost".to_owned(),
+                port: 8080,
+                tags: vec!["a".to_owned(), "b".to_owned()],
+            }
+        );
+        // The type is only checked at runtime.
+        assert!(include_dhall!("tests/data/include.dhall", Wrong).is_err());
+    }
+
+    #[test]
+    fn test_any() {
+        use serde_dhall::Any;
+
+        let any: Any = from_str(
+            r#"{
+                servers = [{ host = "a", port = Some 80 }, { host = "b", port = None Natural }],
+                mode = < Fast | Slow : Integer >.Slow -1,
+                debug = < Fast | Slow : Integer >.Fast,
+            }"#,
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(any["servers"][0]["host"].as_str(), Some("a"));
+        assert_eq!(any["servers"][0]["port"].as_u64(), Some(80));
+        assert_eq!(any["servers"][0]["port"].as_i64(), Some(80));
+        assert!(any["servers"][1]["port"].is_null());
+        assert!(any["servers"][2]["host"].is_null());
+        assert!(any["missing"][0]["field"].is_null());
+        assert_eq!(any["servers"].as_list().map(|xs| xs.len()), Some(2));
+        assert_eq!(
+            any["mode"],
+            Any::Union("Slow".to_owned(), Some(Box::new(Any::Integer(-1))))
+        );
+        assert_eq!(any["debug"], Any::Union("Fast".to_owned(), None));
+        assert_eq!(any.get("missing"), None);
+
+        let any: Any = from_str(r#"{ name = "x", tags = ["a"], size = 1.5 }"#)
+            .parse()
+            .unwrap();
+        assert_eq!(any["size"].as_f64(), Some(1.5));
+        assert_eq!(
+            serialize(&any).to_string().unwrap(),
+            r#"{ name = "x", size = 1.5, tags = ["a"] }"#
+        );
+    }
+
+    #[test]
+    fn test_parse_with_report() {
+        let dir = std::env::temp_dir().join("serde_dhall_test_report");
+        std::fs::create_dir_all(&dir).unwrap();
+        let outer = dir.join("outer.dhall");
+        let inner = dir.join("inner.dhall");
+        std::fs::write(&outer, "./inner.dhall + ./inner.dhall").unwrap();
+        std::fs::write(
+            &inner,
+            "Natural/fold 3 Natural (λ(n : Natural) → n + 1) 0",
+        )
+        .unwrap();
+
+        let input = format!("{} * 2", outer.display());
+        let (data, report) =
+            from_str(&input).parse_with_report::<u64>().unwrap();
+        assert_eq!(data, 12);
+        // The second import of `inner.dhall` isn't loaded again.
+        let locations: Vec<_> = report
+            .imports
+            .iter()
+            .map(|cost| cost.location.as_local_path().unwrap().to_owned())
+            .collect();
+        assert_eq!(locations, vec![inner, outer]);
+        assert!(report.imports.iter().all(|cost| !cost.from_cache));
+        assert!(report.imports[1].resolve.time >= report.imports[0].time());
+        assert!(report.resolve.time >= report.imports[1].time());
+        // No counting allocator in this test.
+        assert_eq!(report.parse.peak_memory, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        use serde_dhall::Value;
+
+        let roundtrip = |s: &str, provenance: bool| {
+            let value: Value =
+                from_str(s).track_provenance(provenance).parse().unwrap();
+            let snapshot = value.to_snapshot().unwrap();
+            let loaded = Value::from_snapshot(&snapshot).unwrap();
+            assert_eq!(loaded, value);
+            assert_eq!(loaded.to_string(), value.to_string());
+            loaded
+        };
+        roundtrip(
+            "{ a = [] : List Natural, b = None Text, c = Some -1 }",
+            false,
+        );
+        roundtrip("< A | B : Double >.B 1.5", false);
+        roundtrip("[< A | B : Bool >.A]", false);
+        roundtrip("{ x : Optional Text, y : List { z : Integer } }", false);
+        let value = roundtrip("{ a = { b = \"c\" } }", true);
+        assert_eq!(
+            value.field_provenance("a.b").unwrap().line_col,
+            Some((1, 13))
+        );
+        assert!(Value::from_snapshot(b"not a snapshot").is_err());
+
+        let dir = std::env::temp_dir().join("serde_dhall_test_snapshot");
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("config.dhall");
+        let inner = dir.join("inner.dhall");
+        let snapshot = dir.join("config.snapshot");
+        std::fs::write(&config, "{ port = ./inner.dhall + 80 }").unwrap();
+        std::fs::write(&inner, "8000").unwrap();
+        let load = || {
+            let (value, report) = serde_dhall::from_file(&config)
+                .snapshot(&snapshot)
+                .parse_with_report::<collections::HashMap<String, u64>>()
+                .unwrap();
+            // Nothing is imported when the snapshot is used.
+            (value["port"], !report.imports.is_empty())
+        };
+        assert_eq!(load(), (8080, true));
+        assert!(snapshot.exists());
+        assert_eq!(load(), (8080, false));
+        std::fs::write(&inner, "9000").unwrap();
+        assert_eq!(load(), (9080, true));
+        assert_eq!(load(), (9080, false));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     // TODO: test various builder configurations
     // In particular test cloning and reusing builder
 }
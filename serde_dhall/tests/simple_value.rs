@@ -1,7 +1,8 @@
 mod simple_value {
     use serde::{Deserialize, Serialize};
     use serde_dhall::{
-        from_str, serialize, FromDhall, NumKind, SimpleValue, ToDhall, Value,
+        from_str, serialize, FromDhall, NumKind, SimpleType, SimpleValue,
+        ToDhall, Value,
     };
 
     fn assert_de<T>(s: &str, x: T)
@@ -56,15 +57,339 @@ mod simple_value {
         );
 
         // Neither a simple value or a simple type.
-        let not_simple = "Type → Type";
+        let err =
+            |s: &str| from_str(s).parse::<Value>().unwrap_err().to_string();
         assert_eq!(
-            from_str(not_simple)
-                .parse::<Value>()
-                .map_err(|e| e.to_string()),
-            Err(format!(
-                "this is neither a simple type nor a simple value: {}",
-                not_simple
-            ))
+            err("Type → Type"),
+            "function types are not supported: `Type → Type`"
         );
+        assert_eq!(
+            err("{ a = [{ b = λ(x : Bool) → x }] }"),
+            "a[0].b: functions are not supported: `λ(x : Bool) → x`"
+        );
+        assert_eq!(
+            err("{ a : Optional Bool, b : { c : Type } }"),
+            "b.c: kinds and sorts are not supported: `Type`"
+        );
+        assert_eq!(
+            err("Some < A : Natural | B >.A"),
+            "union constructors are not supported: `< A: Natural | B >.A`"
+        );
+        assert_eq!(
+            err("{ fs = [Natural/even] }"),
+            "fs[0]: builtin functions are not supported: `Natural/even`"
+        );
+
+        // Values and types don't convert into each other.
+        let value = from_str("{ a = 1 }").parse::<Value>().unwrap();
+        assert!(value.to_simple_value().is_ok());
+        assert_eq!(
+            value.to_simple_type().unwrap_err().to_string(),
+            "this is a value, not a type: `{ a = 1 }`"
+        );
+        let ty = from_str("{ a : Natural }").parse::<Value>().unwrap();
+        assert!(ty.to_simple_type().is_ok());
+        assert_eq!(
+            ty.to_simple_value().unwrap_err().to_string(),
+            "this is a type, not a value: `{ a : Natural }`"
+        );
+    }
+
+    #[test]
+    fn test_scalar_accessors() {
+        let value = |s: &str| from_str(s).parse::<Value>().unwrap();
+        assert!(!value("True && False").as_bool().unwrap());
+        assert_eq!(value("2 * 21").as_natural().unwrap(), 42);
+        assert_eq!(value("-3").as_integer().unwrap(), -3);
+        assert_eq!(value("1.5").as_double().unwrap(), 1.5);
+        assert_eq!(value("\"a\" ++ \"b\"").as_text().unwrap(), "ab");
+
+        let err = |s: &str, f: fn(&Value) -> serde_dhall::Result<()>| {
+            f(&value(s)).unwrap_err().to_string()
+        };
+        assert_eq!(
+            err("{ a = 1 }", |v| v.as_natural().map(drop)),
+            "expected a value of type `Natural`, found `{ a = 1 }` of type \
+             `{ a : Natural }`"
+        );
+        assert_eq!(
+            err("1", |v| v.as_integer().map(drop)),
+            "expected a value of type `Integer`, found `1` of type `Natural`"
+        );
+        assert_eq!(
+            err("Bool", |v| v.as_bool().map(drop)),
+            "expected a value of type `Bool`, found the type `Bool`"
+        );
+    }
+
+    #[test]
+    fn test_record_merges() {
+        let value = |s: &str| from_str(s).parse::<Value>().unwrap();
+        let base = value("{ a = { b = 1 }, c = True }");
+        let merged = base.merge_prefer(&value("{ a = { d = 2 } }")).unwrap();
+        assert_eq!(merged.to_string(), "{ a = { d = 2 }, c = True }");
+        let merged = base.merge_recursive(&value("{ a = { d = 2 } }")).unwrap();
+        assert_eq!(merged.to_string(), "{ a = { b = 1, d = 2 }, c = True }");
+        assert!(base.merge_prefer(&value("1")).is_err());
+        assert!(base.merge_types(&value("{ c : Bool }")).is_err());
+        let ty = value("{ a : { b : Natural } }")
+            .merge_types(&value("{ a : { d : Bool } }"))
+            .unwrap();
+        assert_eq!(ty.to_string(), "{ a : { b : Natural, d : Bool } }");
+    }
+
+    #[test]
+    fn test_project() {
+        let value = |s: &str| from_str(s).parse::<Value>().unwrap();
+        let config =
+            value("{ host = \"h\", port = 1, tls = { cert = \"c\" } }");
+        let projected = config.project(&["port", "tls"]).unwrap();
+        assert_eq!(
+            projected.to_string(),
+            "{ port = 1, tls = { cert = \"c\" } }"
+        );
+        assert_eq!(config.project(&[]).unwrap().to_string(), "{=}");
+        assert!(config.project(&["prot"]).is_err());
+        assert!(value("[1]").project(&["host"]).is_err());
+        let ty = value("{ a : Bool, b : Natural }").project(&["b"]);
+        assert!(ty.is_err());
+    }
+
+    #[test]
+    fn test_match_union() {
+        let value = |s: &str| from_str(s).parse::<Value>().unwrap();
+        let union = value("< A : Natural | B >.B");
+        let matched = union
+            .match_union()
+            .case("A", |_| 1)
+            .case_without_payload("B", || 2)
+            .finish();
+        assert_eq!(matched.unwrap(), 2);
+
+        let err = |m: serde_dhall::UnionMatch<'_, ()>| {
+            m.finish().unwrap_err().to_string()
+        };
+        assert_eq!(
+            err(union.match_union().case("A", |_| ()).case("B", |_| ())),
+            "alternative `B` has no payload, handle it with \
+             `case_without_payload`"
+        );
+        assert_eq!(
+            err(union.match_union().case("C", |_| ())),
+            "no alternative `C` in the union type `< A: Natural | B >`"
+        );
+        assert_eq!(
+            err(union.match_union().case("A", |_| ()).case("A", |_| ())),
+            "duplicate handler for alternative `A`"
+        );
+        assert_eq!(
+            err(value("[1]").match_union()),
+            "expected a union value, found `[1]`"
+        );
+    }
+
+    #[test]
+    fn test_load_schema() {
+        let schema = serde_dhall::from_file("tests/data/schema.dhall")
+            .parse::<SimpleType>()
+            .unwrap();
+        let data = r#"{ name = "web", port = 80, tags = [] : List Text }"#;
+        assert!(from_str(data)
+            .type_annotation(&schema)
+            .parse::<SimpleValue>()
+            .is_ok());
+        let data = r#"{ name = "web", port = "80", tags = [] : List Text }"#;
+        assert!(from_str(data)
+            .type_annotation(&schema)
+            .parse::<SimpleValue>()
+            .is_err());
+
+        let ty = |s: &str| from_str(s).parse::<SimpleType>();
+        assert_eq!(
+            ty("1").unwrap_err().to_string(),
+            "this is a value, not a type: `1`"
+        );
+        assert_eq!(
+            ty("Type").unwrap_err().to_string(),
+            "kinds and sorts are not supported: `Type`"
+        );
+        assert_eq!(
+            ty("{ Port = Natural }").unwrap_err().to_string(),
+            "Port: types are only supported as the whole value: `Natural`"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let ty = |s: &str| from_str(s).parse::<SimpleType>().unwrap();
+        let val = |s: &str| from_str(s).parse::<SimpleValue>().unwrap();
+        let errors = |t: &str, v: &str| match ty(t).validate(&val(v)) {
+            Ok(()) => vec![],
+            Err(e) => e.violations().iter().map(|v| v.to_string()).collect(),
+        };
+        let ok =
+            |t: &str, v: &str| assert_eq!(errors(t, v), Vec::<String>::new());
+
+        ok("Natural", "1");
+        ok("Natural", "+1");
+        ok("Integer", "1");
+        ok("Double", "-1");
+        ok("Optional Natural", "Some 1");
+        ok("Optional Natural", "None Natural");
+        ok("Optional Natural", "1");
+        ok("{ a : Optional Text, b : Bool }", "{ b = True }");
+        ok("List { mapKey : Text, mapValue : Bool }", "{ a = True }");
+        ok("< A | B : Natural >", "< A | B : Natural >.B 1");
+        assert_eq!(
+            errors("Bool", "1"),
+            vec!["expected `Bool`, found the Natural 1"]
+        );
+        assert_eq!(
+            errors("Text", "[1]"),
+            vec!["expected `Text`, found a List"]
+        );
+        assert_eq!(
+            errors(
+                "{ servers : List { host : Text, port : Natural }, name : Text }",
+                "{ servers = [{ host = 1, port = +1 }, { host = 2, port = -1 }] }"
+            ),
+            vec![
+                "name: missing field of type `Text`",
+                "servers[0].host: expected `Text`, found the Natural 1",
+                "servers[1].host: expected `Text`, found the Natural 2",
+                "servers[1].port: expected `Natural`, found the negative Integer -1",
+            ]
+        );
+        assert_eq!(
+            errors("List { mapKey : Text, mapValue : Bool }", "{ a = 1 }"),
+            vec!["a: expected `Bool`, found the Natural 1"]
+        );
+        assert_eq!(
+            errors("< A | B : Natural >", "< C | D : Bool >.D True"),
+            vec!["no alternative `D` in the union type `< A | B: Natural >`"]
+        );
+        assert_eq!(
+            errors("{ u : < A | B : Natural > }", "{ u = < A | B >.B }"),
+            vec!["u: alternative `B` needs a payload"]
+        );
+
+        let err: serde_dhall::Error =
+            ty("Bool").validate(&val("1")).unwrap_err().into();
+        assert_eq!(err.to_string(), "expected `Bool`, found the Natural 1");
+    }
+
+    #[test]
+    fn test_union_value() {
+        let ty: SimpleType =
+            from_str("< A | B : Natural | C : Bool >").parse().unwrap();
+        let a = ty.union_value("A", None).unwrap();
+        assert_eq!(a.to_string(), "< A | B: Natural | C: Bool >.A");
+        assert_eq!(a, from_str(&a.to_string()).parse::<Value>().unwrap());
+        assert_eq!(
+            a.to_simple_value().unwrap(),
+            SimpleValue::Union("A".to_string(), None)
+        );
+        let one: SimpleValue = from_str("1").parse().unwrap();
+        let b = ty.union_value("B", Some(one.clone())).unwrap();
+        assert_eq!(b.to_string(), "< A | B: Natural | C: Bool >.B 1");
+        let matched = b
+            .match_union()
+            .case_without_payload("A", || 0)
+            .case("B", |n| n.as_natural().unwrap())
+            .case("C", |_| 2)
+            .finish();
+        assert_eq!(matched.unwrap(), 1);
+
+        let err = |name: &str, payload: Option<SimpleValue>| {
+            ty.union_value(name, payload).unwrap_err().to_string()
+        };
+        assert_eq!(
+            err("D", None),
+            "no alternative `D` in the union type \
+             `< A | B: Natural | C: Bool >`"
+        );
+        assert_eq!(
+            err("A", Some(one.clone())),
+            "alternative `A` has no payload"
+        );
+        assert_eq!(err("B", None), "alternative `B` needs a payload");
+        assert!(ty.union_value("C", Some(one.clone())).is_err());
+        assert_eq!(
+            SimpleType::Natural
+                .union_value("A", None)
+                .unwrap_err()
+                .to_string(),
+            "expected a union type, found `Natural`"
+        );
+    }
+
+    #[test]
+    fn test_record_order() {
+        fn keys(s: &str) -> Vec<String> {
+            match from_str(s).parse::<SimpleValue>().unwrap() {
+                SimpleValue::Record(kvs) => kvs.keys().cloned().collect(),
+                _ => panic!("not a record"),
+            }
+        }
+        assert_eq!(keys("{ b = 1, a = 2 }"), vec!["a", "b"]);
+
+        let assoc_list =
+            "[{ mapKey = \"b\", mapValue = 1 }, { mapKey = \"a\", mapValue = 2 }]";
+        #[cfg(not(feature = "preserve_order"))]
+        assert_eq!(keys(assoc_list), vec!["a", "b"]);
+        #[cfg(feature = "preserve_order")]
+        assert_eq!(keys(assoc_list), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn test_display() {
+        // Printed values read back to the same value and type.
+        for (source, ty) in &[
+            (
+                r#"{ `if` = "a \"b\"\n${"$"}{c}", `user name` = +1, x = [1.5] }"#,
+                "{ `if` : Text, `user name` : Integer, x : List Double }",
+            ),
+            (
+                "[Some [< A | B : Bool >.B True], None (List < A | B : Bool >)]",
+                "List (Optional (List < A | B : Bool >))",
+            ),
+            (
+                "< `Some Thing` | B : Natural >.`Some Thing`",
+                "< `Some Thing` | B : Natural >",
+            ),
+            ("[] : List { x : Natural }", "List { x : Natural }"),
+        ] {
+            let ty: SimpleType = from_str(ty).parse().unwrap();
+            let value: SimpleValue =
+                from_str(source).type_annotation(&ty).parse().unwrap();
+            let printed = value.to_dhall_string(Some(&ty)).unwrap();
+            let back: SimpleValue =
+                from_str(&printed).type_annotation(&ty).parse().unwrap();
+            assert_eq!(back, value);
+            let printed_ty: SimpleType =
+                from_str(&ty.to_string()).parse().unwrap();
+            assert_eq!(printed_ty, ty);
+        }
+
+        let value: SimpleValue =
+            from_str("{ a = [Some 1, None Natural] }").parse().unwrap();
+        assert_eq!(value.to_string(), "{ a = [Some 1, None Natural] }");
+        assert_eq!(value.to_dhall_string(None).unwrap(), value.to_string());
+
+        // Types the value doesn't tell are guessed when displaying.
+        let value: SimpleValue =
+            from_str("let T = < A | B : Natural | C > in [T.A, T.B 1, T.C]")
+                .parse()
+                .unwrap();
+        assert!(value.to_dhall_string(None).is_err());
+        assert_eq!(
+            value.to_string(),
+            "[< A | B: Natural | C >.A, < A | B: Natural | C >.B 1, < A | B: Natural | C >.C]"
+        );
+        let value: SimpleValue =
+            from_str("{ x = [] : List Bool, y = None Text }")
+                .parse()
+                .unwrap();
+        assert_eq!(value.to_string(), "{ x = [] : List {}, y = None {} }");
     }
 }
@@ -9,6 +9,17 @@ fn test_static_type() {
     assert_eq!(bool::static_type(), parse("Bool"));
     assert_eq!(String::static_type(), parse("Text"));
     assert_eq!(<Option<bool>>::static_type(), parse("Optional Bool"));
+    assert_eq!(
+        <Option<Vec<std::collections::HashMap<String, Vec<u64>>>>>::static_type(
+        ),
+        parse(
+            "Optional (List (List { mapKey: Text, mapValue: List Natural }))"
+        )
+    );
+    assert_eq!(
+        <std::collections::BTreeMap<u64, Option<bool>>>::static_type(),
+        parse("List { mapKey: Natural, mapValue: Optional Bool }")
+    );
     assert_eq!(
         <(bool, Vec<String>)>::static_type(),
         parse("{ _1: Bool, _2: List Text }")